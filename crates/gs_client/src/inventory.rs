@@ -0,0 +1,296 @@
+//! The hotbar and inventory window of the local player, sending the item moves to the server as inventory transactions.
+//! Opening the window also opens the nearest container in reach, if there is one.
+
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+use bevy_egui::egui;
+use bevy_egui::EguiContexts;
+use gs_common::inventory::{
+    ClientInventories, Inventory, InventoryAction, InventoryContainer, InventoryRef, InventoryTransaction, SlotRef,
+    HOTBAR_SIZE, MAX_INVENTORY_VIEW_DISTANCE,
+};
+use gs_common::items::ItemRegistryHolder;
+use gs_common::network::entity_replication::ReplicatedFromServer;
+use gs_common::network::thread::NetworkThread;
+use gs_schemas::items::{ItemRegistry, ItemStack};
+
+use crate::debugcam::FlyCam;
+use crate::network::NetworkThreadClientState;
+use crate::states::{ClientAppState, InGameSystemSet};
+use crate::ClientNetworkThreadHolder;
+
+/// Opens and closes the inventory window.
+const TOGGLE_INVENTORY_KEY: KeyCode = KeyCode::KeyE;
+/// Select the hotbar slots, in order.
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SIZE] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+/// The width and height of a slot button, in points.
+const SLOT_SIZE: f32 = 48.0;
+
+/// The plugin drawing the hotbar and inventory window while in game.
+pub struct InventoryUiPlugin;
+
+impl Plugin for InventoryUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InventoryUiState>()
+            .add_systems(
+                Update,
+                (inventory_keyboard_input, inventory_ui).chain().in_set(InGameSystemSet),
+            )
+            .add_systems(OnExit(ClientAppState::InGame), inventory_ui_cleanup_on_exit);
+    }
+}
+
+/// The local state of the inventory UI.
+#[derive(Resource, Default, Debug)]
+pub struct InventoryUiState {
+    /// The selected hotbar slot, an index into the player's inventory.
+    pub selected_hotbar_slot: usize,
+    window_open: bool,
+    /// The slot clicked first, the source of the transaction sent when clicking the target slot.
+    picked: Option<SlotRef>,
+}
+
+/// Asks the server to apply the transaction, the new contents then arrive on the inventory stream.
+pub fn send_inventory_transaction(
+    net_thread: &NetworkThread<NetworkThreadClientState>,
+    transaction: InventoryTransaction,
+) {
+    // Failures are logged by the task itself, the scheduling result carries no extra information.
+    let _ = net_thread.schedule_task(move |state| {
+        Box::pin(async move {
+            let Some(auth_rpc) = state.borrow().server_auth_rpc().cloned() else {
+                warn!("Cannot send an inventory transaction without a server connection");
+                return Ok(());
+            };
+            let mut rq = auth_rpc.apply_inventory_transaction_request();
+            transaction.write_to_message(rq.get().init_transaction());
+            match rq.send().promise.await {
+                Ok(response) if response.get()?.get_accepted() => {}
+                Ok(_) => debug!("The server rejected inventory transaction {transaction:?}"),
+                Err(e) => warn!("Inventory transaction request failed: {e}"),
+            }
+            Ok(())
+        })
+    });
+}
+
+/// Asks the server to start viewing the inventory of the given server-side entity, or to stop viewing with `None`.
+pub fn request_inventory_view(net_thread: &NetworkThread<NetworkThreadClientState>, entity: Option<u64>) {
+    // Failures are logged by the task itself, the scheduling result carries no extra information.
+    let _ = net_thread.schedule_task(move |state| {
+        Box::pin(async move {
+            let Some(auth_rpc) = state.borrow().server_auth_rpc().cloned() else {
+                warn!("Cannot open an inventory without a server connection");
+                return Ok(());
+            };
+            let mut rq = auth_rpc.open_inventory_request();
+            rq.get().set_entity_id(entity.unwrap_or(0));
+            match rq.send().promise.await {
+                Ok(response) if response.get()?.get_accepted() => {}
+                Ok(_) => debug!("The server refused to open the inventory of {entity:?}"),
+                Err(e) => warn!("Open inventory request failed: {e}"),
+            }
+            Ok(())
+        })
+    });
+}
+
+/// The server-side ID of the nearest replicated container within reach of the camera.
+fn nearest_container(
+    camera: &Transform,
+    containers: &Query<(&ReplicatedFromServer, &Transform), With<InventoryContainer>>,
+) -> Option<u64> {
+    containers
+        .iter()
+        .map(|(replicated, transform)| {
+            let distance = transform.translation.distance_squared(camera.translation);
+            (replicated.server_entity, distance)
+        })
+        .filter(|&(_, distance)| distance <= MAX_INVENTORY_VIEW_DISTANCE.powi(2))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(id, _)| id)
+}
+
+fn inventory_keyboard_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut ui_state: ResMut<InventoryUiState>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+    inventories: Res<ClientInventories>,
+    net_thread: Option<Res<ClientNetworkThreadHolder>>,
+    camera: Query<&Transform, With<FlyCam>>,
+    containers: Query<(&ReplicatedFromServer, &Transform), With<InventoryContainer>>,
+) {
+    if let Some(slot) = HOTBAR_KEYS.iter().position(|&key| keys.just_pressed(key)) {
+        ui_state.selected_hotbar_slot = slot;
+    }
+    if keys.just_pressed(TOGGLE_INVENTORY_KEY) {
+        ui_state.window_open = !ui_state.window_open;
+        ui_state.picked = None;
+        if let Some(net_thread) = net_thread {
+            if ui_state.window_open {
+                let container = camera
+                    .get_single()
+                    .ok()
+                    .and_then(|camera| nearest_container(camera, &containers));
+                if container.is_some() {
+                    request_inventory_view(&net_thread.0, container);
+                }
+            } else if inventories.viewed().is_some() {
+                request_inventory_view(&net_thread.0, None);
+            }
+        }
+        // Release the cursor grabbed by the camera to be able to click the slots.
+        if let (true, Ok(mut window)) = (ui_state.window_open, primary_window.get_single_mut()) {
+            window.cursor.grab_mode = CursorGrabMode::None;
+            window.cursor.visible = true;
+        }
+    }
+}
+
+/// Draws a single slot with the item name and count.
+fn slot_button(ui: &mut egui::Ui, items: &ItemRegistry, stack: Option<&ItemStack>, selected: bool) -> egui::Response {
+    let text = match stack {
+        Some(stack) => {
+            let name = items
+                .lookup_id_to_object(stack.item)
+                .map_or_else(|| String::from("?"), |item| item.name.key.to_string());
+            format!("{name}\n{}", stack.count)
+        }
+        None => String::new(),
+    };
+    ui.add_sized(
+        [SLOT_SIZE, SLOT_SIZE],
+        egui::SelectableLabel::new(selected, egui::RichText::new(text).small()),
+    )
+}
+
+/// Draws the slots of an inventory in rows as long as the hotbar, returns the clicked slot and if it was a right click.
+fn slot_grid(
+    ui: &mut egui::Ui,
+    items: &ItemRegistry,
+    inventory: &Inventory,
+    inventory_ref: InventoryRef,
+    picked: Option<SlotRef>,
+) -> Option<(SlotRef, bool)> {
+    let mut clicked = None;
+    egui::Grid::new(inventory_ref).spacing([4.0, 4.0]).show(ui, |ui| {
+        for (slot, stack) in inventory.slots().iter().enumerate() {
+            let slot_ref = SlotRef {
+                inventory: inventory_ref,
+                slot: slot as u32,
+            };
+            let response = slot_button(ui, items, stack.as_ref(), picked == Some(slot_ref));
+            if response.clicked() {
+                clicked = Some((slot_ref, false));
+            } else if response.secondary_clicked() {
+                clicked = Some((slot_ref, true));
+            }
+            if (slot + 1) % HOTBAR_SIZE == 0 {
+                ui.end_row();
+            }
+        }
+    });
+    clicked
+}
+
+/// Picks the clicked slot as the source of a transaction, or sends the transaction if a source was already picked.
+/// Left clicks move the whole stack, or as many items as fit into a stack of the same item; right clicks move half.
+fn click_slot(
+    ui_state: &mut InventoryUiState,
+    inventories: &ClientInventories,
+    net_thread: &NetworkThread<NetworkThreadClientState>,
+    slot: SlotRef,
+    right_click: bool,
+) {
+    let stack_at = |slot: SlotRef| {
+        inventories
+            .get(slot.inventory)
+            .and_then(|inventory| inventory.get(slot.slot as usize))
+    };
+    let Some(from) = ui_state.picked.take() else {
+        if stack_at(slot).is_some() {
+            ui_state.picked = Some(slot);
+        }
+        return;
+    };
+    let Some(source) = stack_at(from).filter(|_| from != slot) else {
+        return;
+    };
+    let action = if right_click {
+        InventoryAction::Split {
+            count: source.count.div_ceil(2),
+        }
+    } else if stack_at(slot).is_some_and(|target| target.is_same_item(source)) {
+        InventoryAction::Merge
+    } else {
+        InventoryAction::Move
+    };
+    send_inventory_transaction(net_thread, InventoryTransaction { from, to: slot, action });
+}
+
+fn inventory_ui(
+    mut contexts: EguiContexts,
+    mut ui_state: ResMut<InventoryUiState>,
+    inventories: Res<ClientInventories>,
+    items: Option<Res<ItemRegistryHolder>>,
+    net_thread: Option<Res<ClientNetworkThreadHolder>>,
+) {
+    let (Some(items), Some(net_thread), Some(own)) = (items, net_thread, inventories.own()) else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+
+    egui::Area::new(egui::Id::new("hotbar"))
+        .anchor(egui::Align2::CENTER_BOTTOM, (0.0, -16.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for slot in 0..HOTBAR_SIZE.min(own.len()) {
+                    let selected = slot == ui_state.selected_hotbar_slot;
+                    if slot_button(ui, &items, own.get(slot), selected).clicked() {
+                        ui_state.selected_hotbar_slot = slot;
+                    }
+                }
+            });
+        });
+
+    if !ui_state.window_open {
+        return;
+    }
+    let picked = ui_state.picked;
+    let mut clicked = None;
+    egui::Window::new("Inventory")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, (0.0, 0.0))
+        .show(ctx, |ui| {
+            if let Some((id, viewed)) = inventories.viewed() {
+                ui.horizontal(|ui| {
+                    ui.strong("Container");
+                    if ui.button("Close").clicked() {
+                        request_inventory_view(&net_thread.0, None);
+                    }
+                });
+                clicked = clicked.or(slot_grid(ui, &items, viewed, InventoryRef::Entity(id), picked));
+                ui.separator();
+            }
+            ui.strong("Inventory");
+            clicked = clicked.or(slot_grid(ui, &items, own, InventoryRef::Own, picked));
+        });
+    if let Some((slot, right_click)) = clicked {
+        click_slot(&mut ui_state, &inventories, &net_thread.0, slot, right_click);
+    }
+}
+
+fn inventory_ui_cleanup_on_exit(mut ui_state: ResMut<InventoryUiState>) {
+    *ui_state = default();
+}
@@ -8,10 +8,14 @@
 
 //! The clientside of Geosia
 mod debugcam;
+pub mod inventory;
 pub mod network;
+pub mod players;
 pub mod states;
 pub mod voxel;
 
+use std::path::PathBuf;
+
 use bevy::a11y::AccessibilityPlugin;
 use bevy::audio::AudioPlugin;
 use bevy::core_pipeline::CorePipelinePlugin;
@@ -33,8 +37,11 @@ use bevy::utils::synccell::SyncCell;
 use bevy::window::{ExitCondition, PresentMode};
 use bevy::winit::WinitPlugin;
 use bevy_egui::EguiPlugin;
+use gs_common::inventory::InventoryPlugin;
+use gs_common::network::entity_replication::EntityReplicationPlugin;
 use gs_common::network::thread::NetworkThread;
 use gs_common::prelude::*;
+use gs_common::tick::SharedServerClock;
 use gs_common::voxel::plugin::VoxelUniversePlugin;
 use gs_common::{GameBevyCommand, GAME_BRAND_NAME};
 use gs_schemas::dependencies::smallvec::SmallVec;
@@ -49,6 +56,12 @@ use crate::network::NetworkThreadClientState;
 pub struct ClientData {
     /// Shared client/server registries.
     pub shared_registries: GameRegistries,
+    /// The content pack directories the local registries are loaded from after the base content pack, see
+    /// [`gs_common::config::ClientConfig::content_packs`].
+    pub content_packs: Vec<PathBuf>,
+    /// Development mode: the content pack directory the local registries are loaded from last, see
+    /// [`gs_common::config::ClientConfig::dev_content_directory`].
+    pub dev_content_directory: Option<PathBuf>,
 }
 
 impl GsExtraData for ClientData {
@@ -137,6 +150,10 @@ pub fn client_main() {
 
     app.add_plugins(debugcam::PlayerPlugin)
         .add_plugins(VoxelUniversePlugin::<ClientData>::new())
+        .add_plugins(EntityReplicationPlugin::<ClientData>::new())
+        .add_plugins(InventoryPlugin::<ClientData>::new())
+        .add_plugins(players::RemotePlayersPlugin)
+        .add_plugins(inventory::InventoryUiPlugin)
         .add_plugins(states::main_menu::MainMenuPlugin)
         .add_plugins(states::loading_game::LoadingGamePlugin)
         .add_plugins(states::in_game::InGamePlugin);
@@ -153,6 +170,10 @@ struct GameClientControlCommandReceiver(SyncCell<StdUnboundedReceiver<Box<GameBe
 #[derive(Resource)]
 struct ClientNetworkThreadHolder(Arc<NetworkThread<NetworkThreadClientState>>);
 
+/// The estimated clock of the connected server, for interpolation and prediction of server state.
+#[derive(Resource, Clone, Deref)]
+pub struct ClientServerClock(pub SharedServerClock);
+
 fn control_command_handler_system(world: &mut World) {
     let pending_cmds: SmallVec<[Box<GameBevyCommand>; 32]> = {
         let Some(mut ctrl_rx) = world.get_resource_mut::<GameClientControlCommandReceiver>() else {
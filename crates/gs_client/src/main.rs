@@ -5,11 +5,21 @@
 //! The clientside of Geosia - the main binary
 
 use bevy::app::App;
-use bevy::log::LogPlugin;
+use bevy::log::{error, LogPlugin};
 use gs_client::client_main;
+use gs_client::states::loading_game::integrated_server_config;
+use gs_common::config::GameConfig;
+use gs_common::network::process::{run_server_process, SERVER_PROCESS_FLAG};
 
 fn main() {
     // Set up bevy's logging once per process
     App::new().add_plugins(LogPlugin::default()).run();
+    if std::env::args().any(|arg| arg == SERVER_PROCESS_FLAG) {
+        if let Err(e) = run_server_process(GameConfig::new_handle(integrated_server_config())) {
+            error!("Integrated server process error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
     client_main()
 }
@@ -0,0 +1,64 @@
+//! The client-side LAN server discovery listener.
+
+use bevy::log::*;
+use gs_common::network::discovery::{lan_discovery_listener_task, SharedLanServerList, LAN_DISCOVERY_PORT};
+use gs_common::network::thread::{NetworkThread, NetworkThreadState};
+use gs_common::prelude::*;
+use gs_schemas::GameSide;
+use tokio::task::{spawn_local, JoinHandle};
+use tracing::Instrument;
+
+/// The network thread state used while browsing for servers, before any game connection exists.
+#[derive(Default)]
+pub struct NetworkThreadLanDiscoveryState {
+    listener_task: Option<JoinHandle<Result<()>>>,
+}
+
+impl NetworkThreadState for NetworkThreadLanDiscoveryState {
+    async fn shutdown(this: Rc<RefCell<Self>>) {
+        if let Some(task) = this.borrow_mut().listener_task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// A running LAN discovery listener along with the servers it found.
+pub struct LanDiscovery {
+    net_thread: NetworkThread<NetworkThreadLanDiscoveryState>,
+    servers: SharedLanServerList,
+}
+
+impl LanDiscovery {
+    /// Starts a new network thread listening for LAN server announcements.
+    pub fn start() -> Self {
+        let net_thread = NetworkThread::new(GameSide::Client, NetworkThreadLanDiscoveryState::default);
+        let servers = SharedLanServerList::default();
+        let task_servers = Arc::clone(&servers);
+        // Errors are logged by the listener itself, the scheduling result carries no extra information.
+        let _ = net_thread.schedule_task(move |state| {
+            Box::pin(async move {
+                let listener = async move {
+                    let result = lan_discovery_listener_task(LAN_DISCOVERY_PORT, task_servers).await;
+                    if let Err(e) = &result {
+                        warn!("LAN discovery stopped: {e:#}");
+                    }
+                    result
+                };
+                let task = spawn_local(listener.instrument(tracing::info_span!("client-lan-discovery")));
+                state.borrow_mut().listener_task = Some(task);
+                Ok(())
+            })
+        });
+        Self { net_thread, servers }
+    }
+
+    /// The list of servers discovered so far.
+    pub fn servers(&self) -> &SharedLanServerList {
+        &self.servers
+    }
+
+    /// Stops listening and shuts down the discovery network thread.
+    pub fn stop(&self) {
+        self.net_thread.sync_shutdown();
+    }
+}
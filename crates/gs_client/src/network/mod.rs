@@ -1,25 +1,44 @@
 //! The network client thread implementation.
 
+pub mod lan_discovery;
+
+use std::time::Instant;
+
 use bevy::log::*;
+use bevy::prelude::World;
 use capnp::capability::Promise;
 use capnp::Error;
 use capnp_rpc::rpc_twoparty_capnp::Side;
 use capnp_rpc::twoparty::{VatId, VatNetwork};
 use capnp_rpc::{pry, Disconnector, RpcSystem};
+use gs_common::content_reload::rebuild_registries_with_server_ids;
+use gs_common::items::ItemRegistryHolder;
+use gs_common::load_content_packs;
+use gs_common::network::custom_stream::{lookup_stream_type, reject_stream, CustomStream, CustomStreamError};
+use gs_common::network::datagram::{DatagramReceiver, DatagramSender};
+use gs_common::network::protocol::negotiate_protocol;
 use gs_common::network::server::LocalConnectionPipe;
 use gs_common::network::thread::{NetworkThread, NetworkThreadState};
 use gs_common::network::transport::{InProcessStream, RPC_LOCAL_READER_OPTIONS};
 use gs_common::network::PeerAddress;
 use gs_common::prelude::*;
+use gs_common::tick::{SharedServerClock, CLOCK_SYNC_INTERVAL};
+use gs_common::voxel::plugin::{BiomeRegistryHolder, BlockRegistryHolder};
+use gs_schemas::registry::RegistryNameRef;
+use gs_schemas::schemas::game_types_capnp::game_bootstrap_data;
 use gs_schemas::schemas::network_capnp as rpc;
 use gs_schemas::schemas::network_capnp::authenticated_client_connection::{
-    AddChatMessageParams, AddChatMessageResults, TerminateConnectionParams, TerminateConnectionResults,
+    AddChatMessageParams, AddChatMessageResults, ReloadRegistriesParams, ReloadRegistriesResults,
+    TerminateConnectionParams, TerminateConnectionResults,
 };
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::stream_types::StreamTypeRegistry;
 use tokio::sync::Barrier;
 use tokio::task::{spawn_local, JoinHandle};
+use tokio_util::bytes::Bytes;
 use tracing::Instrument;
 
-use crate::GameControlChannel;
+use crate::{ClientData, GameControlChannel};
 
 /// Pre-authentication
 pub struct NetworkThreadClientConnectingState {
@@ -35,7 +54,11 @@ pub struct NetworkThreadClientConnectingState {
     stream_task: JoinHandle<Result<()>>,
     /// The async stream creation channel.
     // TODO: use a trait object here, so we can use sockets too.
-    _stream_sender: AsyncUnboundedSender<InProcessStream>,
+    stream_sender: AsyncUnboundedSender<InProcessStream>,
+    /// The unreliable datagram sender.
+    datagram_sender: AsyncBoundedSender<Bytes>,
+    /// The unreliable datagram receiver, until taken by the game systems.
+    datagram_receiver: Option<AsyncBoundedReceiver<Bytes>>,
 }
 
 /// Post-authentication
@@ -44,6 +67,8 @@ pub struct NetworkThreadClientAuthenticatedState {
     connection: NetworkThreadClientConnectingState,
     /// The authenticated RPC object.
     server_auth_rpc: rpc::authenticated_server_connection::Client,
+    /// The server clock synchronization task.
+    clock_task: JoinHandle<Result<()>>,
 }
 
 /// The state machine for [`NetworkThreadClientState`].
@@ -61,9 +86,11 @@ pub enum NetworkThreadClientStateVariant {
 /// The network thread game client state, accessible from network functions.
 pub struct NetworkThreadClientState {
     /// Channel for communicating with the client bevy instance
-    _game_control: GameControlChannel,
+    game_control: GameControlChannel,
     /// The current variant storage.
     variant: NetworkThreadClientStateVariant,
+    /// The estimated server clock, kept up to date while authenticated.
+    server_clock: SharedServerClock,
     ready_to_accept_streams: Option<Arc<Barrier>>,
 }
 
@@ -82,15 +109,19 @@ impl NetworkThreadState for NetworkThreadClientState {
             s.rpc_task.abort();
             s.stream_task.abort();
         }
+        if let Some(s) = this.borrow().authenticated_state() {
+            s.clock_task.abort();
+        }
     }
 }
 
 impl NetworkThreadClientState {
     /// Constructor.
-    pub fn new(game_control: GameControlChannel) -> Self {
+    pub fn new(game_control: GameControlChannel, server_clock: SharedServerClock) -> Self {
         Self {
-            _game_control: game_control,
+            game_control,
             variant: Default::default(),
+            server_clock,
             ready_to_accept_streams: Some(Arc::new(Barrier::new(2))),
         }
     }
@@ -143,11 +174,44 @@ impl NetworkThreadClientState {
         self.connecting_state().map(|s| &s.server_rpc)
     }
 
+    /// Returns the estimated server clock.
+    pub fn server_clock(&self) -> &SharedServerClock {
+        &self.server_clock
+    }
+
     /// Returns the authenticated server RPC object, if authenticated.
     pub fn server_auth_rpc(&self) -> Option<&rpc::authenticated_server_connection::Client> {
         self.authenticated_state().map(|s| &s.server_auth_rpc)
     }
 
+    /// Takes the datagram channel of the connection, can only succeed once per connection.
+    pub fn take_datagram_channel(&mut self) -> Option<(DatagramSender, DatagramReceiver)> {
+        let state = self.connecting_state_mut()?;
+        let receiver = state.datagram_receiver.take()?;
+        Some((
+            DatagramSender::new(state.datagram_sender.clone()),
+            DatagramReceiver::new(receiver),
+        ))
+    }
+
+    /// Opens a stream of a registered custom type to the server, `registry` must be the one received in the bootstrap.
+    pub fn open_custom_stream<T: capnp::traits::Owned>(
+        &self,
+        registry: &StreamTypeRegistry,
+        name: RegistryNameRef,
+    ) -> Result<CustomStream<T>, CustomStreamError> {
+        let header = lookup_stream_type(registry, name)?;
+        let state = self.authenticated_state().ok_or(CustomStreamError::NotConnected)?;
+        let (local, remote) = InProcessStream::new_pair(header);
+        state
+            .connection
+            .stream_sender
+            .send(remote)
+            .map_err(|_| CustomStreamError::Closed)?;
+        // Packets are self-describing, so the server accepts uncompressed ones regardless of the negotiated compression.
+        CustomStream::new(state.connection.server_address, local, PacketCompression::None)
+    }
+
     /// Initiates a new local connection on the given pipe.
     pub async fn connect_locally(
         this: &Rc<RefCell<Self>>,
@@ -165,12 +229,18 @@ impl NetworkThreadClientState {
                 .instrument(tracing::info_span!("client-rpc", address = ?address)),
         );
 
+        negotiate_protocol(&connection.server_rpc)
+            .await
+            .context("Protocol negotiation with the integrated server failed")?;
+
         // Authenticate
         let mut auth_request = connection.server_rpc.authenticate_request();
         {
             let mut builder = auth_request.get();
             builder.set_username("LocalPlayer");
-            let auth_rpc = AuthenticatedClientConnectionImpl {};
+            let auth_rpc = AuthenticatedClientConnectionImpl {
+                game_control: this.borrow().game_control.clone(),
+            };
             builder.set_connection(capnp_rpc::new_client(auth_rpc));
         }
         let auth_response = auth_request
@@ -198,8 +268,13 @@ impl NetworkThreadClientState {
             connection.server_addr
         );
 
+        let clock_task: JoinHandle<Result<()>> = spawn_local(
+            Self::clock_sync_task(server_auth_rpc.clone(), Arc::clone(&this.borrow().server_clock))
+                .instrument(tracing::info_span!("client-clock", address = ?address)),
+        );
+
         let stream_task: JoinHandle<Result<()>> = spawn_local(
-            Self::local_stream_acceptor(Rc::clone(this), Arc::clone(&net_thread), pipe.incoming_streams)
+            Self::local_stream_acceptor(Rc::clone(this), Arc::clone(&net_thread), address, pipe.incoming_streams)
                 .instrument(tracing::info_span!("client-stream", address = ?address)),
         );
 
@@ -211,17 +286,45 @@ impl NetworkThreadClientState {
                     rpc_disconnector: Some(rpc_disconnector),
                     rpc_task,
                     stream_task,
-                    _stream_sender: pipe.outgoing_streams,
+                    stream_sender: pipe.outgoing_streams,
+                    datagram_sender: pipe.outgoing_datagrams,
+                    datagram_receiver: Some(pipe.incoming_datagrams),
                 },
                 server_auth_rpc,
+                clock_task,
             });
 
         Ok(())
     }
 
+    /// Periodically samples the server tick to keep the estimated server clock up to date, until aborted.
+    async fn clock_sync_task(
+        server_auth_rpc: rpc::authenticated_server_connection::Client,
+        clock: SharedServerClock,
+    ) -> Result<()> {
+        let mut interval = tokio::time::interval(CLOCK_SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            let sent_at = Instant::now();
+            let response = server_auth_rpc
+                .get_server_tick_request()
+                .send()
+                .promise
+                .await
+                .context("RPC failure to get the server tick")?;
+            let received_at = Instant::now();
+            let response = response.get()?;
+            clock
+                .lock()
+                .unwrap()
+                .record_sample(response.get_tick(), response.get_paused(), sent_at, received_at);
+        }
+    }
+
     async fn local_stream_acceptor(
         this: Rc<RefCell<Self>>,
         net_thread: Arc<NetworkThread<NetworkThreadClientState>>,
+        server_address: PeerAddress,
         mut incoming_streams: AsyncUnboundedReceiver<InProcessStream>,
     ) -> Result<()> {
         let barrier = Arc::clone(this.borrow().ready_to_accept_streams.as_ref().unwrap());
@@ -229,17 +332,12 @@ impl NetworkThreadClientState {
         this.borrow_mut().ready_to_accept_streams = None;
 
         while let Some(stream) = incoming_streams.recv().await {
-            let handler = net_thread.create_stream_handler(Rc::clone(&this), stream);
+            let handler = net_thread.create_stream_handler(Rc::clone(&this), server_address, stream);
             match handler {
                 Ok(handler) => {
                     spawn_local(handler);
                 }
-                Err(stream) => {
-                    error!(
-                        "No stream handler found for incoming server stream of type {:?}",
-                        stream.header
-                    );
-                }
+                Err(stream) => reject_stream(server_address, stream),
             }
         }
         Ok(())
@@ -252,7 +350,9 @@ pub struct Client2ServerConnection {
     server_rpc: rpc::game_server::Client,
 }
 
-struct AuthenticatedClientConnectionImpl {}
+struct AuthenticatedClientConnectionImpl {
+    game_control: GameControlChannel,
+}
 
 impl Client2ServerConnection {
     /// Constructor.
@@ -277,10 +377,15 @@ impl Client2ServerConnection {
 impl gs_schemas::schemas::network_capnp::authenticated_client_connection::Server for AuthenticatedClientConnectionImpl {
     fn terminate_connection(
         &mut self,
-        _: TerminateConnectionParams,
+        params: TerminateConnectionParams,
         _: TerminateConnectionResults,
     ) -> Promise<(), Error> {
-        //
+        let reason = pry!(pry!(params.get()).get_reason());
+        let kind = reason
+            .get_kind()
+            .map_or_else(|e| format!("{e:?}"), |kind| format!("{kind:?}"));
+        let message = pry!(pry!(reason.get_message()).to_str());
+        warn!("Server is terminating the connection ({kind}): {message}");
         Promise::ok(())
     }
 
@@ -291,6 +396,39 @@ impl gs_schemas::schemas::network_capnp::authenticated_client_connection::Server
         info!("Client received chat message: {chat_text}");
         Promise::ok(())
     }
+
+    fn reload_registries(&mut self, params: ReloadRegistriesParams, _: ReloadRegistriesResults) -> Promise<(), Error> {
+        let data = pry!(pry!(params.get()).get_data());
+        let mut message = capnp::message::Builder::new_default();
+        pry!(message.set_root(data));
+        let message = capnp::serialize::write_message_to_words(&message);
+        let _ = self.game_control.send(Box::new(move |world: &mut World| {
+            if let Err(e) = apply_reloaded_registries(world, &message) {
+                error!("Could not apply the registries reloaded by the server: {e:#}");
+            }
+        }));
+        Promise::ok(())
+    }
+}
+
+/// Rebuilds the client registries with the ID mappings sent by the server after it reloaded its content, the changed
+/// registry resources make the chunks get remeshed.
+fn apply_reloaded_registries(world: &mut World, mut message: &[u8]) -> Result<()> {
+    let message = capnp::serialize::read_message_from_flat_slice(&mut message, RPC_LOCAL_READER_OPTIONS)?;
+    let data = message.get_root::<game_bootstrap_data::Reader>()?;
+    let client_data = world.get_resource::<ClientData>().context("Not in game")?;
+    let packs = load_content_packs(&client_data.content_packs, client_data.dev_content_directory.as_deref())?;
+    let registries = rebuild_registries_with_server_ids(&packs, &client_data.shared_registries, &data)?;
+    info!(
+        "Server reloaded its content, now with {} block types",
+        registries.block_types.len()
+    );
+
+    world.insert_resource(BlockRegistryHolder(Arc::clone(&registries.block_types)));
+    world.insert_resource(BiomeRegistryHolder(Arc::clone(&registries.biome_types)));
+    world.insert_resource(ItemRegistryHolder(Arc::clone(&registries.item_types)));
+    world.resource_mut::<ClientData>().shared_registries = registries;
+    Ok(())
 }
 
 /// Create a Future that will handle in-memory messages coming from a [`Server2ClientEndpoint`] and any child RPC objects on the given `server`&`id`.
@@ -0,0 +1,202 @@
+//! Movement input for the local player and rendering of the other players replicated from the server.
+
+use std::time::Instant;
+
+use bevy::prelude::*;
+use capnp::message::Builder;
+use gs_common::network::datagram::{DatagramReceiver, DatagramSender};
+use gs_common::network::player_replication::{
+    read_quat, read_vec3, write_quat, write_vec3, SnapshotBuffer, TransformSnapshot, INTERPOLATION_DELAY_TICKS,
+};
+use gs_common::prelude::*;
+use gs_common::TICKS_PER_SECOND;
+use gs_schemas::schemas::network_capnp::datagram;
+
+use crate::debugcam::FlyCam;
+use crate::states::{ClientAppState, InGameSystemSet};
+use crate::ClientServerClock;
+
+/// Other players are removed after not being seen in any snapshot for this many ticks.
+const REMOTE_PLAYER_TIMEOUT_TICKS: u64 = 2 * TICKS_PER_SECOND as u64;
+
+/// The plugin sending local player movement and displaying other players.
+pub struct RemotePlayersPlugin;
+
+impl Plugin for RemotePlayersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RemotePlayerMap>()
+            .add_systems(FixedUpdate, (client_system_send_player_input).in_set(InGameSystemSet))
+            .add_systems(
+                PreUpdate,
+                (client_system_receive_player_snapshots).in_set(InGameSystemSet),
+            )
+            .add_systems(
+                Update,
+                (client_system_interpolate_remote_players).in_set(InGameSystemSet),
+            )
+            .add_systems(OnExit(ClientAppState::InGame), remote_players_cleanup_on_exit);
+    }
+}
+
+/// The datagram channel of the server connection.
+#[derive(Resource)]
+pub struct ServerDatagrams {
+    /// For sending movement input.
+    pub sender: DatagramSender,
+    /// For receiving player snapshots.
+    pub receiver: DatagramReceiver,
+}
+
+/// A player replicated from the server.
+#[derive(Component, Clone, Debug)]
+pub struct RemotePlayer {
+    /// The entity ID of the player on the server.
+    pub server_entity: u64,
+}
+
+/// Maps server-side player entity IDs to the local entities.
+#[derive(Resource, Default)]
+struct RemotePlayerMap(HashMap<u64, Entity>);
+
+/// The shared assets of the placeholder player model.
+struct PlaceholderModel {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn client_system_send_player_input(
+    datagrams: Option<Res<ServerDatagrams>>,
+    clock: Option<Res<ClientServerClock>>,
+    camera: Query<&Transform, With<FlyCam>>,
+    mut sequence: Local<u32>,
+) {
+    let (Some(datagrams), Ok(transform)) = (datagrams, camera.get_single()) else {
+        return;
+    };
+    let tick = clock
+        .and_then(|c| c.lock().unwrap().estimated_tick(Instant::now()))
+        .unwrap_or(0);
+    *sequence = sequence.wrapping_add(1);
+
+    let mut message = Builder::new_default();
+    let mut input = message.init_root::<datagram::Builder>().init_player_input();
+    input.set_sequence(*sequence);
+    input.set_tick(tick);
+    write_vec3(input.reborrow().init_position(), transform.translation);
+    write_quat(input.reborrow().init_orientation(), transform.rotation);
+    // Lost inputs are superseded by the next tick's input.
+    datagrams.sender.send(&message);
+}
+
+fn client_system_receive_player_snapshots(
+    mut commands: Commands,
+    datagrams: Option<ResMut<ServerDatagrams>>,
+    mut player_map: ResMut<RemotePlayerMap>,
+    mut buffers: Query<&mut SnapshotBuffer, With<RemotePlayer>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut placeholder: Local<Option<PlaceholderModel>>,
+) {
+    let Some(mut datagrams) = datagrams else {
+        return;
+    };
+    // Newly seen players get spawned by deferred commands, so collect all received snapshots first.
+    let mut received: HashMap<u64, Vec<TransformSnapshot>> = HashMap::default();
+    while let Some(message) = datagrams.receiver.try_recv() {
+        if let Err(e) = read_player_snapshots(&message, &mut received) {
+            warn!("Invalid datagram from the server: {e:#}");
+        }
+    }
+
+    for (server_entity, snapshots) in received {
+        if let Some(mut buffer) = player_map.0.get(&server_entity).and_then(|&e| buffers.get_mut(e).ok()) {
+            for snapshot in snapshots {
+                buffer.push(snapshot);
+            }
+            continue;
+        }
+        let model = placeholder.get_or_insert_with(|| PlaceholderModel {
+            mesh: meshes.add(Capsule3d::new(0.4, 1.0)),
+            material: materials.add(Color::srgb(0.9, 0.4, 0.2)),
+        });
+        let mut buffer = SnapshotBuffer::default();
+        for snapshot in snapshots {
+            buffer.push(snapshot);
+        }
+        let (translation, rotation) = buffer.sample(0.0).unwrap_or_default();
+        let entity = commands
+            .spawn((
+                RemotePlayer { server_entity },
+                buffer,
+                PbrBundle {
+                    mesh: model.mesh.clone(),
+                    material: model.material.clone(),
+                    transform: Transform::from_translation(translation).with_rotation(rotation),
+                    ..default()
+                },
+            ))
+            .id();
+        player_map.0.insert(server_entity, entity);
+    }
+}
+
+fn read_player_snapshots(
+    message: &capnp::message::Reader<capnp::serialize::OwnedSegments>,
+    received: &mut HashMap<u64, Vec<TransformSnapshot>>,
+) -> Result<()> {
+    let datagram::PlayerSnapshots(snapshots) = message.get_root::<datagram::Reader>()?.which()? else {
+        bail!("Unexpected datagram type");
+    };
+    let snapshots = snapshots?;
+    let tick = snapshots.get_tick();
+    for player in snapshots.get_players()? {
+        received
+            .entry(player.get_entity_id())
+            .or_default()
+            .push(TransformSnapshot {
+                tick,
+                translation: read_vec3(player.get_position()?),
+                rotation: read_quat(player.get_orientation()?),
+            });
+    }
+    Ok(())
+}
+
+fn client_system_interpolate_remote_players(
+    mut commands: Commands,
+    clock: Option<Res<ClientServerClock>>,
+    mut player_map: ResMut<RemotePlayerMap>,
+    mut players: Query<(Entity, &RemotePlayer, &mut SnapshotBuffer, &mut Transform)>,
+) {
+    let Some(now_tick) = clock.and_then(|c| c.lock().unwrap().estimated_tick_f64(Instant::now())) else {
+        return;
+    };
+    let render_tick = now_tick - INTERPOLATION_DELAY_TICKS;
+    for (entity, player, mut buffer, mut transform) in players.iter_mut() {
+        let is_stale = buffer
+            .latest_tick()
+            .map_or(true, |t| ((t + REMOTE_PLAYER_TIMEOUT_TICKS) as f64) < render_tick);
+        if is_stale {
+            commands.entity(entity).despawn_recursive();
+            player_map.0.remove(&player.server_entity);
+            continue;
+        }
+        buffer.discard_before(render_tick);
+        if let Some((translation, rotation)) = buffer.sample(render_tick) {
+            transform.translation = translation;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+fn remote_players_cleanup_on_exit(
+    mut commands: Commands,
+    mut player_map: ResMut<RemotePlayerMap>,
+    players: Query<Entity, With<RemotePlayer>>,
+) {
+    for entity in players.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    player_map.0.clear();
+    commands.remove_resource::<ServerDatagrams>();
+}
@@ -1,6 +1,9 @@
 //! The state for when the player is in game, with all basic gameplay resources fully loaded.
 
 use bevy::prelude::*;
+use gs_common::inventory::reset_client_inventories;
+use gs_common::network::entity_replication::despawn_replicated_entities;
+use gs_common::voxel::block_entities::despawn_block_entities;
 
 use crate::states::ClientAppState;
 use crate::ClientNetworkThreadHolder;
@@ -10,7 +13,15 @@ pub struct InGamePlugin;
 
 impl Plugin for InGamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnExit(ClientAppState::InGame), ingame_cleanup_on_exit);
+        app.add_systems(
+            OnExit(ClientAppState::InGame),
+            (
+                ingame_cleanup_on_exit,
+                despawn_replicated_entities,
+                reset_client_inventories,
+                despawn_block_entities,
+            ),
+        );
     }
 }
 
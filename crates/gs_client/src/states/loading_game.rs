@@ -1,22 +1,32 @@
 //! The transitional state that waits for asynchronous game initialization and server connection, before switching to the in game state.
 
+use std::path::PathBuf;
+
 use bevy::prelude::*;
 use bevy::utils::synccell::SyncCell;
-use gs_common::config::{GameConfig, ServerConfig};
+use gs_common::config::{ClientConfig, GameConfig, ServerConfig};
+use gs_common::content::warn_resolved_aliases;
+use gs_common::inventory::register_inventory_stream_handler;
+use gs_common::items::ItemRegistryHolder;
+use gs_common::network::conditions::simulate_network_conditions;
+use gs_common::network::entity_replication::register_entity_stream_handler;
+use gs_common::network::process::ServerProcess;
 use gs_common::network::thread::NetworkThread;
 use gs_common::prelude::std_unbounded_channel;
 use gs_common::prelude::*;
+use gs_common::tick::SharedServerClock;
 use gs_common::voxel::plugin::VoxelUniverseBuilder;
-use gs_common::{builtin_game_registries, GameServer};
+use gs_common::{content_game_registries, load_content_packs, GameServer};
 use gs_schemas::dependencies::uuid::Uuid;
 use gs_schemas::registries::GameRegistries;
 use gs_schemas::schemas::SchemaUuidExt;
 use gs_schemas::GameSide;
 
 use crate::network::NetworkThreadClientState;
+use crate::players::ServerDatagrams;
 use crate::states::{ClientAppState, LoadingGameSystemSet};
 use crate::voxel::ClientVoxelUniverseBuilder;
-use crate::{ClientData, ClientNetworkThreadHolder, GameClientControlCommandReceiver};
+use crate::{ClientData, ClientNetworkThreadHolder, ClientServerClock, GameClientControlCommandReceiver};
 
 /// The "plugin" implementing the load transition for the game.
 pub struct LoadingGamePlugin;
@@ -26,7 +36,52 @@ impl Plugin for LoadingGamePlugin {
         app.init_resource::<LoadingTransitionParams>()
             .init_resource::<LoadingPromiseHolder>();
         app.add_systems(OnEnter(ClientAppState::LoadingGame), kickoff_game_transition)
-            .add_systems(Update, (loading_game_transition_handler,).in_set(LoadingGameSystemSet));
+            .add_systems(
+                OnTransition {
+                    exited: ClientAppState::LoadingGame,
+                    entered: ClientAppState::MainMenu,
+                },
+                abort_loading_game,
+            )
+            .add_systems(Update, (loading_game_transition_handler,).in_set(LoadingGameSystemSet))
+            .add_systems(Update, integrated_server_monitor_system);
+    }
+}
+
+/// The integrated server hosting the current single player game.
+#[derive(Resource, Clone)]
+pub enum IntegratedServer {
+    /// Running on its own threads in the client process.
+    InProcess(Arc<GameServer>),
+    /// Running in a child process, see [`ServerProcess`].
+    ChildProcess(Arc<ServerProcess>),
+}
+
+/// The environment variable enabling the content development mode, with the content pack directory to load and watch.
+pub const DEV_CONTENT_DIRECTORY_ENV: &str = "GS_DEV_CONTENT_DIR";
+
+/// The environment variable listing the extra content pack directories to load after the base content pack, separated
+/// like the `PATH` variable.
+pub const CONTENT_PACKS_ENV: &str = "GS_CONTENT_PACKS";
+
+/// The configuration of the integrated server, shared by the client and the server child process.
+pub fn integrated_server_config() -> GameConfig {
+    let content_packs: Vec<PathBuf> = std::env::var_os(CONTENT_PACKS_ENV)
+        .map(|packs| std::env::split_paths(&packs).collect())
+        .unwrap_or_default();
+    let dev_content_directory = std::env::var_os(DEV_CONTENT_DIRECTORY_ENV).map(PathBuf::from);
+    GameConfig {
+        server: ServerConfig {
+            server_title: String::from("Integrated server"),
+            content_packs: content_packs.clone(),
+            dev_content_directory: dev_content_directory.clone(),
+            ..Default::default()
+        },
+        client: ClientConfig {
+            content_packs,
+            dev_content_directory,
+            ..Default::default()
+        },
     }
 }
 
@@ -61,102 +116,160 @@ fn kickoff_game_transition(world: &mut World) {
         }
         LoadingTransitionParams::SinglePlayer {} => {
             info!("Starting a new single player game");
-
-            let default_registries = builtin_game_registries();
-            let game_config = GameConfig {
-                server: ServerConfig {
-                    server_title: String::from("Integrated server"),
-                    ..Default::default()
-                },
-            };
-            let game_config = GameConfig::new_handle(game_config);
-            let integ_server = GameServer::new(game_config).expect("Could not start integrated server");
-            integ_server.set_paused(false);
-            let server_pipe = integ_server.create_local_connection();
-            let (control_tx, control_rx) = std_unbounded_channel();
-
-            let net_thread = NetworkThread::new(GameSide::Client, move || NetworkThreadClientState::new(control_tx));
-            let net_thread = Arc::new(net_thread);
-
-            struct IntegBootstrap {
-                registries: GameRegistries,
+            if let Err(e) = start_single_player_game(world) {
+                error!("Could not start the single player game: {e:#}");
+                world
+                    .resource_mut::<NextState<ClientAppState>>()
+                    .set(ClientAppState::MainMenu);
             }
-
-            let net_thread2 = Arc::clone(&net_thread);
-            let bootstrap_data = net_thread
-                .schedule_task(|state| {
-                    Box::pin(async move {
-                        let local_conn = server_pipe
-                            .async_wait()
-                            .await
-                            .context("integ_server.create_local_connection")?;
-                        NetworkThreadClientState::connect_locally(state, net_thread2, local_conn)
-                            .await
-                            .context("NetworkThreadClientState::connect_locally")?;
-                        let bootstrap_request = state
-                            .borrow()
-                            .server_auth_rpc()
-                            .context("Missing auth endpoint")?
-                            .bootstrap_game_data_request();
-                        let bootstrap_response = bootstrap_request
-                            .send()
-                            .promise
-                            .await
-                            .context("Failed bootstrap request to the integrated server")?;
-                        let bootstrap_response = bootstrap_response.get()?.get_data()?;
-                        let uuid = Uuid::read_from_message(&bootstrap_response.get_universe_id()?);
-                        let registries = default_registries.clone_with_serialized_ids(&bootstrap_response)?;
-                        let nblocks = registries.block_types.len();
-                        info!("Joining server world {uuid} with {nblocks} block types.");
-
-                        Ok(IntegBootstrap { registries })
-                    })
-                })
-                .blocking_wait()
-                .expect("Could not connect the client to the integrated server");
-
-            let client_data = ClientData {
-                shared_registries: bootstrap_data.registries,
-            };
-
-            let mut promises = world.resource_mut::<LoadingPromiseHolder>();
-            promises.promises.push(Box::new(net_thread.schedule_task(|state| {
-                Box::pin(async move {
-                    let auth_rpc = state.borrow().server_auth_rpc().cloned();
-                    if let Some(auth_rpc) = auth_rpc {
-                        let mut rq = auth_rpc.send_chat_message_request();
-                        rq.get().set_text("Hello in-process networking!");
-                        let _ = rq.send().promise.await;
-                    }
-                    Ok(())
-                })
-            })));
-
-            let block_registry = Arc::clone(&client_data.shared_registries.block_types);
-            let biome_registry = Arc::clone(&client_data.shared_registries.biome_types);
-
-            world.insert_resource(client_data);
-            world.insert_resource(ClientNetworkThreadHolder(Arc::clone(&net_thread)));
-            world.insert_resource(GameClientControlCommandReceiver(SyncCell::new(control_rx)));
-
-            VoxelUniverseBuilder::<ClientData>::new(world, block_registry, biome_registry)
-                .unwrap()
-                .with_network_client(&net_thread)
-                .unwrap()
-                .with_client_chunk_system()
-                .build();
-
-            let mut promises = world.resource_mut::<LoadingPromiseHolder>();
-            promises.promises.push(Box::new(net_thread.schedule_task(|state| {
-                Box::pin(async move {
-                    NetworkThreadClientState::allow_streams(state).await;
-                    Ok(())
-                })
-            })));
         }
     }
 }
 
+/// Starts the integrated server and connects to it.
+/// On errors, the caller should return to the main menu to clean up the partially started game.
+fn start_single_player_game(world: &mut World) -> Result<()> {
+    let game_config = integrated_server_config();
+    let content_packs = game_config.client.content_packs.clone();
+    let dev_content_directory = game_config.client.dev_content_directory.clone();
+    let packs = load_content_packs(&content_packs, dev_content_directory.as_deref())
+        .context("Could not load the content packs")?;
+    let default_registries =
+        content_game_registries(&packs, None).context("Could not build the game registries from the content")?;
+    let render_distance = game_config.client.render_distance;
+    let simulated_network = game_config.client.simulated_network.clone();
+    let integ_server = if game_config.client.integrated_server_process {
+        let executable = std::env::current_exe().context("Could not find the game executable")?;
+        let process = ServerProcess::spawn(&executable).context("Could not start the integrated server process")?;
+        IntegratedServer::ChildProcess(Arc::new(process))
+    } else {
+        let server =
+            GameServer::new(GameConfig::new_handle(game_config)).context("Could not start integrated server")?;
+        server.set_paused(false);
+        IntegratedServer::InProcess(server)
+    };
+    let net_integ_server = integ_server.clone();
+    let (control_tx, control_rx) = std_unbounded_channel();
+    let server_clock = SharedServerClock::default();
+
+    let net_clock = Arc::clone(&server_clock);
+    let net_thread = NetworkThread::new(GameSide::Client, move || {
+        NetworkThreadClientState::new(control_tx, net_clock)
+    });
+    let net_thread = Arc::new(net_thread);
+    // Inserted before connecting, so that leaving the loading state shuts them down if joining fails.
+    world.insert_resource(integ_server);
+    world.insert_resource(ClientNetworkThreadHolder(Arc::clone(&net_thread)));
+
+    struct IntegBootstrap {
+        registries: GameRegistries,
+        datagrams: ServerDatagrams,
+    }
+
+    let net_thread2 = Arc::clone(&net_thread);
+    let bootstrap_data = net_thread
+        .schedule_task(move |state| {
+            Box::pin(async move {
+                let local_conn = match net_integ_server {
+                    IntegratedServer::InProcess(server) => server
+                        .create_simulated_local_connection(simulated_network)
+                        .async_wait()
+                        .await
+                        .context("integ_server.create_simulated_local_connection")?,
+                    IntegratedServer::ChildProcess(process) => {
+                        let (address, pipe) = process.connect().context("process.connect")?;
+                        if simulated_network.is_ideal() {
+                            (address, pipe)
+                        } else {
+                            (address, simulate_network_conditions(pipe, &simulated_network))
+                        }
+                    }
+                };
+                NetworkThreadClientState::connect_locally(state, net_thread2, local_conn)
+                    .await
+                    .context("NetworkThreadClientState::connect_locally")?;
+                let bootstrap_request = state
+                    .borrow()
+                    .server_auth_rpc()
+                    .context("Missing auth endpoint")?
+                    .bootstrap_game_data_request();
+                let bootstrap_response = bootstrap_request
+                    .send()
+                    .promise
+                    .await
+                    .context("Failed bootstrap request to the server")?;
+                let bootstrap_response = bootstrap_response.get()?.get_data()?;
+                let uuid = Uuid::read_from_message(&bootstrap_response.get_universe_id()?);
+                let (registries, resolved_aliases) =
+                    default_registries.clone_with_serialized_ids(&bootstrap_response)?;
+                warn_resolved_aliases("The server", &resolved_aliases);
+                let nblocks = registries.block_types.len();
+                info!("Joining server world {uuid} with {nblocks} block types.");
+
+                let (sender, receiver) = state
+                    .borrow_mut()
+                    .take_datagram_channel()
+                    .context("Missing datagram channel")?;
+                let datagrams = ServerDatagrams { sender, receiver };
+
+                Ok(IntegBootstrap { registries, datagrams })
+            })
+        })
+        .blocking_wait()
+        .context("Could not connect the client to the server")?;
+
+    let client_data = ClientData {
+        shared_registries: bootstrap_data.registries,
+        content_packs,
+        dev_content_directory,
+    };
+
+    let mut promises = world.resource_mut::<LoadingPromiseHolder>();
+    promises.promises.push(Box::new(net_thread.schedule_task(move |state| {
+        Box::pin(async move {
+            let auth_rpc = state.borrow().server_auth_rpc().cloned();
+            if let Some(auth_rpc) = auth_rpc {
+                let mut rq = auth_rpc.send_chat_message_request();
+                rq.get().set_text("Hello in-process networking!");
+                let _ = rq.send().promise.await;
+
+                let mut rq = auth_rpc.set_render_distance_request();
+                rq.get().set_radius(render_distance);
+                let response = rq.send().promise.await?;
+                let radius = response.get()?.get_radius();
+                info!("Using a render distance of {radius} chunks (requested {render_distance})");
+            }
+            Ok(())
+        })
+    })));
+
+    let block_registry = Arc::clone(&client_data.shared_registries.block_types);
+    let biome_registry = Arc::clone(&client_data.shared_registries.biome_types);
+    let item_registry = Arc::clone(&client_data.shared_registries.item_types);
+
+    world.insert_resource(client_data);
+    world.insert_resource(ClientServerClock(server_clock));
+    world.insert_resource(bootstrap_data.datagrams);
+    world.insert_resource(GameClientControlCommandReceiver(SyncCell::new(control_rx)));
+    world.insert_resource(register_entity_stream_handler(&net_thread));
+    world.insert_resource(register_inventory_stream_handler(&net_thread));
+    world.insert_resource(ItemRegistryHolder(item_registry));
+
+    VoxelUniverseBuilder::<ClientData>::new(world, block_registry, biome_registry)?
+        .with_network_client(&net_thread)?
+        .with_client_chunk_system()
+        .build();
+
+    let mut promises = world.resource_mut::<LoadingPromiseHolder>();
+    promises.promises.push(Box::new(net_thread.schedule_task(|state| {
+        Box::pin(async move {
+            NetworkThreadClientState::allow_streams(state).await;
+            Ok(())
+        })
+    })));
+    Ok(())
+}
+
 fn loading_game_transition_handler(
     mut next_state: ResMut<NextState<ClientAppState>>,
     mut promises: ResMut<LoadingPromiseHolder>,
@@ -179,3 +292,39 @@ fn loading_game_transition_handler(
         promises.promises.extend(remaining_promises);
     }
 }
+
+/// Cleans up after a game that stopped loading, the in game state cleans up after the loaded ones.
+fn abort_loading_game(
+    mut commands: Commands,
+    mut promises: ResMut<LoadingPromiseHolder>,
+    net_thread: Option<Res<ClientNetworkThreadHolder>>,
+    integ_server: Option<Res<IntegratedServer>>,
+) {
+    promises.promises.clear();
+    if let Some(net_thread) = net_thread {
+        net_thread.0.sync_shutdown();
+    }
+    // Dropping the resource stops a server child process, the in-process one has to be told to shut down.
+    if let Some(IntegratedServer::InProcess(server)) = integ_server.as_deref() {
+        drop(server.shutdown());
+    }
+    commands.remove_resource::<IntegratedServer>();
+}
+
+/// Returns to the main menu when the integrated server process exits while the game is running, e.g. because it crashed.
+fn integrated_server_monitor_system(
+    mut commands: Commands,
+    integ_server: Option<Res<IntegratedServer>>,
+    mut next_state: ResMut<NextState<ClientAppState>>,
+) {
+    let Some(IntegratedServer::ChildProcess(process)) = integ_server.as_deref() else {
+        return;
+    };
+    match process.try_exit_status() {
+        Ok(None) => return,
+        Ok(Some(status)) => error!("The integrated server process exited unexpectedly with {status}"),
+        Err(e) => error!("Could not check the integrated server process status: {e:#}"),
+    }
+    commands.remove_resource::<IntegratedServer>();
+    next_state.set(ClientAppState::MainMenu);
+}
@@ -1,11 +1,14 @@
 //! The main menu state that lets the user start a single player game or connect to a server.
 
+use std::time::Instant;
+
 use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy_egui::egui;
 use bevy_egui::EguiContexts;
 use gs_common::GAME_BRAND_NAME;
 
+use crate::network::lan_discovery::LanDiscovery;
 use crate::states::loading_game::LoadingTransitionParams;
 use crate::states::{ClientAppState, MainMenuSystemSet};
 
@@ -14,7 +17,24 @@ pub struct MainMenuPlugin;
 
 impl Plugin for MainMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (main_menu_ui,).in_set(MainMenuSystemSet));
+        app.add_systems(OnEnter(ClientAppState::MainMenu), start_lan_discovery)
+            .add_systems(OnExit(ClientAppState::MainMenu), stop_lan_discovery)
+            .add_systems(Update, (main_menu_ui,).in_set(MainMenuSystemSet));
+    }
+}
+
+/// The LAN discovery listener running while the main menu is open.
+#[derive(Resource)]
+struct MainMenuLanDiscovery(LanDiscovery);
+
+fn start_lan_discovery(mut commands: Commands) {
+    commands.insert_resource(MainMenuLanDiscovery(LanDiscovery::start()));
+}
+
+fn stop_lan_discovery(mut commands: Commands, discovery: Option<Res<MainMenuLanDiscovery>>) {
+    if let Some(discovery) = discovery {
+        discovery.0.stop();
+        commands.remove_resource::<MainMenuLanDiscovery>();
     }
 }
 
@@ -23,6 +43,7 @@ fn main_menu_ui(
     mut quit: EventWriter<AppExit>,
     mut loading_data: ResMut<LoadingTransitionParams>,
     mut state_switch: ResMut<NextState<ClientAppState>>,
+    lan_discovery: Option<Res<MainMenuLanDiscovery>>,
 ) {
     egui::Window::new(GAME_BRAND_NAME)
         .collapsible(false)
@@ -43,4 +64,31 @@ fn main_menu_ui(
                 ui.add_space(16.0);
             });
         });
+
+    let Some(lan_discovery) = lan_discovery else {
+        return;
+    };
+    egui::Window::new("LAN servers")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, (0.0, 16.0))
+        .show(contexts.ctx_mut(), |ui| {
+            let mut servers = lan_discovery.0.servers().lock().unwrap();
+            servers.remove_expired(Instant::now());
+            if servers.is_empty() {
+                ui.label("Searching for servers on the local network...");
+            }
+            for server in servers.sorted_servers() {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        ui.strong(&server.title);
+                        ui.label(format!("{}/{} players", server.player_count, server.player_limit));
+                    });
+                    if !server.subtitle.is_empty() {
+                        ui.label(&server.subtitle);
+                    }
+                    ui.small(format!("{} - version {}", server.address, server.version));
+                });
+            }
+        });
 }
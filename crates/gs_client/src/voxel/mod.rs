@@ -6,6 +6,9 @@ use bevy::prelude::*;
 use capnp::message::TypedReader;
 use gs_common::network::transport::RPC_LOCAL_READER_OPTIONS;
 use gs_common::prelude::*;
+use gs_common::voxel::block_entities::{
+    apply_block_entity_updates, replace_chunk_block_entities, spawn_chunk_block_entities,
+};
 use gs_common::voxel::plugin::{
     BlockRegistryHolder, NetworkVoxelClient, VoxelUniverse, VoxelUniverseBuilder, CHUNK_PACKET_QUEUE_LENGTH,
 };
@@ -13,6 +16,8 @@ use gs_common::InGameSystemSet;
 use gs_schemas::coordinates::{AbsBlockPos, AbsChunkPos};
 use gs_schemas::mutwatcher::{MutWatcher, RevisionNumber};
 use gs_schemas::schemas::network_capnp as rpc;
+use gs_schemas::schemas::stream_packet::decode_stream_packet;
+use gs_schemas::voxel::block_entity::BlockEntityRecord;
 use gs_schemas::voxel::chunk::Chunk;
 use gs_schemas::voxel::chunk_group::ChunkGroup;
 use meshgen::mesh_from_chunk;
@@ -77,6 +82,7 @@ impl<'world> ClientVoxelUniverseBuilder for VoxelUniverseBuilder<'world, ClientD
 fn client_chunk_packet_receiver_system(
     mut nvc_q: Query<&mut NetworkVoxelClient<ClientData>>,
     mut voxel_q: Query<&mut ClientVoxelUniverse>,
+    mut commands: Commands,
 ) {
     let mut voxels = voxel_q
         .get_single_mut()
@@ -94,27 +100,39 @@ fn client_chunk_packet_receiver_system(
 
     let voxels = &mut *voxels;
     for raw_packet in batch {
-        if let Err(e) = handle_chunk_packet(raw_packet, voxels) {
+        if let Err(e) = handle_chunk_packet(raw_packet, voxels, &mut commands) {
             error!("Error while processing received chunk packet: {e}");
         }
     }
 }
 
-fn handle_chunk_packet(raw_packet: Bytes, voxels: &mut ClientVoxelUniverse) -> Result<()> {
-    let mut slice = &raw_packet as &[u8];
-    let msg = capnp::serialize::read_message_from_flat_slice(&mut slice, RPC_LOCAL_READER_OPTIONS)?;
+fn handle_chunk_packet(raw_packet: Bytes, voxels: &mut ClientVoxelUniverse, commands: &mut Commands) -> Result<()> {
+    let msg = decode_stream_packet(&raw_packet, RPC_LOCAL_READER_OPTIONS)?;
     let typed_reader = TypedReader::<_, rpc::chunk_data_stream_packet::Owned>::new(msg);
     let root = typed_reader.get()?;
     let cpos_r = root.reborrow().get_position()?;
     let pos = AbsChunkPos::new(cpos_r.get_x(), cpos_r.get_y(), cpos_r.get_z());
+    if !root.has_data() {
+        let updates = root
+            .get_block_entity_updates()?
+            .iter()
+            .map(BlockEntityRecord::read_from_message)
+            .collect::<Result<Vec<_>, _>>()?;
+        commands.add(move |world: &mut World| apply_block_entity_updates::<ClientData>(world, pos, updates));
+        return Ok(());
+    }
     let data_r = root.reborrow().get_data()?;
     let revision: RevisionNumber = root.get_revision().try_into()?;
-    let chunk = ClientChunk::read_full(&data_r, default())?;
+    let mut chunk = ClientChunk::read_full(&data_r, default())?;
 
-    voxels
-        .loaded_chunks_mut()
-        .chunks
-        .insert(pos, MutWatcher::new_saved(chunk, revision));
+    let chunks = &mut voxels.loaded_chunks_mut().chunks;
+    if let Some(old_chunk) = chunks.get(&pos) {
+        replace_chunk_block_entities(commands, old_chunk, &mut chunk);
+    }
+    if !chunk.block_entities.is_empty() {
+        commands.add(move |world: &mut World| spawn_chunk_block_entities::<ClientData>(world, pos));
+    }
+    chunks.insert(pos, MutWatcher::new_saved(chunk, revision));
 
     Ok(())
 }
@@ -139,13 +157,14 @@ fn client_chunk_mesher_system(
         })
     });
 
-    // Schedule new meshes for all outdated chunks
+    // Schedule new meshes for all outdated chunks, or for all chunks after the block definitions got reloaded
+    let registry_changed = block_registry.is_changed();
     let mut new_entries = Vec::new();
     let loaded_chunks = voxels.loaded_chunks();
     for (&pos, chunk) in loaded_chunks.chunks.iter() {
         let old_mesh = chunk.extra_data.mesh.as_ref();
         let needs_mesh = if let Some(old_mesh) = old_mesh {
-            old_mesh.is_older_than(chunk)
+            registry_changed || old_mesh.is_older_than(chunk)
         } else {
             true
         };
@@ -204,3 +223,65 @@ fn client_chunk_mesher_system(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use gs_common::builtin_game_registries;
+    use gs_common::voxel::blocks::STONE_BLOCK_NAME;
+    use gs_schemas::voxel::voxeltypes::BlockEntry;
+
+    use super::*;
+
+    /// The mesh of the only chunk with all its neighbors loaded.
+    fn chunk_mesh(world: &mut World) -> Handle<Mesh> {
+        let mut voxels = world.query::<&ClientVoxelUniverse>();
+        let meshes: Vec<_> = voxels
+            .single(world)
+            .loaded_chunks()
+            .chunks
+            .values()
+            .filter_map(|chunk| chunk.extra_data.mesh.as_ref())
+            .collect();
+        assert_eq!(meshes.len(), 1);
+        meshes[0].meshes[0].clone()
+    }
+
+    #[test]
+    fn reloaded_block_registry_remeshes_chunks() {
+        let registries = builtin_game_registries();
+        let (stone, _) = registries
+            .block_types
+            .lookup_name_to_object(STONE_BLOCK_NAME.as_ref())
+            .unwrap();
+        let mut voxels = ClientVoxelUniverse::new(default());
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    voxels.loaded_chunks_mut().chunks.insert(
+                        AbsChunkPos::new(x, y, z),
+                        MutWatcher::new(ClientChunk::new(BlockEntry::new(stone, 0), default())),
+                    );
+                }
+            }
+        }
+        let mut world = World::new();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.insert_resource(BlockRegistryHolder(Arc::clone(&registries.block_types)));
+        world.spawn(voxels);
+        let mesher = world.register_system(client_chunk_mesher_system);
+
+        world.run_system(mesher).unwrap();
+        assert_eq!(world.resource::<Assets<Mesh>>().len(), 1);
+        let first_mesh = chunk_mesh(&mut world);
+
+        world.run_system(mesher).unwrap();
+        assert_eq!(chunk_mesh(&mut world), first_mesh);
+
+        // The client replaces the registry resources when the server reloads its content.
+        world.insert_resource(BlockRegistryHolder(Arc::clone(&registries.block_types)));
+        world.run_system(mesher).unwrap();
+        assert_ne!(chunk_mesh(&mut world), first_mesh);
+        assert_eq!(world.resource::<Assets<Mesh>>().len(), 1);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory of the base content pack embedded into the executable, relative to the crate directory.
+const BASE_CONTENT_DIRECTORY: &str = "../../assets/content/base";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_hash = git(&["rev-parse", "--short=10", "HEAD"]).unwrap_or_else(|| "unknown".to_owned());
+    // Rebuild when the checked out commit changes, so that the embedded hash stays accurate.
+    if let Some(path) = git(&["rev-parse", "--git-path", "HEAD"]) {
+        println!("cargo:rerun-if-changed={path}");
+    }
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        if let Some(path) = git(&["rev-parse", "--git-path", &head_ref]) {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+
+    // Honor SOURCE_DATE_EPOCH for reproducible builds, otherwise use the date of the checked out commit, which changes
+    // along with the hash the script reruns for. The current date is only used outside of a git checkout.
+    let epoch_seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .or_else(|| git(&["log", "-1", "--format=%ct", "HEAD"])?.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    let (year, month, day) = civil_from_days((epoch_seconds / 86400) as i64);
+    let build_date = format!("{year:04}-{month:02}-{day:02}");
+
+    println!("cargo:rustc-env=GS_BUILD_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=GS_BUILD_DATE={build_date}");
+
+    embed_base_content();
+}
+
+/// Writes the list of the base content pack files as `(relative path, include_str!(...))` pairs into
+/// `$OUT_DIR/base_content_files.rs`, so that new content files get embedded without listing them in the code.
+fn embed_base_content() {
+    let root = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join(BASE_CONTENT_DIRECTORY);
+    // A directory is scanned recursively for changes, so adding or removing files also reruns the script.
+    println!("cargo:rerun-if-changed={}", root.display());
+    let mut files = Vec::new();
+    collect_content_files(&root, &mut files);
+    files.sort();
+
+    let mut list = String::from("&[\n");
+    for file in files {
+        let relative = file.strip_prefix(&root).unwrap();
+        let relative = relative
+            .components()
+            .map(|component| {
+                component
+                    .as_os_str()
+                    .to_str()
+                    .expect("Content paths must be valid UTF-8")
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let absolute = file.canonicalize().unwrap();
+        writeln!(
+            list,
+            "    ({relative:?}, include_str!({:?})),",
+            absolute.display().to_string()
+        )
+        .unwrap();
+    }
+    list.push(']');
+    let output = Path::new(&std::env::var("OUT_DIR").unwrap()).join("base_content_files.rs");
+    std::fs::write(output, list).unwrap();
+}
+
+/// Recursively collects the `.ron` files in the given directory.
+fn collect_content_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let entries = std::fs::read_dir(directory)
+        .unwrap_or_else(|e| panic!("Could not read the content directory {}: {e}", directory.display()));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_content_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "ron") {
+            files.push(path);
+        }
+    }
+}
+
+/// Runs git in the crate directory, returning the trimmed stdout on success.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    (!stdout.is_empty()).then(|| stdout.to_owned())
+}
+
+/// Converts a number of days since the Unix epoch into a (year, month, day) UTC date.
+/// Algorithm from <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,287 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use capnp_rpc::rpc_twoparty_capnp::Side;
+use capnp_rpc::twoparty::{VatId, VatNetwork};
+use capnp_rpc::RpcSystem;
+use clap::{Parser, Subcommand};
+use gs_common::network::admin::{ADMIN_READER_OPTIONS, MAX_TAILED_LOG_LINES};
+use gs_common::prelude::*;
+use tokio::net::TcpStream;
+
+/// The environment variable read for the admin credential when `--credential` is not given.
+const CREDENTIAL_ENV: &str = "GS_ADMIN_CREDENTIAL";
+
+#[derive(Parser)]
+#[command(name = "gs_admin", about = "Geosia remote server administration tool")]
+struct CliOptions {
+    /// The admin listen address of the server.
+    #[arg(long, default_value = "127.0.0.1:28033")]
+    address: SocketAddr,
+    /// The admin credential, read from the GS_ADMIN_CREDENTIAL environment variable if not given.
+    #[arg(long)]
+    credential: Option<String>,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Shows the server status.
+    Status,
+    /// Lists the online players.
+    Players,
+    /// Disconnects a player.
+    Kick {
+        nickname: String,
+        #[arg(default_value = "Kicked by an administrator")]
+        reason: String,
+    },
+    /// Bans and disconnects a player.
+    Ban {
+        nickname: String,
+        #[arg(default_value = "Banned by an administrator")]
+        reason: String,
+    },
+    /// Lifts the ban of a player.
+    Unban { nickname: String },
+    /// Runs a server console command, run `help` for the list.
+    Run {
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Saves the loaded world.
+    Save,
+    /// Writes a backup of the loaded world into the server's backup directory.
+    Backup { name: String },
+    /// Shows a server configuration option, or changes it if a value is given.
+    Config { key: String, value: Option<String> },
+    /// Shows the server performance diagnostics.
+    Diagnostics,
+    /// Prints the recent server log lines.
+    Logs {
+        /// The maximum number of lines to print.
+        #[arg(long, default_value_t = 100)]
+        lines: u32,
+        /// Keep printing new log lines as they arrive.
+        #[arg(long)]
+        follow: bool,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = CliOptions::parse();
+    let credential = match cli.credential.clone() {
+        Some(credential) => credential,
+        None => std::env::var(CREDENTIAL_ENV)
+            .with_context(|| format!("No admin credential given with --credential or {CREDENTIAL_ENV}"))?,
+    };
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(tokio::task::LocalSet::new().run_until(run(cli, credential)))
+}
+
+async fn run(cli: CliOptions, credential: String) -> Result<()> {
+    let stream = TcpStream::connect(cli.address)
+        .await
+        .with_context(|| format!("Could not connect to {}", cli.address))?;
+    stream.set_nodelay(true)?;
+    let (read, write) = stream.compat().split();
+    let network = VatNetwork::new(read, write, Side::Client, ADMIN_READER_OPTIONS);
+    let mut rpc_system = RpcSystem::new(Box::new(network), None);
+    let server: rpc::admin_server::Client = rpc_system.bootstrap(VatId::Server);
+    let rpc_task = tokio::task::spawn_local(rpc_system);
+
+    let mut login = server.login_request();
+    login.get().set_credential(credential.as_str());
+    let connection = login.send().pipeline.get_connection();
+    let result = run_command(&connection, cli.command).await;
+    rpc_task.abort();
+    result
+}
+
+async fn run_command(connection: &rpc::admin_connection::Client, command: AdminCommand) -> Result<()> {
+    match command {
+        AdminCommand::Status => {
+            let response = connection.get_status_request().send().promise.await?;
+            let status = response.get()?.get_status()?;
+            let metadata = status.get_metadata()?;
+            let version = metadata.get_server_version()?;
+            println!(
+                "{} - {}",
+                metadata.get_title()?.to_str()?,
+                metadata.get_subtitle()?.to_str()?
+            );
+            println!(
+                "Version {}.{}.{}, protocol {}",
+                version.get_major(),
+                version.get_minor(),
+                version.get_patch(),
+                metadata.get_protocol_version()
+            );
+            println!(
+                "Players: {}/{}",
+                metadata.get_player_count(),
+                metadata.get_player_limit()
+            );
+            println!(
+                "Tick {}{}, last tick took {:.2} ms",
+                status.get_tick(),
+                if status.get_paused() { " (paused)" } else { "" },
+                status.get_last_tick_micros() as f64 / 1000.0
+            );
+        }
+        AdminCommand::Players => {
+            let response = connection.list_players_request().send().promise.await?;
+            let players = response.get()?.get_players()?;
+            println!("{} players online", players.len());
+            for player in players {
+                let position = player.get_position()?;
+                println!(
+                    "{:<24} {:<24} ({:.1}, {:.1}, {:.1})",
+                    player.get_nickname()?.to_str()?,
+                    player.get_address()?.to_str()?,
+                    position.get_x(),
+                    position.get_y(),
+                    position.get_z()
+                );
+            }
+        }
+        AdminCommand::Kick { nickname, reason } => {
+            let mut request = connection.kick_player_request();
+            request.get().set_nickname(nickname.as_str());
+            request.get().set_reason(reason.as_str());
+            let response = request.send().promise.await?;
+            if !response.get()?.get_found() {
+                bail!("{nickname} is not online");
+            }
+            println!("Kicked {nickname}");
+        }
+        AdminCommand::Ban { nickname, reason } => {
+            let mut request = connection.ban_player_request();
+            request.get().set_nickname(nickname.as_str());
+            request.get().set_reason(reason.as_str());
+            let response = request.send().promise.await?;
+            if response.get()?.get_added() {
+                println!("Banned {nickname}");
+            } else {
+                println!("{nickname} was already banned");
+            }
+        }
+        AdminCommand::Unban { nickname } => {
+            let mut request = connection.unban_player_request();
+            request.get().set_nickname(nickname.as_str());
+            let response = request.send().promise.await?;
+            if !response.get()?.get_removed() {
+                bail!("{nickname} is not banned");
+            }
+            println!("Unbanned {nickname}");
+        }
+        AdminCommand::Run { command } => {
+            let mut request = connection.run_command_request();
+            request.get().set_command(command.join(" ").as_str());
+            let response = request.send().promise.await?;
+            println!("{}", response.get()?.get_output()?.to_str()?);
+        }
+        AdminCommand::Save => {
+            let response = connection.save_world_request().send().promise.await?;
+            println!("Queued {} chunks for saving", response.get()?.get_chunks());
+        }
+        AdminCommand::Backup { name } => {
+            let mut request = connection.backup_world_request();
+            request.get().set_name(name.as_str());
+            let response = request.send().promise.await?;
+            let results = response.get()?;
+            println!(
+                "Backed up {} chunks into {}",
+                results.get_chunks(),
+                results.get_path()?.to_str()?
+            );
+        }
+        AdminCommand::Config { key, value: None } => {
+            let mut request = connection.get_config_request();
+            request.get().set_key(key.as_str());
+            let response = request.send().promise.await?;
+            println!("{key} = {}", response.get()?.get_value()?.to_str()?);
+        }
+        AdminCommand::Config {
+            key,
+            value: Some(value),
+        } => {
+            let mut request = connection.set_config_request();
+            request.get().set_key(key.as_str());
+            request.get().set_value(value.as_str());
+            let response = request.send().promise.await?;
+            println!("{key} = {value} (was {})", response.get()?.get_previous()?.to_str()?);
+        }
+        AdminCommand::Diagnostics => {
+            let response = connection.get_diagnostics_request().send().promise.await?;
+            let diagnostics = response.get()?.get_diagnostics()?;
+            println!(
+                "Tick: {:.2} ms average, {:.2} ms max",
+                diagnostics.get_tick_micros() as f64 / 1000.0,
+                diagnostics.get_max_tick_micros() as f64 / 1000.0
+            );
+            println!(
+                "Chunks: {} loaded, {:.1} generated per second",
+                diagnostics.get_loaded_chunks(),
+                diagnostics.get_chunk_generation_rate()
+            );
+            println!(
+                "Persistence queues: {} loads, {} saves, {} responses",
+                diagnostics.get_loads_queued(),
+                diagnostics.get_saves_queued(),
+                diagnostics.get_responses_queued()
+            );
+            let clients = diagnostics.get_clients()?;
+            println!("{} clients", clients.len());
+            for client in clients {
+                println!(
+                    "{:<24} {:<24} {:>10.1} KiB/s {:>12} bytes sent {:>4} packets ({} bytes) queued",
+                    client.get_nickname()?.to_str()?,
+                    client.get_address()?.to_str()?,
+                    client.get_bytes_per_second() / 1024.0,
+                    client.get_sent_bytes(),
+                    client.get_queued_packets(),
+                    client.get_queued_bytes()
+                );
+            }
+        }
+        AdminCommand::Logs { lines, follow } => tail_logs(connection, lines, follow).await?,
+    }
+    Ok(())
+}
+
+async fn tail_logs(connection: &rpc::admin_connection::Client, lines: u32, follow: bool) -> Result<()> {
+    // Find the end of the log first, to start printing from the requested number of lines before it.
+    let mut request = connection.tail_logs_request();
+    request.get().set_after(u64::MAX);
+    let response = request.send().promise.await?;
+    let mut after = response.get()?.get_next().saturating_sub(u64::from(lines));
+    loop {
+        let mut request = connection.tail_logs_request();
+        request.get().set_after(after);
+        request.get().set_limit(MAX_TAILED_LOG_LINES);
+        let response = request.send().promise.await?;
+        let results = response.get()?;
+        let received = results.get_lines()?;
+        for line in received {
+            println!(
+                "{:>13} {:>5} {}: {}",
+                line.get_time(),
+                line.get_level()?.to_str()?,
+                line.get_target()?.to_str()?,
+                line.get_message()?.to_str()?
+            );
+        }
+        after = results.get_next();
+        if received.len() < MAX_TAILED_LOG_LINES {
+            if !follow {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use bevy::app::App;
+use bevy::log::{error, info, LogPlugin};
+use clap::Parser;
+use gs_common::config::GameConfig;
+use gs_common::network::bot::{square_path, BotScript, BotStats, BotStatsSnapshot, HeadlessBot};
+use gs_common::prelude::*;
+use gs_common::GameServer;
+
+#[derive(Parser)]
+#[command(
+    name = "gs_bot",
+    about = "Geosia load tester, runs headless bots against an in-process server"
+)]
+struct CliOptions {
+    /// The number of bots to connect.
+    #[arg(long, default_value_t = 8)]
+    bots: u32,
+    /// How long to run the test for, in seconds.
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+    /// How often to print statistics, in seconds.
+    #[arg(long, default_value_t = 5)]
+    report_interval: u64,
+    /// The side length of the square path walked by each bot, in blocks.
+    #[arg(long, default_value_t = 32.0)]
+    path_size: f32,
+    /// The walking speed of the bots, in blocks per second.
+    #[arg(long, default_value_t = 8.0)]
+    speed: f32,
+    /// How often each bot edits a block, in milliseconds, or 0 to disable block edits.
+    #[arg(long, default_value_t = 1000)]
+    edit_interval_ms: u64,
+    /// The render distance requested by each bot, in chunks.
+    #[arg(long, default_value_t = 4)]
+    render_distance: i32,
+    /// The delay between connecting consecutive bots, in milliseconds.
+    #[arg(long, default_value_t = 100)]
+    ramp_up_ms: u64,
+}
+
+fn main() -> Result<()> {
+    // Set up bevy's logging once per process
+    App::new().add_plugins(LogPlugin::default()).run();
+    let cli = CliOptions::parse();
+
+    let mut config = GameConfig::default();
+    "Bot test server".clone_into(&mut config.server.server_title);
+    config.server.listen_addresses.clear();
+    config.server.max_players = cli.bots;
+    let server = GameServer::new(config.new_handle())?;
+    server.set_paused(false);
+
+    let script = BotScript {
+        waypoints: square_path(cli.path_size),
+        speed: cli.speed,
+        edit_interval: (cli.edit_interval_ms > 0).then(|| Duration::from_millis(cli.edit_interval_ms)),
+        render_distance: cli.render_distance,
+        ..Default::default()
+    };
+    let stats = Arc::new(BotStats::default());
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(tokio::task::LocalSet::new().run_until(run_bots(&server, &cli, script, &stats)));
+
+    let totals = stats.snapshot();
+    info!(
+        "Finished: {} chunks loaded, {} block edits ({} rejected), {} errors",
+        totals.chunks_loaded, totals.block_edits, totals.rejected_edits, totals.errors
+    );
+    let _ = server.shutdown().blocking_recv();
+    Ok(())
+}
+
+async fn run_bots(server: &Arc<GameServer>, cli: &CliOptions, script: BotScript, stats: &Arc<BotStats>) {
+    let (stop_tx, stop_rx) = async_watch_channel(false);
+    let start = Instant::now();
+    let end = start + Duration::from_secs(cli.duration);
+
+    let mut bots = Vec::new();
+    let mut report = tokio::time::interval(Duration::from_secs(cli.report_interval.max(1)));
+    report.tick().await;
+    let mut last_report = (Instant::now(), stats.snapshot());
+    let mut connect_next = tokio::time::interval(Duration::from_millis(cli.ramp_up_ms.max(1)));
+    while Instant::now() < end {
+        tokio::select! {
+            _ = connect_next.tick(), if bots.len() < cli.bots as usize => {
+                let connection = match server.create_local_connection().async_wait().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Could not create a local connection: {e:#}");
+                        break;
+                    }
+                };
+                let bot = HeadlessBot::new(format!("Bot{}", bots.len()), script.clone(), Arc::clone(stats));
+                bots.push(tokio::task::spawn_local(bot.run(connection, stop_rx.clone())));
+            }
+            _ = report.tick() => {
+                let now = Instant::now();
+                let snapshot = stats.snapshot();
+                print_report(&snapshot.since(&last_report.1), now - last_report.0, server);
+                last_report = (now, snapshot);
+            }
+            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(end)) => {}
+        }
+    }
+
+    let _ = stop_tx.send(true);
+    for bot in bots {
+        match bot.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Bot failed: {e:#}"),
+            Err(e) => error!("Bot task panicked: {e}"),
+        }
+    }
+}
+
+fn print_report(delta: &BotStatsSnapshot, elapsed: Duration, server: &GameServer) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let kib_per_second = |bytes: u64| bytes as f64 / 1024.0 / seconds;
+    info!(
+        "{} bots | tick {:.2} ms, max {:.2} ms | streams {:.1} KiB/s, {} chunks | datagrams {:.1} KiB/s, {:.0}/s | {} edits, {} rejected | {} errors",
+        delta.connected_bots,
+        server.last_tick_duration().as_secs_f64() * 1000.0,
+        server.take_max_tick_duration().as_secs_f64() * 1000.0,
+        kib_per_second(delta.stream_bytes),
+        delta.chunks_loaded,
+        kib_per_second(delta.datagram_bytes),
+        delta.datagrams as f64 / seconds,
+        delta.block_edits,
+        delta.rejected_edits,
+        delta.errors,
+    );
+}
@@ -0,0 +1,151 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use gs_common::network::capture::{CaptureInfo, CaptureReader, CaptureRecord};
+use gs_schemas::schemas::network_capnp::captured_packet::{Channel, Direction};
+
+#[derive(Parser)]
+#[command(name = "gs_capture_inspect", about = "Geosia network traffic capture inspector")]
+struct CliOptions {
+    /// The capture file to decode.
+    capture: PathBuf,
+    /// The output format.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// Only show packets of streams of this type (e.g. chunkData, or ns:key for custom streams), can be repeated.
+    #[arg(long = "stream", value_name = "TYPE")]
+    streams: Vec<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line, starting with the capture metadata.
+    Json,
+}
+
+fn main() -> Result<()> {
+    let cli = CliOptions::parse();
+    let file = File::open(&cli.capture).with_context(|| format!("Opening {}", cli.capture.display()))?;
+    let (reader, info) = CaptureReader::open(BufReader::new(file))?;
+    let mut out = BufWriter::new(std::io::stdout().lock());
+
+    match cli.format {
+        OutputFormat::Text => writeln!(
+            out,
+            "Capture of {} from the {} side, started at unix time {} ms",
+            info.peer, info.side, info.start_time
+        )?,
+        OutputFormat::Json => writeln!(out, "{}", info_json(&info))?,
+    }
+    for record in reader {
+        let record = record?;
+        if !cli.streams.is_empty() {
+            let Some(stream_type) = record.stream_type_name() else {
+                continue;
+            };
+            if !cli.streams.iter().any(|s| s.eq_ignore_ascii_case(&stream_type)) {
+                continue;
+            }
+        }
+        let decoded = record.decode_payload();
+        match cli.format {
+            OutputFormat::Text => {
+                let mut line = format!(
+                    "[{:>12.6}s tick {:>6}] {:<8} {}",
+                    record.time.as_secs_f64(),
+                    record.tick,
+                    direction_name(record.direction),
+                    channel_name(record.channel)
+                );
+                if let (Some((id, _)), Some(stream_type)) = (&record.stream, record.stream_type_name()) {
+                    write!(line, " #{id} {stream_type}")?;
+                }
+                write!(line, ", {} bytes", record.data.len())?;
+                writeln!(out, "{line}")?;
+                match decoded {
+                    Ok(Some(text)) => writeln!(out, "    {text}")?,
+                    Ok(None) => {}
+                    Err(e) => writeln!(out, "    <decoding error: {e:#}>")?,
+                }
+            }
+            OutputFormat::Json => writeln!(out, "{}", record_json(&record, decoded))?,
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Sent => "sent",
+        Direction::Received => "received",
+    }
+}
+
+fn channel_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Rpc => "rpc",
+        Channel::Stream => "stream",
+        Channel::Datagram => "datagram",
+    }
+}
+
+fn info_json(info: &CaptureInfo) -> String {
+    format!(
+        "{{\"start_time\":{},\"peer\":{},\"side\":{}}}",
+        info.start_time,
+        json_string(&info.peer),
+        json_string(&info.side)
+    )
+}
+
+fn record_json(record: &CaptureRecord, decoded: Result<Option<String>>) -> String {
+    let mut json = format!(
+        "{{\"time_us\":{},\"tick\":{},\"direction\":\"{}\",\"channel\":\"{}\",\"size\":{}",
+        record.time.as_micros(),
+        record.tick,
+        direction_name(record.direction),
+        channel_name(record.channel),
+        record.data.len()
+    );
+    if let (Some((id, _)), Some(stream_type)) = (&record.stream, record.stream_type_name()) {
+        let _ = write!(
+            json,
+            ",\"stream_id\":{id},\"stream_type\":{}",
+            json_string(&stream_type)
+        );
+    }
+    let _ = match decoded {
+        Ok(Some(text)) => write!(json, ",\"decoded\":{}", json_string(&text)),
+        Ok(None) => write!(json, ",\"decoded\":null"),
+        Err(e) => write!(json, ",\"error\":{}", json_string(&format!("{e:#}"))),
+    };
+    json.push('}');
+    json
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -1,13 +1,106 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
 use bevy::app::App;
-use bevy::log::LogPlugin;
+use bevy::log::{info, LogPlugin};
+use bevy::utils::default;
 use clap::Parser;
+use gs_common::config::{GameConfig, ServerConfig};
+use gs_common::network::admin::admin_log_layer;
+use gs_common::voxel::persistence::savefile::SaveRemapPolicy;
+use gs_common::voxel::plugin::compact_saved_world;
+use gs_common::{content_game_registries, load_content_packs, GameServer};
+use gs_schemas::registry_remap::MissingEntryPolicy;
 
 #[derive(Parser)]
 #[command(name = "gs_dedi_server", about = "Geosia dedicated server")]
-struct CliOptions {}
+struct CliOptions {
+    /// The address to accept remote administration connections on. The admin credential is sent unencrypted, so only
+    /// bind it to a loopback address or to an address on a trusted network.
+    #[arg(long, default_value = "127.0.0.1:28033")]
+    admin_listen: SocketAddr,
+    /// The credential admin tools have to log in with, read from the GS_ADMIN_CREDENTIAL environment variable if not given.
+    #[arg(long)]
+    admin_credential: Option<String>,
+    /// A content pack directory to load after the base content pack, can be repeated to load several packs in order.
+    #[arg(long = "content-pack")]
+    content_packs: Vec<PathBuf>,
+    /// Development mode: load the given content pack directory after all the others and reload it when its files change.
+    #[arg(long)]
+    dev_content: Option<PathBuf>,
+    /// Load the world from the given savefile, such as a world backup.
+    #[arg(long)]
+    world: Option<PathBuf>,
+    /// Rewrite the world savefile given with --world into this file with compacted registry IDs, then exit without
+    /// starting the server.
+    #[arg(long, requires = "world")]
+    compact_world: Option<PathBuf>,
+    /// Refuse to load a world with blocks or biomes missing from the content, instead of replacing them.
+    #[arg(long)]
+    strict_content: bool,
+    /// The file to keep the server options changed by admins and the banned players in across restarts.
+    #[arg(long, default_value = "server_settings.ron")]
+    settings: PathBuf,
+}
+
+fn main() -> Result<()> {
+    // Set up bevy's logging once per process, keeping recent log lines for admin tools
+    App::new()
+        .add_plugins(LogPlugin {
+            custom_layer: admin_log_layer,
+            ..default()
+        })
+        .run();
+    let cli = CliOptions::parse();
+
+    let mut config = GameConfig::default();
+    config.server.runtime_settings_file = Some(cli.settings);
+    config.server.load_runtime_settings()?;
+    config.server.admin_listen_address = Some(cli.admin_listen);
+    config.server.admin_credential = cli
+        .admin_credential
+        .or_else(|| std::env::var("GS_ADMIN_CREDENTIAL").ok());
+    config.server.content_packs = cli.content_packs;
+    config.server.dev_content_directory = cli.dev_content;
+    config.server.world_file = cli.world;
+    if cli.strict_content {
+        config.server.world_remap_policy = SaveRemapPolicy {
+            blocks: MissingEntryPolicy::Error,
+            biomes: MissingEntryPolicy::Error,
+        };
+    }
+    if let Some(output) = cli.compact_world {
+        return compact_world(&config.server, &output);
+    }
+    let server = GameServer::new(config.new_handle())?;
+    server.set_paused(false);
+
+    while server.is_alive() {
+        std::thread::sleep(Duration::from_millis(250));
+    }
+    info!("Server stopped");
+    Ok(())
+}
 
-fn main() {
-    // Set up bevy's logging once per process
-    App::new().add_plugins(LogPlugin::default()).run();
-    let _cli = CliOptions::parse();
+/// Rewrites the world savefile of the server config into `output` with dense registry IDs for the configured content.
+fn compact_world(config: &ServerConfig, output: &Path) -> Result<()> {
+    let input = config.world_file.as_deref().context("No world savefile to compact")?;
+    let packs = load_content_packs(&config.content_packs, config.dev_content_directory.as_deref())?;
+    let registries = content_game_registries(&packs, None).context("Could not build the game registries")?;
+    let file = File::open(input).with_context(|| format!("Opening the world savefile {input:?}"))?;
+    let mut writer = BufWriter::new(File::create(output).with_context(|| format!("Creating {output:?}"))?);
+    let count = compact_saved_world(
+        &mut BufReader::new(file),
+        &mut writer,
+        &registries,
+        &config.world_remap_policy,
+    )
+    .with_context(|| format!("Compacting the world savefile {input:?}"))?;
+    writer.flush()?;
+    info!("Wrote {count} chunks with compacted registry IDs to {output:?}");
+    Ok(())
 }
@@ -1,11 +1,19 @@
 //! Game configuration handling
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
+use anyhow::{anyhow, bail, Context, Result};
+use bevy::log::warn;
+use serde::{Deserialize, Serialize};
 use smart_default::SmartDefault;
 
+use crate::network::conditions::NetworkConditions;
 use crate::prelude::{async_watch_channel, AsyncWatchReceiver, AsyncWatchSender};
+use crate::voxel::persistence::savefile::SaveRemapPolicy;
 
 /// The server-specific configuration.
 #[derive(Clone, Eq, PartialEq, Debug, SmartDefault)]
@@ -19,16 +27,196 @@ pub struct ServerConfig {
     /// The maximum number of players allowed to join the server.
     #[default = 4]
     pub max_players: u32,
+    /// The maximum chunk data bandwidth sent to each remote client, in bytes per second, or 0 for no limit.
+    #[default(4 * 1024 * 1024)]
+    pub chunk_bandwidth_limit: u64,
     /// The network IPs and ports to listen on.
     #[default(default_listen_addresses())]
     pub listen_addresses: Vec<SocketAddr>,
+    /// Whether to periodically announce the server on the local network for LAN discovery.
+    #[default = false]
+    pub lan_discovery: bool,
+    /// The maximum chunk loading radius around each player, in chunks; larger client requests are capped to it.
+    #[default = 12]
+    pub max_render_distance: i32,
+    /// The directory to record network traffic captures of all client connections into, or None to disable recording.
+    #[default(None)]
+    pub traffic_capture_directory: Option<PathBuf>,
+    /// The address to accept remote administration connections on, or None to disable remote administration.
+    /// The admin credential is sent unencrypted, so this must be a loopback address or an address on a trusted network.
+    #[default(default_admin_listen_address())]
+    pub admin_listen_address: Option<SocketAddr>,
+    /// The credential admin tools have to log in with, remote administration is disabled while unset.
+    #[default(None)]
+    pub admin_credential: Option<String>,
+    /// Nicknames of the players not allowed to join the server.
+    pub banned_players: BTreeSet<String>,
+    /// The directory world backups are written into.
+    #[default(PathBuf::from("backups"))]
+    pub backup_directory: PathBuf,
+    /// How often to log a summary of the server diagnostics, in seconds, or 0 to disable the summary.
+    #[default = 60]
+    pub diagnostics_log_interval: u64,
+    /// The content pack directories loaded in order after the base content pack, each one adding to and overriding the
+    /// content of the packs before it.
+    pub content_packs: Vec<PathBuf>,
+    /// Development mode: the content pack directory loaded after all the other content packs, the registries are
+    /// rebuilt and re-sent to the clients whenever a file in it changes.
+    #[default(None)]
+    pub dev_content_directory: Option<PathBuf>,
+    /// The world savefile (e.g. a backup) to load the world from at startup, or None to generate a new world.
+    #[default(None)]
+    pub world_file: Option<PathBuf>,
+    /// How the blocks and biomes of the loaded world that are missing from the current content are handled.
+    pub world_remap_policy: SaveRemapPolicy,
+    /// The file the runtime options and the banned players are saved into whenever they change, and loaded from at
+    /// startup, or None to forget them on restart.
+    #[default(None)]
+    pub runtime_settings_file: Option<PathBuf>,
+}
+
+impl ServerConfig {
+    /// Names of the options that can be read and changed at runtime with [`Self::get_option`] and [`Self::set_option`].
+    pub const RUNTIME_OPTIONS: &'static [&'static str] = &[
+        "server_title",
+        "server_subtitle",
+        "max_players",
+        "chunk_bandwidth_limit",
+        "lan_discovery",
+        "max_render_distance",
+        "diagnostics_log_interval",
+    ];
+
+    /// Gets the text representation of a runtime-changeable option.
+    pub fn get_option(&self, key: &str) -> Result<String> {
+        Ok(match key {
+            "server_title" => self.server_title.clone(),
+            "server_subtitle" => self.server_subtitle.clone(),
+            "max_players" => self.max_players.to_string(),
+            "chunk_bandwidth_limit" => self.chunk_bandwidth_limit.to_string(),
+            "lan_discovery" => self.lan_discovery.to_string(),
+            "max_render_distance" => self.max_render_distance.to_string(),
+            "diagnostics_log_interval" => self.diagnostics_log_interval.to_string(),
+            _ => bail!(
+                "Unknown server option {key}, known options: {}",
+                Self::RUNTIME_OPTIONS.join(", ")
+            ),
+        })
+    }
+
+    /// Changes a runtime-changeable option from its text representation, returns the previous value.
+    pub fn set_option(&mut self, key: &str, value: &str) -> Result<String> {
+        let previous = self.get_option(key)?;
+        match key {
+            "server_title" => value.clone_into(&mut self.server_title),
+            "server_subtitle" => value.clone_into(&mut self.server_subtitle),
+            "max_players" => self.max_players = parse_option(key, value)?,
+            "chunk_bandwidth_limit" => self.chunk_bandwidth_limit = parse_option(key, value)?,
+            "lan_discovery" => self.lan_discovery = parse_option(key, value)?,
+            "max_render_distance" => {
+                let distance: i32 = parse_option(key, value)?;
+                if distance < 0 {
+                    bail!("The max_render_distance option can't be negative");
+                }
+                self.max_render_distance = distance;
+            }
+            "diagnostics_log_interval" => self.diagnostics_log_interval = parse_option(key, value)?,
+            _ => unreachable!("get_option accepted an unknown option"),
+        }
+        Ok(previous)
+    }
+
+    /// Applies the settings saved in [`Self::runtime_settings_file`] if it exists.
+    pub fn load_runtime_settings(&mut self) -> Result<()> {
+        let Some(path) = self.runtime_settings_file.clone() else {
+            return Ok(());
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        ServerRuntimeSettings::load(&path)?.apply_to(self);
+        Ok(())
+    }
+}
+
+/// The server settings admins can change at runtime, persisted in [`ServerConfig::runtime_settings_file`].
+#[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct ServerRuntimeSettings {
+    /// The values of the [`ServerConfig::RUNTIME_OPTIONS`], in their text representation.
+    pub options: BTreeMap<String, String>,
+    /// Nicknames of the players not allowed to join the server.
+    pub banned_players: BTreeSet<String>,
+}
+
+impl ServerRuntimeSettings {
+    /// Copies the runtime settings from the server configuration.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let options = ServerConfig::RUNTIME_OPTIONS
+            .iter()
+            .map(|&key| (key.to_owned(), config.get_option(key).unwrap()))
+            .collect();
+        Self {
+            options,
+            banned_players: config.banned_players.clone(),
+        }
+    }
+
+    /// Overwrites the server configuration with these settings, skipping the invalid or no longer known options.
+    pub fn apply_to(&self, config: &mut ServerConfig) {
+        for (key, value) in self.options.iter() {
+            if let Err(e) = config.set_option(key, value) {
+                warn!("Ignoring the saved server option {key}: {e:#}");
+            }
+        }
+        config.banned_players.clone_from(&self.banned_players);
+    }
+
+    /// Reads the settings saved by [`Self::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the server settings from {}", path.display()))?;
+        ron::from_str(&text).with_context(|| format!("Invalid server settings in {}", path.display()))
+    }
+
+    /// Writes the settings into a file, replacing it only once it's fully written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, text)
+            .with_context(|| format!("Could not write the server settings into {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, path)
+            .with_context(|| format!("Could not replace the server settings in {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// The client-specific configuration.
+#[derive(Clone, PartialEq, Debug, SmartDefault)]
+pub struct ClientConfig {
+    /// The requested chunk loading radius around the player, in chunks.
+    #[default = 6]
+    pub render_distance: i32,
+    /// Network conditions simulated on the connection to the integrated server, for testing the netcode in single player.
+    pub simulated_network: NetworkConditions,
+    /// Whether to run the integrated server in a separate process, so a crash in one can't corrupt the other's data.
+    #[default = false]
+    pub integrated_server_process: bool,
+    /// The content pack directories loaded in order after the base content pack, they should match the ones used by the
+    /// server.
+    pub content_packs: Vec<PathBuf>,
+    /// Development mode: the content pack directory loaded after all the other content packs, it should match the one
+    /// used by the server so that reloaded registries can be matched up.
+    #[default(None)]
+    pub dev_content_directory: Option<PathBuf>,
 }
 
 /// All game configuration saved into the config file.
-#[derive(Clone, Eq, PartialEq, Debug, SmartDefault)]
+#[derive(Clone, PartialEq, Debug, SmartDefault)]
 pub struct GameConfig {
     /// Server configuration.
     pub server: ServerConfig,
+    /// Client configuration.
+    pub client: ClientConfig,
 }
 
 /// A GameConfig handle that can listen to changes, used as the primary way of accessing the game configuration.
@@ -41,6 +229,63 @@ impl GameConfig {
     }
 }
 
+fn parse_option<T: FromStr>(key: &str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid value {value:?} for the {key} option: {e}"))
+}
+
 fn default_listen_addresses() -> Vec<SocketAddr> {
     vec!["0.0.0.0:28032".parse().unwrap(), "[::]:28032".parse().unwrap()]
 }
+
+fn default_admin_listen_address() -> Option<SocketAddr> {
+    Some("127.0.0.1:28033".parse().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runtime_settings_roundtrip() {
+        let path = std::env::temp_dir().join(format!("gs-settings-test-{}.ron", std::process::id()));
+        let mut config = ServerConfig::default();
+        config.set_option("server_title", "Saved server").unwrap();
+        config.set_option("lan_discovery", "true").unwrap();
+        config.banned_players.insert("Griefer".to_owned());
+        ServerRuntimeSettings::from_config(&config).save(&path).unwrap();
+
+        let mut loaded = ServerConfig {
+            runtime_settings_file: Some(path.clone()),
+            ..Default::default()
+        };
+        loaded.load_runtime_settings().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.server_title, "Saved server");
+        assert!(loaded.lan_discovery);
+        assert!(loaded.banned_players.contains("Griefer"));
+        assert_eq!(
+            ServerRuntimeSettings::from_config(&loaded),
+            ServerRuntimeSettings::from_config(&config)
+        );
+    }
+
+    #[test]
+    fn invalid_saved_options_are_skipped() {
+        let mut settings = ServerRuntimeSettings::from_config(&ServerConfig::default());
+        settings.options.insert("max_players".to_owned(), "many".to_owned());
+        settings.options.insert("removed_option".to_owned(), "1".to_owned());
+        settings
+            .options
+            .insert("max_render_distance".to_owned(), "8".to_owned());
+        let mut config = ServerConfig::default();
+        settings.apply_to(&mut config);
+        assert_eq!(config.max_players, ServerConfig::default().max_players);
+        assert_eq!(config.max_render_distance, 8);
+    }
+}
@@ -0,0 +1,29 @@
+//! The content packs built into the game.
+
+use bevy::log::warn;
+use gs_schemas::content::ContentPack;
+use gs_schemas::registry_aliases::ResolvedAlias;
+
+/// The name of the base content pack.
+pub const BASE_CONTENT_PACK_NAME: &str = "base";
+
+/// The files of the base content pack as (path relative to the pack root, contents) pairs, all the `.ron` files under
+/// `assets/content/base` are embedded by the build script.
+const BASE_CONTENT_FILES: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/base_content_files.rs"));
+
+/// The base game content, embedded into the executable from `assets/content/base`.
+pub fn base_content_pack() -> ContentPack {
+    let mut pack = ContentPack::new(BASE_CONTENT_PACK_NAME);
+    for (path, contents) in BASE_CONTENT_FILES {
+        pack.add_file(*path, *contents);
+    }
+    pack
+}
+
+/// Logs a warning for every name from `source` that was resolved through an alias, so that content authors can track
+/// the data still relying on old names.
+pub fn warn_resolved_aliases(source: &str, aliases: &[ResolvedAlias]) {
+    for ResolvedAlias { alias, target } in aliases {
+        warn!("{source} uses the old name {alias}, resolved to {target} through an alias");
+    }
+}
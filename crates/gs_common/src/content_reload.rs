@@ -0,0 +1,267 @@
+//! Development mode reloading of the content registries.
+//!
+//! While [`ServerConfig::dev_content_directory`](crate::config::ServerConfig::dev_content_directory) is set, the server
+//! polls the content files for changes in a background task and rebuilds the game registries from them. The IDs
+//! already in use keep their meaning (see
+//! [`Registry::rebuild_with_stable_ids`](gs_schemas::registry::Registry::rebuild_with_stable_ids)), so the loaded
+//! chunks stay valid. The new registries replace the shared ones, the registry resources and the world generator, and
+//! their ID mappings are sent to all the connected clients, which rebuild their own registries and remesh their chunks.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use bevy::prelude::*;
+use bevy::tasks::{block_on, IoTaskPool, Task};
+use gs_schemas::content::ContentPack;
+use gs_schemas::registries::GameRegistries;
+use gs_schemas::schemas::game_types_capnp::game_bootstrap_data;
+
+use crate::content::warn_resolved_aliases;
+use crate::items::ItemRegistryHolder;
+use crate::prelude::*;
+use crate::voxel::plugin::{BiomeRegistryHolder, BlockRegistryHolder};
+use crate::{content_game_registries, load_content_packs, GameServer, GameServerResource};
+
+/// How often the development content directory is checked for changes.
+pub const CONTENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Rebuilds the game registries of the server when the development content files change.
+pub struct ContentReloadPlugin;
+
+impl Plugin for ContentReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Last, watch_content_directory);
+    }
+}
+
+/// The modification times and sizes of all the files in a content directory, compared between polls to detect changes.
+type ContentFingerprint = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+#[derive(Default)]
+struct ContentWatcher {
+    last_poll: Option<Instant>,
+    /// The state of the content files at the previous poll, moved into the running poll task.
+    fingerprint: Option<ContentFingerprint>,
+    poll_task: Option<Task<ContentPoll>>,
+}
+
+/// The outcome of checking the content directory in the background.
+struct ContentPoll {
+    /// The current state of the content files, or the previous one if they could not be read.
+    fingerprint: Option<ContentFingerprint>,
+    /// The rebuilt registries if the content changed.
+    reloaded: Option<Result<Arc<GameRegistries>>>,
+}
+
+fn watch_content_directory(
+    mut watcher: Local<ContentWatcher>,
+    engine: Res<GameServerResource>,
+    mut commands: Commands,
+) {
+    if watcher.poll_task.as_ref().is_some_and(Task::is_finished) {
+        let poll = block_on(watcher.poll_task.take().unwrap());
+        watcher.fingerprint = poll.fingerprint;
+        match poll.reloaded {
+            Some(Ok(registries)) => {
+                apply_reloaded_content(&mut commands, &registries);
+                info!(
+                    "Reloaded the registries with {} block, {} biome and {} item types",
+                    registries.block_types.len(),
+                    registries.biome_types.len(),
+                    registries.item_types.len()
+                );
+            }
+            Some(Err(e)) => error!("Could not reload the content, keeping the previous registries: {e:#}"),
+            None => {}
+        }
+    }
+    if watcher.poll_task.is_some() {
+        return;
+    }
+    let Some(directory) = engine.0.config().borrow().server.dev_content_directory.clone() else {
+        return;
+    };
+    let now = Instant::now();
+    if watcher.last_poll.is_some_and(|last| now - last < CONTENT_POLL_INTERVAL) {
+        return;
+    }
+    watcher.last_poll = Some(now);
+
+    let engine = Arc::clone(&engine.0);
+    let previous = watcher.fingerprint.take();
+    watcher.poll_task = Some(IoTaskPool::get().spawn(async move { poll_content(&engine, &directory, previous) }));
+}
+
+/// Checks the content directory for changes since the `previous` poll, and reloads the content if there are any.
+fn poll_content(engine: &Arc<GameServer>, directory: &Path, previous: Option<ContentFingerprint>) -> ContentPoll {
+    let fingerprint = match content_fingerprint(directory) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            warn!("Could not check {} for content changes: {e}", directory.display());
+            return ContentPoll {
+                fingerprint: previous,
+                reloaded: None,
+            };
+        }
+    };
+    // The first poll only records the state the registries were built from.
+    let reloaded = match previous {
+        Some(previous) if previous != fingerprint => {
+            info!("Content changed in {}, reloading the registries", directory.display());
+            Some(reload_content(engine, directory))
+        }
+        _ => None,
+    };
+    ContentPoll {
+        fingerprint: Some(fingerprint),
+        reloaded,
+    }
+}
+
+/// Replaces the registry resources of the game world with the reloaded ones.
+fn apply_reloaded_content(commands: &mut Commands, registries: &GameRegistries) {
+    commands.insert_resource(BlockRegistryHolder(Arc::clone(&registries.block_types)));
+    commands.insert_resource(BiomeRegistryHolder(Arc::clone(&registries.biome_types)));
+    commands.insert_resource(ItemRegistryHolder(Arc::clone(&registries.item_types)));
+}
+
+/// Rebuilds the shared registries of the server from the content in the given directory, keeping the IDs in use,
+/// rebuilds the world generator with them and sends the new ID mappings to all the connected clients. Returns the new
+/// registries, the caller should update the registry resources in the game world.
+pub fn reload_content(engine: &Arc<GameServer>, directory: &Path) -> Result<Arc<GameRegistries>> {
+    let pack_directories = engine.config().borrow().server.content_packs.clone();
+    let packs = load_content_packs(&pack_directories, Some(directory))?;
+    let previous = engine.server_data.shared_registries.load_full();
+    let registries = Arc::new(content_game_registries(&packs, Some(&previous))?);
+    let generator = &engine.server_data.world_generator;
+    generator.replace(
+        generator
+            .current()
+            .with_registries(Arc::clone(&registries.biome_types), Arc::clone(&registries.block_types)),
+    );
+    engine.server_data.shared_registries.store(Arc::clone(&registries));
+
+    let net_registries = Arc::clone(&registries);
+    // Nothing to wait for, the clients that are not connected yet will get the new registries when bootstrapping.
+    let _ = engine.network_thread.schedule_task(move |state| {
+        Box::pin(async move {
+            state.borrow().broadcast_reloaded_registries(&net_registries);
+            Ok(())
+        })
+    });
+    Ok(registries)
+}
+
+/// Rebuilds the `current` registries of a client from its local content `packs` after the server reloaded its content,
+/// with the ID mappings the server sent in `server_ids`.
+pub fn rebuild_registries_with_server_ids(
+    packs: &[ContentPack],
+    current: &GameRegistries,
+    server_ids: &game_bootstrap_data::Reader,
+) -> Result<GameRegistries> {
+    // Rebuilding on top of the current registries keeps the objects removed from the content, the server still has
+    // them until it restarts.
+    let local_registries = content_game_registries(packs, Some(current))?;
+    let (registries, resolved_aliases) = local_registries.clone_with_serialized_ids(server_ids)?;
+    warn_resolved_aliases("The server", &resolved_aliases);
+    Ok(registries)
+}
+
+fn content_fingerprint(root: &Path) -> std::io::Result<ContentFingerprint> {
+    let mut fingerprint = ContentFingerprint::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                fingerprint.insert(entry.path(), (metadata.modified()?, metadata.len()));
+            }
+        }
+    }
+    Ok(fingerprint)
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::registry::RegistryNameRef;
+
+    use super::*;
+    use crate::builtin_game_registries;
+    use crate::content::base_content_pack;
+
+    const TERRAIN_FILE: &str = include_str!("../../../assets/content/base/blocks/terrain.ron");
+
+    #[test]
+    fn reloaded_content_keeps_ids() {
+        let previous = builtin_game_registries();
+        // A new block listed first would take the ID of stone in a fresh build.
+        let terrain = TERRAIN_FILE
+            .replacen('[', "[\n    (name: \"clay\", color: \"#a05a3c\"),", 1)
+            .replace("#404040", "#505050");
+        let packs = [base_content_pack().with_file("blocks/terrain.ron", terrain)];
+        let reloaded = content_game_registries(&packs, Some(&previous)).unwrap();
+
+        for (id, name, _) in previous.block_types.iter() {
+            assert_eq!(reloaded.block_types.lookup_name_to_object(name).unwrap().0, id);
+        }
+        let (stone_id, stone) = reloaded
+            .block_types
+            .lookup_name_to_object(RegistryNameRef::gs("stone"))
+            .unwrap();
+        // Definitions compare by name only, the changed color tells the objects apart.
+        let previous_stone = previous.block_types.lookup_id_to_object(stone_id).unwrap();
+        assert_ne!(previous_stone.representative_color, stone.representative_color);
+        let (clay_id, _) = reloaded
+            .block_types
+            .lookup_name_to_object(RegistryNameRef::gs("clay"))
+            .unwrap();
+        assert!(previous.block_types.iter().all(|(id, _, _)| id < clay_id));
+
+        // The biomes and items refer to the blocks by ID, they are unchanged as long as the block IDs are stable.
+        for (id, name, biome) in previous.biome_types.iter() {
+            assert!(reloaded.biome_types.lookup_name_to_object(name) == Some((id, biome)));
+        }
+        for (id, name, item) in previous.item_types.iter() {
+            assert!(reloaded.item_types.lookup_name_to_object(name) == Some((id, item)));
+        }
+    }
+
+    #[test]
+    fn client_registries_follow_server_ids() {
+        let initial = builtin_game_registries();
+        let server_terrain = TERRAIN_FILE.replacen('[', "[\n    (name: \"clay\", color: \"#a05a3c\"),", 1);
+        let server_packs = [base_content_pack().with_file("blocks/terrain.ron", server_terrain)];
+        let server = content_game_registries(&server_packs, Some(&initial)).unwrap();
+        let mut message = capnp::message::Builder::new_default();
+        server.serialize_ids(&mut message.init_root::<game_bootstrap_data::Builder>());
+        let server_ids = message.get_root_as_reader::<game_bootstrap_data::Reader>().unwrap();
+
+        // The client content lists the new blocks in another order than the server content.
+        let client_terrain = TERRAIN_FILE.replacen(
+            '[',
+            "[\n    (name: \"mud\", color: \"#4a3a2a\"),\n    (name: \"clay\", color: \"#a05a3c\"),",
+            1,
+        );
+        let client_packs = [base_content_pack().with_file("blocks/terrain.ron", client_terrain)];
+        let client = rebuild_registries_with_server_ids(&client_packs, &initial, &server_ids).unwrap();
+
+        for (id, name, _) in server.block_types.iter() {
+            assert_eq!(client.block_types.lookup_name_to_object(name).unwrap().0, id);
+        }
+        // Objects only the client defines are left out, the server cannot send them.
+        assert!(client
+            .block_types
+            .lookup_name_to_object(RegistryNameRef::gs("mud"))
+            .is_none());
+        for (id, name, _) in server.biome_types.iter() {
+            assert_eq!(client.biome_types.lookup_name_to_object(name).unwrap().0, id);
+        }
+        for (id, name, _) in server.item_types.iter() {
+            assert_eq!(client.item_types.lookup_name_to_object(name).unwrap().0, id);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Server performance diagnostics.
+//!
+//! The measurements are recorded into bevy's [`DiagnosticsStore`] under the `server/` paths defined here, so anything
+//! with world access can read them, tests included. Per-client values don't fit the fixed diagnostic paths, so they are
+//! kept in the [`ServerDiagnostics`] resource instead. [`ServerDiagnosticsReport`] combines both into a snapshot for the
+//! admin interface and the periodic log summary.
+
+use std::time::{Duration, Instant};
+
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic};
+use bevy::prelude::*;
+
+use crate::network::chunk_stream::ChunkStreamSender;
+use crate::network::server::ConnectedPlayer;
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::voxel::plugin::{PersistentVoxelStorage, VoxelUniverse};
+use crate::{GameServer, GameServerResource, ServerData};
+
+/// How often the chunk, persistence and network diagnostics are sampled, the tick duration is measured every tick.
+pub const DIAGNOSTICS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The time it took to simulate a game tick, in milliseconds.
+pub const TICK_DURATION: DiagnosticPath = DiagnosticPath::const_new("server/tick_duration");
+/// Number of chunks loaded in the server universe.
+pub const LOADED_CHUNKS: DiagnosticPath = DiagnosticPath::const_new("server/loaded_chunks");
+/// Number of chunk loads queued in the persistence layer.
+pub const PERSISTENCE_LOADS_QUEUED: DiagnosticPath = DiagnosticPath::const_new("server/persistence/loads_queued");
+/// Number of chunk saves queued in the persistence layer.
+pub const PERSISTENCE_SAVES_QUEUED: DiagnosticPath = DiagnosticPath::const_new("server/persistence/saves_queued");
+/// Number of chunk load responses waiting to be picked up from the persistence layer.
+pub const PERSISTENCE_RESPONSES_QUEUED: DiagnosticPath =
+    DiagnosticPath::const_new("server/persistence/responses_queued");
+/// Number of chunks generated per second.
+pub const CHUNK_GENERATION_RATE: DiagnosticPath = DiagnosticPath::const_new("server/chunk_generation_rate");
+/// Number of connected players.
+pub const CONNECTED_PLAYERS: DiagnosticPath = DiagnosticPath::const_new("server/connected_players");
+/// Chunk data sent to all clients, in bytes per second.
+pub const CHUNK_STREAM_BANDWIDTH: DiagnosticPath = DiagnosticPath::const_new("server/chunk_stream/bandwidth");
+/// Number of chunk packets waiting in the send queues of all clients.
+pub const CHUNK_STREAM_QUEUED_PACKETS: DiagnosticPath = DiagnosticPath::const_new("server/chunk_stream/queued_packets");
+
+/// Registers the server diagnostics and the systems measuring them.
+pub struct ServerDiagnosticsPlugin;
+
+impl Plugin for ServerDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(TICK_DURATION).with_suffix(" ms"))
+            .register_diagnostic(Diagnostic::new(LOADED_CHUNKS))
+            .register_diagnostic(Diagnostic::new(PERSISTENCE_LOADS_QUEUED))
+            .register_diagnostic(Diagnostic::new(PERSISTENCE_SAVES_QUEUED))
+            .register_diagnostic(Diagnostic::new(PERSISTENCE_RESPONSES_QUEUED))
+            .register_diagnostic(Diagnostic::new(CHUNK_GENERATION_RATE).with_suffix(" chunks/s"))
+            .register_diagnostic(Diagnostic::new(CONNECTED_PLAYERS))
+            .register_diagnostic(Diagnostic::new(CHUNK_STREAM_BANDWIDTH).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(CHUNK_STREAM_QUEUED_PACKETS))
+            .init_resource::<ServerDiagnostics>()
+            // Frames run FixedUpdate any number of times, so the tick is measured right after it's timed instead.
+            .add_systems(FixedLast, measure_tick_duration.after(GameServer::tick_timer_system))
+            .add_systems(Last, (sample_server_diagnostics, log_server_diagnostics).chain());
+    }
+}
+
+/// Network diagnostics of a single connected client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientDiagnostics {
+    /// The player nickname.
+    pub nickname: String,
+    /// The network address of the client.
+    pub address: PeerAddress,
+    /// Total chunk data sent to the client, in bytes.
+    pub sent_bytes: u64,
+    /// Chunk data sent to the client over the last sample interval, in bytes per second.
+    pub bytes_per_second: f64,
+    /// Number of chunk packets waiting in the client's send queue.
+    pub queued_packets: usize,
+    /// Number of bytes waiting in the client's send queue.
+    pub queued_bytes: usize,
+}
+
+/// The latest sampled per-client diagnostics, sorted by nickname.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ServerDiagnostics {
+    /// Diagnostics of all connected clients.
+    pub clients: Vec<ClientDiagnostics>,
+}
+
+/// A snapshot of all the server diagnostics.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerDiagnosticsReport {
+    /// The average tick duration over the diagnostic history, in milliseconds.
+    pub tick_duration_ms: f64,
+    /// The longest tick duration in the diagnostic history, in milliseconds.
+    pub max_tick_duration_ms: f64,
+    /// Number of chunks loaded in the server universe.
+    pub loaded_chunks: usize,
+    /// Number of chunk loads queued in the persistence layer.
+    pub loads_queued: usize,
+    /// Number of chunk saves queued in the persistence layer.
+    pub saves_queued: usize,
+    /// Number of chunk load responses waiting to be picked up from the persistence layer.
+    pub responses_queued: usize,
+    /// Number of chunks generated per second.
+    pub chunk_generation_rate: f64,
+    /// Diagnostics of all connected clients.
+    pub clients: Vec<ClientDiagnostics>,
+}
+
+impl ServerDiagnosticsReport {
+    /// Collects the latest diagnostics.
+    pub fn new(store: &DiagnosticsStore, server_diagnostics: &ServerDiagnostics) -> Self {
+        let latest = |path: &DiagnosticPath| store.get(path).and_then(Diagnostic::value).unwrap_or(0.0);
+        let tick = store.get(&TICK_DURATION);
+        Self {
+            tick_duration_ms: tick.and_then(Diagnostic::average).unwrap_or(0.0),
+            max_tick_duration_ms: tick.map_or(0.0, |tick| {
+                tick.measurements()
+                    .map(|measurement| measurement.value)
+                    .fold(0.0, f64::max)
+            }),
+            loaded_chunks: latest(&LOADED_CHUNKS) as usize,
+            loads_queued: latest(&PERSISTENCE_LOADS_QUEUED) as usize,
+            saves_queued: latest(&PERSISTENCE_SAVES_QUEUED) as usize,
+            responses_queued: latest(&PERSISTENCE_RESPONSES_QUEUED) as usize,
+            chunk_generation_rate: latest(&CHUNK_GENERATION_RATE),
+            clients: server_diagnostics.clients.clone(),
+        }
+    }
+
+    /// Collects the latest diagnostics from the server world.
+    pub fn from_world(world: &World) -> Self {
+        Self::new(world.resource(), world.resource())
+    }
+
+    /// A one-line human-readable summary of the diagnostics.
+    pub fn summary(&self) -> String {
+        let bandwidth: f64 = self.clients.iter().map(|client| client.bytes_per_second).sum();
+        let queued_packets: usize = self.clients.iter().map(|client| client.queued_packets).sum();
+        format!(
+            "tick {:.2} ms avg, {:.2} ms max | {} chunks loaded, {:.1} chunks/s generated | persistence queues: {} loads, {} saves, {} responses | {} players, {:.1} KiB/s chunk data, {} packets queued",
+            self.tick_duration_ms,
+            self.max_tick_duration_ms,
+            self.loaded_chunks,
+            self.chunk_generation_rate,
+            self.loads_queued,
+            self.saves_queued,
+            self.responses_queued,
+            self.clients.len(),
+            bandwidth / 1024.0,
+            queued_packets,
+        )
+    }
+}
+
+fn measure_tick_duration(engine: Res<GameServerResource>, mut diagnostics: Diagnostics) {
+    diagnostics.add_measurement(&TICK_DURATION, || engine.0.last_tick_duration().as_secs_f64() * 1000.0);
+}
+
+/// Values from the previous sample, for calculating rates.
+#[derive(Default)]
+struct DiagnosticsSampler {
+    last_sample: Option<Instant>,
+    chunks_generated: u64,
+    sent_bytes: HashMap<PeerAddress, u64>,
+}
+
+fn sample_server_diagnostics(
+    mut sampler: Local<DiagnosticsSampler>,
+    mut diagnostics: Diagnostics,
+    mut server_diagnostics: ResMut<ServerDiagnostics>,
+    voxels: Query<(&VoxelUniverse<ServerData>, &PersistentVoxelStorage<ServerData>)>,
+    players: Query<(&ConnectedPlayer, Option<&ChunkStreamSender>)>,
+) {
+    let now = Instant::now();
+    let elapsed = match sampler.last_sample {
+        Some(last) if now - last < DIAGNOSTICS_SAMPLE_INTERVAL => return,
+        Some(last) => Some((now - last).as_secs_f64()),
+        None => None,
+    };
+    sampler.last_sample = Some(now);
+
+    if let Ok((universe, storage)) = voxels.get_single() {
+        let stats = storage.persistence_stats();
+        diagnostics.add_measurement(&LOADED_CHUNKS, || universe.loaded_chunks().chunks.len() as f64);
+        diagnostics.add_measurement(&PERSISTENCE_LOADS_QUEUED, || stats.loads_queued as f64);
+        diagnostics.add_measurement(&PERSISTENCE_SAVES_QUEUED, || stats.saves_queued as f64);
+        diagnostics.add_measurement(&PERSISTENCE_RESPONSES_QUEUED, || stats.responses_queued as f64);
+        if let Some(elapsed) = elapsed {
+            let generated = stats.chunks_generated.saturating_sub(sampler.chunks_generated);
+            diagnostics.add_measurement(&CHUNK_GENERATION_RATE, || generated as f64 / elapsed);
+        }
+        sampler.chunks_generated = stats.chunks_generated;
+    }
+
+    let mut sent_bytes = HashMap::with_capacity(sampler.sent_bytes.len());
+    let mut clients = Vec::new();
+    for (player, sender) in players.iter() {
+        let stats = sender.map(ChunkStreamSender::stats);
+        let sent = stats.map_or(0, |stats| stats.sent_bytes.load(AtomicOrdering::Relaxed));
+        let bytes_per_second = match (elapsed, sampler.sent_bytes.get(&player.address)) {
+            (Some(elapsed), Some(&previous)) => sent.saturating_sub(previous) as f64 / elapsed,
+            _ => 0.0,
+        };
+        sent_bytes.insert(player.address, sent);
+        clients.push(ClientDiagnostics {
+            nickname: player.nickname.to_string(),
+            address: player.address,
+            sent_bytes: sent,
+            bytes_per_second,
+            queued_packets: stats.map_or(0, |stats| stats.queued_packets.load(AtomicOrdering::Relaxed)),
+            queued_bytes: stats.map_or(0, |stats| stats.queued_bytes.load(AtomicOrdering::Relaxed)),
+        });
+    }
+    clients.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+    sampler.sent_bytes = sent_bytes;
+
+    let bandwidth: f64 = clients.iter().map(|client| client.bytes_per_second).sum();
+    let queued_packets: usize = clients.iter().map(|client| client.queued_packets).sum();
+    diagnostics.add_measurement(&CONNECTED_PLAYERS, || clients.len() as f64);
+    diagnostics.add_measurement(&CHUNK_STREAM_BANDWIDTH, || bandwidth);
+    diagnostics.add_measurement(&CHUNK_STREAM_QUEUED_PACKETS, || queued_packets as f64);
+    server_diagnostics.clients = clients;
+}
+
+fn log_server_diagnostics(
+    mut last_log: Local<Option<Instant>>,
+    engine: Res<GameServerResource>,
+    store: Res<DiagnosticsStore>,
+    server_diagnostics: Res<ServerDiagnostics>,
+) {
+    let interval = engine.0.config().borrow().server.diagnostics_log_interval;
+    if interval == 0 {
+        return;
+    }
+    let now = Instant::now();
+    let last = *last_log.get_or_insert(now);
+    if now - last < Duration::from_secs(interval) {
+        return;
+    }
+    *last_log = Some(now);
+    info!(
+        "Server diagnostics: {}",
+        ServerDiagnosticsReport::new(&store, &server_diagnostics).summary()
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diagnostics_are_recorded() {
+        let server = GameServer::new_test();
+        server.set_paused(false);
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let report = loop {
+            let report = server
+                .schedule_bevy(|world| Ok(ServerDiagnosticsReport::from_world(world)))
+                .blocking_wait()
+                .unwrap();
+            if (report.loaded_chunks > 0 && report.tick_duration_ms > 0.0) || Instant::now() > deadline {
+                break report;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        assert!(report.loaded_chunks > 0, "{report:?}");
+        assert!(report.tick_duration_ms > 0.0, "{report:?}");
+        assert!(report.max_tick_duration_ms >= report.tick_duration_ms, "{report:?}");
+        assert!(report.clients.is_empty(), "{report:?}");
+        assert!(report.summary().contains("chunks loaded"));
+
+        server.shutdown().blocking_recv().unwrap();
+    }
+}
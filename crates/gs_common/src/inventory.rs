@@ -0,0 +1,1089 @@
+//! Entity inventories: fixed-size arrays of item stack slots, modified only through server-validated transactions.
+//!
+//! Clients never edit inventory contents directly, they ask the server to apply an [`InventoryTransaction`] moving items
+//! between two slots they have access to: the ones of their own inventory and of the inventory they are viewing (see
+//! [`InventoryViewer`]), which must be an [`InventoryContainer`]. The server applies each transaction atomically, and sends the full contents of the changed
+//! inventories to the players viewing them on the reliable inventory stream.
+
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use capnp::message::{Builder, TypedReader};
+use gs_schemas::items::{ItemRegistry, ItemStack};
+use gs_schemas::registry::RegistryId;
+use gs_schemas::schemas::game_types_capnp::inventory;
+use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::{decode_stream_packet, encode_stream_packet};
+use gs_schemas::schemas::NetworkStreamHeader;
+use gs_schemas::{GameSide, GsExtraData};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_util::bytes::Bytes;
+use tracing::Instrument;
+
+use crate::items::ItemRegistryHolder;
+use crate::network::entity_replication::{ReplicationAppExt, SerdeEncoding};
+use crate::network::server::ConnectedPlayer;
+use crate::network::thread::{NetworkThread, NetworkThreadState};
+use crate::network::transport::{InProcessStream, RPC_LOCAL_READER_OPTIONS};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::tick::ServerTick;
+use crate::InGameSystemSet;
+
+/// The number of slots in a player's inventory.
+pub const PLAYER_INVENTORY_SIZE: usize = 36;
+/// The number of player inventory slots shown in the hotbar, the first slots of the inventory.
+pub const HOTBAR_SIZE: usize = 9;
+/// The maximum number of slots of an inventory received over the network.
+pub const MAX_INVENTORY_SIZE: usize = 1024;
+/// The maximum distance from a player to an entity whose inventory it views, in blocks.
+pub const MAX_INVENTORY_VIEW_DISTANCE: f32 = 8.0;
+/// The maximum number of received inventory packets buffered on the client before applying stream backpressure.
+pub const INVENTORY_PACKET_QUEUE_LENGTH: usize = 16;
+/// The maximum number of inventory packets queued for sending to a client, further updates wait until there is room.
+pub const INVENTORY_SEND_QUEUE_LENGTH: usize = 16;
+
+static_assertions::const_assert!(HOTBAR_SIZE <= PLAYER_INVENTORY_SIZE);
+
+/// Registers the inventory synchronization systems for the given side.
+#[derive(Default)]
+pub struct InventoryPlugin<ExtraData: GsExtraData> {
+    _extra_data: PhantomData<ExtraData>,
+}
+
+impl<ExtraData: GsExtraData> InventoryPlugin<ExtraData> {
+    /// Constructor.
+    pub fn new() -> Self {
+        Self { _extra_data: default() }
+    }
+}
+
+impl<ExtraData: GsExtraData> Plugin for InventoryPlugin<ExtraData> {
+    fn build(&self, app: &mut App) {
+        app.replicate_component::<InventoryContainer, SerdeEncoding>("gs:inventory_container");
+        if ExtraData::SIDE == GameSide::Server {
+            app.add_systems(
+                FixedPostUpdate,
+                (server_system_send_inventories).in_set(InGameSystemSet),
+            );
+        } else {
+            app.init_resource::<ClientInventories>().add_systems(
+                FixedPreUpdate,
+                (client_system_apply_inventory_packets).in_set(InGameSystemSet),
+            );
+        }
+    }
+
+    fn name(&self) -> &str {
+        "common::InventoryPlugin"
+    }
+
+    fn is_unique(&self) -> bool {
+        true
+    }
+}
+
+/// Why an inventory transaction was rejected.
+#[derive(Copy, Clone, Debug, Error, Eq, PartialEq)]
+pub enum InventoryError {
+    /// The slot index is out of the inventory bounds.
+    #[error("Slot {slot} is out of bounds of an inventory with {size} slots")]
+    InvalidSlot {
+        /// The requested slot index.
+        slot: u32,
+        /// The number of slots in the inventory.
+        size: usize,
+    },
+    /// The source and target are the same slot.
+    #[error("Cannot move items into the slot they are taken from")]
+    SameSlot,
+    /// There are no items to move in the source slot.
+    #[error("The source slot is empty")]
+    EmptySource,
+    /// The target slot must hold a stack of the source item, but is empty.
+    #[error("The target slot is empty")]
+    EmptyTarget,
+    /// The target slot holds a different item than the source slot.
+    #[error("The target slot holds a different item")]
+    IncompatibleItems,
+    /// Splits must move at least one item.
+    #[error("Cannot split off zero items")]
+    ZeroCount,
+    /// The source stack is smaller than the requested number of items.
+    #[error("Cannot take {requested} items from a stack of {available}")]
+    NotEnoughItems {
+        /// The number of items to move.
+        requested: u32,
+        /// The number of items in the source stack.
+        available: u32,
+    },
+    /// The target stack has no room for the moved items.
+    #[error("The target stack has no room for the items")]
+    TargetFull,
+    /// The item is not in the item registry.
+    #[error("Unknown item ID {0:?}")]
+    UnknownItem(RegistryId),
+    /// The player cannot access the inventory.
+    #[error("The inventory is not accessible")]
+    NotAccessible,
+}
+
+/// A fixed number of slots, each holding an optional stack of items.
+#[derive(Component, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+}
+
+impl Inventory {
+    /// Constructs an inventory with the given number of empty slots.
+    pub fn new(size: usize) -> Self {
+        Self {
+            slots: vec![None; size],
+        }
+    }
+
+    /// The number of slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Checks if the inventory has no slots at all.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// All the slots, in order.
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    /// The stack in the given slot, if the slot exists and is not empty.
+    pub fn get(&self, slot: usize) -> Option<&ItemStack> {
+        self.slots.get(slot).and_then(Option::as_ref)
+    }
+
+    /// Replaces the contents of a slot, returns the previous contents. Empty stacks are stored as empty slots.
+    pub fn set(&mut self, slot: usize, stack: Option<ItemStack>) -> Result<Option<ItemStack>, InventoryError> {
+        let size = self.len();
+        let Some(target) = self.slots.get_mut(slot) else {
+            return Err(InventoryError::InvalidSlot {
+                slot: slot as u32,
+                size,
+            });
+        };
+        Ok(std::mem::replace(target, stack.filter(|s| !s.is_empty())))
+    }
+
+    fn check_slot(&self, slot: u32) -> Result<usize, InventoryError> {
+        let index = slot as usize;
+        if index < self.len() {
+            Ok(index)
+        } else {
+            Err(InventoryError::InvalidSlot { slot, size: self.len() })
+        }
+    }
+
+    /// Atomically applies the action to two slots of this inventory.
+    pub fn transfer(
+        &mut self,
+        from: u32,
+        to: u32,
+        action: InventoryAction,
+        items: &ItemRegistry,
+    ) -> Result<(), InventoryError> {
+        let (from, to) = (self.check_slot(from)?, self.check_slot(to)?);
+        if from == to {
+            return Err(InventoryError::SameSlot);
+        }
+        let (source, target) = action.apply(self.slots[from].as_ref(), self.slots[to].as_ref(), items)?;
+        self.slots[from] = source;
+        self.slots[to] = target;
+        Ok(())
+    }
+
+    /// Atomically applies the action to a slot of this inventory and a slot of another one.
+    pub fn transfer_to(
+        &mut self,
+        from: u32,
+        target_inventory: &mut Inventory,
+        to: u32,
+        action: InventoryAction,
+        items: &ItemRegistry,
+    ) -> Result<(), InventoryError> {
+        let (from, to) = (self.check_slot(from)?, target_inventory.check_slot(to)?);
+        let (source, target) = action.apply(self.slots[from].as_ref(), target_inventory.slots[to].as_ref(), items)?;
+        self.slots[from] = source;
+        target_inventory.slots[to] = target;
+        Ok(())
+    }
+
+    /// Serializes the inventory into a capnp message.
+    pub fn write_to_message(&self, builder: inventory::Builder) {
+        let mut slots = builder.init_slots(self.slots.len() as u32);
+        for (i, stack) in self.slots.iter().enumerate() {
+            if let Some(stack) = stack {
+                stack.write_to_message(&mut slots.reborrow().get(i as u32));
+            }
+        }
+    }
+
+    /// Deserializes an inventory from a capnp message.
+    pub fn read_from_message(reader: inventory::Reader) -> Result<Self> {
+        let slots = reader.get_slots()?;
+        ensure!(
+            slots.len() as usize <= MAX_INVENTORY_SIZE,
+            "Inventory with {} slots exceeds the limit of {MAX_INVENTORY_SIZE}",
+            slots.len()
+        );
+        let slots = slots
+            .iter()
+            .map(|stack| {
+                if stack.get_item() == 0 || stack.get_count() == 0 {
+                    Ok(None)
+                } else {
+                    ItemStack::read_from_message(&stack).map(Some)
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { slots })
+    }
+}
+
+/// The initial inventory of a newly connected player: a full stack of every placeable item, in registry order.
+pub fn starting_player_inventory(items: &ItemRegistry) -> Inventory {
+    let mut inventory = Inventory::new(PLAYER_INVENTORY_SIZE);
+    let placeable = items.iter().filter(|(_, _, item)| item.block.is_some());
+    for (slot, (id, _, item)) in inventory.slots.iter_mut().zip(placeable) {
+        *slot = Some(ItemStack::new(id, item.max_stack_size));
+    }
+    inventory
+}
+
+/// How the items are moved between the two slots of a transaction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InventoryAction {
+    /// Moves the whole source stack, merging it into a stack of the same item or swapping it with a different one.
+    Move,
+    /// Moves `count` items of the source stack into an empty slot or a stack of the same item.
+    Split {
+        /// The number of items to move.
+        count: u32,
+    },
+    /// Moves as many items of the source stack as fit into a stack of the same item.
+    Merge,
+}
+
+impl InventoryAction {
+    /// Computes the new contents of the source and target slots, without modifying anything.
+    pub fn apply(
+        self,
+        source: Option<&ItemStack>,
+        target: Option<&ItemStack>,
+        items: &ItemRegistry,
+    ) -> Result<(Option<ItemStack>, Option<ItemStack>), InventoryError> {
+        let Some(source) = source else {
+            return Err(InventoryError::EmptySource);
+        };
+        let Some(definition) = items.lookup_id_to_object(source.item) else {
+            return Err(InventoryError::UnknownItem(source.item));
+        };
+        let max_stack_size = definition.max_stack_size;
+        let room = |target: &ItemStack| max_stack_size.saturating_sub(target.count);
+        // Moves `count` items of the source onto the target, which must be empty or hold the same item.
+        let moved = |count: u32, target: Option<&ItemStack>| {
+            let remaining = source.count - count;
+            let new_source = (remaining > 0).then(|| ItemStack {
+                count: remaining,
+                ..source.clone()
+            });
+            let new_target = ItemStack {
+                count: target.map_or(0, |t| t.count) + count,
+                ..source.clone()
+            };
+            (new_source, Some(new_target))
+        };
+
+        match (self, target) {
+            (InventoryAction::Move, None) => Ok((None, Some(source.clone()))),
+            (InventoryAction::Move, Some(target)) if !target.is_same_item(source) => {
+                Ok((Some(target.clone()), Some(source.clone())))
+            }
+            (InventoryAction::Move, Some(target)) => {
+                if room(target) < source.count {
+                    return Err(InventoryError::TargetFull);
+                }
+                Ok(moved(source.count, Some(target)))
+            }
+            (InventoryAction::Split { count: 0 }, _) => Err(InventoryError::ZeroCount),
+            (InventoryAction::Split { count }, _) if count > source.count => Err(InventoryError::NotEnoughItems {
+                requested: count,
+                available: source.count,
+            }),
+            (InventoryAction::Split { count }, target) => {
+                if let Some(target) = target {
+                    if !target.is_same_item(source) {
+                        return Err(InventoryError::IncompatibleItems);
+                    }
+                    if room(target) < count {
+                        return Err(InventoryError::TargetFull);
+                    }
+                } else if count > max_stack_size {
+                    return Err(InventoryError::TargetFull);
+                }
+                Ok(moved(count, target))
+            }
+            (InventoryAction::Merge, None) => Err(InventoryError::EmptyTarget),
+            (InventoryAction::Merge, Some(target)) => {
+                if !target.is_same_item(source) {
+                    return Err(InventoryError::IncompatibleItems);
+                }
+                match room(target).min(source.count) {
+                    0 => Err(InventoryError::TargetFull),
+                    count => Ok(moved(count, Some(target))),
+                }
+            }
+        }
+    }
+}
+
+/// The inventory a transaction slot belongs to, from the point of view of the player requesting it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum InventoryRef {
+    /// The player's own inventory.
+    Own,
+    /// The inventory of the entity with the given server-side ID, which the player must be viewing.
+    Entity(u64),
+}
+
+impl InventoryRef {
+    /// The ID used on the wire, 0 for the player's own inventory.
+    pub fn to_wire_id(self) -> u64 {
+        match self {
+            InventoryRef::Own => 0,
+            InventoryRef::Entity(id) => id,
+        }
+    }
+
+    /// Inverse of [`Self::to_wire_id`].
+    pub fn from_wire_id(id: u64) -> Self {
+        match id {
+            0 => InventoryRef::Own,
+            id => InventoryRef::Entity(id),
+        }
+    }
+}
+
+/// A slot of an inventory accessible to the player.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SlotRef {
+    /// The inventory holding the slot.
+    pub inventory: InventoryRef,
+    /// The slot index.
+    pub slot: u32,
+}
+
+impl SlotRef {
+    /// A slot of the player's own inventory.
+    pub fn own(slot: u32) -> Self {
+        Self {
+            inventory: InventoryRef::Own,
+            slot,
+        }
+    }
+
+    fn write_to_message(&self, mut builder: rpc::inventory_slot::Builder) {
+        builder.set_entity_id(self.inventory.to_wire_id());
+        builder.set_slot(self.slot);
+    }
+
+    fn read_from_message(reader: rpc::inventory_slot::Reader) -> Self {
+        Self {
+            inventory: InventoryRef::from_wire_id(reader.get_entity_id()),
+            slot: reader.get_slot(),
+        }
+    }
+}
+
+/// A request to atomically move items between two slots, see [`InventoryAction`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct InventoryTransaction {
+    /// The slot the items are taken from.
+    pub from: SlotRef,
+    /// The slot the items are put into.
+    pub to: SlotRef,
+    /// How the items are moved.
+    pub action: InventoryAction,
+}
+
+impl InventoryTransaction {
+    /// Serializes the transaction into a capnp message.
+    pub fn write_to_message(&self, mut builder: rpc::inventory_transaction::Builder) {
+        self.from.write_to_message(builder.reborrow().init_from());
+        self.to.write_to_message(builder.reborrow().init_to());
+        let (action, count) = match self.action {
+            InventoryAction::Move => (rpc::InventoryAction::Move, 0),
+            InventoryAction::Split { count } => (rpc::InventoryAction::Split, count),
+            InventoryAction::Merge => (rpc::InventoryAction::Merge, 0),
+        };
+        builder.set_action(action);
+        builder.set_count(count);
+    }
+
+    /// Deserializes a transaction from a capnp message.
+    pub fn read_from_message(reader: rpc::inventory_transaction::Reader) -> Result<Self> {
+        let action = match reader.get_action()? {
+            rpc::InventoryAction::Move => InventoryAction::Move,
+            rpc::InventoryAction::Split => InventoryAction::Split {
+                count: reader.get_count(),
+            },
+            rpc::InventoryAction::Merge => InventoryAction::Merge,
+        };
+        Ok(Self {
+            from: SlotRef::read_from_message(reader.get_from()?),
+            to: SlotRef::read_from_message(reader.get_to()?),
+            action,
+        })
+    }
+}
+
+/// Marks entities whose [`Inventory`] players can open, like chests. Replicated, so clients know what they can open.
+#[derive(Component, Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InventoryContainer;
+
+/// The other entity whose inventory a player has open, attached to [`ConnectedPlayer`] entities.
+#[derive(Component, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InventoryViewer {
+    /// The viewed entity, closed automatically when it moves out of reach or loses its inventory.
+    pub viewing: Option<Entity>,
+}
+
+/// Checks if the player can view the inventory of the given entity: it must be an [`InventoryContainer`] with an
+/// inventory within reach, and not a player.
+pub fn can_view_inventory(world: &World, player: Entity, target: Entity) -> bool {
+    let Some(target) = world.get_entity(target) else {
+        return false;
+    };
+    if player == target.id()
+        || !target.contains::<InventoryContainer>()
+        || !target.contains::<Inventory>()
+        || target.contains::<ConnectedPlayer>()
+    {
+        return false;
+    }
+    let (Some(player), Some(target)) = (world.get::<Transform>(player), target.get::<Transform>()) else {
+        return false;
+    };
+    in_view_distance(player, target)
+}
+
+fn in_view_distance(player: &Transform, target: &Transform) -> bool {
+    player.translation.distance_squared(target.translation) <= MAX_INVENTORY_VIEW_DISTANCE.powi(2)
+}
+
+/// Resolves a transaction slot to the entity holding the inventory, checking that the player can access it.
+fn resolve_inventory(world: &World, player: Entity, inventory: InventoryRef) -> Result<Entity, InventoryError> {
+    match inventory {
+        InventoryRef::Own => Ok(player),
+        InventoryRef::Entity(id) => {
+            let viewing = world.get::<InventoryViewer>(player).and_then(|viewer| viewer.viewing);
+            match viewing {
+                Some(entity) if entity.to_bits() == id && can_view_inventory(world, player, entity) => Ok(entity),
+                _ => Err(InventoryError::NotAccessible),
+            }
+        }
+    }
+}
+
+/// Atomically applies a transaction requested by the given player, if it only touches inventories the player can
+/// access.
+pub fn apply_player_transaction(
+    world: &mut World,
+    player: Entity,
+    transaction: &InventoryTransaction,
+) -> Result<Result<(), InventoryError>> {
+    let items = Arc::clone(&world.resource::<ItemRegistryHolder>().0);
+    let source = match resolve_inventory(world, player, transaction.from.inventory) {
+        Ok(entity) => entity,
+        Err(e) => return Ok(Err(e)),
+    };
+    let target = match resolve_inventory(world, player, transaction.to.inventory) {
+        Ok(entity) => entity,
+        Err(e) => return Ok(Err(e)),
+    };
+    let (from, to, action) = (transaction.from.slot, transaction.to.slot, transaction.action);
+    // Only mark the inventories as changed if the transaction succeeds.
+    if source == target {
+        let Some(mut inventory) = world.get_mut::<Inventory>(source) else {
+            bail!("Entity {source:?} has no inventory");
+        };
+        let result = inventory.bypass_change_detection().transfer(from, to, action, &items);
+        if result.is_ok() {
+            inventory.set_changed();
+        }
+        return Ok(result);
+    }
+    let mut inventories = world.query::<&mut Inventory>();
+    let [mut source, mut target] = inventories.get_many_mut(world, [source, target])?;
+    let result =
+        source
+            .bypass_change_detection()
+            .transfer_to(from, target.bypass_change_detection(), to, action, &items);
+    if result.is_ok() {
+        source.set_changed();
+        target.set_changed();
+    }
+    Ok(result)
+}
+
+/// The game-side handle for sending inventory contents to a connected player, attached to [`ConnectedPlayer`] entities.
+#[derive(Component)]
+pub struct InventorySender {
+    queue: AsyncBoundedSender<Bytes>,
+    compression: PacketCompression,
+    /// If the client may have outdated contents, because nothing was sent yet or the last packet didn't fit the queue.
+    resend: bool,
+    /// The viewed inventory the client has the contents of.
+    sent_view: Option<Entity>,
+}
+
+impl InventorySender {
+    fn new(queue: AsyncBoundedSender<Bytes>, compression: PacketCompression) -> Self {
+        Self {
+            queue,
+            compression,
+            resend: true,
+            sent_view: None,
+        }
+    }
+
+    /// A sender with no network task, returning the receiving end of its queue.
+    #[cfg(test)]
+    pub(crate) fn new_test(queue_length: usize) -> (Self, AsyncBoundedReceiver<Bytes>) {
+        let (queue_tx, queue_rx) = async_bounded_channel(queue_length);
+        (Self::new(queue_tx, PacketCompression::None), queue_rx)
+    }
+}
+
+/// Creates the game-side sender and spawns the network task forwarding its packets into `stream`.
+/// Must be called within the tokio LocalSet.
+pub fn spawn_inventory_stream(
+    peer: PeerAddress,
+    stream: InProcessStream,
+    compression: PacketCompression,
+) -> InventorySender {
+    let (queue_tx, mut queue_rx) = async_bounded_channel::<Bytes>(INVENTORY_SEND_QUEUE_LENGTH);
+    let task = async move {
+        // The queue closes when the player entity (holding the sender) is despawned.
+        while let Some(packet) = queue_rx.recv().await {
+            if stream.tx.send(packet).await.is_err() {
+                debug!("Inventory stream to {peer:?} was closed by the client");
+                return;
+            }
+        }
+    };
+    tokio::task::spawn_local(task.instrument(tracing::info_span!("server-inventory-stream", address = ?peer)));
+    InventorySender::new(queue_tx, compression)
+}
+
+fn server_system_send_inventories(
+    tick: Res<ServerTick>,
+    mut players: Query<
+        (
+            Entity,
+            &mut InventorySender,
+            &mut InventoryViewer,
+            Ref<Inventory>,
+            &Transform,
+        ),
+        With<ConnectedPlayer>,
+    >,
+    containers: Query<(Ref<Inventory>, &Transform), (With<InventoryContainer>, Without<ConnectedPlayer>)>,
+) {
+    for (player, mut sender, mut viewer, own, player_transform) in players.iter_mut() {
+        let mut updated: Vec<(u64, &Inventory)> = Vec::new();
+        let mut closed = Vec::new();
+        if sender.resend || own.is_changed() {
+            updated.push((InventoryRef::Own.to_wire_id(), own.into_inner()));
+        }
+
+        let viewed = viewer.viewing.and_then(|entity| {
+            let (inventory, transform) = containers.get(entity).ok()?;
+            in_view_distance(player_transform, transform).then_some((entity, inventory))
+        });
+        if viewer.viewing.is_some() && viewed.is_none() {
+            viewer.viewing = None;
+        }
+        let viewed_entity = viewed.as_ref().map(|(entity, _)| *entity);
+        if sender.sent_view != viewed_entity {
+            if let Some(old) = sender.sent_view {
+                closed.push(old.to_bits());
+            }
+        }
+        if let Some((entity, inventory)) = viewed {
+            if sender.resend || sender.sent_view != Some(entity) || inventory.is_changed() {
+                updated.push((entity.to_bits(), inventory.into_inner()));
+            }
+        }
+
+        if updated.is_empty() && closed.is_empty() {
+            continue;
+        }
+        let mut message = Builder::new_default();
+        let mut packet = message.init_root::<rpc::inventory_packet::Builder>();
+        packet.set_tick(tick.0);
+        let mut updated_list = packet.reborrow().init_updated(updated.len() as u32);
+        for (i, (id, inventory)) in updated.iter().enumerate() {
+            let mut update = updated_list.reborrow().get(i as u32);
+            update.set_entity_id(*id);
+            inventory.write_to_message(update.init_contents());
+        }
+        let mut closed_list = packet.init_closed(closed.len() as u32);
+        for (i, &id) in closed.iter().enumerate() {
+            closed_list.set(i as u32, id);
+        }
+        let packet = encode_stream_packet(&message, sender.compression);
+        match sender.queue.try_send(Bytes::from(packet)) {
+            Ok(()) => {
+                sender.resend = false;
+                sender.sent_view = viewed_entity;
+            }
+            // The whole contents are sent again once the client catches up.
+            Err(TrySendError::Full(_)) => sender.resend = true,
+            Err(TrySendError::Closed(_)) => trace!("Skipping inventory update to a closed stream of {player:?}"),
+        }
+    }
+}
+
+/// The receiving end of the inventory stream on the client.
+#[derive(Resource)]
+pub struct InventoryPacketReceiver(AsyncBoundedReceiver<Bytes>);
+
+/// Registers the inventory stream handler, returns the receiver of its packets to insert into the world.
+pub fn register_inventory_stream_handler<NS: NetworkThreadState>(
+    net_thread: &NetworkThread<NS>,
+) -> InventoryPacketReceiver {
+    let (tx, rx) = async_bounded_channel(INVENTORY_PACKET_QUEUE_LENGTH);
+    net_thread.insert_stream_handler(
+        NetworkStreamHeader::Standard(StandardTypes::Inventory),
+        Box::new(move |_state, _peer, stream| {
+            let tx: AsyncBoundedSender<Bytes> = tx.clone();
+            Box::pin(async move {
+                let InProcessStream { mut rx, .. } = stream;
+                while let Some(raw_packet) = rx.recv().await {
+                    if let Err(e) = tx.send(raw_packet).await {
+                        error!("Error while queueing inventory packet: {e}");
+                        break;
+                    }
+                }
+            })
+        }),
+    );
+    InventoryPacketReceiver(rx)
+}
+
+/// The latest inventory contents received from the server.
+#[derive(Resource, Default, Debug)]
+pub struct ClientInventories {
+    own: Option<Inventory>,
+    viewed: Option<(u64, Inventory)>,
+}
+
+impl ClientInventories {
+    /// The local player's inventory, once received.
+    pub fn own(&self) -> Option<&Inventory> {
+        self.own.as_ref()
+    }
+
+    /// The server-side entity ID and inventory of the entity the player is viewing, if any.
+    pub fn viewed(&self) -> Option<(u64, &Inventory)> {
+        self.viewed.as_ref().map(|(id, inventory)| (*id, inventory))
+    }
+
+    /// The contents of the inventory of a transaction slot, if known.
+    pub fn get(&self, inventory: InventoryRef) -> Option<&Inventory> {
+        match inventory {
+            InventoryRef::Own => self.own(),
+            InventoryRef::Entity(id) => self.viewed().filter(|(viewed, _)| *viewed == id).map(|(_, i)| i),
+        }
+    }
+
+    fn apply_packet(&mut self, raw_packet: &[u8]) -> Result<()> {
+        let message = decode_stream_packet(raw_packet, RPC_LOCAL_READER_OPTIONS)?;
+        let message = TypedReader::<_, rpc::inventory_packet::Owned>::new(message);
+        let packet = message.get()?;
+        for id in packet.get_closed()? {
+            if self.viewed.as_ref().is_some_and(|(viewed, _)| *viewed == id) {
+                self.viewed = None;
+            }
+        }
+        for update in packet.get_updated()? {
+            let inventory = Inventory::read_from_message(update.get_contents()?)?;
+            match InventoryRef::from_wire_id(update.get_entity_id()) {
+                InventoryRef::Own => self.own = Some(inventory),
+                InventoryRef::Entity(id) => self.viewed = Some((id, inventory)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forgets the received inventories, used when leaving a game.
+pub fn reset_client_inventories(world: &mut World) {
+    if let Some(mut inventories) = world.get_resource_mut::<ClientInventories>() {
+        *inventories = default();
+    }
+    world.remove_resource::<InventoryPacketReceiver>();
+}
+
+fn client_system_apply_inventory_packets(
+    receiver: Option<ResMut<InventoryPacketReceiver>>,
+    mut inventories: ResMut<ClientInventories>,
+) {
+    let Some(mut receiver) = receiver else {
+        return;
+    };
+    for _ in 0..INVENTORY_PACKET_QUEUE_LENGTH {
+        let Ok(raw_packet) = receiver.0.try_recv() else {
+            break;
+        };
+        if let Err(e) = inventories.apply_packet(&raw_packet) {
+            error!("Error while processing received inventory packet: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::items::{ItemData, ItemDefinition};
+    use gs_schemas::registry::RegistryName;
+
+    use super::*;
+
+    fn items() -> (ItemRegistry, RegistryId, RegistryId) {
+        let mut registry = ItemRegistry::default();
+        let mut push = |name, max_stack_size| {
+            registry
+                .push_object(ItemDefinition {
+                    name: RegistryName::gs(name),
+                    max_stack_size,
+                    block: None,
+                })
+                .unwrap()
+        };
+        let stone = push("stone", 64);
+        let gem = push("gem", 16);
+        (registry, stone, gem)
+    }
+
+    fn inventory(slots: &[Option<ItemStack>]) -> Inventory {
+        Inventory { slots: slots.to_vec() }
+    }
+
+    #[test]
+    fn move_swap_and_merge() {
+        let (items, stone, gem) = items();
+        let mut inv = inventory(&[Some(ItemStack::new(stone, 10)), None, Some(ItemStack::new(gem, 3))]);
+
+        inv.transfer(0, 1, InventoryAction::Move, &items).unwrap();
+        assert_eq!(
+            inv.slots(),
+            &[None, Some(ItemStack::new(stone, 10)), Some(ItemStack::new(gem, 3))]
+        );
+
+        inv.transfer(1, 2, InventoryAction::Move, &items).unwrap();
+        assert_eq!(
+            inv.slots(),
+            &[None, Some(ItemStack::new(gem, 3)), Some(ItemStack::new(stone, 10))]
+        );
+
+        inv.set(0, Some(ItemStack::new(gem, 5))).unwrap();
+        inv.transfer(0, 1, InventoryAction::Move, &items).unwrap();
+        assert_eq!(inv.get(0), None);
+        assert_eq!(inv.get(1), Some(&ItemStack::new(gem, 8)));
+
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Move, &items),
+            Err(InventoryError::EmptySource)
+        );
+        assert_eq!(
+            inv.transfer(1, 1, InventoryAction::Move, &items),
+            Err(InventoryError::SameSlot)
+        );
+        assert_eq!(
+            inv.transfer(1, 3, InventoryAction::Move, &items),
+            Err(InventoryError::InvalidSlot { slot: 3, size: 3 })
+        );
+    }
+
+    #[test]
+    fn failed_transactions_change_nothing() {
+        let (items, stone, gem) = items();
+        let original = inventory(&[Some(ItemStack::new(gem, 10)), Some(ItemStack::new(gem, 12))]);
+        let mut inv = original.clone();
+        // 10 + 12 gems overflow the stack size of 16, so the whole move is rejected.
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Move, &items),
+            Err(InventoryError::TargetFull)
+        );
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Split { count: 5 }, &items),
+            Err(InventoryError::TargetFull)
+        );
+        assert_eq!(inv, original);
+
+        let mut other = inventory(&[Some(ItemStack::new(stone, 1))]);
+        assert_eq!(
+            inv.transfer_to(0, &mut other, 0, InventoryAction::Merge, &items),
+            Err(InventoryError::IncompatibleItems)
+        );
+        assert_eq!(
+            inv.transfer_to(0, &mut other, 1, InventoryAction::Move, &items),
+            Err(InventoryError::InvalidSlot { slot: 1, size: 1 })
+        );
+        assert_eq!(inv, original);
+        assert_eq!(other.get(0), Some(&ItemStack::new(stone, 1)));
+
+        let unknown = RegistryId::try_from(100).unwrap();
+        inv.set(0, Some(ItemStack::new(unknown, 1))).unwrap();
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Move, &items),
+            Err(InventoryError::UnknownItem(unknown))
+        );
+    }
+
+    #[test]
+    fn split_stacks() {
+        let (items, stone, gem) = items();
+        let mut inv = inventory(&[Some(ItemStack::new(stone, 10)), None, Some(ItemStack::new(gem, 1))]);
+
+        inv.transfer(0, 1, InventoryAction::Split { count: 4 }, &items).unwrap();
+        assert_eq!(inv.get(0), Some(&ItemStack::new(stone, 6)));
+        assert_eq!(inv.get(1), Some(&ItemStack::new(stone, 4)));
+
+        inv.transfer(1, 0, InventoryAction::Split { count: 4 }, &items).unwrap();
+        assert_eq!(inv.get(0), Some(&ItemStack::new(stone, 10)));
+        assert_eq!(inv.get(1), None);
+
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Split { count: 0 }, &items),
+            Err(InventoryError::ZeroCount)
+        );
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Split { count: 11 }, &items),
+            Err(InventoryError::NotEnoughItems {
+                requested: 11,
+                available: 10
+            })
+        );
+        assert_eq!(
+            inv.transfer(0, 2, InventoryAction::Split { count: 1 }, &items),
+            Err(InventoryError::IncompatibleItems)
+        );
+    }
+
+    #[test]
+    fn merge_partial_stacks() {
+        let (items, _, gem) = items();
+        let mut inv = inventory(&[Some(ItemStack::new(gem, 10)), Some(ItemStack::new(gem, 12)), None]);
+
+        inv.transfer(0, 1, InventoryAction::Merge, &items).unwrap();
+        assert_eq!(inv.get(0), Some(&ItemStack::new(gem, 6)));
+        assert_eq!(inv.get(1), Some(&ItemStack::new(gem, 16)));
+
+        assert_eq!(
+            inv.transfer(0, 1, InventoryAction::Merge, &items),
+            Err(InventoryError::TargetFull)
+        );
+        assert_eq!(
+            inv.transfer(0, 2, InventoryAction::Merge, &items),
+            Err(InventoryError::EmptyTarget)
+        );
+
+        // Stacks with different complex data never merge.
+        let data = ItemData::from_bytes(vec![0xc3]);
+        inv.set(2, Some(ItemStack::new(gem, 1).with_data(data))).unwrap();
+        assert_eq!(
+            inv.transfer(0, 2, InventoryAction::Merge, &items),
+            Err(InventoryError::IncompatibleItems)
+        );
+    }
+
+    #[test]
+    fn serialize_inventories() {
+        let (items, stone, _) = items();
+        let mut inv = starting_player_inventory(&items);
+        assert_eq!(inv.len(), PLAYER_INVENTORY_SIZE);
+        assert!(inv.slots().iter().all(Option::is_none), "No items place blocks");
+        inv.set(3, Some(ItemStack::new(stone, 7))).unwrap();
+        inv.set(4, Some(ItemStack::new(stone, 0))).unwrap();
+        assert_eq!(inv.get(4), None);
+
+        let mut message = Builder::new_default();
+        inv.write_to_message(message.init_root());
+        let read = Inventory::read_from_message(message.get_root_as_reader().unwrap()).unwrap();
+        assert_eq!(read, inv);
+
+        let transaction = InventoryTransaction {
+            from: SlotRef::own(3),
+            to: SlotRef {
+                inventory: InventoryRef::Entity(42),
+                slot: 1,
+            },
+            action: InventoryAction::Split { count: 2 },
+        };
+        let mut message = Builder::new_default();
+        transaction.write_to_message(message.init_root());
+        let read = InventoryTransaction::read_from_message(message.get_root_as_reader().unwrap()).unwrap();
+        assert_eq!(read, transaction);
+    }
+
+    fn spawn_player(world: &mut World, position: Vec3) -> Entity {
+        let address = PeerAddress::Local(world.entities().len() as i32);
+        world
+            .spawn((
+                ConnectedPlayer {
+                    nickname: format!("{address:?}").into(),
+                    address,
+                },
+                Transform::from_translation(position),
+                Inventory::new(2),
+                InventoryViewer::default(),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn only_containers_can_be_viewed() {
+        let mut world = World::new();
+        let player = spawn_player(&mut world, Vec3::ZERO);
+        let other_player = spawn_player(&mut world, Vec3::X);
+        let chest = world
+            .spawn((
+                InventoryContainer,
+                Inventory::new(2),
+                Transform::from_xyz(0.0, 1.0, 0.0),
+            ))
+            .id();
+        let far_chest = world
+            .spawn((
+                InventoryContainer,
+                Inventory::new(2),
+                Transform::from_xyz(0.0, 20.0, 0.0),
+            ))
+            .id();
+        let not_container = world
+            .spawn((Inventory::new(2), Transform::from_xyz(1.0, 0.0, 0.0)))
+            .id();
+        // Even a player marked as a container must not expose its inventory.
+        world.entity_mut(other_player).insert(InventoryContainer);
+
+        assert!(can_view_inventory(&world, player, chest));
+        assert!(!can_view_inventory(&world, player, far_chest));
+        assert!(!can_view_inventory(&world, player, not_container));
+        assert!(!can_view_inventory(&world, player, other_player));
+        assert!(!can_view_inventory(&world, player, player));
+    }
+
+    #[test]
+    fn failed_own_transactions_are_not_marked_changed() {
+        let (items, stone, _) = items();
+        let mut world = World::new();
+        world.insert_resource(ItemRegistryHolder(Arc::new(items.freeze())));
+        let player = spawn_player(&mut world, Vec3::ZERO);
+        world
+            .get_mut::<Inventory>(player)
+            .unwrap()
+            .set(0, Some(ItemStack::new(stone, 3)))
+            .unwrap();
+        let last_run = world.change_tick();
+        world.increment_change_tick();
+        let is_changed = |world: &World| {
+            let ticks = world.entity(player).get_change_ticks::<Inventory>().unwrap();
+            ticks.is_changed(last_run, world.read_change_tick())
+        };
+
+        let invalid = InventoryTransaction {
+            from: SlotRef::own(1),
+            to: SlotRef::own(0),
+            action: InventoryAction::Move,
+        };
+        assert_eq!(
+            apply_player_transaction(&mut world, player, &invalid).unwrap(),
+            Err(InventoryError::EmptySource)
+        );
+        assert!(!is_changed(&world));
+
+        let valid = InventoryTransaction {
+            from: SlotRef::own(0),
+            to: SlotRef::own(1),
+            action: InventoryAction::Move,
+        };
+        apply_player_transaction(&mut world, player, &valid).unwrap().unwrap();
+        assert!(is_changed(&world));
+    }
+
+    /// The updated and closed inventory IDs of each queued packet, with the updated IDs sorted.
+    fn received_packets(receiver: &mut AsyncBoundedReceiver<Bytes>) -> Vec<(Vec<u64>, Vec<u64>)> {
+        let mut packets = Vec::new();
+        while let Ok(raw_packet) = receiver.try_recv() {
+            let message = decode_stream_packet(&raw_packet, RPC_LOCAL_READER_OPTIONS).unwrap();
+            let message = TypedReader::<_, rpc::inventory_packet::Owned>::new(message);
+            let packet = message.get().unwrap();
+            let mut updated = packet
+                .get_updated()
+                .unwrap()
+                .iter()
+                .map(|update| update.get_entity_id())
+                .collect::<Vec<_>>();
+            updated.sort();
+            packets.push((updated, packet.get_closed().unwrap().iter().collect()));
+        }
+        packets
+    }
+
+    #[test]
+    fn send_viewed_inventories() {
+        let mut app = App::new();
+        app.insert_resource(ServerTick(1))
+            .add_systems(Update, server_system_send_inventories);
+        let player = spawn_player(app.world_mut(), Vec3::ZERO);
+        let (sender, mut receiver) = InventorySender::new_test(1);
+        app.world_mut().entity_mut(player).insert(sender);
+        let chest = app
+            .world_mut()
+            .spawn((
+                InventoryContainer,
+                Inventory::new(4),
+                Transform::from_xyz(2.0, 0.0, 0.0),
+            ))
+            .id();
+        let chest_id = chest.to_bits();
+
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![(vec![0], vec![])]);
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![]);
+
+        app.world_mut().get_mut::<InventoryViewer>(player).unwrap().viewing = Some(chest);
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![(vec![chest_id], vec![])]);
+
+        // While the queue is full the changes are held back, then all the viewed contents are sent again.
+        app.world_mut().get_mut::<Inventory>(chest).unwrap().set_changed();
+        app.update();
+        app.world_mut().get_mut::<Inventory>(player).unwrap().set_changed();
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![(vec![chest_id], vec![])]);
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![(vec![0, chest_id], vec![])]);
+
+        // Moving out of reach closes the view.
+        app.world_mut().get_mut::<Transform>(chest).unwrap().translation.x = 20.0;
+        app.update();
+        assert_eq!(received_packets(&mut receiver), vec![(vec![], vec![chest_id])]);
+        assert_eq!(app.world().get::<InventoryViewer>(player).unwrap().viewing, None);
+    }
+}
@@ -0,0 +1,68 @@
+//! The builtin item types, defined in the base content pack.
+
+use bevy::prelude::{Deref, Resource};
+use gs_schemas::content::{ContentError, ContentPack};
+use gs_schemas::items::{load_item_definitions, FrozenItemRegistry, ItemRegistry, ITEMS_DIRECTORY};
+use gs_schemas::registry::RegistryName;
+use gs_schemas::registry_aliases::load_alias_definitions;
+use gs_schemas::registry_tags::load_tag_definitions;
+use gs_schemas::voxel::voxeltypes::BlockRegistry;
+
+use crate::prelude::*;
+
+/// Registry name for the stone block item.
+pub const STONE_ITEM_NAME: RegistryName = RegistryName::gs_const("stone");
+/// Registry name for the dirt block item.
+pub const DIRT_ITEM_NAME: RegistryName = RegistryName::gs_const("dirt");
+/// Registry name for the grass block item.
+pub const GRASS_ITEM_NAME: RegistryName = RegistryName::gs_const("grass");
+/// Registry name for the sand block item.
+pub const SAND_ITEM_NAME: RegistryName = RegistryName::gs_const("sand");
+
+/// The bevy [`Resource`] for shared item registry access from systems.
+#[derive(Resource, Clone, Deref)]
+pub struct ItemRegistryHolder(pub Arc<FrozenItemRegistry>);
+
+/// Installs the items, item aliases and item tags defined in the given content packs into the given item registry.
+pub fn setup_content_items(
+    item_registry: &mut ItemRegistry,
+    packs: &[ContentPack],
+    block_registry: &BlockRegistry,
+) -> Result<(), ContentError> {
+    for item in load_item_definitions(packs, block_registry)? {
+        item_registry.push_object(item)?;
+    }
+    item_registry.add_aliases(load_alias_definitions(packs, ITEMS_DIRECTORY)?)?;
+    item_registry.resolve_tags(&load_tag_definitions(packs, ITEMS_DIRECTORY)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::items::BlockItemLinks;
+
+    use super::*;
+    use crate::content::base_content_pack;
+    use crate::voxel::blocks::{setup_content_blocks, STONE_BLOCK_NAME, WATER_BLOCK_NAME};
+
+    #[test]
+    fn base_items_place_blocks() {
+        let packs = [base_content_pack()];
+        let mut block_registry = BlockRegistry::default();
+        setup_content_blocks(&mut block_registry, &packs).unwrap();
+        let mut item_registry = ItemRegistry::default();
+        setup_content_items(&mut item_registry, &packs, &block_registry).unwrap();
+        let links = BlockItemLinks::new(&item_registry, &block_registry);
+
+        for name in [STONE_ITEM_NAME, DIRT_ITEM_NAME, GRASS_ITEM_NAME, SAND_ITEM_NAME] {
+            let (item_id, item) = item_registry.lookup_name_to_object(name.as_ref()).unwrap();
+            let block_id = links.block_for_item(item_id).unwrap();
+            assert_eq!(item.block.as_ref(), Some(&name));
+            assert_eq!(links.item_for_block(block_id), Some(item_id));
+        }
+        let (stone_id, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
+        assert!(links.item_for_block(stone_id).is_some());
+        let (water_id, _) = block_registry.lookup_name_to_object(WATER_BLOCK_NAME.as_ref()).unwrap();
+        assert_eq!(links.item_for_block(water_id), None);
+    }
+}
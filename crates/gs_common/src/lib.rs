@@ -9,14 +9,22 @@
 //! The common client&server code for Geosia
 
 pub mod config;
+pub mod content;
+pub mod content_reload;
+pub mod diagnostics;
+pub mod inventory;
+pub mod items;
 pub mod network;
 pub mod prelude;
 pub mod promises;
+pub mod tick;
 pub mod voxel;
 
+use std::path::{Path, PathBuf};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwap;
 use bevy::app::{AppExit, ScheduleRunnerPlugin};
 use bevy::diagnostic::DiagnosticsPlugin;
 use bevy::ecs::schedule::ScheduleLabel;
@@ -24,32 +32,45 @@ use bevy::prelude::*;
 use bevy::state::app::StatesPlugin;
 use bevy::time::TimePlugin;
 use bevy::utils::synccell::SyncCell;
+use gs_schemas::content::{ContentError, ContentPack};
 use gs_schemas::registries::GameRegistries;
-use gs_schemas::registry::Registry;
+use gs_schemas::registry::{FrozenRegistry, Registry, RegistryError, RegistryObject};
 use gs_schemas::{GameSide, GsExtraData};
 use smallvec::SmallVec;
 use voxel::persistence::generator::GeneratorPersistenceLayer;
 use voxel::plugin::VoxelUniverseBuilder;
 
 use crate::config::{GameConfig, GameConfigHandle};
+use crate::content_reload::ContentReloadPlugin;
+use crate::diagnostics::ServerDiagnosticsPlugin;
+use crate::inventory::InventoryPlugin;
+use crate::items::ItemRegistryHolder;
+use crate::network::conditions::NetworkConditions;
+use crate::network::entity_replication::EntityReplicationPlugin;
+use crate::network::player_replication::PlayerReplicationPlugin;
 use crate::network::server::{LocalConnectionPipe, NetworkServerPlugin, NetworkThreadServerState};
 use crate::network::thread::NetworkThread;
 use crate::prelude::*;
+use crate::tick::ServerTick;
 use crate::voxel::generator::multi_noise::MultiNoiseGenerator;
+use crate::voxel::generator::SwappableGenerator;
 use crate::voxel::persistence::memory::MemoryPersistenceLayer;
-use crate::voxel::plugin::VoxelUniversePlugin;
+use crate::voxel::plugin::{SavedWorld, VoxelUniversePlugin};
 
-// TODO: Populate these from build/git info
 /// The major SemVer field of the current build's version
 pub static GAME_VERSION_MAJOR: u32 = 0;
 /// The minor SemVer field of the current build's version
 pub static GAME_VERSION_MINOR: u32 = 0;
 /// The patch SemVer field of the current build's version
 pub static GAME_VERSION_PATCH: u32 = 1;
-/// The build SemVer field of the current build's version
-pub static GAME_VERSION_BUILD: &str = "todo";
+/// The build SemVer field of the current build's version, made of the git commit hash and build date
+pub static GAME_VERSION_BUILD: &str = concat!(env!("GS_BUILD_GIT_HASH"), ".", env!("GS_BUILD_DATE"));
 /// The prerelease SemVer field of the current build's version
 pub static GAME_VERSION_PRERELEASE: &str = "";
+/// The short git commit hash the current build was made from, or "unknown" if built outside of a git checkout
+pub static GAME_BUILD_GIT_HASH: &str = env!("GS_BUILD_GIT_HASH");
+/// The UTC date (YYYY-MM-DD) the current build was made on
+pub static GAME_BUILD_DATE: &str = env!("GS_BUILD_DATE");
 /// The name of the game
 pub static GAME_BRAND_NAME: &str = "Geosia";
 
@@ -78,8 +99,10 @@ pub struct InGameSystemSet;
 /// An [`GsExtraData`] implementation containing server-side data for the game engine.
 /// The struct holds server state, the trait points to per chunk/group/etc. data.
 pub struct ServerData {
-    /// Shared client/server registries.
-    pub shared_registries: GameRegistries,
+    /// Shared client/server registries, replaced when the content is reloaded in development mode.
+    pub shared_registries: ArcSwap<GameRegistries>,
+    /// The world generator, rebuilt along with the shared registries.
+    pub world_generator: Arc<SwappableGenerator<MultiNoiseGenerator>>,
 }
 
 impl GsExtraData for ServerData {
@@ -108,6 +131,9 @@ pub struct GameServer {
     engine_thread: JoinHandle<()>,
     network_thread: NetworkThread<NetworkThreadServerState>,
     pause: AtomicBool,
+    current_tick: AtomicU64,
+    last_tick_micros: AtomicU64,
+    max_tick_micros: AtomicU64,
     control_channel: StdUnboundedSender<GameServerControlCommand>,
 }
 
@@ -115,6 +141,10 @@ pub struct GameServer {
 #[derive(Resource, Clone)]
 pub struct GameServerResource(Arc<GameServer>);
 
+/// When the current fixed game tick started, for measuring the tick duration.
+#[derive(Resource, Default)]
+struct TickStartTime(Option<Instant>);
+
 #[derive(Resource)]
 struct GameServerControlCommandReceiver(SyncCell<StdUnboundedReceiver<GameServerControlCommand>>);
 
@@ -122,6 +152,25 @@ impl GameServer {
     /// Spawns a new thread that runs the engine in a paused state, and returns a handle to control it.
     #[allow(clippy::new_ret_no_self)]
     pub fn new(config: GameConfigHandle) -> Result<Arc<GameServer>> {
+        let packs = {
+            let config = config.1.borrow();
+            load_content_packs(
+                &config.server.content_packs,
+                config.server.dev_content_directory.as_deref(),
+            )?
+        };
+        let shared_registries =
+            content_game_registries(&packs, None).context("Could not build the game registries from the content")?;
+        let saved_world = {
+            let config = config.1.borrow();
+            config
+                .server
+                .world_file
+                .as_deref()
+                .map(|path| SavedWorld::load_file(path, &shared_registries, &config.server.world_remap_policy))
+                .transpose()?
+        };
+
         let (tx, rx) = std_bounded_channel(1);
         let (ctrl_tx, ctrl_rx) = std_unbounded_channel();
 
@@ -133,8 +182,14 @@ impl GameServer {
             .spawn(move || GameServer::engine_thread_main(rx, ctrl_rx))
             .expect("Could not create a thread for the engine");
 
+        let generator = MultiNoiseGenerator::new(
+            123456789,
+            Arc::clone(&shared_registries.biome_types),
+            Arc::clone(&shared_registries.block_types),
+        );
         let server_data = ServerData {
-            shared_registries: builtin_game_registries(),
+            shared_registries: ArcSwap::from_pointee(shared_registries),
+            world_generator: Arc::new(SwappableGenerator::new(generator)),
         };
 
         let server = Self {
@@ -143,10 +198,13 @@ impl GameServer {
             engine_thread,
             network_thread,
             pause: AtomicBool::new(true),
+            current_tick: AtomicU64::new(0),
+            last_tick_micros: AtomicU64::new(0),
+            max_tick_micros: AtomicU64::new(0),
             control_channel: ctrl_tx,
         };
         let server = Arc::new(server);
-        tx.send(Arc::clone(&server))
+        tx.send((Arc::clone(&server), saved_world))
             .expect("Could not pass initialization data to the server engine thread");
         Ok(server)
     }
@@ -185,6 +243,21 @@ impl GameServer {
         self.pause.swap(paused, AtomicOrdering::SeqCst)
     }
 
+    /// The latest value of the authoritative [`ServerTick`] counter, accessible outside of the engine thread.
+    pub fn current_tick(&self) -> u64 {
+        self.current_tick.load(AtomicOrdering::Acquire)
+    }
+
+    /// The time it took to simulate the latest game tick.
+    pub fn last_tick_duration(&self) -> Duration {
+        Duration::from_micros(self.last_tick_micros.load(AtomicOrdering::Relaxed))
+    }
+
+    /// The longest time it took to simulate a game tick since the previous call, for performance measurements.
+    pub fn take_max_tick_duration(&self) -> Duration {
+        Duration::from_micros(self.max_tick_micros.swap(0, AtomicOrdering::Relaxed))
+    }
+
     /// Checks if the engine thread is still alive.
     pub fn is_alive(&self) -> bool {
         !self.engine_thread.is_finished()
@@ -195,6 +268,13 @@ impl GameServer {
         self.network_thread.is_alive()
     }
 
+    /// Requests a graceful shutdown of the engine and network threads, the returned channel is notified when done.
+    pub fn shutdown(&self) -> AsyncOneshotReceiver<()> {
+        let (tx, rx) = async_oneshot_channel();
+        let _ = self.control_channel.send(GameServerControlCommand::Shutdown(tx));
+        rx
+    }
+
     /// Queues the given function to run with exclusive access to the bevy [`World`].
     pub fn schedule_bevy<
         BevyCmd: (FnOnce(&mut World) -> Result<Output>) + Send + 'static,
@@ -215,17 +295,30 @@ impl GameServer {
 
     /// Asynchronously creates a new local connection to this server's network runtime.
     pub fn create_local_connection(self: &Arc<Self>) -> AsyncResult<LocalConnectionPipe> {
+        self.create_simulated_local_connection(NetworkConditions::default())
+    }
+
+    /// Asynchronously creates a new local connection to this server's network runtime, with simulated latency, bandwidth
+    /// limits and datagram loss.
+    pub fn create_simulated_local_connection(
+        self: &Arc<Self>,
+        conditions: NetworkConditions,
+    ) -> AsyncResult<LocalConnectionPipe> {
         let inner_engine = Arc::clone(self);
         self.network_thread.schedule_task(move |state| {
-            Box::pin(NetworkThreadServerState::accept_local_connection(state, inner_engine))
+            Box::pin(NetworkThreadServerState::accept_local_connection(
+                state,
+                inner_engine,
+                conditions,
+            ))
         })
     }
 
     fn engine_thread_main(
-        engine: StdUnboundedReceiver<Arc<GameServer>>,
+        engine: StdUnboundedReceiver<(Arc<GameServer>, Option<SavedWorld>)>,
         ctrl_rx: StdUnboundedReceiver<GameServerControlCommand>,
     ) {
-        let engine = {
+        let (engine, saved_world) = {
             let e = engine
                 .recv()
                 .expect("Could not receive initialization data in the engine thread");
@@ -255,14 +348,24 @@ impl GameServer {
             .add_plugins(ScheduleRunnerPlugin::run_loop(TICK));
 
         app.add_plugins(VoxelUniversePlugin::<ServerData>::new())
-            .add_plugins(NetworkServerPlugin);
-
-        let block_registry = Arc::clone(&engine.server_data.shared_registries.block_types);
-        let biome_registry = Arc::clone(&engine.server_data.shared_registries.biome_types);
-
-        let generator = MultiNoiseGenerator::new(123456789, Arc::clone(&biome_registry), Arc::clone(&block_registry));
-        let gen_world = GeneratorPersistenceLayer::new(Arc::new(generator), default());
-        let persistence = MemoryPersistenceLayer::new(Box::new(gen_world));
+            .add_plugins(NetworkServerPlugin)
+            .add_plugins(ServerDiagnosticsPlugin)
+            .add_plugins(PlayerReplicationPlugin)
+            .add_plugins(EntityReplicationPlugin::<ServerData>::new())
+            .add_plugins(InventoryPlugin::<ServerData>::new())
+            .add_plugins(ContentReloadPlugin);
+
+        let shared_registries = engine.server_data.shared_registries.load_full();
+        let block_registry = Arc::clone(&shared_registries.block_types);
+        let biome_registry = Arc::clone(&shared_registries.biome_types);
+        let item_registry = Arc::clone(&shared_registries.item_types);
+
+        let generator = Arc::clone(&engine.server_data.world_generator);
+        let gen_world = GeneratorPersistenceLayer::new(generator, default());
+        let mut persistence = MemoryPersistenceLayer::new(Box::new(gen_world));
+        if let Some(saved_world) = saved_world {
+            saved_world.store_into(&mut persistence);
+        }
 
         fn configure_sets(app: &mut App, schedule: impl ScheduleLabel) {
             app.configure_sets(schedule, InGameSystemSet);
@@ -277,6 +380,9 @@ impl GameServer {
         app.insert_resource(Time::<Fixed>::from_duration(TICK));
         app.insert_resource(GameServerControlCommandReceiver(SyncCell::new(ctrl_rx)));
         app.insert_resource(GameServerResource(engine));
+        app.insert_resource(ItemRegistryHolder(item_registry));
+        app.init_resource::<ServerTick>();
+        app.init_resource::<TickStartTime>();
 
         VoxelUniverseBuilder::<ServerData>::new(app.world_mut(), block_registry, biome_registry)
             .unwrap()
@@ -285,6 +391,8 @@ impl GameServer {
             .build();
 
         app.add_systems(Startup, Self::network_startup_system);
+        app.add_systems(FixedFirst, Self::tick_counter_system);
+        app.add_systems(FixedLast, Self::tick_timer_system);
         app.add_systems(FixedPostUpdate, Self::control_command_handler_system);
         info!("Engine thread starting");
         app.run();
@@ -309,6 +417,29 @@ impl GameServer {
         info!("Bootstrapping network done");
     }
 
+    fn tick_counter_system(
+        engine: Res<GameServerResource>,
+        mut tick: ResMut<ServerTick>,
+        mut tick_start: ResMut<TickStartTime>,
+    ) {
+        let engine = &engine.0;
+        tick_start.0 = Some(Instant::now());
+        if engine.is_paused() {
+            return;
+        }
+        tick.0 += 1;
+        engine.current_tick.store(tick.0, AtomicOrdering::Release);
+    }
+
+    fn tick_timer_system(engine: Res<GameServerResource>, tick_start: Res<TickStartTime>) {
+        let Some(start) = tick_start.0 else {
+            return;
+        };
+        let micros = start.elapsed().as_micros() as u64;
+        engine.0.last_tick_micros.store(micros, AtomicOrdering::Relaxed);
+        engine.0.max_tick_micros.fetch_max(micros, AtomicOrdering::Relaxed);
+    }
+
     fn control_command_handler_system(world: &mut World) {
         let pending_cmds: SmallVec<[GameServerControlCommand; 32]> = {
             let mut ctrl_rx: Mut<GameServerControlCommandReceiver> = world.resource_mut();
@@ -332,15 +463,106 @@ impl GameServer {
     }
 }
 
-/// Simple hardcoded registries of some game objects.
+/// The registries of the game objects built into the game, with blocks, biomes and items loaded from the base content
+/// pack.
 pub fn builtin_game_registries() -> GameRegistries {
+    content_game_registries(&[content::base_content_pack()], None).expect("The base content pack should be valid")
+}
+
+/// The content packs to build the game registries from, in override order: the base content pack built into the game,
+/// then the packs read from `pack_directories`, then in development mode the pack read from `dev_content_directory`.
+pub fn load_content_packs(
+    pack_directories: &[PathBuf],
+    dev_content_directory: Option<&Path>,
+) -> Result<Vec<ContentPack>, ContentError> {
+    let mut packs = vec![content::base_content_pack()];
+    for directory in pack_directories
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(dev_content_directory)
+    {
+        packs.push(ContentPack::load_directory(directory)?);
+    }
+    Ok(packs)
+}
+
+/// Builds the registries of the game objects defined by the given content packs.
+/// When reloading content in a running game, the `previous` registries should be given to keep the IDs of the objects
+/// already in use (see [`Registry::rebuild_with_stable_ids`]).
+pub fn content_game_registries(
+    packs: &[ContentPack],
+    previous: Option<&GameRegistries>,
+) -> Result<GameRegistries, ContentError> {
+    fn stabilize<Object: RegistryObject + Clone>(
+        rebuilt: Registry<Object>,
+        previous: Option<&FrozenRegistry<Object>>,
+    ) -> Result<Registry<Object>, RegistryError> {
+        match previous {
+            Some(previous) => previous.rebuild_with_stable_ids(&rebuilt),
+            None => Ok(rebuilt),
+        }
+    }
+
     let mut block_types = Registry::default();
-    voxel::blocks::setup_basic_blocks(&mut block_types);
+    voxel::blocks::setup_content_blocks(&mut block_types, packs)?;
+    let block_types = stabilize(block_types, previous.map(|p| &*p.block_types))?;
+    // Biomes and items refer to the blocks by ID, so they are loaded against the stable block IDs.
     let mut biome_types = Registry::default();
-    voxel::biomes::setup_basic_biomes(&mut biome_types);
+    voxel::biomes::setup_content_biomes(&mut biome_types, packs, &block_types)?;
+    let biome_types = stabilize(biome_types, previous.map(|p| &*p.biome_types))?;
+    let mut item_types = Registry::default();
+    items::setup_content_items(&mut item_types, packs, &block_types)?;
+    let item_types = stabilize(item_types, previous.map(|p| &*p.item_types))?;
+    let mut stream_types = Registry::default();
+    network::custom_stream::setup_content_stream_types(&mut stream_types, packs)?;
+    let stream_types = stabilize(stream_types, previous.map(|p| &*p.stream_types))?;
+
+    Ok(GameRegistries {
+        block_types: Arc::new(block_types.freeze()),
+        biome_types: Arc::new(biome_types.freeze()),
+        stream_types: Arc::new(stream_types.freeze()),
+        item_types: Arc::new(item_types.freeze()),
+    })
+}
 
-    GameRegistries {
-        block_types: Arc::new(block_types),
-        biome_types: Arc::new(biome_types),
+#[cfg(test)]
+mod test {
+    use gs_schemas::registry::RegistryNameRef;
+
+    use super::*;
+
+    #[test]
+    fn later_content_pack_overrides_base_blocks() {
+        let directory = std::env::temp_dir().join(format!("gs-content-pack-test-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("blocks")).unwrap();
+        std::fs::write(
+            directory.join("blocks/overrides.ron"),
+            r##"[(name: "stone", color: "#808080"), (name: "marble", color: "#f0f0f0")]"##,
+        )
+        .unwrap();
+        let packs = load_content_packs(&[directory.clone()], None);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let packs = packs.unwrap();
+        assert_eq!(packs[0].name(), content::BASE_CONTENT_PACK_NAME);
+        let registries = content_game_registries(&packs, None).unwrap();
+        let builtin = builtin_game_registries();
+
+        // Definitions compare by name only, the changed color tells the objects apart.
+        let (stone_id, stone) = registries
+            .block_types
+            .lookup_name_to_object(RegistryNameRef::gs("stone"))
+            .unwrap();
+        let base_stone = builtin.block_types.lookup_id_to_object(stone_id).unwrap();
+        assert_eq!(base_stone.registry_name(), RegistryNameRef::gs("stone"));
+        assert_ne!(base_stone.representative_color, stone.representative_color);
+        // Overridden blocks keep their place in the base pack order, new blocks come after all of them.
+        for (id, name, _) in builtin.block_types.iter() {
+            assert_eq!(registries.block_types.lookup_name_to_object(name).unwrap().0, id);
+        }
+        let (marble_id, _) = registries
+            .block_types
+            .lookup_name_to_object(RegistryNameRef::gs("marble"))
+            .unwrap();
+        assert!(builtin.block_types.iter().all(|(id, _, _)| id < marble_id));
     }
 }
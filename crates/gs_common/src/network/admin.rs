@@ -0,0 +1,932 @@
+//! Remote server administration over Cap'n Proto RPC.
+//!
+//! Admin tools connect over TCP to the separate admin listen address, so administration works regardless of the game
+//! transport and is never exposed on the game ports. The bootstrap [`rpc::admin_server`] object only allows logging in,
+//! the [`rpc::admin_connection`] capability with the actual management methods is handed out for the configured admin
+//! credential. Server log lines are kept in a bounded in-memory buffer for admin tools to tail, filled by the tracing
+//! layer from [`admin_log_layer`] installed along with the process-wide logger.
+//!
+//! The admin connections are neither encrypted nor authenticated beyond the credential, which is sent in plaintext, so
+//! the admin listener must only be bound to a loopback address (the default) or to an address on a trusted network.
+//! Failed logins are counted per IP address, and addresses with too many of them have to wait increasingly longer
+//! before they can try again, see [`AdminLoginThrottle`].
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bevy::log::tracing_subscriber::layer::Context as LayerContext;
+use bevy::log::tracing_subscriber::Layer;
+use bevy::log::BoxedLayer;
+use bevy::prelude::*;
+use capnp::capability::Promise;
+use capnp::message::ReaderOptions;
+use capnp::Error;
+use capnp_rpc::rpc_twoparty_capnp::Side;
+use capnp_rpc::twoparty::VatNetwork;
+use capnp_rpc::{pry, RpcSystem};
+use gs_schemas::coordinates::AbsBlockPos;
+use gs_schemas::registries::GameRegistries;
+use gs_schemas::schemas::network_capnp::admin_connection::{
+    BackupWorldParams, BackupWorldResults, BanPlayerParams, BanPlayerResults, GetConfigParams, GetConfigResults,
+    GetDiagnosticsParams, GetDiagnosticsResults, GetStatusParams, GetStatusResults, KickPlayerParams,
+    KickPlayerResults, ListPlayersParams, ListPlayersResults, RunCommandParams, RunCommandResults, SaveWorldParams,
+    SaveWorldResults, SetConfigParams, SetConfigResults, TailLogsParams, TailLogsResults, UnbanPlayerParams,
+    UnbanPlayerResults,
+};
+use gs_schemas::schemas::network_capnp::admin_server::{LoginParams, LoginResults};
+use gs_schemas::schemas::network_capnp::authenticated_client_connection::connection_termination::Kind as ConnectionTerminationKind;
+use tokio::net::TcpListener;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Instrument, Level, Subscriber};
+
+use crate::diagnostics::ServerDiagnosticsReport;
+use crate::network::player_replication::write_vec3;
+use crate::network::server::{write_server_metadata, ConnectedPlayer, NetworkThreadServerState};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::voxel::plugin::{place_spawn_chunk_loader, save_loaded_chunks, snapshot_loaded_world, WorldSnapshot};
+use crate::GameServer;
+
+/// The number of most recent log lines kept for admin tools.
+pub const ADMIN_LOG_CAPACITY: usize = 2048;
+/// The maximum number of log lines returned by a single `tailLogs` call.
+pub const MAX_TAILED_LOG_LINES: u32 = 512;
+/// The file extension of world backups written by `backupWorld`.
+pub const WORLD_BACKUP_EXTENSION: &str = "gsbackup";
+
+/// The number of failed admin logins from an IP address before its next attempts get delayed.
+pub const ADMIN_LOGIN_FREE_ATTEMPTS: u32 = 3;
+/// The delay after the first failed admin login past the free attempts, doubled by every further failure.
+pub const ADMIN_LOGIN_BASE_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay between failed admin logins from an IP address.
+pub const ADMIN_LOGIN_MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+/// How long after its last failed admin login an IP address starts over with the free attempts.
+pub const ADMIN_LOGIN_FAILURE_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Capnproto reader options for admin connections, which only carry small messages.
+pub static ADMIN_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(8 * 1024 * 1024),
+    nesting_limit: 64,
+};
+
+/// The commands understood by `runCommand`, with their descriptions.
+const CONSOLE_COMMANDS: &[(&str, &str)] = &[
+    ("help", "lists the available commands"),
+    ("list", "lists the online players"),
+    ("diagnostics", "shows a summary of the server performance diagnostics"),
+    ("say <message>", "sends a chat message to all players"),
+    ("pause", "pauses the game simulation"),
+    ("resume", "resumes the game simulation"),
+    ("save", "queues all loaded chunks for saving"),
+    (
+        "loader <x> <y> <z> <radius>",
+        "places a persistent chunk loader at the given block position",
+    ),
+    ("stop", "shuts down the server"),
+];
+
+/// A line of the server log, as kept for admin tools.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminLogLine {
+    /// Increases by one with every logged line.
+    pub sequence: u64,
+    /// Unix time of the log line, in milliseconds.
+    pub time: u64,
+    /// The log level name.
+    pub level: &'static str,
+    /// The module that logged the line.
+    pub target: String,
+    /// The formatted message with all the event fields.
+    pub message: String,
+}
+
+struct AdminLogBuffer {
+    lines: VecDeque<AdminLogLine>,
+    next_sequence: u64,
+}
+
+static ADMIN_LOG: Mutex<AdminLogBuffer> = Mutex::new(AdminLogBuffer {
+    lines: VecDeque::new(),
+    next_sequence: 0,
+});
+
+/// Adds a line to the admin log buffer, evicting the oldest lines once it's full.
+pub fn push_admin_log_line(level: Level, target: &str, message: String) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64);
+    let mut log = ADMIN_LOG.lock().unwrap();
+    let sequence = log.next_sequence;
+    log.next_sequence += 1;
+    if log.lines.len() >= ADMIN_LOG_CAPACITY {
+        log.lines.pop_front();
+    }
+    log.lines.push_back(AdminLogLine {
+        sequence,
+        time,
+        level: level.as_str(),
+        target: target.to_owned(),
+        message,
+    });
+}
+
+/// Reads up to `limit` buffered log lines with a sequence number of at least `after`.
+/// Returns the lines and the sequence number to continue reading from.
+pub fn read_admin_log(after: u64, limit: usize) -> (Vec<AdminLogLine>, u64) {
+    let log = ADMIN_LOG.lock().unwrap();
+    let lines: Vec<AdminLogLine> = log
+        .lines
+        .iter()
+        .skip_while(|line| line.sequence < after)
+        .take(limit)
+        .cloned()
+        .collect();
+    let next = match lines.last() {
+        Some(line) => line.sequence + 1,
+        None => {
+            let first = log.lines.front().map_or(log.next_sequence, |line| line.sequence);
+            after.clamp(first, log.next_sequence)
+        }
+    };
+    (lines, next)
+}
+
+/// A custom layer for bevy's `LogPlugin` copying info and more severe log lines into the admin log buffer.
+pub fn admin_log_layer(_app: &mut App) -> Option<BoxedLayer> {
+    Some(Box::new(AdminLogLayer))
+}
+
+struct AdminLogLayer;
+
+impl<S: Subscriber> Layer<S> for AdminLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() > Level::INFO {
+            return;
+        }
+        let mut message = String::new();
+        event.record(&mut MessageVisitor(&mut message));
+        push_admin_log_line(*metadata.level(), metadata.target(), message);
+    }
+}
+
+struct MessageVisitor<'s>(&'s mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+/// The failed admin logins of an IP address.
+#[derive(Copy, Clone, Debug)]
+struct AdminLoginFailures {
+    count: u32,
+    last: Instant,
+}
+
+/// Counts the failed admin logins per IP address, locking out addresses with too many of them for a time that doubles
+/// with every further failure, so that the credential can't be guessed by reconnecting over and over.
+#[derive(Default, Debug)]
+pub struct AdminLoginThrottle {
+    failures: HashMap<IpAddr, AdminLoginFailures>,
+}
+
+impl AdminLoginThrottle {
+    /// Returns how long the address still has to wait before it may attempt to log in, or None if it may log in now.
+    pub fn lockout_remaining(&self, address: IpAddr, now: Instant) -> Option<Duration> {
+        let failures = self.failures.get(&address)?;
+        let excess = failures.count.checked_sub(ADMIN_LOGIN_FREE_ATTEMPTS)?;
+        let backoff = ADMIN_LOGIN_BASE_BACKOFF
+            .checked_mul(1u32.checked_shl(excess).unwrap_or(u32::MAX))
+            .map_or(ADMIN_LOGIN_MAX_BACKOFF, |backoff| backoff.min(ADMIN_LOGIN_MAX_BACKOFF));
+        let remaining = (failures.last + backoff).saturating_duration_since(now);
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Records a failed login from the address, and forgets the addresses that have not failed in a long time.
+    pub fn record_failure(&mut self, address: IpAddr, now: Instant) {
+        self.failures
+            .retain(|_, failures| now.saturating_duration_since(failures.last) < ADMIN_LOGIN_FAILURE_EXPIRY);
+        let failures = self
+            .failures
+            .entry(address)
+            .or_insert(AdminLoginFailures { count: 0, last: now });
+        failures.count = failures.count.saturating_add(1);
+        failures.last = now;
+    }
+
+    /// Forgets the failed logins of an address after it logged in successfully.
+    pub fn record_success(&mut self, address: IpAddr) {
+        self.failures.remove(&address);
+    }
+}
+
+/// Accepts admin tool connections on the given address until aborted.
+/// Must be called within the tokio LocalSet.
+pub async fn admin_listener_task(
+    net_state: Rc<RefCell<NetworkThreadServerState>>,
+    engine: Arc<GameServer>,
+    address: SocketAddr,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Could not listen for admin connections on {address}"))?;
+    let local_address = listener.local_addr()?;
+    info!("Accepting remote administration connections on {local_address}");
+    if !local_address.ip().is_loopback() {
+        warn!("The admin credential is sent unencrypted, only accept admin connections from trusted networks");
+    }
+    let throttle = Rc::new(RefCell::new(AdminLoginThrottle::default()));
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        debug!("Accepted an admin connection from {peer}");
+        let (read, write) = stream.compat().split();
+        let network = VatNetwork::new(read, write, Side::Server, ADMIN_READER_OPTIONS);
+        let endpoint = AdminServerEndpoint::new(Rc::clone(&net_state), Arc::clone(&engine), peer, Rc::clone(&throttle));
+        let bootstrap: rpc::admin_server::Client = capnp_rpc::new_client(endpoint);
+        let rpc_system = RpcSystem::new(Box::new(network), Some(bootstrap.client));
+        tokio::task::spawn_local(
+            async move {
+                if let Err(e) = rpc_system.await {
+                    debug!("Admin connection from {peer} closed with an error: {e}");
+                }
+            }
+            .instrument(tracing::info_span!("server-admin", %peer)),
+        );
+    }
+}
+
+/// Compares the credentials in constant time, to not leak the length of the matching prefix through timing.
+fn credentials_match(expected: &str, provided: &str) -> bool {
+    let (expected, provided) = (expected.as_bytes(), provided.as_bytes());
+    if expected.len() != provided.len() {
+        return false;
+    }
+    expected.iter().zip(provided).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn rpc_error(error: anyhow::Error) -> Error {
+    Error::failed(format!("{error:#}"))
+}
+
+/// The unauthenticated admin RPC endpoint on the server side, the bootstrap object of admin connections.
+pub struct AdminServerEndpoint {
+    net_state: Rc<RefCell<NetworkThreadServerState>>,
+    server: Arc<GameServer>,
+    peer: SocketAddr,
+    throttle: Rc<RefCell<AdminLoginThrottle>>,
+    login_attempted: bool,
+}
+
+impl AdminServerEndpoint {
+    /// Constructor, `throttle` is shared by all the connections of a listener.
+    pub fn new(
+        net_state: Rc<RefCell<NetworkThreadServerState>>,
+        server: Arc<GameServer>,
+        peer: SocketAddr,
+        throttle: Rc<RefCell<AdminLoginThrottle>>,
+    ) -> Self {
+        Self {
+            net_state,
+            server,
+            peer,
+            throttle,
+            login_attempted: false,
+        }
+    }
+}
+
+impl rpc::admin_server::Server for AdminServerEndpoint {
+    fn login(&mut self, params: LoginParams, mut results: LoginResults) -> Promise<(), Error> {
+        // One attempt per connection, the throttle delays the attempts over new connections after repeated failures.
+        if self.login_attempted {
+            return Promise::err(Error::failed("Login was already attempted once".to_owned()));
+        }
+        self.login_attempted = true;
+        let now = Instant::now();
+        if let Some(remaining) = self.throttle.borrow().lockout_remaining(self.peer.ip(), now) {
+            warn!("Rejected an admin login from {} during its lockout", self.peer);
+            return Promise::err(Error::failed(format!(
+                "Too many failed logins, try again in {} seconds",
+                remaining.as_secs().max(1)
+            )));
+        }
+
+        let credential = pry!(pry!(pry!(params.get()).get_credential()).to_str());
+        let accepted = match &self.server.config().borrow().server.admin_credential {
+            Some(expected) => !expected.is_empty() && credentials_match(expected, credential),
+            None => false,
+        };
+        if !accepted {
+            warn!("Rejected an admin login from {} with an invalid credential", self.peer);
+            self.throttle.borrow_mut().record_failure(self.peer.ip(), now);
+            return Promise::err(Error::failed("Invalid admin credential".to_owned()));
+        }
+        self.throttle.borrow_mut().record_success(self.peer.ip());
+        info!("Admin logged in from {}", self.peer);
+        let connection = AdminConnectionEndpoint {
+            net_state: Rc::clone(&self.net_state),
+            server: Arc::clone(&self.server),
+            peer: self.peer,
+        };
+        results.get().set_connection(capnp_rpc::new_client(connection));
+        Promise::ok(())
+    }
+}
+
+/// The authenticated admin RPC endpoint on the server side.
+pub struct AdminConnectionEndpoint {
+    net_state: Rc<RefCell<NetworkThreadServerState>>,
+    server: Arc<GameServer>,
+    peer: SocketAddr,
+}
+
+impl AdminConnectionEndpoint {
+    /// Disconnects the player with the given nickname, returns false if no such player is online.
+    async fn kick(
+        net_state: Rc<RefCell<NetworkThreadServerState>>,
+        server: Arc<GameServer>,
+        nickname: &str,
+        kind: ConnectionTerminationKind,
+        reason: &str,
+    ) -> bool {
+        let address = net_state
+            .borrow()
+            .find_bootstrapped_client_by_nickname(nickname)
+            .map(|(address, _)| address);
+        let Some(address) = address else {
+            return false;
+        };
+        NetworkThreadServerState::terminate_client(&net_state, &server, address, kind, reason).await;
+        true
+    }
+
+    /// Sends a chat message to all bootstrapped clients.
+    fn broadcast_chat_message(&self, text: &str) {
+        let tick = self.server.current_tick();
+        let state = self.net_state.borrow();
+        for (address, client) in state.bootstrapped_clients() {
+            let mut request = client.borrow().rpc().add_chat_message_request();
+            request.get().set_tick(tick);
+            request.get().set_text(text);
+            tokio::task::spawn_local(async move {
+                if let Err(e) = request.send().promise.await {
+                    debug!("Could not send a chat message to {address:?}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Runs a console command, returns its output.
+    async fn run_console_command(&self, command: &str) -> Result<String> {
+        let (name, arguments) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+        let arguments = arguments.trim();
+        Ok(match name {
+            "help" => {
+                let mut output = String::from("Available commands:");
+                for (usage, description) in CONSOLE_COMMANDS {
+                    write!(output, "\n  {usage} - {description}")?;
+                }
+                output
+            }
+            "list" => {
+                let state = self.net_state.borrow();
+                let nicknames = state
+                    .bootstrapped_clients()
+                    .map(|(_, client)| client.borrow().username().to_string())
+                    .collect::<Vec<_>>();
+                format!("{} players online: {}", nicknames.len(), nicknames.join(", "))
+            }
+            "diagnostics" => {
+                let report = self
+                    .server
+                    .schedule_bevy(|world| Ok(ServerDiagnosticsReport::from_world(world)))
+                    .async_wait()
+                    .await?;
+                report.summary()
+            }
+            "say" => {
+                ensure!(!arguments.is_empty(), "Usage: say <message>");
+                let text = format!("[Server] {arguments}");
+                self.broadcast_chat_message(&text);
+                info!("{text}");
+                format!("Sent: {text}")
+            }
+            "pause" => {
+                self.server.set_paused(true);
+                "Game simulation paused".to_owned()
+            }
+            "resume" => {
+                self.server.set_paused(false);
+                "Game simulation resumed".to_owned()
+            }
+            "save" => {
+                let chunks = self.server.schedule_bevy(save_loaded_chunks).async_wait().await?;
+                format!("Queued {chunks} chunks for saving")
+            }
+            "loader" => {
+                let usage = "Usage: loader <x> <y> <z> <radius>";
+                let values = arguments
+                    .split_whitespace()
+                    .map(str::parse::<i32>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .context(usage)?;
+                let &[x, y, z, radius] = &values[..] else {
+                    bail!(usage);
+                };
+                ensure!(radius > 0, "The loader radius must be positive");
+                let position = AbsBlockPos::new(x, y, z);
+                self.server
+                    .schedule_bevy(move |world| Ok(place_spawn_chunk_loader(world, position, radius)))
+                    .async_wait()
+                    .await?;
+                format!("Placed a chunk loader with radius {radius} at {x} {y} {z}")
+            }
+            "stop" => {
+                info!("Admin {} requested a server shutdown", self.peer);
+                // The server notifies the receiver once done, nobody needs to wait for it here.
+                drop(self.server.shutdown());
+                "Shutting down the server".to_owned()
+            }
+            "" => bail!("Empty command, type help for the list of commands"),
+            _ => bail!("Unknown command {name}, type help for the list of commands"),
+        })
+    }
+}
+
+/// Checks that a backup name can be safely used as a file name.
+fn validate_backup_name(name: &str) -> Result<()> {
+    ensure!(!name.is_empty(), "The backup name can't be empty");
+    ensure!(
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "The backup name {name:?} can only contain ASCII letters, digits, - and _"
+    );
+    Ok(())
+}
+
+/// Writes a world backup into a new file at `path`, creating its `directory` if needed.
+/// Returns the number of written chunks.
+fn write_world_backup(
+    directory: &Path,
+    path: &Path,
+    snapshot: &WorldSnapshot,
+    registries: &GameRegistries,
+) -> Result<usize> {
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Could not create the backup directory {}", directory.display()))?;
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Could not create the backup file {}", path.display()))?;
+    let mut output = BufWriter::new(file);
+    let chunks = snapshot.write(registries, &mut output)?;
+    output.flush()?;
+    Ok(chunks)
+}
+
+impl rpc::admin_connection::Server for AdminConnectionEndpoint {
+    fn get_status(&mut self, _: GetStatusParams, mut results: GetStatusResults) -> Promise<(), Error> {
+        let player_count = self.net_state.borrow().player_count() as u32;
+        let mut status = results.get().init_status();
+        write_server_metadata(&self.server, player_count, status.reborrow().init_metadata());
+        status.set_tick(self.server.current_tick());
+        status.set_paused(self.server.is_paused());
+        status.set_last_tick_micros(self.server.last_tick_duration().as_micros() as u64);
+        Promise::ok(())
+    }
+
+    fn list_players(&mut self, _: ListPlayersParams, mut results: ListPlayersResults) -> Promise<(), Error> {
+        let players = self.server.schedule_bevy(|world| {
+            let mut players = world.query::<(&ConnectedPlayer, &Transform)>();
+            Ok(players
+                .iter(world)
+                .map(|(player, transform)| (player.nickname.clone(), player.address, transform.translation))
+                .collect::<Vec<_>>())
+        });
+        Promise::from_future(async move {
+            let mut players = players.async_wait().await.map_err(rpc_error)?;
+            players.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut list = results.get().init_players(players.len() as u32);
+            for (i, (nickname, address, position)) in players.into_iter().enumerate() {
+                let mut info = list.reborrow().get(i as u32);
+                info.set_nickname(nickname.as_str());
+                info.set_address(format_peer_address(address));
+                write_vec3(info.init_position(), position);
+            }
+            Ok(())
+        })
+    }
+
+    fn kick_player(&mut self, params: KickPlayerParams, mut results: KickPlayerResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let nickname = pry!(pry!(params.get_nickname()).to_string());
+        let reason = pry!(pry!(params.get_reason()).to_string());
+        let (net_state, server, peer) = (Rc::clone(&self.net_state), Arc::clone(&self.server), self.peer);
+        Promise::from_future(async move {
+            let found = Self::kick(net_state, server, &nickname, ConnectionTerminationKind::Kick, &reason).await;
+            if found {
+                info!("Admin {peer} kicked {nickname}: {reason}");
+            }
+            results.get().set_found(found);
+            Ok(())
+        })
+    }
+
+    fn ban_player(&mut self, params: BanPlayerParams, mut results: BanPlayerResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let nickname = pry!(pry!(params.get_nickname()).to_string());
+        let reason = pry!(pry!(params.get_reason()).to_string());
+        if nickname.is_empty() {
+            return Promise::err(Error::failed("The nickname can't be empty".to_owned()));
+        }
+        let added = self
+            .server
+            .config_updater()
+            .send_if_modified(|config| config.server.banned_players.insert(nickname.clone()));
+        if added {
+            info!("Admin {} banned {nickname}: {reason}", self.peer);
+        }
+        let (net_state, server) = (Rc::clone(&self.net_state), Arc::clone(&self.server));
+        Promise::from_future(async move {
+            Self::kick(net_state, server, &nickname, ConnectionTerminationKind::Ban, &reason).await;
+            results.get().set_added(added);
+            Ok(())
+        })
+    }
+
+    fn unban_player(&mut self, params: UnbanPlayerParams, mut results: UnbanPlayerResults) -> Promise<(), Error> {
+        let nickname = pry!(pry!(pry!(params.get()).get_nickname()).to_str());
+        let removed = self
+            .server
+            .config_updater()
+            .send_if_modified(|config| config.server.banned_players.remove(nickname));
+        if removed {
+            info!("Admin {} unbanned {nickname}", self.peer);
+        }
+        results.get().set_removed(removed);
+        Promise::ok(())
+    }
+
+    fn run_command(&mut self, params: RunCommandParams, mut results: RunCommandResults) -> Promise<(), Error> {
+        let command = pry!(pry!(pry!(params.get()).get_command()).to_string());
+        let this = AdminConnectionEndpoint {
+            net_state: Rc::clone(&self.net_state),
+            server: Arc::clone(&self.server),
+            peer: self.peer,
+        };
+        Promise::from_future(async move {
+            let output = this.run_console_command(&command).await.map_err(rpc_error)?;
+            results.get().set_output(output);
+            Ok(())
+        })
+    }
+
+    fn save_world(&mut self, _: SaveWorldParams, mut results: SaveWorldResults) -> Promise<(), Error> {
+        let chunks = self.server.schedule_bevy(save_loaded_chunks);
+        Promise::from_future(async move {
+            let chunks = chunks.async_wait().await.map_err(rpc_error)?;
+            results.get().set_chunks(chunks as u32);
+            Ok(())
+        })
+    }
+
+    fn backup_world(&mut self, params: BackupWorldParams, mut results: BackupWorldResults) -> Promise<(), Error> {
+        let name = pry!(pry!(pry!(params.get()).get_name()).to_str());
+        pry!(validate_backup_name(name).map_err(rpc_error));
+        let directory = self.server.config().borrow().server.backup_directory.clone();
+        let path: PathBuf = directory.join(format!("{name}.{WORLD_BACKUP_EXTENSION}"));
+        let written_path = path.clone();
+        let registries = self.server.server_data.shared_registries.load_full();
+        let snapshot = self.server.schedule_bevy(snapshot_loaded_world);
+        let peer = self.peer;
+        Promise::from_future(async move {
+            let snapshot = snapshot.async_wait().await.map_err(rpc_error)?;
+            // Only copying the chunks holds up the game thread, the file is written on the blocking thread pool.
+            let chunks =
+                tokio::task::spawn_blocking(move || write_world_backup(&directory, &path, &snapshot, &registries))
+                    .await
+                    .map_err(|e| rpc_error(e.into()))?
+                    .map_err(rpc_error)?;
+            info!("Admin {peer} backed up {chunks} chunks into {}", written_path.display());
+            let mut results = results.get();
+            results.set_path(written_path.display().to_string());
+            results.set_chunks(chunks as u32);
+            Ok(())
+        })
+    }
+
+    fn get_config(&mut self, params: GetConfigParams, mut results: GetConfigResults) -> Promise<(), Error> {
+        let key = pry!(pry!(pry!(params.get()).get_key()).to_str());
+        let value = pry!(self.server.config().borrow().server.get_option(key).map_err(rpc_error));
+        results.get().set_value(value);
+        Promise::ok(())
+    }
+
+    fn set_config(&mut self, params: SetConfigParams, mut results: SetConfigResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let key = pry!(pry!(params.get_key()).to_str());
+        let value = pry!(pry!(params.get_value()).to_str());
+        let mut outcome = Err(anyhow!("The configuration was not updated"));
+        self.server.config_updater().send_if_modified(|config| {
+            outcome = config.server.set_option(key, value);
+            outcome.is_ok()
+        });
+        let previous = pry!(outcome.map_err(rpc_error));
+        info!("Admin {} changed {key} from {previous:?} to {value:?}", self.peer);
+        results.get().set_previous(previous);
+        Promise::ok(())
+    }
+
+    fn tail_logs(&mut self, params: TailLogsParams, mut results: TailLogsResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let limit = params.get_limit().min(MAX_TAILED_LOG_LINES) as usize;
+        let (lines, next) = read_admin_log(params.get_after(), limit);
+        let mut results = results.get();
+        results.set_next(next);
+        let mut list = results.init_lines(lines.len() as u32);
+        for (i, line) in lines.iter().enumerate() {
+            let mut entry = list.reborrow().get(i as u32);
+            entry.set_sequence(line.sequence);
+            entry.set_time(line.time);
+            entry.set_level(line.level);
+            entry.set_target(line.target.as_str());
+            entry.set_message(line.message.as_str());
+        }
+        Promise::ok(())
+    }
+
+    fn get_diagnostics(&mut self, _: GetDiagnosticsParams, mut results: GetDiagnosticsResults) -> Promise<(), Error> {
+        let report = self
+            .server
+            .schedule_bevy(|world| Ok(ServerDiagnosticsReport::from_world(world)));
+        Promise::from_future(async move {
+            let report = report.async_wait().await.map_err(rpc_error)?;
+            let mut diagnostics = results.get().init_diagnostics();
+            diagnostics.set_tick_micros((report.tick_duration_ms * 1000.0) as u64);
+            diagnostics.set_max_tick_micros((report.max_tick_duration_ms * 1000.0) as u64);
+            diagnostics.set_loaded_chunks(report.loaded_chunks as u32);
+            diagnostics.set_loads_queued(report.loads_queued as u32);
+            diagnostics.set_saves_queued(report.saves_queued as u32);
+            diagnostics.set_responses_queued(report.responses_queued as u32);
+            diagnostics.set_chunk_generation_rate(report.chunk_generation_rate);
+            let mut clients = diagnostics.init_clients(report.clients.len() as u32);
+            for (i, client) in report.clients.iter().enumerate() {
+                let mut entry = clients.reborrow().get(i as u32);
+                entry.set_nickname(client.nickname.as_str());
+                entry.set_address(format_peer_address(client.address));
+                entry.set_sent_bytes(client.sent_bytes);
+                entry.set_bytes_per_second(client.bytes_per_second);
+                entry.set_queued_packets(client.queued_packets as u32);
+                entry.set_queued_bytes(client.queued_bytes as u64);
+            }
+            Ok(())
+        })
+    }
+}
+
+fn format_peer_address(address: PeerAddress) -> String {
+    match address {
+        PeerAddress::Local(id) => format!("local#{id}"),
+        PeerAddress::Remote(address) => address.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::network::bot::{BotScript, BotStats, HeadlessBot};
+
+    #[test]
+    fn credential_comparison() {
+        assert!(credentials_match("hunter2", "hunter2"));
+        assert!(!credentials_match("hunter2", "hunter3"));
+        assert!(!credentials_match("hunter2", "hunter22"));
+        assert!(!credentials_match("hunter2", ""));
+    }
+
+    #[test]
+    fn login_throttling() {
+        let mut throttle = AdminLoginThrottle::default();
+        let attacker: IpAddr = "192.168.1.20".parse().unwrap();
+        let admin: IpAddr = "192.168.1.21".parse().unwrap();
+        let start = Instant::now();
+        for _ in 0..ADMIN_LOGIN_FREE_ATTEMPTS {
+            assert_eq!(throttle.lockout_remaining(attacker, start), None);
+            throttle.record_failure(attacker, start);
+        }
+        assert_eq!(
+            throttle.lockout_remaining(attacker, start),
+            Some(ADMIN_LOGIN_BASE_BACKOFF)
+        );
+        assert_eq!(throttle.lockout_remaining(admin, start), None);
+
+        // Every further failure doubles the lockout, up to the maximum.
+        let later = start + ADMIN_LOGIN_BASE_BACKOFF;
+        assert_eq!(throttle.lockout_remaining(attacker, later), None);
+        throttle.record_failure(attacker, later);
+        assert_eq!(
+            throttle.lockout_remaining(attacker, later),
+            Some(ADMIN_LOGIN_BASE_BACKOFF * 2)
+        );
+        for _ in 0..40 {
+            throttle.record_failure(attacker, later);
+        }
+        assert_eq!(
+            throttle.lockout_remaining(attacker, later),
+            Some(ADMIN_LOGIN_MAX_BACKOFF)
+        );
+
+        // Old failures are forgotten when another address fails, successful logins reset the count.
+        throttle.record_failure(admin, later + ADMIN_LOGIN_FAILURE_EXPIRY);
+        assert_eq!(throttle.lockout_remaining(attacker, later), None);
+        throttle.record_success(admin);
+        assert!(throttle.failures.is_empty());
+    }
+
+    #[test]
+    fn backup_names() {
+        assert!(validate_backup_name("before-update_2").is_ok());
+        assert!(validate_backup_name("").is_err());
+        assert!(validate_backup_name("../world").is_err());
+        assert!(validate_backup_name("a b").is_err());
+    }
+
+    #[test]
+    fn log_tailing() {
+        // The buffer is global, so only look at lines logged by this test.
+        let (_, start) = read_admin_log(u64::MAX, 0);
+        for i in 0..5 {
+            push_admin_log_line(Level::INFO, "admin_test", format!("line {i}"));
+        }
+        let (lines, next) = read_admin_log(start, 3);
+        let ours: Vec<_> = lines.iter().filter(|line| line.target == "admin_test").collect();
+        assert_eq!(ours.len(), 3);
+        assert_eq!(ours[0].message, "line 0");
+        let (lines, _) = read_admin_log(next, MAX_TAILED_LOG_LINES as usize);
+        assert!(lines.iter().any(|line| line.message == "line 4"));
+        assert!(lines.iter().all(|line| line.sequence >= next));
+    }
+
+    fn admin_server(
+        state: &Rc<RefCell<NetworkThreadServerState>>,
+        engine: &Arc<GameServer>,
+    ) -> rpc::admin_server::Client {
+        let peer = "127.0.0.1:40000".parse().unwrap();
+        capnp_rpc::new_client(AdminServerEndpoint::new(
+            Rc::clone(state),
+            Arc::clone(engine),
+            peer,
+            default(),
+        ))
+    }
+
+    async fn login(
+        admin: &rpc::admin_server::Client,
+        credential: &str,
+    ) -> Result<rpc::admin_connection::Client, Error> {
+        let mut request = admin.login_request();
+        request.get().set_credential(credential);
+        request.send().promise.await?.get()?.get_connection()
+    }
+
+    fn new_admin_test_server() -> Arc<GameServer> {
+        let server = GameServer::new_test();
+        server
+            .config_updater()
+            .send_modify(|config| config.server.admin_credential = Some("hunter2".to_owned()));
+        server
+    }
+
+    #[test]
+    fn invalid_admin_logins_are_rejected() {
+        let server = new_admin_test_server();
+        let engine = Arc::clone(&server);
+        server
+            .network_thread
+            .schedule_task(move |state| {
+                Box::pin(async move {
+                    let admin = admin_server(state, &engine);
+                    assert!(login(&admin, "hunter3").await.is_err());
+                    // Only one attempt is allowed per connection, even with the right credential.
+                    assert!(login(&admin, "hunter2").await.is_err());
+
+                    let connection = login(&admin_server(state, &engine), "hunter2").await.unwrap();
+                    connection.get_status_request().send().promise.await.unwrap();
+
+                    // Remote administration stays locked without a configured credential.
+                    engine
+                        .config_updater()
+                        .send_modify(|config| config.server.admin_credential = None);
+                    assert!(login(&admin_server(state, &engine), "").await.is_err());
+                    assert!(login(&admin_server(state, &engine), "hunter2").await.is_err());
+                    Ok(())
+                })
+            })
+            .blocking_wait()
+            .unwrap();
+        server.shutdown().blocking_recv().unwrap();
+    }
+
+    #[test]
+    fn kicked_and_banned_players() {
+        let server = new_admin_test_server();
+        let stats = Arc::new(BotStats::default());
+        let script = BotScript {
+            edit_interval: None,
+            render_distance: 0,
+            ..Default::default()
+        };
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(tokio::task::LocalSet::new().run_until(async {
+                let (stop_tx, stop_rx) = async_watch_channel(false);
+                let connection = server.create_local_connection().async_wait().await.unwrap();
+                let bot = HeadlessBot::new("Victim", script.clone(), Arc::clone(&stats));
+                let bot = tokio::task::spawn_local(bot.run(connection, stop_rx.clone()));
+                let deadline = Instant::now() + Duration::from_secs(30);
+                while Instant::now() < deadline && stats.snapshot().connected_bots < 1 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+
+                let engine = Arc::clone(&server);
+                server
+                    .network_thread
+                    .schedule_task(move |state| {
+                        Box::pin(async move {
+                            let admin = login(&admin_server(state, &engine), "hunter2").await.unwrap();
+                            let kick = |nickname: &str| {
+                                let mut request = admin.kick_player_request();
+                                request.get().set_nickname(nickname);
+                                request.get().set_reason("Testing");
+                                request.send().promise
+                            };
+                            assert!(!kick("Nobody").await.unwrap().get().unwrap().get_found());
+                            assert!(kick("Victim").await.unwrap().get().unwrap().get_found());
+                            assert_eq!(state.borrow().player_count(), 0);
+
+                            let ban = |nickname: &str| {
+                                let mut request = admin.ban_player_request();
+                                request.get().set_nickname(nickname);
+                                request.get().set_reason("Testing");
+                                request.send().promise
+                            };
+                            assert!(ban("Victim").await.unwrap().get().unwrap().get_added());
+                            assert!(!ban("Victim").await.unwrap().get().unwrap().get_added());
+                            assert!(ban("").await.is_err());
+                            Ok(())
+                        })
+                    })
+                    .async_wait()
+                    .await
+                    .unwrap();
+                assert!(server.config().borrow().server.banned_players.contains("Victim"));
+
+                // The banned player can't join again.
+                let connection = server.create_local_connection().async_wait().await.unwrap();
+                let rejected = HeadlessBot::new("Victim", script.clone(), Arc::clone(&stats))
+                    .run(connection, stop_rx.clone())
+                    .await;
+                assert!(format!("{:#}", rejected.unwrap_err()).contains("banned"));
+
+                let engine = Arc::clone(&server);
+                server
+                    .network_thread
+                    .schedule_task(move |state| {
+                        Box::pin(async move {
+                            let admin = login(&admin_server(state, &engine), "hunter2").await.unwrap();
+                            let mut request = admin.unban_player_request();
+                            request.get().set_nickname("Victim");
+                            assert!(request.send().promise.await.unwrap().get().unwrap().get_removed());
+                            Ok(())
+                        })
+                    })
+                    .async_wait()
+                    .await
+                    .unwrap();
+                assert!(server.config().borrow().server.banned_players.is_empty());
+
+                stop_tx.send(true).unwrap();
+                let _ = bot.await.unwrap();
+                server.shutdown().await.unwrap();
+            }));
+    }
+}
@@ -0,0 +1,671 @@
+//! A headless bot client for load and soak testing the server.
+//!
+//! Bots speak the same protocol as the game client: they authenticate, bootstrap the registries, decode the received
+//! chunk and entity streams, send movement input datagrams while walking a scripted path, and periodically edit blocks.
+//! They don't render anything, so many of them can run in a single process without a GPU.
+//! Only in-process connections are supported for now, as the network (QUIC) transport is not implemented yet.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use capnp::message::{Builder, TypedReader};
+use capnp_rpc::rpc_twoparty_capnp::Side;
+use capnp_rpc::twoparty::{VatId, VatNetwork};
+use capnp_rpc::{pry, RpcSystem};
+use gs_schemas::coordinates::AbsBlockPos;
+use gs_schemas::dependencies::capnp::capability::Promise;
+use gs_schemas::dependencies::capnp::Error;
+use gs_schemas::registries::GameRegistries;
+use gs_schemas::registry::RegistryName;
+use gs_schemas::schemas::network_capnp::authenticated_client_connection::{
+    AddChatMessageParams, AddChatMessageResults, ReloadRegistriesParams, ReloadRegistriesResults,
+    TerminateConnectionParams, TerminateConnectionResults,
+};
+use gs_schemas::schemas::network_capnp::datagram;
+use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::stream_packet::decode_stream_packet;
+use gs_schemas::schemas::NetworkStreamHeader;
+use gs_schemas::voxel::chunk::Chunk;
+use gs_schemas::voxel::voxeltypes::{BlockEntry, BlockRegistry, FrozenBlockRegistry, EMPTY_BLOCK_NAME};
+use smart_default::SmartDefault;
+use tokio::task::{spawn_local, JoinHandle};
+use tokio_util::bytes::Bytes;
+use tracing::Instrument;
+
+use crate::content::warn_resolved_aliases;
+use crate::network::datagram::DatagramSender;
+use crate::network::player_replication::{
+    voxel_position_of, write_quat, write_vec3, MAX_PLAYER_SPEED, PLAYER_SPAWN_POSITION,
+};
+use crate::network::protocol::negotiate_protocol;
+use crate::network::server::LocalConnectionPipe;
+use crate::network::transport::{InProcessStream, RPC_LOCAL_READER_OPTIONS};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::voxel::blocks::STONE_BLOCK_NAME;
+use crate::{builtin_game_registries, ServerData, SECONDS_PER_TICK_F32, TICK};
+
+/// The scripted behaviour of a [`HeadlessBot`].
+#[derive(Clone, Debug, SmartDefault)]
+pub struct BotScript {
+    /// The points the bot walks between in a loop, relative to the player spawn position.
+    #[default(square_path(32.0))]
+    pub waypoints: Vec<Vec3>,
+    /// The walking speed in blocks per second, capped to the maximum speed accepted by the server.
+    #[default = 8.0]
+    pub speed: f32,
+    /// How often the bot edits a block below itself, or None to never edit blocks.
+    #[default(Some(Duration::from_secs(1)))]
+    pub edit_interval: Option<Duration>,
+    /// The block placed by the edits, alternating with removing it.
+    #[default(STONE_BLOCK_NAME)]
+    pub edit_block: RegistryName,
+    /// The requested chunk loading radius, in chunks.
+    #[default = 4]
+    pub render_distance: i32,
+}
+
+/// Returns the corners of a square with the given side length in the horizontal plane, starting at the origin.
+pub fn square_path(size: f32) -> Vec<Vec3> {
+    vec![
+        Vec3::ZERO,
+        Vec3::new(size, 0.0, 0.0),
+        Vec3::new(size, 0.0, size),
+        Vec3::new(0.0, 0.0, size),
+    ]
+}
+
+/// Counters shared by a group of bots, updated as they receive and send data.
+#[derive(Debug, Default)]
+pub struct BotStats {
+    /// The number of bots currently connected and bootstrapped.
+    pub connected_bots: AtomicU64,
+    /// The number of received chunk data packets.
+    pub chunk_packets: AtomicU64,
+    /// The number of distinct chunks received by each bot, summed over all bots.
+    pub chunks_loaded: AtomicU64,
+    /// The number of received entity replication packets.
+    pub entity_packets: AtomicU64,
+    /// The total size of all received stream packets, in bytes.
+    pub stream_bytes: AtomicU64,
+    /// The number of received datagrams.
+    pub datagrams: AtomicU64,
+    /// The total size of all received datagrams, in bytes.
+    pub datagram_bytes: AtomicU64,
+    /// The number of block edits accepted by the server.
+    pub block_edits: AtomicU64,
+    /// The number of block edits rejected by the server.
+    pub rejected_edits: AtomicU64,
+    /// The number of registry ID mappings received after the server reloaded its content.
+    pub registry_reloads: AtomicU64,
+    /// The number of protocol errors, such as invalid packets or failed requests.
+    pub errors: AtomicU64,
+}
+
+/// A point-in-time copy of [`BotStats`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BotStatsSnapshot {
+    /// See [`BotStats::connected_bots`].
+    pub connected_bots: u64,
+    /// See [`BotStats::chunk_packets`].
+    pub chunk_packets: u64,
+    /// See [`BotStats::chunks_loaded`].
+    pub chunks_loaded: u64,
+    /// See [`BotStats::entity_packets`].
+    pub entity_packets: u64,
+    /// See [`BotStats::stream_bytes`].
+    pub stream_bytes: u64,
+    /// See [`BotStats::datagrams`].
+    pub datagrams: u64,
+    /// See [`BotStats::datagram_bytes`].
+    pub datagram_bytes: u64,
+    /// See [`BotStats::block_edits`].
+    pub block_edits: u64,
+    /// See [`BotStats::rejected_edits`].
+    pub rejected_edits: u64,
+    /// See [`BotStats::registry_reloads`].
+    pub registry_reloads: u64,
+    /// See [`BotStats::errors`].
+    pub errors: u64,
+}
+
+impl BotStats {
+    /// Reads the current values of all counters.
+    pub fn snapshot(&self) -> BotStatsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(AtomicOrdering::Relaxed);
+        BotStatsSnapshot {
+            connected_bots: load(&self.connected_bots),
+            chunk_packets: load(&self.chunk_packets),
+            chunks_loaded: load(&self.chunks_loaded),
+            entity_packets: load(&self.entity_packets),
+            stream_bytes: load(&self.stream_bytes),
+            datagrams: load(&self.datagrams),
+            datagram_bytes: load(&self.datagram_bytes),
+            block_edits: load(&self.block_edits),
+            rejected_edits: load(&self.rejected_edits),
+            registry_reloads: load(&self.registry_reloads),
+            errors: load(&self.errors),
+        }
+    }
+
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, AtomicOrdering::Relaxed);
+    }
+}
+
+impl BotStatsSnapshot {
+    /// Computes the counter increments since an earlier snapshot, keeping the current number of connected bots.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            connected_bots: self.connected_bots,
+            chunk_packets: self.chunk_packets.saturating_sub(earlier.chunk_packets),
+            chunks_loaded: self.chunks_loaded.saturating_sub(earlier.chunks_loaded),
+            entity_packets: self.entity_packets.saturating_sub(earlier.entity_packets),
+            stream_bytes: self.stream_bytes.saturating_sub(earlier.stream_bytes),
+            datagrams: self.datagrams.saturating_sub(earlier.datagrams),
+            datagram_bytes: self.datagram_bytes.saturating_sub(earlier.datagram_bytes),
+            block_edits: self.block_edits.saturating_sub(earlier.block_edits),
+            rejected_edits: self.rejected_edits.saturating_sub(earlier.rejected_edits),
+            registry_reloads: self.registry_reloads.saturating_sub(earlier.registry_reloads),
+            errors: self.errors.saturating_sub(earlier.errors),
+        }
+    }
+}
+
+/// A single scripted client without any rendering, running on the current tokio [`tokio::task::LocalSet`].
+pub struct HeadlessBot {
+    username: String,
+    script: BotScript,
+    stats: Arc<BotStats>,
+    registries: GameRegistries,
+    custom_streams: Option<AsyncUnboundedSender<InProcessStream>>,
+}
+
+/// The state of the bot's connection after a successful bootstrap.
+struct BotSession {
+    server_auth_rpc: rpc::authenticated_server_connection::Client,
+    registries: GameRegistries,
+    datagram_sender: DatagramSender,
+    tick_base: (u64, Instant),
+    /// The positions of the chunks received from the server, edits are only sent for blocks in loaded chunks.
+    loaded_chunks: Rc<RefCell<HashSet<IVec3>>>,
+}
+
+impl HeadlessBot {
+    /// Creates a bot that will authenticate with the given username, reporting to the given shared counters.
+    pub fn new(username: impl Into<String>, script: BotScript, stats: Arc<BotStats>) -> Self {
+        Self {
+            username: username.into(),
+            script,
+            stats,
+            registries: builtin_game_registries(),
+            custom_streams: None,
+        }
+    }
+
+    /// Makes the bot match the server's registry IDs against the given registries instead of the built-in ones, e.g.
+    /// the registries built from the content packs of the server.
+    pub fn with_registries(mut self, registries: GameRegistries) -> Self {
+        self.registries = registries;
+        self
+    }
+
+    /// Forwards the custom streams opened by the server to `sink` instead of rejecting them.
+    pub fn with_custom_streams(mut self, sink: AsyncUnboundedSender<InProcessStream>) -> Self {
+        self.custom_streams = Some(sink);
+        self
+    }
+
+    /// Connects to the server over the given local connection and runs the script until `stop` is set to true.
+    pub async fn run(self, (address, pipe): LocalConnectionPipe, mut stop: AsyncWatchReceiver<bool>) -> Result<()> {
+        let (read, write) = pipe.rpc_pipe.compat().split();
+        let network = VatNetwork::new(read, write, Side::Client, RPC_LOCAL_READER_OPTIONS);
+        let mut rpc_system = RpcSystem::new(Box::new(network), None);
+        let server_rpc: rpc::game_server::Client = rpc_system.bootstrap(VatId::Server);
+        let rpc_disconnector = rpc_system.get_disconnector();
+        let rpc_task = spawn_local(rpc_system.instrument(tracing::info_span!("bot-rpc", address = ?address)));
+
+        let mut tasks: Vec<JoinHandle<()>> = Vec::new();
+        let session = self
+            .connect(address, &server_rpc, pipe.outgoing_datagrams)
+            .await
+            .with_context(|| format!("Connecting bot {}", self.username));
+        let result = match session {
+            Ok(session) => {
+                tasks.push(spawn_local(
+                    stream_acceptor(
+                        pipe.incoming_streams,
+                        Arc::clone(&session.registries.block_types),
+                        Rc::clone(&session.loaded_chunks),
+                        self.custom_streams.clone(),
+                        Arc::clone(&self.stats),
+                    )
+                    .instrument(tracing::info_span!("bot-streams", address = ?address)),
+                ));
+                tasks.push(spawn_local(datagram_counter(
+                    pipe.incoming_datagrams,
+                    Arc::clone(&self.stats),
+                )));
+                BotStats::add(&self.stats.connected_bots, 1);
+                let result = self.walk(&session, &mut stop).await;
+                self.stats.connected_bots.fetch_sub(1, AtomicOrdering::Relaxed);
+                result
+            }
+            Err(e) => Err(e),
+        };
+
+        for task in tasks {
+            task.abort();
+        }
+        let _ = rpc_disconnector.await;
+        let _ = rpc_task.await;
+        if result.is_err() {
+            BotStats::add(&self.stats.errors, 1);
+        }
+        result
+    }
+
+    /// Negotiates the protocol, authenticates and bootstraps the registries.
+    async fn connect(
+        &self,
+        address: PeerAddress,
+        server_rpc: &rpc::game_server::Client,
+        outgoing_datagrams: AsyncBoundedSender<Bytes>,
+    ) -> Result<BotSession> {
+        negotiate_protocol(server_rpc).await?;
+
+        let mut auth_request = server_rpc.authenticate_request();
+        {
+            let mut builder = auth_request.get();
+            builder.set_username(self.username.as_str());
+            builder.set_connection(capnp_rpc::new_client(BotClientConnection {
+                registries: self.registries.clone(),
+                stats: Arc::clone(&self.stats),
+            }));
+        }
+        let auth_response = auth_request
+            .send()
+            .promise
+            .await
+            .context("RPC failure to authenticate")?;
+        let auth_response = auth_response.get().context("Invalid authentication response")?;
+        let auth_response = auth_response
+            .get_conn()
+            .context("Missing authentication response")?
+            .which()
+            .context("Illegal authentication response")?;
+        let server_auth_rpc = match auth_response {
+            gs_schemas::schemas::game_types_capnp::result::Which::Ok(ok) => ok?,
+            gs_schemas::schemas::game_types_capnp::result::Which::Err(err) => {
+                let err = err?;
+                let msg = err.get_message()?.to_str()?;
+                bail!("Authentication error {msg}");
+            }
+        };
+
+        let bootstrap_response = server_auth_rpc
+            .bootstrap_game_data_request()
+            .send()
+            .promise
+            .await
+            .context("Failed bootstrap request")?;
+        let (registries, resolved_aliases) = self
+            .registries
+            .clone_with_serialized_ids(&bootstrap_response.get()?.get_data()?)?;
+        warn_resolved_aliases("The server", &resolved_aliases);
+
+        let mut rq = server_auth_rpc.set_render_distance_request();
+        rq.get().set_radius(self.script.render_distance);
+        rq.send().promise.await.context("Failed render distance request")?;
+
+        let tick_response = server_auth_rpc
+            .get_server_tick_request()
+            .send()
+            .promise
+            .await
+            .context("Failed server tick request")?;
+        let tick_base = (tick_response.get()?.get_tick(), Instant::now());
+
+        debug!("Bot {} connected via {address:?}", self.username);
+        Ok(BotSession {
+            server_auth_rpc,
+            registries,
+            datagram_sender: DatagramSender::new(outgoing_datagrams),
+            tick_base,
+            loaded_chunks: default(),
+        })
+    }
+
+    /// Walks along the scripted path once per tick, editing blocks on the way, until stopped.
+    async fn walk(&self, session: &BotSession, stop: &mut AsyncWatchReceiver<bool>) -> Result<()> {
+        let edit_block = self.lookup_block(&session.registries, &self.script.edit_block)?;
+        let empty_block = self.lookup_block(&session.registries, &EMPTY_BLOCK_NAME)?;
+        let step = self.script.speed.clamp(0.0, MAX_PLAYER_SPEED) * SECONDS_PER_TICK_F32;
+
+        let mut position = PLAYER_SPAWN_POSITION;
+        let mut orientation = Quat::IDENTITY;
+        let mut next_waypoint = 0;
+        let mut sequence: u32 = 0;
+        let mut last_edit = Instant::now();
+        let mut place_next = true;
+        let mut interval = tokio::time::interval(TICK);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        while !*stop.borrow() {
+            tokio::select! {
+                _ = interval.tick() => {}
+                changed = stop.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(&waypoint) = self.script.waypoints.get(next_waypoint) {
+                let target = PLAYER_SPAWN_POSITION + waypoint;
+                let delta = target - position;
+                if delta.length() <= step {
+                    position = target;
+                    next_waypoint = (next_waypoint + 1) % self.script.waypoints.len();
+                } else {
+                    position += delta.normalize() * step;
+                }
+                if delta.x != 0.0 || delta.z != 0.0 {
+                    orientation = Quat::from_rotation_y(f32::atan2(-delta.x, -delta.z));
+                }
+            }
+
+            let (base_tick, base_time) = session.tick_base;
+            let tick = base_tick + (base_time.elapsed().as_micros() / TICK.as_micros()) as u64;
+            sequence = sequence.wrapping_add(1);
+            let mut message = Builder::new_default();
+            let mut input = message.init_root::<datagram::Builder>().init_player_input();
+            input.set_sequence(sequence);
+            input.set_tick(tick);
+            write_vec3(input.reborrow().init_position(), position);
+            write_quat(input.reborrow().init_orientation(), orientation);
+            session.datagram_sender.send(&message);
+
+            if let Some(edit_interval) = self.script.edit_interval {
+                let feet = voxel_position_of(position).into_ivec3();
+                let target = AbsBlockPos::new(feet.x, feet.y - 2, feet.z);
+                let (target_chunk, _) = target.split_chunk_component();
+                // Like a player, a bot can only edit the blocks it can see.
+                let target_loaded = session.loaded_chunks.borrow().contains(&IVec3::from(target_chunk));
+                if target_loaded && last_edit.elapsed() >= edit_interval {
+                    last_edit = Instant::now();
+                    let block = if place_next { edit_block } else { empty_block };
+                    place_next = !place_next;
+                    spawn_local(send_block_edit(
+                        session.server_auth_rpc.clone(),
+                        target,
+                        block,
+                        Arc::clone(&self.stats),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup_block(&self, registries: &GameRegistries, name: &RegistryName) -> Result<BlockEntry> {
+        let Some((id, _)) = registries.block_types.lookup_name_to_object(name.as_ref()) else {
+            bail!("Block {name} is not registered on the server");
+        };
+        Ok(BlockEntry::new(id, 0))
+    }
+}
+
+/// Requests a block edit, counting whether the server accepted it.
+async fn send_block_edit(
+    server_auth_rpc: rpc::authenticated_server_connection::Client,
+    position: AbsBlockPos,
+    block: BlockEntry,
+    stats: Arc<BotStats>,
+) {
+    let mut rq = server_auth_rpc.set_block_request();
+    {
+        let mut builder = rq.get();
+        let mut pos = builder.reborrow().init_position();
+        let position = position.into_ivec3();
+        pos.set_x(position.x);
+        pos.set_y(position.y);
+        pos.set_z(position.z);
+        builder.set_block(block.as_packed());
+    }
+    let accepted = match rq.send().promise.await {
+        Ok(response) => response.get().map(|r| r.get_accepted()),
+        Err(e) => Err(e),
+    };
+    match accepted {
+        Ok(true) => BotStats::add(&stats.block_edits, 1),
+        Ok(false) => BotStats::add(&stats.rejected_edits, 1),
+        Err(e) => {
+            warn!("Block edit request failed: {e}");
+            BotStats::add(&stats.errors, 1);
+        }
+    }
+}
+
+/// Accepts the streams opened by the server and consumes their packets.
+async fn stream_acceptor(
+    mut incoming_streams: AsyncUnboundedReceiver<InProcessStream>,
+    block_registry: Arc<FrozenBlockRegistry>,
+    loaded_chunks: Rc<RefCell<HashSet<IVec3>>>,
+    custom_streams: Option<AsyncUnboundedSender<InProcessStream>>,
+    stats: Arc<BotStats>,
+) {
+    while let Some(stream) = incoming_streams.recv().await {
+        let stats = Arc::clone(&stats);
+        match stream.header {
+            NetworkStreamHeader::Standard(StandardTypes::ChunkData) => {
+                let block_registry = Arc::clone(&block_registry);
+                let loaded_chunks = Rc::clone(&loaded_chunks);
+                spawn_local(consume_stream(stream.rx, stats.clone(), move |packet| {
+                    BotStats::add(&stats.chunk_packets, 1);
+                    let position = validate_chunk_packet(packet, &block_registry)?;
+                    if loaded_chunks.borrow_mut().insert(position) {
+                        BotStats::add(&stats.chunks_loaded, 1);
+                    }
+                    Ok(())
+                }));
+            }
+            NetworkStreamHeader::Standard(StandardTypes::EntityReplication) => {
+                spawn_local(consume_stream(stream.rx, stats.clone(), move |_| {
+                    BotStats::add(&stats.entity_packets, 1);
+                    Ok(())
+                }));
+            }
+            // Bots don't look at their inventories, the packets only count towards the stream traffic.
+            NetworkStreamHeader::Standard(StandardTypes::Inventory) => {
+                spawn_local(consume_stream(stream.rx, stats, |_| Ok(())));
+            }
+            // Without a sink, bots reject these streams like a client without the handler would.
+            NetworkStreamHeader::Custom(_) => match &custom_streams {
+                Some(sink) => drop(sink.send(stream)),
+                None => drop(stream),
+            },
+        }
+    }
+}
+
+/// Passes every packet of a stream to the handler until the stream is closed.
+async fn consume_stream(
+    mut rx: AsyncBoundedReceiver<Bytes>,
+    stats: Arc<BotStats>,
+    mut handler: impl FnMut(&[u8]) -> Result<()>,
+) {
+    while let Some(packet) = rx.recv().await {
+        BotStats::add(&stats.stream_bytes, packet.len() as u64);
+        if let Err(e) = handler(&packet) {
+            warn!("Invalid stream packet received by a bot: {e:#}");
+            BotStats::add(&stats.errors, 1);
+        }
+    }
+}
+
+/// Decodes a chunk packet and checks that all of its blocks are registered, returns the chunk position.
+fn validate_chunk_packet(packet: &[u8], block_registry: &BlockRegistry) -> Result<IVec3> {
+    let message = decode_stream_packet(packet, RPC_LOCAL_READER_OPTIONS)?;
+    let reader = TypedReader::<_, rpc::chunk_data_stream_packet::Owned>::new(message);
+    let root = reader.get()?;
+    let position = root.get_position()?;
+    let position = IVec3::new(position.get_x(), position.get_y(), position.get_z());
+    // Block entity updates of an already sent chunk carry no block data.
+    if !root.has_data() {
+        return Ok(position);
+    }
+    let chunk = Chunk::<ServerData>::read_full(&root.get_data()?, Default::default())?;
+    for entry in chunk.blocks.palette_entries() {
+        ensure!(
+            entry.lookup(block_registry).is_some(),
+            "Chunk {position} contains the unregistered block {entry:?}"
+        );
+    }
+    Ok(position)
+}
+
+/// Counts the received datagrams until the connection is closed.
+async fn datagram_counter(mut incoming_datagrams: AsyncBoundedReceiver<Bytes>, stats: Arc<BotStats>) {
+    while let Some(datagram) = incoming_datagrams.recv().await {
+        BotStats::add(&stats.datagrams, 1);
+        BotStats::add(&stats.datagram_bytes, datagram.len() as u64);
+    }
+}
+
+/// The client-side RPC object of a bot, only checking that the reloaded registries can be decoded and ignoring
+/// everything else the server sends.
+struct BotClientConnection {
+    registries: GameRegistries,
+    stats: Arc<BotStats>,
+}
+
+impl rpc::authenticated_client_connection::Server for BotClientConnection {
+    fn terminate_connection(
+        &mut self,
+        _: TerminateConnectionParams,
+        _: TerminateConnectionResults,
+    ) -> Promise<(), Error> {
+        Promise::ok(())
+    }
+
+    fn add_chat_message(&mut self, params: AddChatMessageParams, _: AddChatMessageResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let _ = pry!(params.get_text());
+        Promise::ok(())
+    }
+
+    fn reload_registries(&mut self, params: ReloadRegistriesParams, _: ReloadRegistriesResults) -> Promise<(), Error> {
+        let data = pry!(pry!(params.get()).get_data());
+        match self.registries.clone_with_serialized_ids(&data) {
+            Ok(_) => BotStats::add(&self.stats.registry_reloads, 1),
+            Err(e) => {
+                warn!("Bot could not decode the reloaded registries: {e}");
+                BotStats::add(&self.stats.errors, 1);
+            }
+        }
+        Promise::ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GameServer;
+
+    #[test]
+    fn bot_loads_chunks_and_edits_blocks() {
+        let server = GameServer::new_test();
+        server.set_paused(false);
+        let stats = Arc::new(BotStats::default());
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(tokio::task::LocalSet::new().run_until(async {
+                let connection = server.create_local_connection().async_wait().await.unwrap();
+                let (stop_tx, stop_rx) = async_watch_channel(false);
+                let script = BotScript {
+                    edit_interval: Some(TICK * 4),
+                    render_distance: 1,
+                    ..Default::default()
+                };
+                let bot = spawn_local(HeadlessBot::new("TestBot", script, Arc::clone(&stats)).run(connection, stop_rx));
+
+                let deadline = Instant::now() + Duration::from_secs(30);
+                while Instant::now() < deadline {
+                    let snapshot = stats.snapshot();
+                    if snapshot.chunks_loaded > 0 && snapshot.block_edits > 1 {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                stop_tx.send(true).unwrap();
+                bot.await.unwrap().unwrap();
+                server.shutdown().await.unwrap();
+            }));
+
+        let snapshot = stats.snapshot();
+        assert!(snapshot.chunks_loaded > 0, "{snapshot:?}");
+        // Both the placed block and its removal are accepted.
+        assert!(snapshot.block_edits > 1, "{snapshot:?}");
+        assert_eq!(snapshot.rejected_edits, 0, "{snapshot:?}");
+        assert_eq!(snapshot.errors, 0, "{snapshot:?}");
+        assert_eq!(snapshot.connected_bots, 0);
+    }
+
+    #[test]
+    fn bots_receive_reloaded_registries() {
+        let server = GameServer::new_test();
+        server.set_paused(false);
+        let stats = Arc::new(BotStats::default());
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(tokio::task::LocalSet::new().run_until(async {
+                let (stop_tx, stop_rx) = async_watch_channel(false);
+                let mut bots = Vec::new();
+                for i in 0..2 {
+                    let connection = server.create_local_connection().async_wait().await.unwrap();
+                    let script = BotScript {
+                        edit_interval: None,
+                        render_distance: 0,
+                        ..Default::default()
+                    };
+                    let bot = HeadlessBot::new(format!("TestBot{i}"), script, Arc::clone(&stats));
+                    bots.push(spawn_local(bot.run(connection, stop_rx.clone())));
+                }
+
+                let deadline = Instant::now() + Duration::from_secs(30);
+                while Instant::now() < deadline && stats.snapshot().connected_bots < 2 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                let registries = server.server_data.shared_registries.load_full();
+                server
+                    .network_thread
+                    .schedule_task(move |state| {
+                        Box::pin(async move {
+                            state.borrow().broadcast_reloaded_registries(&registries);
+                            Ok(())
+                        })
+                    })
+                    .async_wait()
+                    .await
+                    .unwrap();
+                while Instant::now() < deadline && stats.snapshot().registry_reloads < 2 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+
+                stop_tx.send(true).unwrap();
+                for bot in bots {
+                    bot.await.unwrap().unwrap();
+                }
+                server.shutdown().await.unwrap();
+            }));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.registry_reloads, 2, "{snapshot:?}");
+        assert_eq!(snapshot.errors, 0, "{snapshot:?}");
+    }
+}
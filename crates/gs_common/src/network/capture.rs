@@ -0,0 +1,546 @@
+//! Opt-in recording of network traffic into capture files, and decoding of the captures for offline inspection.
+//!
+//! The capture file format is defined by `CaptureHeader` and `CapturedPacket` in network.capnp, so captures can be read
+//! by tools written in any language with capnp support. [`record_traffic`] taps one end of an in-process connection,
+//! and the `gs_capture_inspect` tool prints the captured messages as text or JSON.
+
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use bevy::log::error;
+use capnp::message::ReaderOptions;
+use gs_schemas::schemas::network_capnp::captured_packet::{Channel, Direction};
+use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::network_capnp::{capture_header, captured_packet};
+use gs_schemas::schemas::stream_packet::decode_stream_packet;
+use gs_schemas::schemas::{NetworkStreamHeader, NetworkStreamHeaderExt};
+use gs_schemas::GameSide;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::task::spawn_local;
+use tokio_util::bytes::Bytes;
+
+use crate::network::datagram::DATAGRAM_READER_OPTIONS;
+use crate::network::transport::{
+    InProcessDuplex, InProcessStream, INPROCESS_DATAGRAM_QUEUE_LENGTH, INPROCESS_SOCKET_BUFFER_SIZE,
+    RPC_LOCAL_READER_OPTIONS,
+};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+
+/// The file extension of network traffic captures.
+pub const CAPTURE_FILE_EXTENSION: &str = "gscap";
+/// Capnp reader options for capture file entries, large enough for any recorded packet.
+pub static CAPTURE_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(1024 * 1024 * 1024),
+    nesting_limit: 128,
+};
+/// Size of the buffer used for relaying the RPC byte pipe.
+const TAP_READ_BUFFER_SIZE: usize = 16 * 1024;
+/// The maximum number of segments in a capnp message, larger segment tables are not parsed as RPC messages.
+const MAX_RPC_MESSAGE_SEGMENTS: usize = 512;
+
+/// Writes captured packets of a single connection into a capture file, from a background thread.
+pub struct TrafficRecorder {
+    start: Instant,
+    current_tick: Box<dyn Fn() -> u64>,
+    next_stream_id: Cell<u32>,
+    writer: StdUnboundedSender<Vec<u8>>,
+    writer_done: Arc<Mutex<StdUnboundedReceiver<()>>>,
+}
+
+/// Waits for the writer thread of a [`TrafficRecorder`] to flush and close its capture file.
+pub struct CaptureFlushHandle(Arc<Mutex<StdUnboundedReceiver<()>>>);
+
+impl CaptureFlushHandle {
+    /// Blocks until all the recorders sharing the capture file are dropped and the file is fully written.
+    pub fn wait(self) {
+        // The writer thread never sends anything, the channel disconnects when the thread exits.
+        let _ = self.0.lock().unwrap().recv();
+    }
+}
+
+impl TrafficRecorder {
+    /// Creates a new capture file for the connection to `peer` in the given directory, and starts the writer thread.
+    /// `current_tick` is queried for the server tick of every recorded packet.
+    pub fn create_in_directory(
+        directory: &Path,
+        peer: PeerAddress,
+        side: GameSide,
+        current_tick: impl Fn() -> u64 + 'static,
+    ) -> Result<Self> {
+        let start_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Creating capture directory {}", directory.display()))?;
+        let path = directory.join(capture_file_name(peer, side, start_time));
+        let mut file =
+            BufWriter::new(File::create(&path).with_context(|| format!("Creating capture file {}", path.display()))?);
+
+        let mut header = capnp::message::Builder::new_default();
+        let mut root = header.init_root::<capture_header::Builder>();
+        root.set_start_time(start_time);
+        root.set_peer(&format!("{peer:?}")[..]);
+        root.set_side(side_name(side));
+        capnp::serialize::write_message(&mut file, &header)?;
+
+        let (writer, entries) = std_unbounded_channel::<Vec<u8>>();
+        let (done_sender, writer_done) = std_unbounded_channel::<()>();
+        std::thread::Builder::new()
+            .name(format!("GS {side:?} Traffic Recorder"))
+            .spawn(move || {
+                Self::writer_thread_main(path, file, entries);
+                drop(done_sender);
+            })
+            .context("Could not create a thread for the traffic recorder")?;
+
+        Ok(Self {
+            start: Instant::now(),
+            current_tick: Box::new(current_tick),
+            next_stream_id: Cell::new(0),
+            writer,
+            writer_done: Arc::new(Mutex::new(writer_done)),
+        })
+    }
+
+    /// A handle to wait for the capture file to be completely written after the recorder is dropped.
+    pub fn flush_handle(&self) -> CaptureFlushHandle {
+        CaptureFlushHandle(Arc::clone(&self.writer_done))
+    }
+
+    fn writer_thread_main(path: PathBuf, mut file: BufWriter<File>, entries: StdUnboundedReceiver<Vec<u8>>) {
+        for entry in entries {
+            if let Err(e) = file.write_all(&entry) {
+                error!("Could not write to the capture file {}: {e}", path.display());
+                return;
+            }
+        }
+        if let Err(e) = file.flush() {
+            error!("Could not write to the capture file {}: {e}", path.display());
+        }
+    }
+
+    /// Allocates the identifier of a newly opened stream.
+    fn new_stream_id(&self) -> u32 {
+        let id = self.next_stream_id.get();
+        self.next_stream_id.set(id + 1);
+        id
+    }
+
+    /// Records a single message or packet, stream packets have to specify the stream they were sent on.
+    pub fn record(
+        &self,
+        direction: Direction,
+        channel: Channel,
+        stream: Option<(u32, &NetworkStreamHeader)>,
+        data: &[u8],
+    ) {
+        let mut message = capnp::message::Builder::new_default();
+        let mut packet = message.init_root::<captured_packet::Builder>();
+        packet.set_time(self.start.elapsed().as_micros() as u64);
+        packet.set_tick((self.current_tick)());
+        packet.set_direction(direction);
+        packet.set_channel(channel);
+        if let Some((id, header)) = stream {
+            packet.set_stream_id(id);
+            header.write_to_message(&mut packet.reborrow().init_stream_header());
+        }
+        packet.set_data(data);
+        // The writer thread only exits after a write error, which was already logged.
+        let _ = self.writer.send(capnp::serialize::write_message_to_words(&message));
+    }
+}
+
+/// The name of a new capture file, unique for each connection.
+fn capture_file_name(peer: PeerAddress, side: GameSide, start_time: u64) -> String {
+    let peer = match peer {
+        PeerAddress::Local(id) => format!("local{id}"),
+        PeerAddress::Remote(addr) => addr.to_string().replace([':', '[', ']'], "_"),
+    };
+    format!("{}-{peer}-{start_time}.{CAPTURE_FILE_EXTENSION}", side_name(side))
+}
+
+/// The side name stored in capture files.
+fn side_name(side: GameSide) -> &'static str {
+    match side {
+        GameSide::Server => "server",
+        GameSide::Client => "client",
+    }
+}
+
+/// Wraps one end of an in-process connection, recording all the traffic going through it.
+/// Must be called within the tokio LocalSet, the relay tasks stop when the channels are closed on either side.
+pub fn record_traffic(inner: InProcessDuplex, recorder: Rc<TrafficRecorder>) -> InProcessDuplex {
+    let InProcessDuplex {
+        rpc_pipe,
+        mut incoming_streams,
+        outgoing_streams,
+        incoming_datagrams,
+        outgoing_datagrams,
+    } = inner;
+
+    let (outer_rpc_pipe, relay_pipe) = tokio::io::duplex(INPROCESS_SOCKET_BUFFER_SIZE);
+    let (relay_read, relay_write) = tokio::io::split(relay_pipe);
+    let (inner_read, inner_write) = tokio::io::split(rpc_pipe);
+    spawn_local(tap_bytes(
+        Rc::clone(&recorder),
+        Direction::Sent,
+        relay_read,
+        inner_write,
+    ));
+    spawn_local(tap_bytes(
+        Rc::clone(&recorder),
+        Direction::Received,
+        inner_read,
+        relay_write,
+    ));
+
+    let (outer_outgoing_streams, mut relay_outgoing_streams) = async_unbounded_channel::<InProcessStream>();
+    let stream_recorder = Rc::clone(&recorder);
+    spawn_local(async move {
+        while let Some(outer) = relay_outgoing_streams.recv().await {
+            let (relay_side, forwarded) = InProcessStream::new_pair(outer.header.clone());
+            tap_stream(&stream_recorder, outer, relay_side);
+            if outgoing_streams.send(forwarded).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (relay_incoming_streams, outer_incoming_streams) = async_unbounded_channel::<InProcessStream>();
+    let stream_recorder = Rc::clone(&recorder);
+    spawn_local(async move {
+        while let Some(inner) = incoming_streams.recv().await {
+            let (relay_side, forwarded) = InProcessStream::new_pair(inner.header.clone());
+            tap_stream(&stream_recorder, relay_side, inner);
+            if relay_incoming_streams.send(forwarded).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (outer_outgoing_datagrams, relay_outgoing_datagrams) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
+    spawn_local(tap_packets(
+        Rc::clone(&recorder),
+        Direction::Sent,
+        None,
+        relay_outgoing_datagrams,
+        outgoing_datagrams,
+    ));
+    let (relay_incoming_datagrams, outer_incoming_datagrams) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
+    spawn_local(tap_packets(
+        recorder,
+        Direction::Received,
+        None,
+        incoming_datagrams,
+        relay_incoming_datagrams,
+    ));
+
+    InProcessDuplex {
+        rpc_pipe: outer_rpc_pipe,
+        incoming_streams: outer_incoming_streams,
+        outgoing_streams: outer_outgoing_streams,
+        incoming_datagrams: outer_incoming_datagrams,
+        outgoing_datagrams: outer_outgoing_datagrams,
+    }
+}
+
+/// Records the packets of a stream between `outer` (connected to the local user) and `inner` (connected to the peer).
+fn tap_stream(recorder: &Rc<TrafficRecorder>, outer: InProcessStream, inner: InProcessStream) {
+    let stream = Some((recorder.new_stream_id(), outer.header.clone()));
+    spawn_local(tap_packets(
+        Rc::clone(recorder),
+        Direction::Sent,
+        stream.clone(),
+        outer.rx,
+        inner.tx,
+    ));
+    spawn_local(tap_packets(
+        Rc::clone(recorder),
+        Direction::Received,
+        stream,
+        inner.rx,
+        outer.tx,
+    ));
+}
+
+/// Forwards stream packets (if `stream` is set) or datagrams from `rx` to `tx`, recording each of them.
+async fn tap_packets(
+    recorder: Rc<TrafficRecorder>,
+    direction: Direction,
+    stream: Option<(u32, NetworkStreamHeader)>,
+    mut rx: AsyncBoundedReceiver<Bytes>,
+    tx: AsyncBoundedSender<Bytes>,
+) {
+    while let Some(packet) = rx.recv().await {
+        match &stream {
+            Some((id, header)) => {
+                recorder.record(direction, Channel::Stream, Some((*id, header)), &packet);
+                if tx.send(packet).await.is_err() {
+                    return;
+                }
+            }
+            None => {
+                recorder.record(direction, Channel::Datagram, None, &packet);
+                if tx.try_send(packet).is_err() && tx.is_closed() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Forwards the bytes of the RPC pipe, recording every complete RPC message.
+async fn tap_bytes(
+    recorder: Rc<TrafficRecorder>,
+    direction: Direction,
+    mut reader: ReadHalf<DuplexStream>,
+    mut writer: WriteHalf<DuplexStream>,
+) {
+    let mut framer = RpcMessageFramer::default();
+    let mut buffer = vec![0u8; TAP_READ_BUFFER_SIZE];
+    loop {
+        let n = match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        for message in framer.push(&buffer[..n]) {
+            recorder.record(direction, Channel::Rpc, None, &message);
+        }
+        if writer.write_all(&buffer[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Splits a byte stream of unpacked capnp messages into whole messages.
+#[derive(Default)]
+struct RpcMessageFramer {
+    buffer: Vec<u8>,
+}
+
+impl RpcMessageFramer {
+    /// Appends the bytes to the buffer, and takes all the complete messages out of it.
+    fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut messages = Vec::new();
+        while let Some(length) = Self::message_length(&self.buffer) {
+            if self.buffer.len() < length {
+                break;
+            }
+            messages.push(self.buffer.drain(..length).collect());
+        }
+        messages
+    }
+
+    /// The total length of the message at the start of the buffer, once its segment table is complete.
+    fn message_length(buffer: &[u8]) -> Option<usize> {
+        let segment_count = u32::from_le_bytes(buffer.get(0..4)?.try_into().ok()?) as usize + 1;
+        if segment_count > MAX_RPC_MESSAGE_SEGMENTS {
+            // Not a valid message, so take the rest of the buffer to avoid buffering the whole stream.
+            return Some(buffer.len());
+        }
+        let table = buffer.get(4..4 + 4 * segment_count)?;
+        let words: usize = table
+            .chunks_exact(4)
+            .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+            .sum();
+        Some((4 + 4 * segment_count).next_multiple_of(8) + words * 8)
+    }
+}
+
+/// The metadata at the start of a capture file.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CaptureInfo {
+    /// Unix time of the start of the capture, in milliseconds.
+    pub start_time: u64,
+    /// The address of the peer on the other side of the recorded connection.
+    pub peer: String,
+    /// The side of the connection the capture was made on.
+    pub side: String,
+}
+
+/// A single message or packet read from a capture file.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CaptureRecord {
+    /// Time since the start of the capture.
+    pub time: Duration,
+    /// The server game tick at the time of capture.
+    pub tick: u64,
+    /// Whether the packet was sent or received by the recording side.
+    pub direction: Direction,
+    /// The channel the packet was transferred on.
+    pub channel: Channel,
+    /// The stream ID and header, for stream packets.
+    pub stream: Option<(u32, NetworkStreamHeader)>,
+    /// The raw message or packet.
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// A human-readable name of the stream type, `ns:key` for custom streams.
+    pub fn stream_type_name(&self) -> Option<String> {
+        self.stream.as_ref().map(|(_, header)| match header {
+            NetworkStreamHeader::Standard(standard) => match standard {
+                StandardTypes::ChunkData => String::from("chunkData"),
+                StandardTypes::EntityReplication => String::from("entityReplication"),
+                StandardTypes::Inventory => String::from("inventory"),
+            },
+            NetworkStreamHeader::Custom(name) => name.to_string(),
+        })
+    }
+
+    /// Decodes the captured data into the capnp text representation, custom stream packets are left undecoded.
+    pub fn decode_payload(&self) -> Result<Option<String>> {
+        Ok(Some(match self.channel {
+            Channel::Rpc => {
+                let message = capnp::serialize::read_message(&mut &self.data[..], RPC_LOCAL_READER_OPTIONS)?;
+                format!("{:?}", message.get_root::<capnp_rpc::rpc_capnp::message::Reader>()?)
+            }
+            Channel::Datagram => {
+                let message = decode_stream_packet(&self.data, DATAGRAM_READER_OPTIONS)?;
+                format!("{:?}", message.get_root::<rpc::datagram::Reader>()?)
+            }
+            Channel::Stream => {
+                let Some((_, NetworkStreamHeader::Standard(standard))) = &self.stream else {
+                    return Ok(None);
+                };
+                let message = decode_stream_packet(&self.data, RPC_LOCAL_READER_OPTIONS)?;
+                match standard {
+                    StandardTypes::ChunkData => {
+                        format!("{:?}", message.get_root::<rpc::chunk_data_stream_packet::Reader>()?)
+                    }
+                    StandardTypes::EntityReplication => {
+                        format!("{:?}", message.get_root::<rpc::entity_replication_packet::Reader>()?)
+                    }
+                    StandardTypes::Inventory => {
+                        format!("{:?}", message.get_root::<rpc::inventory_packet::Reader>()?)
+                    }
+                }
+            }
+        }))
+    }
+}
+
+/// Reads the records of a capture file one by one.
+pub struct CaptureReader<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    /// Reads the capture file header, returning the reader positioned at the first record.
+    pub fn open(mut reader: R) -> Result<(Self, CaptureInfo)> {
+        let message = capnp::serialize::read_message(&mut reader, CAPTURE_READER_OPTIONS)
+            .context("Reading the capture file header")?;
+        let header = message.get_root::<capture_header::Reader>()?;
+        let info = CaptureInfo {
+            start_time: header.get_start_time(),
+            peer: header.get_peer()?.to_str()?.to_owned(),
+            side: header.get_side()?.to_str()?.to_owned(),
+        };
+        Ok((Self { reader }, info))
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>> {
+        let Some(message) = capnp::serialize::try_read_message(&mut self.reader, CAPTURE_READER_OPTIONS)? else {
+            return Ok(None);
+        };
+        let packet = message.get_root::<captured_packet::Reader>()?;
+        let channel = packet.get_channel()?;
+        let stream = if channel == Channel::Stream {
+            let header = NetworkStreamHeader::read_from_message(&packet.get_stream_header()?)?;
+            Some((packet.get_stream_id(), header))
+        } else {
+            None
+        };
+        Ok(Some(CaptureRecord {
+            time: Duration::from_micros(packet.get_time()),
+            tick: packet.get_tick(),
+            direction: packet.get_direction()?,
+            channel,
+            stream,
+            data: packet.get_data()?.to_vec(),
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use capnp::message::Builder;
+
+    use super::*;
+    use crate::network::run_local;
+
+    #[test]
+    fn rpc_message_framing() {
+        let mut message = Builder::new_default();
+        message.init_root::<rpc::player_input::Builder>().set_tick(42);
+        let bytes = capnp::serialize::write_message_to_words(&message);
+        let stream: Vec<u8> = [&bytes[..], &bytes[..]].concat();
+
+        let mut framer = RpcMessageFramer::default();
+        let mut messages = framer.push(&stream[..3]);
+        assert!(messages.is_empty());
+        messages.extend(framer.push(&stream[3..bytes.len() + 5]));
+        assert_eq!(messages, vec![bytes.clone()]);
+        messages.extend(framer.push(&stream[bytes.len() + 5..]));
+        assert_eq!(messages, vec![bytes.clone(), bytes]);
+        assert!(framer.buffer.is_empty());
+    }
+
+    #[test]
+    fn record_and_read_capture() {
+        let directory = std::env::temp_dir().join(format!("gs-capture-test-{}", std::process::id()));
+        let recorder =
+            TrafficRecorder::create_in_directory(&directory, PeerAddress::Local(0), GameSide::Client, || 7).unwrap();
+        let flushed = recorder.flush_handle();
+        run_local(async move {
+            let (client, mut server) = InProcessDuplex::new_pair();
+            let client = record_traffic(client, Rc::new(recorder));
+
+            let header = NetworkStreamHeader::Standard(StandardTypes::ChunkData);
+            let (local, remote) = InProcessStream::new_pair(header.clone());
+            client.outgoing_streams.send(remote).unwrap();
+            let accepted = server.incoming_streams.recv().await.unwrap();
+            local.tx.send(Bytes::from_static(b"chunk")).await.unwrap();
+            client
+                .outgoing_datagrams
+                .send(Bytes::from_static(b"input"))
+                .await
+                .unwrap();
+            server.incoming_datagrams.recv().await.unwrap();
+            drop((client, local, accepted));
+        });
+        // Dropping the LocalSet stops the relay tasks and the recorder, then the writer thread flushes the file.
+        flushed.wait();
+
+        let path = std::fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+        let (reader, info) = CaptureReader::open(BufReader::new(File::open(&path).unwrap())).unwrap();
+        assert_eq!((info.peer.as_str(), info.side.as_str()), ("Local(0)", "client"));
+        let records: Vec<CaptureRecord> = reader.collect::<Result<_>>().unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let stream_packet = records.iter().find(|r| r.channel == Channel::Stream).unwrap();
+        assert_eq!(stream_packet.direction, Direction::Sent);
+        assert_eq!(stream_packet.tick, 7);
+        assert_eq!(stream_packet.stream_type_name().as_deref(), Some("chunkData"));
+        assert_eq!(stream_packet.data, b"chunk");
+        let datagram = records.iter().find(|r| r.channel == Channel::Datagram).unwrap();
+        assert_eq!(datagram.data, b"input");
+        assert_eq!(datagram.stream, None);
+    }
+}
@@ -0,0 +1,294 @@
+//! Per-client chunk data streaming with bounded queues and bandwidth throttling.
+//!
+//! The voxel systems push serialized chunk packets into a small bounded queue for each player,
+//! a per-client task on the network thread drains that queue into the network stream at a rate limited by the
+//! configured bandwidth budget. A full queue is reported back to the sending system, which then simply retries
+//! on the next tick with a fresh priority order, so slow clients never make the server buffer unbounded data.
+//! Changes of the configured bandwidth budget apply to the already connected clients too.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_util::bytes::Bytes;
+use tracing::Instrument;
+
+use crate::config::GameConfig;
+use crate::network::server::NetworkThreadServerState;
+use crate::network::transport::InProcessStream;
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::GameServer;
+
+/// The maximum number of chunk packets queued for a single client before applying backpressure to the sender.
+pub const CHUNK_SEND_QUEUE_LENGTH: usize = 32;
+
+/// Live statistics of a client's chunk stream, shared between the network thread and the game systems.
+#[derive(Debug, Default)]
+pub struct ChunkStreamStats {
+    /// Number of packets waiting in the send queue.
+    pub queued_packets: AtomicUsize,
+    /// Number of bytes waiting in the send queue.
+    pub queued_bytes: AtomicUsize,
+    /// Total number of packets written to the network stream.
+    pub sent_packets: AtomicU64,
+    /// Total number of bytes written to the network stream.
+    pub sent_bytes: AtomicU64,
+    /// Set when the underlying network stream was closed by the peer.
+    pub dead: AtomicBool,
+}
+
+/// The reason a chunk packet could not be queued for sending.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChunkSendError {
+    /// The client's queue is full, retry later.
+    QueueFull,
+    /// The client's stream died, no more packets can be sent.
+    StreamClosed,
+}
+
+/// The game-side handle for queueing chunk packets to a connected player, attached to [`ConnectedPlayer`] entities.
+///
+/// [`ConnectedPlayer`]: crate::network::server::ConnectedPlayer
+#[derive(Component)]
+pub struct ChunkStreamSender {
+    queue: AsyncBoundedSender<Bytes>,
+    stats: Arc<ChunkStreamStats>,
+    compression: PacketCompression,
+}
+
+impl ChunkStreamSender {
+    /// Number of packets that can currently be queued without hitting backpressure.
+    pub fn free_capacity(&self) -> usize {
+        if self.is_closed() {
+            0
+        } else {
+            self.queue.capacity()
+        }
+    }
+
+    /// Checks if the client's stream died.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed() || self.stats.dead.load(AtomicOrdering::Relaxed)
+    }
+
+    /// The compression negotiated for packets sent on this stream.
+    pub fn compression(&self) -> PacketCompression {
+        self.compression
+    }
+
+    /// The live stream statistics.
+    pub fn stats(&self) -> &Arc<ChunkStreamStats> {
+        &self.stats
+    }
+
+    /// A sender queueing up to `queue_length` packets into the returned receiver, for tests.
+    #[cfg(test)]
+    pub(crate) fn new_test(queue_length: usize) -> (Self, AsyncBoundedReceiver<Bytes>) {
+        let (queue, queue_rx) = async_bounded_channel(queue_length);
+        let sender = Self {
+            queue,
+            stats: default(),
+            compression: PacketCompression::None,
+        };
+        (sender, queue_rx)
+    }
+
+    /// Attempts to queue a packet without blocking.
+    pub fn try_send(&self, packet: Bytes) -> Result<(), ChunkSendError> {
+        let len = packet.len();
+        // Count before sending, the network thread might dequeue the packet immediately.
+        self.stats.queued_packets.fetch_add(1, AtomicOrdering::Relaxed);
+        self.stats.queued_bytes.fetch_add(len, AtomicOrdering::Relaxed);
+        let result = self.queue.try_send(packet);
+        if let Err(e) = result {
+            self.stats.queued_packets.fetch_sub(1, AtomicOrdering::Relaxed);
+            self.stats.queued_bytes.fetch_sub(len, AtomicOrdering::Relaxed);
+            return Err(match e {
+                TrySendError::Full(_) => ChunkSendError::QueueFull,
+                TrySendError::Closed(_) => ChunkSendError::StreamClosed,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket limiting the average throughput, while allowing bursts of up to one second worth of data.
+#[derive(Clone, Debug)]
+pub struct BandwidthLimiter {
+    bytes_per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl BandwidthLimiter {
+    /// Constructs a limiter starting with a full bucket.
+    pub fn new(bytes_per_second: u64, now: Instant) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            tokens: bytes_per_second as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Changes the throughput limit, keeping the tokens accumulated at the previous rate up to the new bucket size.
+    pub fn set_rate(&mut self, bytes_per_second: u64, now: Instant) {
+        self.refill(now);
+        self.bytes_per_second = bytes_per_second as f64;
+        self.tokens = self.tokens.min(self.bytes_per_second);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+    }
+
+    /// Consumes tokens for sending `bytes`, returns how long to wait before actually sending them.
+    pub fn reserve(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.refill(now);
+        // Packets larger than the bucket are allowed, they just put the bucket into debt.
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second)
+        }
+    }
+}
+
+/// Creates the game-side sender and spawns the network task draining it into `stream`.
+/// Must be called within the tokio LocalSet.
+pub fn spawn_chunk_stream(
+    this: Rc<RefCell<NetworkThreadServerState>>,
+    engine: Arc<GameServer>,
+    peer: PeerAddress,
+    stream: InProcessStream,
+    compression: PacketCompression,
+) -> ChunkStreamSender {
+    let (queue_tx, queue_rx) = async_bounded_channel(CHUNK_SEND_QUEUE_LENGTH);
+    let stats = Arc::new(ChunkStreamStats::default());
+    let task = chunk_stream_sender_task(this, engine, peer, stream, queue_rx, Arc::clone(&stats));
+    tokio::task::spawn_local(task.instrument(tracing::info_span!("server-chunk-stream", address = ?peer)));
+    ChunkStreamSender {
+        queue: queue_tx,
+        stats,
+        compression,
+    }
+}
+
+/// The chunk bandwidth budget of a client, in bytes per second, or 0 for no limit.
+fn client_bandwidth_limit(peer: PeerAddress, config: &GameConfig) -> u64 {
+    match peer {
+        // In-process connections are not limited by any real network link.
+        PeerAddress::Local(_) => 0,
+        PeerAddress::Remote(_) => config.server.chunk_bandwidth_limit,
+    }
+}
+
+/// Applies a changed bandwidth budget to the limiter of a client, creating or removing the limiter as needed.
+fn update_bandwidth_limiter(limiter: &mut Option<BandwidthLimiter>, bandwidth_limit: u64, now: Instant) {
+    match limiter {
+        _ if bandwidth_limit == 0 => *limiter = None,
+        Some(limiter) => limiter.set_rate(bandwidth_limit, now),
+        None => *limiter = Some(BandwidthLimiter::new(bandwidth_limit, now)),
+    }
+}
+
+async fn chunk_stream_sender_task(
+    this: Rc<RefCell<NetworkThreadServerState>>,
+    engine: Arc<GameServer>,
+    peer: PeerAddress,
+    stream: InProcessStream,
+    mut queue: AsyncBoundedReceiver<Bytes>,
+    stats: Arc<ChunkStreamStats>,
+) {
+    let mut config = engine.config().clone();
+    let mut bandwidth_limit = client_bandwidth_limit(peer, &config.borrow_and_update());
+    let mut limiter = (bandwidth_limit > 0).then(|| BandwidthLimiter::new(bandwidth_limit, Instant::now()));
+    let mut config_open = true;
+    loop {
+        let packet = tokio::select! {
+            // The queue closes when the player entity (holding the sender) is despawned.
+            packet = queue.recv() => match packet {
+                Some(packet) => packet,
+                None => return,
+            },
+            changed = config.changed(), if config_open => {
+                if changed.is_err() {
+                    // The configuration only goes away with the server, keep the current budget until then.
+                    config_open = false;
+                    continue;
+                }
+                let new_limit = client_bandwidth_limit(peer, &config.borrow_and_update());
+                if new_limit != bandwidth_limit {
+                    debug!("Changing the chunk bandwidth limit of {peer:?} to {new_limit} B/s");
+                    bandwidth_limit = new_limit;
+                    update_bandwidth_limiter(&mut limiter, bandwidth_limit, Instant::now());
+                }
+                continue;
+            }
+        };
+        let len = packet.len();
+        stats.queued_packets.fetch_sub(1, AtomicOrdering::Relaxed);
+        stats.queued_bytes.fetch_sub(len, AtomicOrdering::Relaxed);
+        if let Some(limiter) = &mut limiter {
+            let delay = limiter.reserve(len, Instant::now());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        if stream.tx.send(packet).await.is_err() {
+            stats.dead.store(true, AtomicOrdering::Relaxed);
+            warn!("Chunk stream to {peer:?} was closed by the client, disconnecting it");
+            queue.close();
+            NetworkThreadServerState::disconnect_client(&this, &engine, peer).await;
+            return;
+        }
+        stats.sent_packets.fetch_add(1, AtomicOrdering::Relaxed);
+        stats.sent_bytes.fetch_add(len as u64, AtomicOrdering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bandwidth_limiter() {
+        let start = Instant::now();
+        let mut limiter = BandwidthLimiter::new(1000, start);
+        // The initial burst is free.
+        assert_eq!(limiter.reserve(1000, start), Duration::ZERO);
+        // Then the bucket goes into debt.
+        assert_eq!(limiter.reserve(500, start), Duration::from_millis(500));
+        // After a second the debt is repaid and 500 bytes are available again.
+        assert_eq!(limiter.reserve(500, start + Duration::from_secs(1)), Duration::ZERO);
+        // The bucket never holds more than one second worth of data.
+        assert_eq!(
+            limiter.reserve(2000, start + Duration::from_secs(100)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn bandwidth_limit_changes() {
+        let start = Instant::now();
+        let mut limiter = None;
+        update_bandwidth_limiter(&mut limiter, 1000, start);
+        assert_eq!(
+            limiter.as_mut().unwrap().reserve(1500, start),
+            Duration::from_millis(500)
+        );
+        // Raising the limit repays the debt faster.
+        update_bandwidth_limiter(&mut limiter, 2000, start);
+        assert_eq!(limiter.as_mut().unwrap().reserve(0, start), Duration::from_millis(250));
+        // Lowering it shrinks the bucket of accumulated tokens.
+        let later = start + Duration::from_secs(10);
+        update_bandwidth_limiter(&mut limiter, 100, later);
+        assert_eq!(limiter.as_mut().unwrap().reserve(200, later), Duration::from_secs(1));
+        update_bandwidth_limiter(&mut limiter, 0, later);
+        assert!(limiter.is_none());
+    }
+}
@@ -0,0 +1,384 @@
+//! Simulation of real network conditions on in-process connections: latency, jitter, limited bandwidth, and lost or
+//! reordered datagrams.
+//!
+//! [`simulate_network_conditions`] wraps one end of an in-process connection with relay tasks that hold every packet
+//! back until its simulated delivery time. Reliable channels (the RPC pipe and the streams) keep their order and never
+//! lose data, while datagrams are dropped and delayed independently, so they can arrive out of order.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+use smart_default::SmartDefault;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::task::spawn_local;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::bytes::Bytes;
+
+use crate::network::chunk_stream::BandwidthLimiter;
+use crate::network::transport::{
+    InProcessDuplex, InProcessStream, INPROCESS_DATAGRAM_QUEUE_LENGTH, INPROCESS_SOCKET_BUFFER_SIZE,
+    INPROCESS_STREAM_QUEUE_LENGTH,
+};
+use crate::prelude::*;
+
+/// Size of the chunks the RPC byte pipe is split into for delaying.
+const RELAY_READ_BUFFER_SIZE: usize = 16 * 1024;
+/// The minimum extra delay of a reordered datagram.
+const MIN_REORDER_DELAY: Duration = Duration::from_millis(5);
+
+/// Parameters of the simulated network, applied to each direction of a connection independently.
+#[derive(Clone, PartialEq, Debug, SmartDefault)]
+pub struct NetworkConditions {
+    /// The base one-way delay of every packet.
+    pub latency: Duration,
+    /// The maximum random extra one-way delay of every packet, reliable channels still deliver in order.
+    pub jitter: Duration,
+    /// The maximum throughput in each direction, in bytes per second, or 0 for no limit.
+    #[default = 0]
+    pub bandwidth: u64,
+    /// The probability of dropping each datagram, between 0 and 1.
+    #[default = 0.0]
+    pub datagram_loss: f64,
+    /// The probability of holding a datagram back until after the datagrams sent later, between 0 and 1.
+    #[default = 0.0]
+    pub datagram_reordering: f64,
+    /// The random number generator seed, for reproducible simulations.
+    #[default = 0]
+    pub seed: u64,
+}
+
+impl NetworkConditions {
+    /// Checks if the conditions are the same as the unmodified in-process transport, so no simulation is needed.
+    pub fn is_ideal(&self) -> bool {
+        self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.bandwidth == 0
+            && self.datagram_loss <= 0.0
+            && self.datagram_reordering <= 0.0
+    }
+}
+
+/// One direction of a simulated connection, shared by all the channels going that way.
+struct SimulatedLink {
+    conditions: NetworkConditions,
+    rng: Xoshiro256PlusPlus,
+    bandwidth: Option<BandwidthLimiter>,
+}
+
+impl SimulatedLink {
+    fn new(conditions: &NetworkConditions, seed: u64) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            conditions: conditions.clone(),
+            rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+            bandwidth: (conditions.bandwidth > 0)
+                .then(|| BandwidthLimiter::new(conditions.bandwidth, Instant::now().into_std())),
+        }))
+    }
+
+    /// The delay of a reliably sent packet of the given size.
+    fn packet_delay(&mut self, bytes: usize, now: Instant) -> Duration {
+        let jitter = self.conditions.jitter.as_nanos() as u64;
+        let jitter = if jitter > 0 {
+            Duration::from_nanos(self.rng.gen_range(0..=jitter))
+        } else {
+            Duration::ZERO
+        };
+        let throttle = match &mut self.bandwidth {
+            Some(limiter) => limiter.reserve(bytes, now.into_std()),
+            None => Duration::ZERO,
+        };
+        self.conditions.latency + jitter + throttle
+    }
+
+    /// The delay of a datagram of the given size, or None if it gets lost.
+    fn datagram_delay(&mut self, bytes: usize, now: Instant) -> Option<Duration> {
+        if self.rng.gen_bool(self.conditions.datagram_loss.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let mut delay = self.packet_delay(bytes, now);
+        if self.rng.gen_bool(self.conditions.datagram_reordering.clamp(0.0, 1.0)) {
+            delay += self.conditions.latency.max(MIN_REORDER_DELAY);
+        }
+        Some(delay)
+    }
+}
+
+/// Wraps one end of an in-process connection, simulating the given conditions for the traffic in both directions.
+/// Must be called within the tokio LocalSet, the relay tasks stop when the channels are closed on either side.
+pub fn simulate_network_conditions(inner: InProcessDuplex, conditions: &NetworkConditions) -> InProcessDuplex {
+    let uplink = SimulatedLink::new(conditions, conditions.seed);
+    let downlink = SimulatedLink::new(conditions, conditions.seed.wrapping_add(1));
+    let InProcessDuplex {
+        rpc_pipe,
+        mut incoming_streams,
+        outgoing_streams,
+        incoming_datagrams,
+        outgoing_datagrams,
+    } = inner;
+
+    let (outer_rpc_pipe, relay_pipe) = tokio::io::duplex(INPROCESS_SOCKET_BUFFER_SIZE);
+    let (relay_read, relay_write) = tokio::io::split(relay_pipe);
+    let (inner_read, inner_write) = tokio::io::split(rpc_pipe);
+    spawn_local(relay_bytes(Rc::clone(&uplink), relay_read, inner_write));
+    spawn_local(relay_bytes(Rc::clone(&downlink), inner_read, relay_write));
+
+    let (outer_outgoing_streams, mut relay_outgoing_streams) = async_unbounded_channel::<InProcessStream>();
+    let (stream_uplink, stream_downlink) = (Rc::clone(&uplink), Rc::clone(&downlink));
+    spawn_local(async move {
+        while let Some(outer) = relay_outgoing_streams.recv().await {
+            let (relay_side, forwarded) = InProcessStream::new_pair(outer.header.clone());
+            relay_stream(outer, relay_side, &stream_uplink, &stream_downlink);
+            if outgoing_streams.send(forwarded).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (relay_incoming_streams, outer_incoming_streams) = async_unbounded_channel::<InProcessStream>();
+    let (stream_uplink, stream_downlink) = (Rc::clone(&uplink), Rc::clone(&downlink));
+    spawn_local(async move {
+        while let Some(inner) = incoming_streams.recv().await {
+            let (relay_side, forwarded) = InProcessStream::new_pair(inner.header.clone());
+            relay_stream(relay_side, inner, &stream_uplink, &stream_downlink);
+            if relay_incoming_streams.send(forwarded).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (outer_outgoing_datagrams, relay_outgoing_datagrams) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
+    spawn_local(relay_packets(
+        uplink,
+        relay_outgoing_datagrams,
+        outgoing_datagrams,
+        Delivery::Datagram,
+    ));
+    let (relay_incoming_datagrams, outer_incoming_datagrams) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
+    spawn_local(relay_packets(
+        downlink,
+        incoming_datagrams,
+        relay_incoming_datagrams,
+        Delivery::Datagram,
+    ));
+
+    InProcessDuplex {
+        rpc_pipe: outer_rpc_pipe,
+        incoming_streams: outer_incoming_streams,
+        outgoing_streams: outer_outgoing_streams,
+        incoming_datagrams: outer_incoming_datagrams,
+        outgoing_datagrams: outer_outgoing_datagrams,
+    }
+}
+
+/// How packets are delivered by a relay.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Delivery {
+    /// In order and without loss, blocking the sender when too many packets are in flight.
+    Reliable,
+    /// Possibly lost or reordered, dropped when the receiver is full.
+    Datagram,
+}
+
+/// Relays the packets of a stream between `outer` (connected to the local user) and `inner` (connected to the peer).
+fn relay_stream(
+    outer: InProcessStream,
+    inner: InProcessStream,
+    uplink: &Rc<RefCell<SimulatedLink>>,
+    downlink: &Rc<RefCell<SimulatedLink>>,
+) {
+    spawn_local(relay_packets(Rc::clone(uplink), outer.rx, inner.tx, Delivery::Reliable));
+    spawn_local(relay_packets(
+        Rc::clone(downlink),
+        inner.rx,
+        outer.tx,
+        Delivery::Reliable,
+    ));
+}
+
+/// Forwards packets from `rx` to `tx`, each one after its simulated delay on the link.
+async fn relay_packets(
+    link: Rc<RefCell<SimulatedLink>>,
+    mut rx: AsyncBoundedReceiver<Bytes>,
+    tx: AsyncBoundedSender<Bytes>,
+    delivery: Delivery,
+) {
+    let max_in_flight = match delivery {
+        Delivery::Reliable => INPROCESS_STREAM_QUEUE_LENGTH,
+        Delivery::Datagram => INPROCESS_DATAGRAM_QUEUE_LENGTH,
+    };
+    let mut in_flight: BinaryHeap<Reverse<(Instant, u64, Bytes)>> = BinaryHeap::new();
+    let mut sequence: u64 = 0;
+    let mut last_delivery = Instant::now();
+    let mut open = true;
+    while open || !in_flight.is_empty() {
+        let next_delivery = in_flight.peek().map(|Reverse((at, _, _))| *at);
+        tokio::select! {
+            packet = rx.recv(), if open && in_flight.len() < max_in_flight => {
+                let Some(packet) = packet else {
+                    open = false;
+                    continue;
+                };
+                let now = Instant::now();
+                let deliver_at = match delivery {
+                    Delivery::Reliable => {
+                        let deliver_at = (now + link.borrow_mut().packet_delay(packet.len(), now)).max(last_delivery);
+                        last_delivery = deliver_at;
+                        deliver_at
+                    }
+                    Delivery::Datagram => match link.borrow_mut().datagram_delay(packet.len(), now) {
+                        Some(delay) => now + delay,
+                        None => continue,
+                    },
+                };
+                in_flight.push(Reverse((deliver_at, sequence, packet)));
+                sequence += 1;
+            }
+            _ = sleep_until(next_delivery.unwrap_or_else(Instant::now)), if next_delivery.is_some() => {
+                let Some(Reverse((_, _, packet))) = in_flight.pop() else {
+                    continue;
+                };
+                match delivery {
+                    Delivery::Reliable => {
+                        if tx.send(packet).await.is_err() {
+                            return;
+                        }
+                    }
+                    Delivery::Datagram => {
+                        if tx.try_send(packet).is_err() && tx.is_closed() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Forwards the bytes of a pipe, split into chunks delivered reliably after their simulated delay on the link.
+async fn relay_bytes(
+    link: Rc<RefCell<SimulatedLink>>,
+    mut reader: ReadHalf<DuplexStream>,
+    mut writer: WriteHalf<DuplexStream>,
+) {
+    let (chunk_tx, chunk_rx) = async_bounded_channel(INPROCESS_STREAM_QUEUE_LENGTH);
+    let (delayed_tx, mut delayed_rx) = async_bounded_channel::<Bytes>(1);
+    spawn_local(relay_packets(link, chunk_rx, delayed_tx, Delivery::Reliable));
+    spawn_local(async move {
+        while let Some(chunk) = delayed_rx.recv().await {
+            if writer.write_all(&chunk).await.is_err() {
+                return;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    let mut buffer = vec![0u8; RELAY_READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if chunk_tx.send(Bytes::copy_from_slice(&buffer[..n])).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+    use gs_schemas::schemas::NetworkStreamHeader;
+
+    use super::*;
+    use crate::network::run_local;
+
+    #[test]
+    fn delayed_streams_stay_ordered() {
+        run_local(async {
+            let conditions = NetworkConditions {
+                latency: Duration::from_millis(30),
+                jitter: Duration::from_millis(20),
+                ..Default::default()
+            };
+            let (client, mut server) = InProcessDuplex::new_pair();
+            let client = simulate_network_conditions(client, &conditions);
+
+            let start = Instant::now();
+            let (mut local, remote) =
+                InProcessStream::new_pair(NetworkStreamHeader::Standard(StandardTypes::ChunkData));
+            client.outgoing_streams.send(remote).unwrap();
+            let mut accepted = server.incoming_streams.recv().await.unwrap();
+            for i in 0..10u8 {
+                local.tx.send(Bytes::from(vec![i])).await.unwrap();
+            }
+            for i in 0..10u8 {
+                assert_eq!(accepted.rx.recv().await.unwrap(), Bytes::from(vec![i]));
+            }
+            assert!(start.elapsed() >= conditions.latency);
+
+            // The other direction is delayed too.
+            let start = Instant::now();
+            accepted.tx.send(Bytes::from_static(b"pong")).await.unwrap();
+            assert_eq!(local.rx.recv().await.unwrap(), Bytes::from_static(b"pong"));
+            assert!(start.elapsed() >= conditions.latency);
+        });
+    }
+
+    #[test]
+    fn delayed_rpc_pipe() {
+        run_local(async {
+            let conditions = NetworkConditions {
+                latency: Duration::from_millis(10),
+                bandwidth: 64 * 1024,
+                ..Default::default()
+            };
+            let (client, server) = InProcessDuplex::new_pair();
+            let mut client = simulate_network_conditions(client, &conditions);
+            let mut server_pipe = server.rpc_pipe;
+
+            let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+            let writer = data.clone();
+            let write_task = spawn_local(async move {
+                client.rpc_pipe.write_all(&writer).await.unwrap();
+                client
+            });
+            let mut received = vec![0u8; data.len()];
+            server_pipe.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, data);
+            drop(write_task.await.unwrap());
+        });
+    }
+
+    #[test]
+    fn datagram_loss() {
+        run_local(async {
+            let conditions = NetworkConditions {
+                datagram_loss: 0.5,
+                datagram_reordering: 0.2,
+                seed: 1234,
+                ..Default::default()
+            };
+            let (client, mut server) = InProcessDuplex::new_pair();
+            let client = simulate_network_conditions(client, &conditions);
+            for i in 0..INPROCESS_DATAGRAM_QUEUE_LENGTH as u8 {
+                client.outgoing_datagrams.send(Bytes::from(vec![i])).await.unwrap();
+            }
+            drop(client);
+
+            let mut received = Vec::new();
+            while let Some(datagram) = server.incoming_datagrams.recv().await {
+                received.push(datagram[0]);
+            }
+            assert!(!received.is_empty());
+            assert!(received.len() < INPROCESS_DATAGRAM_QUEUE_LENGTH);
+            let received_count = received.len();
+            received.sort_unstable();
+            received.dedup();
+            assert_eq!(received.len(), received_count);
+        });
+    }
+}
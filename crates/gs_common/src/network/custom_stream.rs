@@ -0,0 +1,374 @@
+//! Mod-defined custom network streams.
+//!
+//! Custom stream types are defined by content packs and registered in the [`StreamTypeRegistry`] (see
+//! [`setup_content_stream_types`]), whose IDs are shared with the client in the
+//! bootstrap data, so a client that does not know a stream type used by the server fails to bootstrap instead of
+//! failing later at runtime. Streams can only be opened for registered types, and incoming streams that have no handler
+//! installed on the receiving side are rejected by dropping them, which the opener observes as a closed stream.
+
+use std::marker::PhantomData;
+
+use bevy::log::debug;
+use capnp::message::{Allocator, ReaderOptions, TypedBuilder, TypedReader};
+use capnp::serialize::OwnedSegments;
+use capnp::traits::Owned;
+use gs_schemas::content::{ContentError, ContentPack};
+use gs_schemas::registry::{RegistryName, RegistryNameRef};
+use gs_schemas::registry_aliases::load_alias_definitions;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::{decode_stream_packet, encode_stream_packet, StreamPacketError};
+use gs_schemas::schemas::NetworkStreamHeader;
+use gs_schemas::stream_types::{load_stream_type_definitions, StreamTypeRegistry, STREAMS_DIRECTORY};
+use thiserror::Error;
+use tokio_util::bytes::Bytes;
+
+use crate::network::server::NetworkThreadServerState;
+use crate::network::thread::{NetworkThread, NetworkThreadState};
+use crate::network::transport::InProcessStream;
+use crate::network::PeerAddress;
+use crate::prelude::*;
+
+/// Reader options used for decoding custom stream packets.
+pub const CUSTOM_STREAM_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(1024 * 1024),
+    nesting_limit: 64,
+};
+
+/// Errors that can happen when opening or using a custom stream.
+#[derive(Debug, Error)]
+pub enum CustomStreamError {
+    /// The stream type is not in the stream type registry.
+    #[error("Unknown custom stream type {0}")]
+    UnknownType(RegistryName),
+    /// There is no connected peer to open the stream to.
+    #[error("The peer is not connected")]
+    NotConnected,
+    /// The stream was closed, or rejected because the peer has no handler for its type.
+    #[error("The custom stream was closed or rejected by the peer")]
+    Closed,
+    /// The stream does not have a custom stream header.
+    #[error("Not a custom stream header: {0:?}")]
+    NotCustom(NetworkStreamHeader),
+    /// A received packet could not be decoded.
+    #[error("Invalid custom stream packet: {0}")]
+    InvalidPacket(#[from] StreamPacketError),
+}
+
+/// One side of a custom stream carrying `Schema` messages in both directions.
+pub struct CustomStream<Schema: Owned> {
+    peer: PeerAddress,
+    name: RegistryName,
+    tx: AsyncBoundedSender<Bytes>,
+    rx: AsyncBoundedReceiver<Bytes>,
+    compression: PacketCompression,
+    _schema: PhantomData<fn() -> Schema>,
+}
+
+impl<Schema: Owned> CustomStream<Schema> {
+    /// Wraps a raw stream with a custom header, sending packets with the given compression.
+    /// Fails if the stream does not have a [`NetworkStreamHeader::Custom`] header.
+    pub fn new(
+        peer: PeerAddress,
+        stream: InProcessStream,
+        compression: PacketCompression,
+    ) -> Result<Self, CustomStreamError> {
+        let InProcessStream { header, tx, rx } = stream;
+        let name = match header {
+            NetworkStreamHeader::Custom(name) => name,
+            header => return Err(CustomStreamError::NotCustom(header)),
+        };
+        Ok(Self {
+            peer,
+            name,
+            tx,
+            rx,
+            compression,
+            _schema: PhantomData,
+        })
+    }
+
+    /// The address of the peer on the other end of the stream.
+    pub fn peer(&self) -> PeerAddress {
+        self.peer
+    }
+
+    /// The registry name of the stream type.
+    pub fn name(&self) -> &RegistryName {
+        &self.name
+    }
+
+    /// The compression used for sent packets.
+    pub fn compression(&self) -> PacketCompression {
+        self.compression
+    }
+
+    /// Changes the compression used for sent packets, received packets are decoded regardless of their compression.
+    pub fn set_compression(&mut self, compression: PacketCompression) {
+        self.compression = compression;
+    }
+
+    /// Checks if the peer closed or rejected the stream.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Sends a message, waiting for free space in the stream if needed.
+    pub async fn send<A: Allocator>(&self, message: &TypedBuilder<Schema, A>) -> Result<(), CustomStreamError> {
+        let packet = encode_stream_packet(message.borrow_inner(), self.compression);
+        self.tx
+            .send(Bytes::from(packet))
+            .await
+            .map_err(|_| CustomStreamError::Closed)
+    }
+
+    /// Receives the next message, returns `Ok(None)` once the peer closed the stream.
+    pub async fn recv(&mut self) -> Result<Option<TypedReader<OwnedSegments, Schema>>, CustomStreamError> {
+        let Some(packet) = self.rx.recv().await else {
+            return Ok(None);
+        };
+        let message = decode_stream_packet(&packet, CUSTOM_STREAM_READER_OPTIONS)?;
+        Ok(Some(TypedReader::new(message)))
+    }
+}
+
+/// Installs the custom stream types and stream type aliases defined in the given content packs into the given registry.
+pub fn setup_content_stream_types(
+    registry: &mut StreamTypeRegistry,
+    packs: &[ContentPack],
+) -> Result<(), ContentError> {
+    for definition in load_stream_type_definitions(packs)? {
+        registry.push_object(definition)?;
+    }
+    registry.add_aliases(load_alias_definitions(packs, STREAMS_DIRECTORY)?)?;
+    Ok(())
+}
+
+/// Looks up a registered custom stream type, returning the header for opening streams of it.
+pub fn lookup_stream_type(
+    registry: &StreamTypeRegistry,
+    name: RegistryNameRef,
+) -> Result<NetworkStreamHeader, CustomStreamError> {
+    registry
+        .lookup_name_to_object(name)
+        .map(|(_, definition)| definition.header())
+        .ok_or_else(|| CustomStreamError::UnknownType(name.to_owned()))
+}
+
+/// Registers the handler for incoming streams of a registered custom type, overwriting any previous handler for it.
+/// Returns the receiver of the accepted streams, which reply with uncompressed packets unless changed.
+pub fn register_custom_stream_handler<NS: NetworkThreadState, Schema: Owned + 'static>(
+    net_thread: &NetworkThread<NS>,
+    registry: &StreamTypeRegistry,
+    name: RegistryNameRef,
+) -> Result<AsyncUnboundedReceiver<CustomStream<Schema>>, CustomStreamError> {
+    let header = lookup_stream_type(registry, name)?;
+    let (tx, rx) = async_unbounded_channel();
+    net_thread.insert_stream_handler(
+        header,
+        Box::new(move |_state, peer, stream| {
+            match CustomStream::new(peer, stream, PacketCompression::None) {
+                Ok(stream) => {
+                    if tx.send(stream).is_err() {
+                        debug!("Dropping a custom stream from {peer:?}, its receiver was closed");
+                    }
+                }
+                Err(e) => debug!("Dropping a stream from {peer:?}: {e}"),
+            }
+            Box::pin(std::future::ready(()))
+        }),
+    );
+    Ok(rx)
+}
+
+/// Opens a stream of a registered custom type to a bootstrapped player, using the player's negotiated compression.
+pub fn open_custom_stream_to_player<Schema: Owned>(
+    state: &NetworkThreadServerState,
+    registry: &StreamTypeRegistry,
+    peer: PeerAddress,
+    name: RegistryNameRef,
+) -> Result<CustomStream<Schema>, CustomStreamError> {
+    let header = lookup_stream_type(registry, name)?;
+    let compression = state
+        .find_bootstrapped_client(peer)
+        .ok_or(CustomStreamError::NotConnected)?
+        .borrow()
+        .packet_compression();
+    let stream = state
+        .find_connected_client(peer)
+        .ok_or(CustomStreamError::NotConnected)?
+        .open_stream(header)
+        .map_err(|_| CustomStreamError::Closed)?;
+    CustomStream::new(peer, stream, compression)
+}
+
+/// Rejects an incoming stream that has no registered handler by dropping it, the opener sees the stream as closed.
+pub fn reject_stream(peer: PeerAddress, stream: InProcessStream) {
+    debug!("Rejecting a stream of unhandled type {:?} from {peer:?}", stream.header);
+    drop(stream);
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use gs_schemas::schemas::game_types_capnp::i_vec3;
+    use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+    use gs_schemas::stream_types::StreamTypeDefinition;
+    use tokio::task::spawn_local;
+
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::content::base_content_pack;
+    use crate::network::bot::{BotScript, BotStats, HeadlessBot};
+    use crate::{content_game_registries, GameServer};
+
+    const TEST_STREAM: RegistryName = RegistryName::gs_const("test_positions");
+
+    fn test_registry() -> StreamTypeRegistry {
+        let mut registry = StreamTypeRegistry::default();
+        registry.push_object(StreamTypeDefinition::new(TEST_STREAM)).unwrap();
+        registry
+    }
+
+    fn position_message(x: i32) -> TypedBuilder<i_vec3::Owned> {
+        let mut message = TypedBuilder::<i_vec3::Owned>::new_default();
+        let mut root = message.init_root();
+        root.set_x(x);
+        root.set_y(-x);
+        root.set_z(2 * x);
+        message
+    }
+
+    #[test]
+    fn unknown_type_is_refused() {
+        let registry = test_registry();
+        assert!(lookup_stream_type(&registry, TEST_STREAM.as_ref()).is_ok());
+        let unknown = RegistryName::gs_const("unknown");
+        assert!(matches!(
+            lookup_stream_type(&registry, unknown.as_ref()),
+            Err(CustomStreamError::UnknownType(name)) if name == unknown
+        ));
+    }
+
+    #[tokio::test]
+    async fn typed_roundtrip() {
+        let header = lookup_stream_type(&test_registry(), TEST_STREAM.as_ref()).unwrap();
+        let (local, remote) = InProcessStream::new_pair(header);
+        let sender =
+            CustomStream::<i_vec3::Owned>::new(PeerAddress::Local(0), local, PacketCompression::Deflate).unwrap();
+        let mut receiver =
+            CustomStream::<i_vec3::Owned>::new(PeerAddress::Local(1), remote, PacketCompression::None).unwrap();
+        assert_eq!(receiver.name(), &TEST_STREAM);
+
+        sender.send(&position_message(3)).await.unwrap();
+        let message = receiver.recv().await.unwrap().unwrap();
+        let position = message.get().unwrap();
+        assert_eq!((position.get_x(), position.get_y(), position.get_z()), (3, -3, 6));
+
+        drop(sender);
+        assert!(receiver.recv().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn non_custom_header_is_refused() {
+        let header = NetworkStreamHeader::Standard(StandardTypes::ChunkData);
+        let (local, _remote) = InProcessStream::new_pair(header.clone());
+        assert!(matches!(
+            CustomStream::<i_vec3::Owned>::new(PeerAddress::Local(0), local, PacketCompression::None),
+            Err(CustomStreamError::NotCustom(rejected)) if rejected == header
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejected_stream_is_closed() {
+        let header = lookup_stream_type(&test_registry(), TEST_STREAM.as_ref()).unwrap();
+        let (local, remote) = InProcessStream::new_pair(header);
+        let mut opener =
+            CustomStream::<i_vec3::Owned>::new(PeerAddress::Local(0), local, PacketCompression::None).unwrap();
+        reject_stream(PeerAddress::Local(0), remote);
+
+        assert!(opener.is_closed());
+        assert!(matches!(
+            opener.send(&position_message(1)).await,
+            Err(CustomStreamError::Closed)
+        ));
+        assert!(opener.recv().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn content_stream_from_server_to_client() {
+        let directory = std::env::temp_dir().join(format!("gs-stream-pack-test-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("streams")).unwrap();
+        std::fs::write(directory.join("streams/test.ron"), r#"[(name: "test_positions")]"#).unwrap();
+        let pack = ContentPack::load_directory(&directory);
+        let mut config = GameConfig::default();
+        config.server.listen_addresses.clear();
+        config.server.content_packs = vec![directory.clone()];
+        let server = GameServer::new(GameConfig::new_handle(config));
+        std::fs::remove_dir_all(&directory).unwrap();
+        let server = server.unwrap();
+        // The client loads the same content packs as the server.
+        let client_registries = content_game_registries(&[base_content_pack(), pack.unwrap()], None).unwrap();
+        server.set_paused(false);
+
+        let stats = Arc::new(BotStats::default());
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(tokio::task::LocalSet::new().run_until(async {
+                let connection = server.create_local_connection().async_wait().await.unwrap();
+                let peer = connection.0;
+                let (stop_tx, stop_rx) = async_watch_channel(false);
+                let (streams_tx, mut streams_rx) = async_unbounded_channel();
+                let script = BotScript {
+                    edit_interval: None,
+                    render_distance: 0,
+                    ..Default::default()
+                };
+                let bot = HeadlessBot::new("StreamBot", script, Arc::clone(&stats))
+                    .with_registries(client_registries)
+                    .with_custom_streams(streams_tx);
+                let bot = spawn_local(bot.run(connection, stop_rx));
+
+                let deadline = Instant::now() + Duration::from_secs(30);
+                while Instant::now() < deadline && stats.snapshot().connected_bots < 1 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                let registries = server.server_data.shared_registries.load_full();
+                server
+                    .network_thread
+                    .schedule_task(move |state| {
+                        Box::pin(async move {
+                            let stream = open_custom_stream_to_player::<i_vec3::Owned>(
+                                &state.borrow(),
+                                &registries.stream_types,
+                                peer,
+                                TEST_STREAM.as_ref(),
+                            )?;
+                            stream.send(&position_message(5)).await?;
+                            Ok(())
+                        })
+                    })
+                    .async_wait()
+                    .await
+                    .unwrap();
+
+                let stream = tokio::time::timeout(Duration::from_secs(30), streams_rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let mut stream = CustomStream::<i_vec3::Owned>::new(peer, stream, PacketCompression::None).unwrap();
+                assert_eq!(stream.name(), &TEST_STREAM);
+                let message = stream.recv().await.unwrap().unwrap();
+                let position = message.get().unwrap();
+                assert_eq!((position.get_x(), position.get_y(), position.get_z()), (5, -5, 10));
+
+                stop_tx.send(true).unwrap();
+                bot.await.unwrap().unwrap();
+                server.shutdown().await.unwrap();
+            }));
+
+        assert_eq!(stats.snapshot().errors, 0);
+    }
+}
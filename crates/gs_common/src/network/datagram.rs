@@ -0,0 +1,78 @@
+//! Unreliable, unordered datagrams for frequently changing state, such as player positions.
+//!
+//! Datagrams are encoded like uncompressed stream packets and silently dropped when the link is congested,
+//! so game systems never retry sending them, they just send the latest state again later.
+
+use bevy::prelude::*;
+use capnp::message::{Allocator, Builder, Reader, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::{decode_stream_packet, encode_stream_packet};
+use tokio_util::bytes::Bytes;
+
+use crate::prelude::*;
+
+/// The maximum encoded size of a single datagram, small enough to avoid IP fragmentation on common links.
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// Capnproto reader options for datagrams: at most 16Ki words (128KiB) may be traversed and structs nest at most 16 levels
+/// deep, which is well above what a [`MAX_DATAGRAM_SIZE`] byte datagram can legitimately contain.
+pub static DATAGRAM_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(16 * 1024),
+    nesting_limit: 16,
+};
+
+/// The sending side of a connection's datagram channel.
+#[derive(Component, Clone)]
+pub struct DatagramSender {
+    queue: AsyncBoundedSender<Bytes>,
+}
+
+impl DatagramSender {
+    /// Wraps the raw datagram queue of a connection.
+    pub fn new(queue: AsyncBoundedSender<Bytes>) -> Self {
+        Self { queue }
+    }
+
+    /// Checks if the other side of the connection is gone.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+
+    /// Encodes and sends a datagram without blocking, returns false if it was dropped.
+    pub fn send<A: Allocator>(&self, message: &Builder<A>) -> bool {
+        let packet = encode_stream_packet(message, PacketCompression::None);
+        if packet.len() > MAX_DATAGRAM_SIZE {
+            warn!(
+                "Dropping a datagram of {} bytes, larger than the limit of {MAX_DATAGRAM_SIZE}",
+                packet.len()
+            );
+            return false;
+        }
+        self.queue.try_send(Bytes::from(packet)).is_ok()
+    }
+}
+
+/// The receiving side of a connection's datagram channel.
+#[derive(Component)]
+pub struct DatagramReceiver {
+    queue: AsyncBoundedReceiver<Bytes>,
+}
+
+impl DatagramReceiver {
+    /// Wraps the raw datagram queue of a connection.
+    pub fn new(queue: AsyncBoundedReceiver<Bytes>) -> Self {
+        Self { queue }
+    }
+
+    /// Takes the next received datagram without blocking, skipping malformed ones.
+    pub fn try_recv(&mut self) -> Option<Reader<OwnedSegments>> {
+        loop {
+            let packet = self.queue.try_recv().ok()?;
+            match decode_stream_packet(&packet, DATAGRAM_READER_OPTIONS) {
+                Ok(reader) => return Some(reader),
+                Err(e) => warn!("Dropping a malformed datagram: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! LAN server discovery via UDP broadcast announcements.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use capnp::message::ReaderOptions;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use crate::network::protocol::format_game_version;
+use crate::network::server::{write_server_metadata, NetworkThreadServerState};
+use crate::prelude::*;
+use crate::{GameServer, GAME_BRAND_NAME};
+
+/// The UDP port servers broadcast LAN announcements to, and clients listen on.
+pub const LAN_DISCOVERY_PORT: u16 = 28033;
+/// How often a server broadcasts its LAN announcement.
+pub const LAN_ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a discovered server stays on the list without a fresh announcement.
+pub const LAN_SERVER_EXPIRY: Duration = Duration::from_secs(7);
+/// Maximum size of a single announcement datagram.
+const MAX_ANNOUNCEMENT_SIZE: usize = 2048;
+
+/// Capnproto reader options for untrusted LAN announcements.
+static LAN_ANNOUNCEMENT_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(MAX_ANNOUNCEMENT_SIZE / 8),
+    nesting_limit: 8,
+};
+
+/// A server found on the local network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredLanServer {
+    /// The address to connect to for joining the server's game.
+    pub address: SocketAddr,
+    /// The server title.
+    pub title: String,
+    /// The server subtitle.
+    pub subtitle: String,
+    /// The formatted SemVer version of the server.
+    pub version: String,
+    /// Number of online players.
+    pub player_count: i32,
+    /// Limit of online players.
+    pub player_limit: i32,
+    /// When the last announcement from this server was received.
+    pub last_seen: Instant,
+}
+
+/// The list of LAN servers discovered so far, keyed by the game address.
+#[derive(Clone, Debug, Default)]
+pub struct LanServerList {
+    servers: HashMap<SocketAddr, DiscoveredLanServer>,
+}
+
+/// A [`LanServerList`] shared between the network thread and the UI.
+pub type SharedLanServerList = Arc<Mutex<LanServerList>>;
+
+impl LanServerList {
+    /// Inserts or refreshes a discovered server.
+    pub fn update(&mut self, server: DiscoveredLanServer) {
+        self.servers.insert(server.address, server);
+    }
+
+    /// Removes servers that have not announced themselves for longer than [`LAN_SERVER_EXPIRY`].
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.servers
+            .retain(|_, server| now.saturating_duration_since(server.last_seen) < LAN_SERVER_EXPIRY);
+    }
+
+    /// Returns the discovered servers sorted by title, then address.
+    pub fn sorted_servers(&self) -> Vec<&DiscoveredLanServer> {
+        let mut servers: Vec<_> = self.servers.values().collect();
+        servers.sort_by(|a, b| a.title.cmp(&b.title).then(a.address.cmp(&b.address)));
+        servers
+    }
+
+    /// Number of currently known servers.
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Checks if no servers are known.
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+}
+
+/// Serializes the current announcement for the given server into a datagram payload.
+pub fn encode_lan_announcement(engine: &GameServer, player_count: u32, game_port: u16) -> Vec<u8> {
+    let mut message = capnp::message::Builder::new_default();
+    let mut announcement = message.init_root::<rpc::lan_server_announcement::Builder>();
+    announcement.set_brand(GAME_BRAND_NAME);
+    announcement.set_game_port(game_port);
+    write_server_metadata(engine, player_count, announcement.init_metadata());
+    capnp::serialize::write_message_to_words(&message)
+}
+
+/// Parses an announcement datagram received from `source`, returns None for foreign or malformed traffic.
+pub fn decode_lan_announcement(source: SocketAddr, payload: &[u8], now: Instant) -> Option<DiscoveredLanServer> {
+    let mut payload = payload;
+    let message = capnp::serialize::read_message_from_flat_slice(&mut payload, LAN_ANNOUNCEMENT_READER_OPTIONS).ok()?;
+    let announcement = message.get_root::<rpc::lan_server_announcement::Reader>().ok()?;
+    if announcement.get_brand().ok()?.to_str().ok()? != GAME_BRAND_NAME {
+        return None;
+    }
+    let metadata = announcement.get_metadata().ok()?;
+    let version = format_game_version(metadata.get_server_version().ok()?).ok()?;
+    Some(DiscoveredLanServer {
+        address: SocketAddr::new(source.ip(), announcement.get_game_port()),
+        title: metadata.get_title().ok()?.to_string().ok()?,
+        subtitle: metadata.get_subtitle().ok()?.to_string().ok()?,
+        version,
+        player_count: metadata.get_player_count(),
+        player_limit: metadata.get_player_limit(),
+        last_seen: now,
+    })
+}
+
+/// Periodically broadcasts the server announcement on the local network, until aborted.
+/// Must be called within the tokio LocalSet.
+pub async fn lan_announcer_task(this: Rc<RefCell<NetworkThreadServerState>>, engine: Arc<GameServer>) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .context("Binding the LAN announcement socket")?;
+    socket.set_broadcast(true)?;
+    let target = SocketAddr::from((Ipv4Addr::BROADCAST, LAN_DISCOVERY_PORT));
+    let mut interval = tokio::time::interval(LAN_ANNOUNCEMENT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let game_port = {
+            let config = engine.config().borrow();
+            let Some(addr) = config.server.listen_addresses.first() else {
+                continue;
+            };
+            addr.port()
+        };
+        let player_count = this.borrow().player_count() as u32;
+        let payload = encode_lan_announcement(&engine, player_count, game_port);
+        if let Err(e) = socket.send_to(&payload, target).await {
+            debug!("Could not broadcast the LAN announcement: {e}");
+        }
+    }
+}
+
+/// Binds the socket receiving LAN announcements on the given port. The port is shared, so all the game clients running
+/// on the same machine receive the broadcast announcements.
+fn bind_discovery_socket(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Listens for LAN announcements and records the discovered servers in `servers`, until aborted.
+pub async fn lan_discovery_listener_task(port: u16, servers: SharedLanServerList) -> Result<()> {
+    let socket =
+        bind_discovery_socket(port).with_context(|| format!("Binding the LAN discovery socket on port {port}"))?;
+    let mut buffer = vec![0u8; MAX_ANNOUNCEMENT_SIZE];
+    loop {
+        let (len, source) = socket.recv_from(&mut buffer).await?;
+        let now = Instant::now();
+        let Some(server) = decode_lan_announcement(source, &buffer[..len], now) else {
+            continue;
+        };
+        let mut servers = servers.lock().unwrap();
+        servers.update(server);
+        servers.remove_expired(now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GameServerControlCommand;
+
+    fn shutdown_test_server(server: &GameServer) {
+        let (shutdown_tx, shutdown_rx) = async_oneshot_channel();
+        server
+            .control_channel
+            .send(GameServerControlCommand::Shutdown(shutdown_tx))
+            .unwrap();
+        shutdown_rx.blocking_recv().unwrap();
+    }
+
+    fn test_server_entry(title: &str, address: &str, last_seen: Instant) -> DiscoveredLanServer {
+        DiscoveredLanServer {
+            address: address.parse().unwrap(),
+            title: title.to_owned(),
+            subtitle: String::new(),
+            version: String::from("0.0.0"),
+            player_count: 0,
+            player_limit: 4,
+            last_seen,
+        }
+    }
+
+    #[test]
+    fn announcement_roundtrip() {
+        let server = GameServer::new_test();
+        let payload = encode_lan_announcement(&server, 3, 1234);
+        let source: SocketAddr = "192.168.1.20:45678".parse().unwrap();
+        let now = Instant::now();
+        let decoded = decode_lan_announcement(source, &payload, now).expect("Announcement failed to decode");
+        assert_eq!(decoded.address, "192.168.1.20:1234".parse().unwrap());
+        assert_eq!(decoded.title, "Test server");
+        assert_eq!(decoded.player_count, 3);
+        assert_eq!(decoded.last_seen, now);
+        shutdown_test_server(&server);
+    }
+
+    #[test]
+    fn foreign_datagrams_are_ignored() {
+        let source: SocketAddr = "192.168.1.20:45678".parse().unwrap();
+        let now = Instant::now();
+        assert!(decode_lan_announcement(source, b"", now).is_none());
+        assert!(decode_lan_announcement(source, b"not an announcement", now).is_none());
+
+        let mut message = capnp::message::Builder::new_default();
+        let mut announcement = message.init_root::<rpc::lan_server_announcement::Builder>();
+        announcement.set_brand("Another game");
+        announcement.set_game_port(1234);
+        let payload = capnp::serialize::write_message_to_words(&message);
+        assert!(decode_lan_announcement(source, &payload, now).is_none());
+    }
+
+    #[test]
+    fn server_list_expiry() {
+        let now = Instant::now();
+        let mut list = LanServerList::default();
+        list.update(test_server_entry("B", "192.168.1.20:1234", now));
+        list.update(test_server_entry(
+            "A",
+            "192.168.1.21:1234",
+            now + LAN_ANNOUNCEMENT_INTERVAL,
+        ));
+        // A fresh announcement replaces the previous entry of the same server.
+        list.update(test_server_entry(
+            "B",
+            "192.168.1.20:1234",
+            now + LAN_ANNOUNCEMENT_INTERVAL,
+        ));
+        assert_eq!(list.len(), 2);
+        let titles: Vec<_> = list
+            .sorted_servers()
+            .iter()
+            .map(|server| server.title.as_str())
+            .collect();
+        assert_eq!(titles, ["A", "B"]);
+
+        list.remove_expired(now + LAN_SERVER_EXPIRY);
+        assert_eq!(list.len(), 2);
+        list.remove_expired(now + LAN_ANNOUNCEMENT_INTERVAL + LAN_SERVER_EXPIRY);
+        assert!(list.is_empty());
+    }
+
+    #[tokio::test]
+    async fn discovery_port_is_shared() {
+        let first = bind_discovery_socket(0).unwrap();
+        let port = first.local_addr().unwrap().port();
+        let second = bind_discovery_socket(port).unwrap();
+        assert_eq!(second.local_addr().unwrap().port(), port);
+    }
+}
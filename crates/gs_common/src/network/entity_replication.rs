@@ -0,0 +1,878 @@
+//! Replication of ECS entities and their components from the server to the clients.
+//!
+//! Component types opt into replication by registering a name and an encoding with
+//! [`ReplicationAppExt::replicate_component`], entities opt in with the [`Replicated`] marker. Every tick the server
+//! compares the replicated entities around each player with the ones that player already knows about, and sends it
+//! the spawns, component changes and despawns as a single packet on the reliable entity replication stream.
+//! Clients keep a map from server entity IDs to the local entities mirroring them, see [`ServerEntityMap`].
+
+use std::marker::PhantomData;
+
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::system::SystemChangeTick;
+use bevy::prelude::*;
+use capnp::message::{Builder, TypedReader};
+use gs_schemas::dependencies::itertools::Itertools;
+use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::{decode_stream_packet, encode_stream_packet};
+use gs_schemas::schemas::NetworkStreamHeader;
+use gs_schemas::{GameSide, GsExtraData};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_util::bytes::Bytes;
+use tracing::Instrument;
+
+use crate::network::server::ConnectedPlayer;
+use crate::network::thread::{NetworkThread, NetworkThreadState};
+use crate::network::transport::{InProcessStream, RPC_LOCAL_READER_OPTIONS};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::tick::ServerTick;
+use crate::InGameSystemSet;
+
+/// Replicated entities further away than this many blocks from a player are not sent to its client.
+pub const ENTITY_VISIBILITY_RADIUS: f32 = 128.0;
+/// The maximum number of component types that can be registered for replication.
+pub const MAX_REPLICATED_COMPONENTS: usize = 64;
+/// The maximum number of received entity packets buffered on the client before applying stream backpressure.
+pub const ENTITY_PACKET_QUEUE_LENGTH: usize = 64;
+/// The maximum number of entity packets queued for sending to a single client, further changes are coalesced until the
+/// queue has room again.
+pub const ENTITY_SEND_QUEUE_LENGTH: usize = 16;
+
+/// Registers the replication systems for the given side, and the builtin replicated component types.
+#[derive(Default)]
+pub struct EntityReplicationPlugin<ExtraData: GsExtraData> {
+    _extra_data: PhantomData<ExtraData>,
+}
+
+impl<ExtraData: GsExtraData> EntityReplicationPlugin<ExtraData> {
+    /// Constructor.
+    pub fn new() -> Self {
+        Self { _extra_data: default() }
+    }
+}
+
+impl<ExtraData: GsExtraData> Plugin for EntityReplicationPlugin<ExtraData> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplicationRegistry>()
+            .replicate_component::<Transform, SerdeEncoding>("gs:transform");
+        if ExtraData::SIDE == GameSide::Server {
+            app.add_systems(
+                FixedPostUpdate,
+                (server_system_replicate_entities).in_set(InGameSystemSet),
+            );
+        } else {
+            app.init_resource::<ServerEntityMap>().add_systems(
+                FixedPreUpdate,
+                (client_system_apply_entity_packets).in_set(InGameSystemSet),
+            );
+        }
+    }
+
+    fn name(&self) -> &str {
+        "common::EntityReplicationPlugin"
+    }
+
+    fn is_unique(&self) -> bool {
+        true
+    }
+}
+
+/// Marks an entity whose registered components are replicated to the clients of nearby players.
+/// Entities without a [`Transform`] are replicated to every client.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Replicated;
+
+/// A client-side entity mirroring a replicated server entity.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ReplicatedFromServer {
+    /// The entity ID on the server.
+    pub server_entity: u64,
+}
+
+/// The wire encoding of a replicated component type.
+pub trait ComponentEncoding<C>: Send + Sync + 'static {
+    /// Serializes the component value.
+    fn encode(component: &C) -> Result<Vec<u8>>;
+    /// Deserializes a component value encoded with [`Self::encode`].
+    fn decode(data: &[u8]) -> Result<C>;
+}
+
+/// Encodes components with serde, in the compact bincode format.
+pub struct SerdeEncoding;
+
+impl<C: Serialize + DeserializeOwned> ComponentEncoding<C> for SerdeEncoding {
+    fn encode(component: &C) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(component)?)
+    }
+
+    fn decode(data: &[u8]) -> Result<C> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+/// A component with a capnproto schema, for use with [`CapnpEncoding`].
+pub trait CapnpComponent: Sized {
+    /// The capnproto struct type the component is stored as.
+    type Schema: capnp::traits::Owned;
+
+    /// Serializes the component into the message root.
+    fn write(&self, builder: <Self::Schema as capnp::traits::Owned>::Builder<'_>);
+    /// Deserializes the component from the message root.
+    fn read(reader: <Self::Schema as capnp::traits::Owned>::Reader<'_>) -> Result<Self>;
+}
+
+/// Encodes components as unpacked capnproto messages, see [`CapnpComponent`].
+pub struct CapnpEncoding;
+
+impl<C: CapnpComponent> ComponentEncoding<C> for CapnpEncoding {
+    fn encode(component: &C) -> Result<Vec<u8>> {
+        let mut message = Builder::new_default();
+        component.write(message.init_root());
+        Ok(capnp::serialize::write_message_to_words(&message))
+    }
+
+    fn decode(mut data: &[u8]) -> Result<C> {
+        let message = capnp::serialize::read_message_from_flat_slice(&mut data, RPC_LOCAL_READER_OPTIONS)?;
+        let root = message.get_root()?;
+        C::read(root)
+    }
+}
+
+/// Type-erased replication functions of a registered component type.
+struct ReplicatedComponentInfo {
+    name: &'static str,
+    component_id: ComponentId,
+    encode: fn(&EntityRef) -> Option<Result<Vec<u8>>>,
+    insert: fn(&mut EntityWorldMut, &[u8]) -> Result<()>,
+    remove: fn(&mut EntityWorldMut),
+}
+
+/// The component types replicated from the server to clients, must be registered identically on both sides.
+#[derive(Resource, Default)]
+pub struct ReplicationRegistry {
+    components: Vec<ReplicatedComponentInfo>,
+    by_name: HashMap<&'static str, usize>,
+}
+
+impl ReplicationRegistry {
+    /// Number of registered component types.
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Checks if no component types are registered.
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Checks if a component type is registered with the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    /// Encodes all the registered components present on the entity, with the names they are registered under.
+    /// Components that fail to encode are logged and skipped.
+    pub fn encode_components(&self, entity: &EntityRef) -> Vec<(&'static str, Vec<u8>)> {
+        self.components
+            .iter()
+            .filter_map(|info| match (info.encode)(entity)? {
+                Ok(data) => Some((info.name, data)),
+                Err(e) => {
+                    error!(
+                        "Could not encode replicated component {} of {:?}: {e}",
+                        info.name,
+                        entity.id()
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Decodes the component registered under `name` and inserts it into the entity.
+    pub fn insert_component(&self, entity: &mut EntityWorldMut, name: &str, data: &[u8]) -> Result<()> {
+        let Some(&index) = self.by_name.get(name) else {
+            bail!("Unknown replicated component {name}");
+        };
+        (self.components[index].insert)(entity, data).with_context(|| format!("Decoding component {name}"))
+    }
+
+    /// Removes the component registered under `name` from the entity.
+    pub fn remove_component(&self, entity: &mut EntityWorldMut, name: &str) -> Result<()> {
+        let Some(&index) = self.by_name.get(name) else {
+            bail!("Unknown replicated component {name}");
+        };
+        (self.components[index].remove)(entity);
+        Ok(())
+    }
+
+    /// Checks if any registered component of the entity was added or changed between the given ticks.
+    pub fn any_changed(&self, entity: &EntityRef, change_tick: &SystemChangeTick) -> bool {
+        self.changed_mask(entity, change_tick) != 0
+    }
+
+    fn register(&mut self, info: ReplicatedComponentInfo) {
+        assert!(
+            self.components.len() < MAX_REPLICATED_COMPONENTS,
+            "Too many replicated component types, the limit is {MAX_REPLICATED_COMPONENTS}"
+        );
+        let index = self.components.len();
+        let previous = self.by_name.insert(info.name, index);
+        assert!(
+            previous.is_none(),
+            "Replicated component {} registered twice",
+            info.name
+        );
+        self.components.push(info);
+    }
+
+    /// Bit mask of the registered components present on the entity.
+    fn present_mask(&self, entity: &EntityRef) -> u64 {
+        self.mask_where(|info| entity.contains_id(info.component_id))
+    }
+
+    /// Bit mask of the registered components of the entity added or changed between the given ticks.
+    fn changed_mask(&self, entity: &EntityRef, change_tick: &SystemChangeTick) -> u64 {
+        self.mask_where(|info| {
+            entity
+                .get_change_ticks_by_id(info.component_id)
+                .is_some_and(|ticks| ticks.is_changed(change_tick.last_run(), change_tick.this_run()))
+        })
+    }
+
+    fn mask_where(&self, mut predicate: impl FnMut(&ReplicatedComponentInfo) -> bool) -> u64 {
+        self.components
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| predicate(info))
+            .fold(0, |mask, (index, _)| mask | (1 << index))
+    }
+}
+
+/// Extension methods for registering replicated component types on an [`App`].
+pub trait ReplicationAppExt {
+    /// Replicates the component type `C` with the encoding `E`, under a name unique among replicated components.
+    /// Must be called in the same way on the server and client apps.
+    fn replicate_component<C: Component, E: ComponentEncoding<C>>(&mut self, name: &'static str) -> &mut Self;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate_component<C: Component, E: ComponentEncoding<C>>(&mut self, name: &'static str) -> &mut Self {
+        let component_id = self.world_mut().init_component::<C>();
+        self.world_mut()
+            .get_resource_or_insert_with(ReplicationRegistry::default)
+            .register(ReplicatedComponentInfo {
+                name,
+                component_id,
+                encode: |entity| entity.get::<C>().map(E::encode),
+                insert: |entity, data| {
+                    entity.insert(E::decode(data)?);
+                    Ok(())
+                },
+                remove: |entity| {
+                    entity.remove::<C>();
+                },
+            });
+        self
+    }
+}
+
+/// The game-side handle for sending entity packets to a connected player, attached to [`ConnectedPlayer`] entities.
+#[derive(Component)]
+pub struct EntityReplicationSender {
+    queue: AsyncBoundedSender<Bytes>,
+    compression: PacketCompression,
+    /// The entities known to the client, with the bit mask of replicated components it has.
+    known_entities: HashMap<Entity, u64>,
+    /// If the last packet didn't fit the queue, so the client may have outdated components of its known entities.
+    resend: bool,
+}
+
+impl EntityReplicationSender {
+    fn new(queue: AsyncBoundedSender<Bytes>, compression: PacketCompression) -> Self {
+        Self {
+            queue,
+            compression,
+            known_entities: default(),
+            resend: false,
+        }
+    }
+
+    /// A sender with no network task, returning the receiving end of its queue.
+    #[cfg(test)]
+    pub(crate) fn new_test(queue_length: usize) -> (Self, AsyncBoundedReceiver<Bytes>) {
+        let (queue_tx, queue_rx) = async_bounded_channel(queue_length);
+        (Self::new(queue_tx, PacketCompression::None), queue_rx)
+    }
+
+    /// Number of entities currently replicated to the client.
+    pub fn known_entity_count(&self) -> usize {
+        self.known_entities.len()
+    }
+}
+
+/// Creates the game-side sender and spawns the network task forwarding its packets into `stream`.
+/// Must be called within the tokio LocalSet.
+pub fn spawn_entity_stream(
+    peer: PeerAddress,
+    stream: InProcessStream,
+    compression: PacketCompression,
+) -> EntityReplicationSender {
+    let (queue_tx, mut queue_rx) = async_bounded_channel::<Bytes>(ENTITY_SEND_QUEUE_LENGTH);
+    let task = async move {
+        // The queue closes when the player entity (holding the sender) is despawned.
+        while let Some(packet) = queue_rx.recv().await {
+            if stream.tx.send(packet).await.is_err() {
+                debug!("Entity stream to {peer:?} was closed by the client");
+                return;
+            }
+        }
+    };
+    tokio::task::spawn_local(task.instrument(tracing::info_span!("server-entity-stream", address = ?peer)));
+    EntityReplicationSender::new(queue_tx, compression)
+}
+
+/// The receiving end of the entity replication stream on the client.
+#[derive(Resource)]
+pub struct EntityPacketReceiver(AsyncBoundedReceiver<Bytes>);
+
+/// Registers the entity replication stream handler, returns the receiver of its packets to insert into the world.
+pub fn register_entity_stream_handler<NS: NetworkThreadState>(net_thread: &NetworkThread<NS>) -> EntityPacketReceiver {
+    let (tx, rx) = async_bounded_channel(ENTITY_PACKET_QUEUE_LENGTH);
+    net_thread.insert_stream_handler(
+        NetworkStreamHeader::Standard(StandardTypes::EntityReplication),
+        Box::new(move |_state, _peer, stream| {
+            let tx: AsyncBoundedSender<Bytes> = tx.clone();
+            Box::pin(async move {
+                let InProcessStream { mut rx, .. } = stream;
+                while let Some(raw_packet) = rx.recv().await {
+                    if let Err(e) = tx.send(raw_packet).await {
+                        error!("Error while queueing entity replication packet: {e}");
+                        break;
+                    }
+                }
+            })
+        }),
+    );
+    EntityPacketReceiver(rx)
+}
+
+/// Encoded component values, shared between all clients within a tick.
+#[derive(Default)]
+struct EncodedComponentCache {
+    values: HashMap<(Entity, usize), Option<Vec<u8>>>,
+}
+
+impl EncodedComponentCache {
+    /// Encodes the components in `mask`, returns the mask of the ones that were encoded successfully.
+    fn encode(&mut self, registry: &ReplicationRegistry, entity: &EntityRef, mask: u64) -> u64 {
+        let mut encoded = 0;
+        for (index, info) in registry.components.iter().enumerate() {
+            if mask & (1 << index) == 0 {
+                continue;
+            }
+            let value = self
+                .values
+                .entry((entity.id(), index))
+                .or_insert_with(|| match (info.encode)(entity) {
+                    Some(Ok(data)) => Some(data),
+                    Some(Err(e)) => {
+                        error!(
+                            "Could not encode replicated component {} of {:?}: {e}",
+                            info.name,
+                            entity.id()
+                        );
+                        None
+                    }
+                    None => None,
+                });
+            if value.is_some() {
+                encoded |= 1 << index;
+            }
+        }
+        encoded
+    }
+
+    fn get(&self, entity: Entity, index: usize) -> &[u8] {
+        self.values[&(entity, index)]
+            .as_deref()
+            .expect("Component must be encoded before writing")
+    }
+}
+
+/// The changes of a single entity to send to a client.
+struct EntityChange {
+    entity: Entity,
+    components: u64,
+    removed: u64,
+}
+
+fn write_entity_changes(
+    mut builder: capnp::struct_list::Builder<'_, rpc::replicated_entity::Owned>,
+    changes: &[EntityChange],
+    registry: &ReplicationRegistry,
+    cache: &EncodedComponentCache,
+) {
+    for (i, change) in changes.iter().enumerate() {
+        let mut entity = builder.reborrow().get(i as u32);
+        entity.set_entity_id(change.entity.to_bits());
+        let mut components = entity.reborrow().init_components(change.components.count_ones());
+        let present = registry
+            .components
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| change.components & (1 << index) != 0);
+        for (j, (index, info)) in present.enumerate() {
+            let mut component = components.reborrow().get(j as u32);
+            component.set_name(info.name);
+            component.set_data(cache.get(change.entity, index));
+        }
+        let mut removed = entity.init_removed_components(change.removed.count_ones());
+        let removed_names = registry
+            .components
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| change.removed & (1 << index) != 0);
+        for (j, (_, info)) in removed_names.enumerate() {
+            removed.set(j as u32, info.name);
+        }
+    }
+}
+
+fn server_system_replicate_entities(
+    tick: Res<ServerTick>,
+    registry: Res<ReplicationRegistry>,
+    change_tick: SystemChangeTick,
+    entities: Query<EntityRef, With<Replicated>>,
+    mut clients: Query<
+        (Entity, &mut EntityReplicationSender, &Transform),
+        (With<ConnectedPlayer>, Without<Replicated>),
+    >,
+) {
+    if clients.is_empty() {
+        return;
+    }
+    let registry = &*registry;
+    let states = entities
+        .iter()
+        .map(|entity| {
+            let position = entity.get::<Transform>().map(|t| t.translation);
+            let present = registry.present_mask(&entity);
+            let changed = registry.changed_mask(&entity, &change_tick);
+            (entity, position, present, changed)
+        })
+        .collect_vec();
+
+    let mut cache = EncodedComponentCache::default();
+    for (player, mut sender, player_transform) in clients.iter_mut() {
+        let center = player_transform.translation;
+        let mut spawned = Vec::new();
+        let mut updated = Vec::new();
+        // Only committed once the packet is queued, so that dropped changes are computed again in the next tick.
+        let mut next_known = HashMap::with_capacity(sender.known_entities.len());
+        for (entity, position, present, changed) in states.iter() {
+            if entity.id() == player
+                || position.is_some_and(|p| p.distance_squared(center) > ENTITY_VISIBILITY_RADIUS.powi(2))
+            {
+                continue;
+            }
+            match sender.known_entities.get(&entity.id()).copied() {
+                None => {
+                    let components = cache.encode(registry, entity, *present);
+                    spawned.push(EntityChange {
+                        entity: entity.id(),
+                        components,
+                        removed: 0,
+                    });
+                    next_known.insert(entity.id(), components);
+                }
+                Some(known) => {
+                    // Changes since the last dropped packet can't be told apart anymore, so resend everything.
+                    let changed = if sender.resend { *present } else { *changed };
+                    let components = cache.encode(registry, entity, (changed | !known) & present);
+                    let removed = known & !present;
+                    next_known.insert(entity.id(), (known & present) | components);
+                    if components == 0 && removed == 0 {
+                        continue;
+                    }
+                    updated.push(EntityChange {
+                        entity: entity.id(),
+                        components,
+                        removed,
+                    });
+                }
+            }
+        }
+        let despawned = sender
+            .known_entities
+            .keys()
+            .filter(|entity| !next_known.contains_key(*entity))
+            .map(|entity| entity.to_bits())
+            .collect_vec();
+
+        if spawned.is_empty() && updated.is_empty() && despawned.is_empty() {
+            sender.resend = false;
+            continue;
+        }
+        let mut message = Builder::new_default();
+        let mut packet = message.init_root::<rpc::entity_replication_packet::Builder>();
+        packet.set_tick(tick.0);
+        write_entity_changes(
+            packet.reborrow().init_spawned(spawned.len() as u32),
+            &spawned,
+            registry,
+            &cache,
+        );
+        write_entity_changes(
+            packet.reborrow().init_updated(updated.len() as u32),
+            &updated,
+            registry,
+            &cache,
+        );
+        let mut despawned_list = packet.init_despawned(despawned.len() as u32);
+        for (i, &id) in despawned.iter().enumerate() {
+            despawned_list.set(i as u32, id);
+        }
+        let packet = encode_stream_packet(&message, sender.compression);
+        match sender.queue.try_send(Bytes::from(packet)) {
+            Ok(()) => {
+                sender.known_entities = next_known;
+                sender.resend = false;
+            }
+            // The changes are coalesced into the next packet once the client catches up.
+            Err(TrySendError::Full(_)) => sender.resend = true,
+            Err(TrySendError::Closed(_)) => trace!("Skipping entity replication to a closed stream of {player:?}"),
+        }
+    }
+}
+
+/// Maps server entity IDs to the local entities replicated from them.
+#[derive(Resource, Default, Debug)]
+pub struct ServerEntityMap {
+    server_to_local: HashMap<u64, Entity>,
+}
+
+impl ServerEntityMap {
+    /// The local entity mirroring the given server entity, if it is currently replicated.
+    pub fn get(&self, server_entity: u64) -> Option<Entity> {
+        self.server_to_local.get(&server_entity).copied()
+    }
+
+    /// Number of currently replicated entities.
+    pub fn len(&self) -> usize {
+        self.server_to_local.len()
+    }
+
+    /// Checks if no entities are currently replicated.
+    pub fn is_empty(&self) -> bool {
+        self.server_to_local.is_empty()
+    }
+}
+
+/// Despawns all the entities replicated from the server, used when leaving a game.
+pub fn despawn_replicated_entities(world: &mut World) {
+    let Some(mut map) = world.get_resource_mut::<ServerEntityMap>() else {
+        return;
+    };
+    let entities = map.server_to_local.drain().map(|(_, local)| local).collect_vec();
+    for entity in entities {
+        if let Some(entity) = world.get_entity_mut(entity) {
+            entity.despawn_recursive();
+        }
+    }
+    world.remove_resource::<EntityPacketReceiver>();
+}
+
+fn apply_replicated_components(
+    registry: &ReplicationRegistry,
+    entity: &mut EntityWorldMut,
+    data: rpc::replicated_entity::Reader,
+) -> Result<()> {
+    for component in data.get_components()? {
+        let name = component.get_name()?.to_str()?;
+        registry.insert_component(entity, name, component.get_data()?)?;
+    }
+    for name in data.get_removed_components()? {
+        registry.remove_component(entity, name?.to_str()?)?;
+    }
+    Ok(())
+}
+
+fn apply_entity_packet(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    map: &mut ServerEntityMap,
+    raw_packet: &[u8],
+) -> Result<()> {
+    let message = decode_stream_packet(raw_packet, RPC_LOCAL_READER_OPTIONS)?;
+    let message = TypedReader::<_, rpc::entity_replication_packet::Owned>::new(message);
+    let packet = message.get()?;
+
+    for data in packet.get_spawned()? {
+        let server_entity = data.get_entity_id();
+        let local = *map.server_to_local.entry(server_entity).or_insert_with(|| {
+            world
+                .spawn((ReplicatedFromServer { server_entity }, TransformBundle::default()))
+                .id()
+        });
+        apply_replicated_components(registry, &mut world.entity_mut(local), data)?;
+    }
+    for data in packet.get_updated()? {
+        let server_entity = data.get_entity_id();
+        let Some(local) = map.get(server_entity).filter(|&e| world.get_entity(e).is_some()) else {
+            warn!("Received an update for unknown server entity {server_entity}");
+            continue;
+        };
+        apply_replicated_components(registry, &mut world.entity_mut(local), data)?;
+    }
+    for server_entity in packet.get_despawned()? {
+        if let Some(local) = map.server_to_local.remove(&server_entity) {
+            if let Some(entity) = world.get_entity_mut(local) {
+                entity.despawn_recursive();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn client_system_apply_entity_packets(world: &mut World) {
+    let Some(mut receiver) = world.remove_resource::<EntityPacketReceiver>() else {
+        return;
+    };
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        world.resource_scope(|world, mut map: Mut<ServerEntityMap>| {
+            for _ in 0..ENTITY_PACKET_QUEUE_LENGTH {
+                let Ok(raw_packet) = receiver.0.try_recv() else {
+                    break;
+                };
+                if let Err(e) = apply_entity_packet(world, &registry, &mut map, &raw_packet) {
+                    error!("Error while processing received entity replication packet: {e:#}");
+                }
+            }
+        });
+    });
+    world.insert_resource(receiver);
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::schemas::game_types_capnp::vec3;
+
+    use super::*;
+    use crate::network::player_replication::{read_vec3, write_vec3};
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Velocity(Vec3);
+
+    #[derive(Component, Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Label(String);
+
+    impl CapnpComponent for Velocity {
+        type Schema = vec3::Owned;
+
+        fn write(&self, builder: vec3::Builder<'_>) {
+            write_vec3(builder, self.0);
+        }
+
+        fn read(reader: vec3::Reader<'_>) -> Result<Self> {
+            Ok(Self(read_vec3(reader)))
+        }
+    }
+
+    #[test]
+    fn component_encodings_roundtrip() {
+        let velocity = Velocity(Vec3::new(1.0, -2.0, 3.5));
+        let data = CapnpEncoding::encode(&velocity).unwrap();
+        assert_eq!(
+            <CapnpEncoding as ComponentEncoding<Velocity>>::decode(&data).unwrap(),
+            velocity
+        );
+
+        let transform = Transform::from_xyz(4.0, 5.0, 6.0).with_scale(Vec3::splat(2.0));
+        let data = SerdeEncoding::encode(&transform).unwrap();
+        assert_eq!(
+            <SerdeEncoding as ComponentEncoding<Transform>>::decode(&data).unwrap(),
+            transform
+        );
+    }
+
+    #[test]
+    fn registry_masks() {
+        let mut app = App::new();
+        app.init_resource::<ReplicationRegistry>()
+            .replicate_component::<Transform, SerdeEncoding>("gs:transform")
+            .replicate_component::<Velocity, CapnpEncoding>("test:velocity");
+        let entity = app.world_mut().spawn((Replicated, Velocity(Vec3::ONE))).id();
+        let registry = app.world().resource::<ReplicationRegistry>();
+        assert_eq!(registry.len(), 2);
+        assert!(registry.contains("test:velocity"));
+        assert_eq!(registry.present_mask(&app.world().entity(entity)), 0b10);
+    }
+    fn replication_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<ReplicationRegistry>()
+            .init_resource::<ServerEntityMap>()
+            .init_resource::<ServerTick>()
+            .replicate_component::<Transform, SerdeEncoding>("gs:transform")
+            .replicate_component::<Label, SerdeEncoding>("test:label");
+        app
+    }
+
+    fn spawn_player(world: &mut World, queue_length: usize) -> (Entity, AsyncBoundedReceiver<Bytes>) {
+        let (sender, receiver) = EntityReplicationSender::new_test(queue_length);
+        let address = PeerAddress::Local(world.entities().len() as i32);
+        let player = world
+            .spawn((
+                ConnectedPlayer {
+                    nickname: format!("{address:?}").into(),
+                    address,
+                },
+                Transform::default(),
+                sender,
+            ))
+            .id();
+        (player, receiver)
+    }
+
+    /// The spawned, updated and despawned entity IDs of a packet.
+    fn packet_entities(raw_packet: &[u8]) -> [Vec<u64>; 3] {
+        let message = decode_stream_packet(raw_packet, RPC_LOCAL_READER_OPTIONS).unwrap();
+        let message = TypedReader::<_, rpc::entity_replication_packet::Owned>::new(message);
+        let packet = message.get().unwrap();
+        let ids = |list: capnp::struct_list::Reader<'_, rpc::replicated_entity::Owned>| {
+            list.iter().map(|entity| entity.get_entity_id()).collect()
+        };
+        [
+            ids(packet.get_spawned().unwrap()),
+            ids(packet.get_updated().unwrap()),
+            packet.get_despawned().unwrap().iter().collect(),
+        ]
+    }
+
+    #[test]
+    fn replicate_visible_entities() {
+        let mut app = replication_app();
+        app.add_systems(Update, server_system_replicate_entities);
+        let (_, mut receiver) = spawn_player(app.world_mut(), 8);
+        let near = app
+            .world_mut()
+            .spawn((Replicated, Transform::from_xyz(1.0, 0.0, 0.0), Label("near".into())))
+            .id()
+            .to_bits();
+        let far_position = Transform::from_xyz(ENTITY_VISIBILITY_RADIUS * 2.0, 0.0, 0.0);
+        let far = app
+            .world_mut()
+            .spawn((Replicated, far_position, Label("far".into())))
+            .id();
+
+        app.update();
+        assert_eq!(
+            packet_entities(&receiver.try_recv().unwrap()),
+            [vec![near], vec![], vec![]]
+        );
+        assert!(receiver.try_recv().is_err());
+
+        // Nothing changed, nothing to send.
+        app.update();
+        assert!(receiver.try_recv().is_err());
+
+        app.world_mut().entity_mut(far).insert(Transform::default());
+        app.world_mut().entity_mut(Entity::from_bits(near)).insert(far_position);
+        app.update();
+        assert_eq!(
+            packet_entities(&receiver.try_recv().unwrap()),
+            [vec![far.to_bits()], vec![], vec![near]]
+        );
+
+        app.world_mut().get_mut::<Label>(far).unwrap().0 = "changed".into();
+        app.update();
+        assert_eq!(
+            packet_entities(&receiver.try_recv().unwrap()),
+            [vec![], vec![far.to_bits()], vec![]]
+        );
+    }
+
+    #[test]
+    fn full_queue_changes_are_resent() {
+        let mut app = replication_app();
+        app.add_systems(Update, server_system_replicate_entities);
+        let (player, mut receiver) = spawn_player(app.world_mut(), 1);
+        let first = app.world_mut().spawn((Replicated, Label("first".into()))).id();
+        app.update();
+
+        // The queue is full, the spawn of the second entity and the change of the first one are dropped.
+        let second = app.world_mut().spawn((Replicated, Label("second".into()))).id();
+        app.world_mut().get_mut::<Label>(first).unwrap().0 = "changed".into();
+        app.update();
+        assert_eq!(
+            packet_entities(&receiver.try_recv().unwrap()),
+            [vec![first.to_bits()], vec![], vec![]]
+        );
+        assert!(receiver.try_recv().is_err());
+        let sender = app.world().get::<EntityReplicationSender>(player).unwrap();
+        assert!(sender.resend);
+        assert_eq!(sender.known_entity_count(), 1);
+
+        app.update();
+        assert_eq!(
+            packet_entities(&receiver.try_recv().unwrap()),
+            [vec![second.to_bits()], vec![first.to_bits()], vec![]]
+        );
+        assert!(!app.world().get::<EntityReplicationSender>(player).unwrap().resend);
+    }
+
+    #[test]
+    fn apply_packets_on_the_client() {
+        let mut server = replication_app();
+        server.add_systems(Update, server_system_replicate_entities);
+        let (_, mut receiver) = spawn_player(server.world_mut(), 8);
+        let entity = server
+            .world_mut()
+            .spawn((Replicated, Transform::from_xyz(1.0, 2.0, 3.0), Label("chest".into())))
+            .id();
+        let mut client = replication_app();
+        let mut apply_next_packet = |server: &mut App, client: &mut App| {
+            server.update();
+            let raw_packet = receiver.try_recv().unwrap();
+            client
+                .world_mut()
+                .resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+                    world.resource_scope(|world, mut map: Mut<ServerEntityMap>| {
+                        apply_entity_packet(world, &registry, &mut map, &raw_packet).unwrap();
+                    });
+                });
+        };
+
+        apply_next_packet(&mut server, &mut client);
+        let map = client.world().resource::<ServerEntityMap>();
+        assert_eq!(map.len(), 1);
+        let local = map.get(entity.to_bits()).unwrap();
+        let world = client.world();
+        assert_eq!(world.get::<Label>(local), Some(&Label("chest".into())));
+        assert_eq!(
+            world.get::<Transform>(local).map(|t| t.translation),
+            Some(Vec3::new(1.0, 2.0, 3.0))
+        );
+        assert_eq!(
+            world.get::<ReplicatedFromServer>(local).map(|r| r.server_entity),
+            Some(entity.to_bits())
+        );
+
+        server.world_mut().entity_mut(entity).remove::<Label>();
+        apply_next_packet(&mut server, &mut client);
+        assert!(client.world().get::<Label>(local).is_none());
+
+        // Leaving the visibility radius despawns the client copy.
+        server
+            .world_mut()
+            .entity_mut(entity)
+            .insert(Transform::from_xyz(ENTITY_VISIBILITY_RADIUS * 2.0, 0.0, 0.0));
+        apply_next_packet(&mut server, &mut client);
+        assert!(client.world().resource::<ServerEntityMap>().is_empty());
+        assert!(client.world().get_entity(local).is_none());
+    }
+}
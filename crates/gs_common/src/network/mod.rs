@@ -1,6 +1,18 @@
 //! The networking layer of the game.
 use std::net::SocketAddr;
 
+pub mod admin;
+pub mod bot;
+pub mod capture;
+pub mod chunk_stream;
+pub mod conditions;
+pub mod custom_stream;
+pub mod datagram;
+pub mod discovery;
+pub mod entity_replication;
+pub mod player_replication;
+pub mod process;
+pub mod protocol;
 pub mod server;
 pub mod thread;
 pub mod transport;
@@ -23,3 +35,13 @@ impl PeerAddress {
         }
     }
 }
+
+/// Runs an async test on a current-thread runtime within a [`tokio::task::LocalSet`], like the network thread tasks.
+#[cfg(test)]
+pub(crate) fn run_local(test: impl std::future::Future<Output = ()>) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(tokio::task::LocalSet::new().run_until(test));
+}
@@ -0,0 +1,311 @@
+//! Replication of player positions: client movement input and snapshots of nearby players, sent as datagrams.
+//!
+//! Clients send the desired position of their player every tick, the server validates the movement and periodically
+//! sends each client the positions of the other players around it. Clients render the other players slightly in the
+//! past, interpolating between the buffered snapshots to hide the network jitter and dropped datagrams.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use capnp::message::Builder;
+use capnp::serialize::OwnedSegments;
+use gs_schemas::coordinates::AbsBlockPos;
+use gs_schemas::schemas::game_types_capnp::{quat, vec3};
+use gs_schemas::schemas::network_capnp::datagram;
+
+use crate::network::datagram::{DatagramReceiver, DatagramSender};
+use crate::network::server::ConnectedPlayer;
+use crate::prelude::*;
+use crate::tick::ServerTick;
+use crate::voxel::plugin::{ChunkLoader, VoxelPosition};
+use crate::{InGameSystemSet, SECONDS_PER_TICK_F32};
+
+/// Where newly connected players appear.
+pub const PLAYER_SPAWN_POSITION: Vec3 = Vec3::new(0.0, 6.0, 12.0);
+/// The maximum distance a player can move in a second, in blocks.
+pub const MAX_PLAYER_SPEED: f32 = 32.0;
+/// How often the server sends player snapshots to clients.
+pub const PLAYER_SNAPSHOT_INTERVAL_TICKS: u64 = 2;
+/// Players further away than this many blocks from a client's player are not replicated to it.
+pub const PLAYER_VISIBILITY_RADIUS: f32 = 256.0;
+/// The maximum number of player snapshots packed into a single datagram, to stay under the datagram size limit.
+pub const MAX_SNAPSHOTS_PER_DATAGRAM: usize = 16;
+/// How far in the past, in ticks, clients render other players, to always have two snapshots to interpolate between.
+pub const INTERPOLATION_DELAY_TICKS: f64 = 2.5 * PLAYER_SNAPSHOT_INTERVAL_TICKS as f64;
+/// The number of snapshots kept for interpolation of each player.
+pub const MAX_BUFFERED_SNAPSHOTS: usize = 32;
+
+/// Registers the server-side player replication systems.
+pub struct PlayerReplicationPlugin;
+
+impl Plugin for PlayerReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedPreUpdate,
+            (server_system_receive_player_input).in_set(InGameSystemSet),
+        )
+        .add_systems(
+            FixedPostUpdate,
+            (server_system_send_player_snapshots).in_set(InGameSystemSet),
+        );
+    }
+}
+
+/// Server-side movement validation state of a connected player.
+#[derive(Component, Clone, Debug, Default)]
+pub struct PlayerMovementState {
+    /// The sequence number of the latest applied input, older inputs are discarded.
+    pub last_input_sequence: Option<u32>,
+    /// Ticks since the latest applied input, limiting how far the next input can move the player.
+    pub ticks_since_input: u32,
+}
+
+/// The initial components of a newly connected player entity, besides the network handles.
+/// The player loads chunks in the given radius around itself.
+pub fn player_spawn_components(
+    render_distance: i32,
+) -> (TransformBundle, VoxelPosition, PlayerMovementState, ChunkLoader) {
+    (
+        TransformBundle::from_transform(Transform::from_translation(PLAYER_SPAWN_POSITION)),
+        VoxelPosition(voxel_position_of(PLAYER_SPAWN_POSITION)),
+        PlayerMovementState::default(),
+        ChunkLoader {
+            radius: render_distance,
+        },
+    )
+}
+
+/// The block containing the given point.
+pub fn voxel_position_of(translation: Vec3) -> AbsBlockPos {
+    AbsBlockPos::from_ivec3(translation.floor().as_ivec3())
+}
+
+/// Serializes a vector.
+pub fn write_vec3(mut builder: vec3::Builder, value: Vec3) {
+    builder.set_x(value.x);
+    builder.set_y(value.y);
+    builder.set_z(value.z);
+}
+
+/// Deserializes a vector.
+pub fn read_vec3(reader: vec3::Reader) -> Vec3 {
+    Vec3::new(reader.get_x(), reader.get_y(), reader.get_z())
+}
+
+/// Serializes a rotation.
+pub fn write_quat(mut builder: quat::Builder, value: Quat) {
+    builder.set_x(value.x);
+    builder.set_y(value.y);
+    builder.set_z(value.z);
+    builder.set_w(value.w);
+}
+
+/// Deserializes a rotation.
+pub fn read_quat(reader: quat::Reader) -> Quat {
+    Quat::from_xyzw(reader.get_x(), reader.get_y(), reader.get_z(), reader.get_w())
+}
+
+/// Reads the sequence number, position and orientation from a movement input datagram.
+fn read_player_input(message: &capnp::message::Reader<OwnedSegments>) -> Result<(u32, Vec3, Quat)> {
+    let datagram::PlayerInput(input) = message.get_root::<datagram::Reader>()?.which()? else {
+        bail!("Unexpected datagram type");
+    };
+    let input = input?;
+    Ok((
+        input.get_sequence(),
+        read_vec3(input.get_position()?),
+        read_quat(input.get_orientation()?),
+    ))
+}
+
+/// Moves players according to their latest valid movement input.
+fn server_system_receive_player_input(
+    mut players: Query<(
+        &ConnectedPlayer,
+        &mut DatagramReceiver,
+        &mut PlayerMovementState,
+        &mut Transform,
+        &mut VoxelPosition,
+    )>,
+) {
+    for (player, mut receiver, mut movement, mut transform, mut voxel_pos) in players.iter_mut() {
+        movement.ticks_since_input = movement.ticks_since_input.saturating_add(1);
+        let mut latest: Option<(u32, Vec3, Quat)> = None;
+        while let Some(message) = receiver.try_recv() {
+            let (sequence, position, orientation) = match read_player_input(&message) {
+                Ok(input) => input,
+                Err(e) => {
+                    warn!("Invalid datagram from player {}: {e:#}", player.nickname);
+                    continue;
+                }
+            };
+            let is_newer = |last: u32| (sequence.wrapping_sub(last) as i32) > 0;
+            if !movement.last_input_sequence.map_or(true, is_newer) || latest.is_some_and(|(l, _, _)| !is_newer(l)) {
+                continue;
+            }
+            latest = Some((sequence, position, orientation));
+        }
+
+        let Some((sequence, position, orientation)) = latest else {
+            continue;
+        };
+        if !position.is_finite() || !orientation.is_finite() {
+            warn!("Non-finite movement input from player {}", player.nickname);
+            continue;
+        }
+        let max_distance = MAX_PLAYER_SPEED * SECONDS_PER_TICK_F32 * movement.ticks_since_input as f32;
+        let delta = (position - transform.translation).clamp_length_max(max_distance);
+        transform.translation += delta;
+        transform.rotation = orientation.normalize();
+        movement.last_input_sequence = Some(sequence);
+        movement.ticks_since_input = 0;
+        let new_voxel_pos = voxel_position_of(transform.translation);
+        if voxel_pos.0 != new_voxel_pos {
+            voxel_pos.0 = new_voxel_pos;
+        }
+    }
+}
+
+/// Sends every client the positions of the other players near it.
+fn server_system_send_player_snapshots(
+    tick: Res<ServerTick>,
+    mut last_sent_tick: Local<Option<u64>>,
+    players: Query<(Entity, &Transform, Option<&DatagramSender>), With<ConnectedPlayer>>,
+) {
+    if tick.0 % PLAYER_SNAPSHOT_INTERVAL_TICKS != 0 || *last_sent_tick == Some(tick.0) {
+        return;
+    }
+    *last_sent_tick = Some(tick.0);
+
+    let all_players: Vec<(Entity, Transform)> = players.iter().map(|(e, t, _)| (e, *t)).collect();
+    for (viewer, viewer_transform, sender) in players.iter() {
+        let Some(sender) = sender else {
+            continue;
+        };
+        let visible: Vec<&(Entity, Transform)> = all_players
+            .iter()
+            .filter(|(other, other_transform)| {
+                *other != viewer
+                    && other_transform
+                        .translation
+                        .distance_squared(viewer_transform.translation)
+                        <= PLAYER_VISIBILITY_RADIUS * PLAYER_VISIBILITY_RADIUS
+            })
+            .collect();
+        for batch in visible.chunks(MAX_SNAPSHOTS_PER_DATAGRAM) {
+            let mut message = Builder::new_default();
+            let mut snapshots = message.init_root::<datagram::Builder>().init_player_snapshots();
+            snapshots.set_tick(tick.0);
+            let mut list = snapshots.init_players(batch.len() as u32);
+            for (i, (entity, transform)) in batch.iter().enumerate() {
+                let mut snapshot = list.reborrow().get(i as u32);
+                snapshot.set_entity_id(entity.to_bits());
+                write_vec3(snapshot.reborrow().init_position(), transform.translation);
+                write_quat(snapshot.reborrow().init_orientation(), transform.rotation);
+            }
+            // Dropped snapshots are superseded by the next ones anyway.
+            sender.send(&message);
+        }
+    }
+}
+
+/// A player transform at a given server tick.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransformSnapshot {
+    /// The server tick the snapshot was taken on.
+    pub tick: u64,
+    /// The position.
+    pub translation: Vec3,
+    /// The orientation.
+    pub rotation: Quat,
+}
+
+/// Recently received snapshots of a replicated entity, ordered by tick, for interpolation.
+#[derive(Component, Clone, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<TransformSnapshot>,
+}
+
+impl SnapshotBuffer {
+    /// Adds a snapshot, which may arrive out of order; duplicates are ignored.
+    pub fn push(&mut self, snapshot: TransformSnapshot) {
+        let index = self.snapshots.partition_point(|s| s.tick < snapshot.tick);
+        if self.snapshots.get(index).is_some_and(|s| s.tick == snapshot.tick) {
+            return;
+        }
+        self.snapshots.insert(index, snapshot);
+        while self.snapshots.len() > MAX_BUFFERED_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// The tick of the newest snapshot.
+    pub fn latest_tick(&self) -> Option<u64> {
+        self.snapshots.back().map(|s| s.tick)
+    }
+
+    /// Interpolates the transform at the given, possibly fractional, tick.
+    /// Outside of the buffered range, the nearest snapshot is returned without extrapolation.
+    pub fn sample(&self, tick: f64) -> Option<(Vec3, Quat)> {
+        let first = self.snapshots.front()?;
+        if tick <= first.tick as f64 {
+            return Some((first.translation, first.rotation));
+        }
+        let index = self.snapshots.partition_point(|s| s.tick as f64 <= tick);
+        let Some(&next) = self.snapshots.get(index) else {
+            let last = self.snapshots.back()?;
+            return Some((last.translation, last.rotation));
+        };
+        let prev = self.snapshots[index - 1];
+        let t = ((tick - prev.tick as f64) / (next.tick - prev.tick) as f64) as f32;
+        Some((
+            prev.translation.lerp(next.translation, t),
+            prev.rotation.slerp(next.rotation, t),
+        ))
+    }
+
+    /// Drops the snapshots no longer needed to interpolate at `tick` or later.
+    pub fn discard_before(&mut self, tick: f64) {
+        while self.snapshots.len() >= 2 && self.snapshots[1].tick as f64 <= tick {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot(tick: u64, x: f32) -> TransformSnapshot {
+        TransformSnapshot {
+            tick,
+            translation: Vec3::new(x, 0.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn snapshot_interpolation() {
+        let mut buffer = SnapshotBuffer::default();
+        assert_eq!(buffer.sample(10.0), None);
+
+        // Out of order arrival, with a duplicate.
+        buffer.push(snapshot(14, 4.0));
+        buffer.push(snapshot(10, 0.0));
+        buffer.push(snapshot(12, 2.0));
+        buffer.push(snapshot(12, 100.0));
+        assert_eq!(buffer.latest_tick(), Some(14));
+
+        let x_at = |buffer: &SnapshotBuffer, tick: f64| buffer.sample(tick).unwrap().0.x;
+        assert_eq!(x_at(&buffer, 5.0), 0.0);
+        assert_eq!(x_at(&buffer, 11.0), 1.0);
+        assert_eq!(x_at(&buffer, 12.0), 2.0);
+        assert_eq!(x_at(&buffer, 13.5), 3.5);
+        // No extrapolation.
+        assert_eq!(x_at(&buffer, 20.0), 4.0);
+
+        buffer.discard_before(13.0);
+        assert_eq!(x_at(&buffer, 13.0), 3.0);
+        assert_eq!(x_at(&buffer, 11.0), 2.0);
+    }
+}
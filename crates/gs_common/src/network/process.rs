@@ -0,0 +1,623 @@
+//! A transport for running the integrated server in a separate child process, so a crash of the server can't take the
+//! client down with it, and vice versa.
+//!
+//! The connection is carried over the standard input and output pipes of the child process: the RPC byte stream,
+//! streams and datagrams of an [`InProcessDuplex`] are multiplexed into length-prefixed frames, and turned back into an
+//! [`InProcessDuplex`] on the other side, so the rest of the networking code is unaware of the process boundary.
+//! Pipes are used instead of shared memory as they need no platform-specific code, and get closed by the OS when the
+//! process on the other end dies, which doubles as crash detection: the server process shuts down gracefully when the
+//! client goes away, and the client sees the connection drop and the exit status of the child when the server dies.
+//!
+//! Streams use credit-based flow control, so a stream that isn't read from doesn't block the other traffic on the pipe.
+
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use bevy::log::{error, info, warn};
+use capnp::message::{Builder, ReaderOptions};
+use gs_schemas::schemas::network_capnp::stream_header;
+use gs_schemas::schemas::{NetworkStreamHeader, NetworkStreamHeaderExt};
+use gs_schemas::GameSide;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+use tokio::task::{spawn_local, AbortHandle, JoinHandle, JoinSet};
+use tokio_util::bytes::Bytes;
+
+use crate::config::GameConfigHandle;
+use crate::network::server::LocalConnectionPipe;
+use crate::network::transport::{InProcessDuplex, InProcessStream, INPROCESS_STREAM_QUEUE_LENGTH};
+use crate::network::PeerAddress;
+use crate::prelude::*;
+use crate::GameServer;
+
+/// The command line flag that makes a game executable run as a server child process, see [`run_server_process`].
+pub const SERVER_PROCESS_FLAG: &str = "--integrated-server-process";
+/// How long to wait for a server child process to exit gracefully before killing it.
+pub const SERVER_PROCESS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// The maximum size of a single frame sent over the process pipes.
+pub const MAX_PIPE_FRAME_SIZE: usize = 64 * 1024 * 1024;
+/// The number of received frames buffered between the pipe reader thread and the network thread.
+const PIPE_FRAME_QUEUE_LENGTH: usize = 64;
+/// Size of the buffer used for reading the RPC byte pipe.
+const PIPE_READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A unit of data multiplexed over the process pipes.
+#[derive(Clone, Debug, PartialEq)]
+enum Frame {
+    /// A piece of the RPC byte stream.
+    Rpc(Bytes),
+    /// Opens a new stream with the given ID, assigned by the opening side.
+    OpenStream { id: u32, header: NetworkStreamHeader },
+    /// A packet sent on a stream.
+    StreamData { id: u32, data: Bytes },
+    /// Allows the other side to send more packets on a stream, after the previous ones were consumed.
+    StreamCredit { id: u32, packets: u32 },
+    /// Closes the sending direction of a stream.
+    CloseStream { id: u32 },
+    /// An unreliable datagram.
+    Datagram(Bytes),
+}
+
+impl Frame {
+    const RPC: u8 = 0;
+    const OPEN_STREAM: u8 = 1;
+    const STREAM_DATA: u8 = 2;
+    const STREAM_CREDIT: u8 = 3;
+    const CLOSE_STREAM: u8 = 4;
+    const DATAGRAM: u8 = 5;
+
+    /// Writes the frame as a type byte, a little-endian stream ID and payload length, followed by the payload.
+    fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let (frame_type, id, payload): (u8, u32, Bytes) = match self {
+            Frame::Rpc(data) => (Self::RPC, 0, data.clone()),
+            Frame::OpenStream { id, header } => {
+                let mut message = Builder::new_default();
+                header.write_to_message(&mut message.init_root::<stream_header::Builder>());
+                (
+                    Self::OPEN_STREAM,
+                    *id,
+                    Bytes::from(capnp::serialize::write_message_to_words(&message)),
+                )
+            }
+            Frame::StreamData { id, data } => (Self::STREAM_DATA, *id, data.clone()),
+            Frame::StreamCredit { id, packets } => {
+                (Self::STREAM_CREDIT, *id, Bytes::copy_from_slice(&packets.to_le_bytes()))
+            }
+            Frame::CloseStream { id } => (Self::CLOSE_STREAM, *id, Bytes::new()),
+            Frame::Datagram(data) => (Self::DATAGRAM, 0, data.clone()),
+        };
+        if payload.len() > MAX_PIPE_FRAME_SIZE {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Frame of {} bytes is too large to send", payload.len()),
+            ));
+        }
+        writer.write_all(&[frame_type])?;
+        writer.write_all(&id.to_le_bytes())?;
+        writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        writer.write_all(&payload)
+    }
+
+    /// Reads a frame written by [`Self::write_to`], returns None if the pipe was closed between frames.
+    fn read_from(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut frame_type = [0u8; 1];
+        match reader.read_exact(&mut frame_type) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let id = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        ensure!(
+            length <= MAX_PIPE_FRAME_SIZE,
+            "Received a frame of {length} bytes, larger than the limit"
+        );
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+
+        Ok(Some(match frame_type[0] {
+            Self::RPC => Frame::Rpc(Bytes::from(payload)),
+            Self::OPEN_STREAM => {
+                let message = capnp::serialize::read_message(&mut &payload[..], ReaderOptions::new())?;
+                let header = NetworkStreamHeader::read_from_message(&message.get_root::<stream_header::Reader>()?)?;
+                Frame::OpenStream { id, header }
+            }
+            Self::STREAM_DATA => Frame::StreamData {
+                id,
+                data: Bytes::from(payload),
+            },
+            Self::STREAM_CREDIT => {
+                let packets: [u8; 4] = payload
+                    .as_slice()
+                    .try_into()
+                    .context("Invalid stream credit frame size")?;
+                Frame::StreamCredit {
+                    id,
+                    packets: u32::from_le_bytes(packets),
+                }
+            }
+            Self::CLOSE_STREAM => Frame::CloseStream { id },
+            Self::DATAGRAM => Frame::Datagram(Bytes::from(payload)),
+            other => bail!("Unknown frame type {other}"),
+        }))
+    }
+}
+
+/// Carries the traffic of `inner` over a pair of byte pipes, to a peer bridging its own end of the connection.
+/// `side` determines the IDs of the locally opened streams, so they never collide with the ones opened by the peer.
+/// The returned task finishes when either the local or the remote side closes its RPC pipe, which closes both pipes.
+/// Must be called from within a tokio [`tokio::task::LocalSet`].
+pub fn bridge_duplex_over_pipe(
+    inner: InProcessDuplex,
+    side: GameSide,
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+) -> JoinHandle<()> {
+    let (frames_tx, frames_rx) = std_unbounded_channel::<Frame>();
+    std::thread::Builder::new()
+        .name("GS Pipe Writer".to_owned())
+        .spawn(move || pipe_writer_thread(frames_rx, writer))
+        .expect("Could not create a thread for the pipe writer");
+
+    let (incoming_tx, incoming_rx) = async_bounded_channel(PIPE_FRAME_QUEUE_LENGTH);
+    std::thread::Builder::new()
+        .name("GS Pipe Reader".to_owned())
+        .spawn(move || pipe_reader_thread(reader, incoming_tx))
+        .expect("Could not create a thread for the pipe reader");
+
+    spawn_local(run_bridge(inner, side, frames_tx, incoming_rx))
+}
+
+fn pipe_writer_thread(frames: StdUnboundedReceiver<Frame>, writer: impl Write) {
+    let mut writer = BufWriter::new(writer);
+    while let Ok(frame) = frames.recv() {
+        let mut result = frame.write_to(&mut writer);
+        // Batch up all the queued frames before flushing.
+        while let (Ok(()), Ok(frame)) = (&result, frames.try_recv()) {
+            result = frame.write_to(&mut writer);
+        }
+        if let Err(e) = result.and_then(|_| writer.flush()) {
+            warn!("Could not write to the process pipe: {e}");
+            return;
+        }
+    }
+}
+
+fn pipe_reader_thread(reader: impl Read, frames: AsyncBoundedSender<Frame>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        match Frame::read_from(&mut reader) {
+            Ok(Some(frame)) => {
+                if frames.blocking_send(frame).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                error!("Invalid data received on the process pipe: {e:#}");
+                return;
+            }
+        }
+    }
+}
+
+/// The bridge state of a stream, indexed by its ID.
+struct BridgedStream {
+    /// Packets received from the peer, to be forwarded to the local stream.
+    to_local: AsyncUnboundedSender<Bytes>,
+    /// The number of packets the peer is ready to receive.
+    send_credit: Rc<Semaphore>,
+}
+
+async fn run_bridge(
+    inner: InProcessDuplex,
+    side: GameSide,
+    frames: StdUnboundedSender<Frame>,
+    mut incoming: AsyncBoundedReceiver<Frame>,
+) {
+    let InProcessDuplex {
+        rpc_pipe,
+        mut incoming_streams,
+        outgoing_streams,
+        mut incoming_datagrams,
+        outgoing_datagrams,
+    } = inner;
+    let (mut rpc_reader, mut rpc_writer) = tokio::io::split(rpc_pipe);
+    // Aborts all the stream tasks when the bridge is closed or aborted, releasing their frame senders.
+    let mut tasks = JoinSet::new();
+    let mut streams: HashMap<u32, BridgedStream> = HashMap::new();
+    let mut next_stream_id: u32 = match side {
+        GameSide::Server => 0,
+        GameSide::Client => 1,
+    };
+    let mut streams_open = true;
+    let mut datagrams_open = true;
+    let mut buffer = vec![0u8; PIPE_READ_BUFFER_SIZE];
+
+    loop {
+        tokio::select! {
+            read = rpc_reader.read(&mut buffer) => {
+                let n = match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if frames.send(Frame::Rpc(Bytes::copy_from_slice(&buffer[..n]))).is_err() {
+                    break;
+                }
+            }
+            stream = incoming_streams.recv(), if streams_open => {
+                let Some(stream) = stream else {
+                    streams_open = false;
+                    continue;
+                };
+                let id = next_stream_id;
+                next_stream_id = next_stream_id.wrapping_add(2);
+                let _ = frames.send(Frame::OpenStream {
+                    id,
+                    header: stream.header.clone(),
+                });
+                streams.insert(id, bridge_stream(&mut tasks, &frames, id, stream));
+            }
+            datagram = incoming_datagrams.recv(), if datagrams_open => {
+                match datagram {
+                    Some(datagram) => {
+                        let _ = frames.send(Frame::Datagram(datagram));
+                    }
+                    None => datagrams_open = false,
+                }
+            }
+            frame = incoming.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                match frame {
+                    Frame::Rpc(data) => {
+                        if rpc_writer.write_all(&data).await.is_err() {
+                            break;
+                        }
+                    }
+                    Frame::OpenStream { id, header } => {
+                        let (bridged, local) = InProcessStream::new_pair(header);
+                        if outgoing_streams.send(local).is_ok() {
+                            streams.insert(id, bridge_stream(&mut tasks, &frames, id, bridged));
+                        }
+                    }
+                    Frame::StreamData { id, data } => {
+                        if let Some(stream) = streams.get(&id) {
+                            let _ = stream.to_local.send(data);
+                        }
+                    }
+                    Frame::StreamCredit { id, packets } => {
+                        if let Some(stream) = streams.get(&id) {
+                            stream.send_credit.add_permits(packets as usize);
+                        }
+                    }
+                    Frame::CloseStream { id } => {
+                        streams.remove(&id);
+                    }
+                    Frame::Datagram(data) => {
+                        // Datagrams are dropped when the queue is full, like on a congested link.
+                        let _ = outgoing_datagrams.try_send(data);
+                    }
+                }
+            }
+        }
+    }
+    let _ = rpc_writer.shutdown().await;
+}
+
+/// Spawns the tasks forwarding the packets of a local stream to the peer and back, with flow control.
+fn bridge_stream(
+    tasks: &mut JoinSet<()>,
+    frames: &StdUnboundedSender<Frame>,
+    id: u32,
+    stream: InProcessStream,
+) -> BridgedStream {
+    let InProcessStream { tx, mut rx, .. } = stream;
+    let send_credit = Rc::new(Semaphore::new(INPROCESS_STREAM_QUEUE_LENGTH));
+    let (to_local, mut from_peer) = async_unbounded_channel::<Bytes>();
+
+    let outgoing_frames = frames.clone();
+    let credit = Rc::clone(&send_credit);
+    tasks.spawn_local(async move {
+        loop {
+            let Ok(permit) = credit.acquire().await else {
+                return;
+            };
+            let Some(packet) = rx.recv().await else {
+                break;
+            };
+            permit.forget();
+            if outgoing_frames.send(Frame::StreamData { id, data: packet }).is_err() {
+                return;
+            }
+        }
+        let _ = outgoing_frames.send(Frame::CloseStream { id });
+    });
+
+    let credit_frames = frames.clone();
+    tasks.spawn_local(async move {
+        while let Some(packet) = from_peer.recv().await {
+            if tx.send(packet).await.is_err() {
+                return;
+            }
+            if credit_frames.send(Frame::StreamCredit { id, packets: 1 }).is_err() {
+                return;
+            }
+        }
+    });
+
+    BridgedStream { to_local, send_credit }
+}
+
+/// A game server running in a child process, connected to over its standard input and output.
+pub struct ServerProcess {
+    child: Arc<Mutex<Child>>,
+    pipes: Mutex<Option<(ChildStdout, ChildStdin)>>,
+    bridge: Mutex<Option<AbortHandle>>,
+}
+
+impl ServerProcess {
+    /// Starts the given game executable with [`SERVER_PROCESS_FLAG`], which should make it call [`run_server_process`].
+    pub fn spawn(executable: &Path) -> Result<Self> {
+        let mut child = Command::new(executable)
+            .arg(SERVER_PROCESS_FLAG)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Starting the server process {}", executable.display()))?;
+        let stdin = child.stdin.take().context("Missing server process stdin")?;
+        let stdout = child.stdout.take().context("Missing server process stdout")?;
+        info!("Started the server process with PID {}", child.id());
+        Ok(Self {
+            child: Arc::new(Mutex::new(child)),
+            pipes: Mutex::new(Some((stdout, stdin))),
+            bridge: Mutex::new(None),
+        })
+    }
+
+    /// The OS process ID of the server.
+    pub fn id(&self) -> u32 {
+        self.child.lock().unwrap().id()
+    }
+
+    /// Connects to the server over the process pipes, can only be done once per process.
+    /// Must be called from within a tokio [`tokio::task::LocalSet`].
+    pub fn connect(&self) -> Result<LocalConnectionPipe> {
+        let (stdout, stdin) = self
+            .pipes
+            .lock()
+            .unwrap()
+            .take()
+            .context("Already connected to the server process")?;
+        let (local, bridged) = InProcessDuplex::new_pair();
+        let bridge = bridge_duplex_over_pipe(bridged, GameSide::Client, stdout, stdin);
+        *self.bridge.lock().unwrap() = Some(bridge.abort_handle());
+        Ok((PeerAddress::Local(self.id() as i32), local))
+    }
+
+    /// Checks if the server process has exited, without blocking.
+    pub fn try_exit_status(&self) -> Result<Option<ExitStatus>> {
+        Ok(self.child.lock().unwrap().try_wait()?)
+    }
+
+    /// Disconnects from the server, and waits for it to shut down gracefully, killing it after the given timeout.
+    pub fn shutdown(&self, timeout: Duration) -> Result<ExitStatus> {
+        self.disconnect();
+        Self::wait_for_exit(&self.child, timeout)
+    }
+
+    /// Closes the connection to the server, which makes it shut down.
+    fn disconnect(&self) {
+        if let Some(bridge) = self.bridge.lock().unwrap().take() {
+            bridge.abort();
+        }
+        drop(self.pipes.lock().unwrap().take());
+    }
+
+    fn wait_for_exit(child: &Mutex<Child>, timeout: Duration) -> Result<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        let mut child = child.lock().unwrap();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                warn!("The server process did not shut down in {timeout:?}, killing it");
+                child.kill()?;
+                return Ok(child.wait()?);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for ServerProcess {
+    /// Signals the server to shut down, and reaps it from a background thread so the caller doesn't wait for it.
+    fn drop(&mut self) {
+        self.disconnect();
+        let child = Arc::clone(&self.child);
+        let reaper = std::thread::Builder::new()
+            .name("GS Server Process Reaper".to_owned())
+            .spawn(
+                move || match Self::wait_for_exit(&child, SERVER_PROCESS_SHUTDOWN_TIMEOUT) {
+                    Ok(status) => info!("The server process exited with {status}"),
+                    Err(e) => error!("Could not shut down the server process: {e:#}"),
+                },
+            );
+        if let Err(e) = reaper {
+            error!("Could not create a thread for waiting on the server process: {e}");
+        }
+    }
+}
+
+/// The main function of a server child process started by [`ServerProcess::spawn`]: hosts the game for the single client
+/// connected over the standard input and output, and shuts down once that client goes away.
+/// Nothing else may write to the standard output of the process.
+pub fn run_server_process(config: GameConfigHandle) -> Result<()> {
+    let server = GameServer::new(config)?;
+    server.set_paused(false);
+    let (_address, pipe) = server
+        .create_local_connection()
+        .blocking_wait()
+        .context("Creating the connection to the parent process")?;
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(tokio::task::LocalSet::new().run_until(async move {
+            let bridge = bridge_duplex_over_pipe(pipe, GameSide::Server, std::io::stdin(), std::io::stdout());
+            let _ = bridge.await;
+        }));
+
+    let crashed = !server.is_alive() || !server.is_network_alive();
+    info!("The client disconnected, shutting down the server process");
+    let _ = server.shutdown().blocking_recv();
+    ensure!(!crashed, "The server stopped unexpectedly");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+
+    use super::*;
+    use crate::network::run_local;
+
+    /// An in-memory blocking pipe, standing in for the OS pipes of a child process.
+    struct MemoryPipeReader {
+        rx: StdUnboundedReceiver<Vec<u8>>,
+        pending: Vec<u8>,
+        offset: usize,
+    }
+
+    struct MemoryPipeWriter(StdUnboundedSender<Vec<u8>>);
+
+    fn memory_pipe() -> (MemoryPipeReader, MemoryPipeWriter) {
+        let (tx, rx) = std_unbounded_channel();
+        (
+            MemoryPipeReader {
+                rx,
+                pending: Vec::new(),
+                offset: 0,
+            },
+            MemoryPipeWriter(tx),
+        )
+    }
+
+    impl Read for MemoryPipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.offset == self.pending.len() {
+                match self.rx.recv() {
+                    Ok(data) => {
+                        self.pending = data;
+                        self.offset = 0;
+                    }
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.pending.len() - self.offset);
+            buf[..n].copy_from_slice(&self.pending[self.offset..self.offset + n]);
+            self.offset += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for MemoryPipeWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let frames = [
+            Frame::Rpc(Bytes::from_static(b"rpc")),
+            Frame::OpenStream {
+                id: 7,
+                header: NetworkStreamHeader::Standard(StandardTypes::ChunkData),
+            },
+            Frame::StreamData {
+                id: 7,
+                data: Bytes::from_static(b"chunk"),
+            },
+            Frame::StreamCredit { id: 7, packets: 3 },
+            Frame::CloseStream { id: 7 },
+            Frame::Datagram(Bytes::new()),
+        ];
+        let mut buffer = Vec::new();
+        for frame in &frames {
+            frame.write_to(&mut buffer).unwrap();
+        }
+        let mut reader = &buffer[..];
+        for frame in &frames {
+            assert_eq!(Frame::read_from(&mut reader).unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(Frame::read_from(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn bridged_connection() {
+        run_local(async {
+            let (mut client, client_bridged) = InProcessDuplex::new_pair();
+            let (server_bridged, mut server) = InProcessDuplex::new_pair();
+            let (client_reader, server_writer) = memory_pipe();
+            let (server_reader, client_writer) = memory_pipe();
+            let client_bridge = bridge_duplex_over_pipe(client_bridged, GameSide::Client, client_reader, client_writer);
+            let server_bridge = bridge_duplex_over_pipe(server_bridged, GameSide::Server, server_reader, server_writer);
+
+            client.rpc_pipe.write_all(b"hello").await.unwrap();
+            let mut received = [0u8; 5];
+            server.rpc_pipe.read_exact(&mut received).await.unwrap();
+            assert_eq!(&received, b"hello");
+
+            // More packets than the stream queue length, sent before the other side starts reading.
+            let (local, remote) = InProcessStream::new_pair(NetworkStreamHeader::Standard(StandardTypes::ChunkData));
+            server.outgoing_streams.send(remote).unwrap();
+            let sender = spawn_local(async move {
+                for i in 0..(INPROCESS_STREAM_QUEUE_LENGTH * 4) {
+                    local.tx.send(Bytes::from(vec![i as u8])).await.unwrap();
+                }
+                local
+            });
+            let mut accepted = client.incoming_streams.recv().await.unwrap();
+            assert_eq!(accepted.header, NetworkStreamHeader::Standard(StandardTypes::ChunkData));
+            for i in 0..(INPROCESS_STREAM_QUEUE_LENGTH * 4) {
+                assert_eq!(accepted.rx.recv().await.unwrap(), Bytes::from(vec![i as u8]));
+            }
+            let mut local = sender.await.unwrap();
+            accepted.tx.send(Bytes::from_static(b"ack")).await.unwrap();
+            assert_eq!(local.rx.recv().await.unwrap(), Bytes::from_static(b"ack"));
+
+            client
+                .outgoing_datagrams
+                .send(Bytes::from_static(b"dgram"))
+                .await
+                .unwrap();
+            assert_eq!(
+                server.incoming_datagrams.recv().await.unwrap(),
+                Bytes::from_static(b"dgram")
+            );
+
+            // Closing one side closes the other.
+            drop(client);
+            let mut rest = Vec::new();
+            server.rpc_pipe.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+            client_bridge.await.unwrap();
+            server_bridge.await.unwrap();
+        });
+    }
+}
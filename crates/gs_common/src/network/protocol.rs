@@ -0,0 +1,142 @@
+//! Network protocol versioning and the compatibility checks performed before authentication.
+
+use std::ops::RangeInclusive;
+
+use gs_schemas::schemas::game_types_capnp::version;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use thiserror::Error;
+
+use crate::prelude::*;
+use crate::{GAME_VERSION_BUILD, GAME_VERSION_MAJOR, GAME_VERSION_MINOR, GAME_VERSION_PATCH, GAME_VERSION_PRERELEASE};
+
+/// The network protocol version spoken by this build, bumped on every change that breaks wire compatibility.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest peer protocol version this build can still communicate with.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 1;
+/// The range of peer protocol versions this build can communicate with.
+pub const SUPPORTED_PROTOCOL_VERSIONS: RangeInclusive<u32> = MIN_SUPPORTED_PROTOCOL_VERSION..=PROTOCOL_VERSION;
+
+static_assertions::const_assert!(MIN_SUPPORTED_PROTOCOL_VERSION <= PROTOCOL_VERSION);
+
+/// The reason a peer's protocol version was rejected.
+#[derive(Copy, Clone, Debug, Error, Eq, PartialEq)]
+pub enum ProtocolIncompatibility {
+    /// The peer speaks an older protocol than the oldest supported one.
+    #[error(
+        "Protocol version {peer} is too old, the oldest supported version is {min}; the client needs to be updated"
+    )]
+    TooOld {
+        /// The peer's protocol version.
+        peer: u32,
+        /// The oldest supported protocol version.
+        min: u32,
+    },
+    /// The peer speaks a newer protocol than this build knows about.
+    #[error(
+        "Protocol version {peer} is too new, the newest supported version is {max}; the server needs to be updated"
+    )]
+    TooNew {
+        /// The peer's protocol version.
+        peer: u32,
+        /// The newest supported protocol version.
+        max: u32,
+    },
+}
+
+/// Checks if a peer speaking the given protocol version can communicate with this build.
+pub fn check_protocol_compatibility(peer_protocol: u32) -> Result<(), ProtocolIncompatibility> {
+    if peer_protocol < *SUPPORTED_PROTOCOL_VERSIONS.start() {
+        Err(ProtocolIncompatibility::TooOld {
+            peer: peer_protocol,
+            min: *SUPPORTED_PROTOCOL_VERSIONS.start(),
+        })
+    } else if peer_protocol > *SUPPORTED_PROTOCOL_VERSIONS.end() {
+        Err(ProtocolIncompatibility::TooNew {
+            peer: peer_protocol,
+            max: *SUPPORTED_PROTOCOL_VERSIONS.end(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Fills in the SemVer version of the current build.
+pub fn write_game_version(mut ver: version::Builder) {
+    ver.set_major(GAME_VERSION_MAJOR);
+    ver.set_minor(GAME_VERSION_MINOR);
+    ver.set_patch(GAME_VERSION_PATCH);
+    ver.set_build(GAME_VERSION_BUILD);
+    ver.set_prerelease(GAME_VERSION_PRERELEASE);
+}
+
+/// Formats a serialized SemVer version as `major.minor.patch[-prerelease][+build]`.
+pub fn format_game_version(ver: version::Reader) -> Result<String> {
+    let mut out = format!("{}.{}.{}", ver.get_major(), ver.get_minor(), ver.get_patch());
+    let prerelease = ver.get_prerelease()?.to_str()?;
+    if !prerelease.is_empty() {
+        out.push('-');
+        out.push_str(prerelease);
+    }
+    let build = ver.get_build()?.to_str()?;
+    if !build.is_empty() {
+        out.push('+');
+        out.push_str(build);
+    }
+    Ok(out)
+}
+
+/// Performs the client side of the protocol negotiation, failing with a descriptive error if the server rejects us.
+pub async fn negotiate_protocol(server_rpc: &rpc::game_server::Client) -> Result<()> {
+    let mut request = server_rpc.negotiate_protocol_request();
+    {
+        let mut builder = request.get();
+        write_game_version(builder.reborrow().init_client_version());
+        builder.set_protocol_version(PROTOCOL_VERSION);
+        let mut compression = builder.init_supported_compression(2);
+        compression.set(0, PacketCompression::None);
+        compression.set(1, PacketCompression::Deflate);
+    }
+    let response = request
+        .send()
+        .promise
+        .await
+        .context("RPC failure to negotiate the protocol version")?;
+    let response = response.get().context("Invalid protocol negotiation response")?;
+    let result = response.get_result().context("Missing protocol negotiation result")?;
+    if !result.get_accepted() {
+        let server_version = format_game_version(result.get_server_version()?)?;
+        let message = result.get_message()?.to_str()?;
+        bail!(
+            "Server version {server_version} rejected our protocol version {PROTOCOL_VERSION} \
+             (server supports {}..={}): {message}",
+            result.get_min_protocol_version(),
+            result.get_max_protocol_version()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protocol_compatibility_range() {
+        assert_eq!(check_protocol_compatibility(PROTOCOL_VERSION), Ok(()));
+        assert_eq!(check_protocol_compatibility(MIN_SUPPORTED_PROTOCOL_VERSION), Ok(()));
+        assert_eq!(
+            check_protocol_compatibility(PROTOCOL_VERSION + 1),
+            Err(ProtocolIncompatibility::TooNew {
+                peer: PROTOCOL_VERSION + 1,
+                max: PROTOCOL_VERSION
+            })
+        );
+        assert_eq!(
+            check_protocol_compatibility(MIN_SUPPORTED_PROTOCOL_VERSION - 1),
+            Err(ProtocolIncompatibility::TooOld {
+                peer: MIN_SUPPORTED_PROTOCOL_VERSION - 1,
+                min: MIN_SUPPORTED_PROTOCOL_VERSION
+            })
+        );
+    }
+}
@@ -2,29 +2,66 @@
 
 use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use bevy::log;
 use bevy::prelude::*;
 use capnp_rpc::rpc_twoparty_capnp::Side;
 use capnp_rpc::{pry, RpcSystem};
+use gs_schemas::coordinates::AbsBlockPos;
 use gs_schemas::dependencies::capnp::capability::Promise;
 use gs_schemas::dependencies::capnp::Error;
 use gs_schemas::dependencies::kstring::KString;
+use gs_schemas::registries::GameRegistries;
+use gs_schemas::schemas::game_types_capnp::game_bootstrap_data;
+use gs_schemas::schemas::network_capnp::authenticated_client_connection::connection_termination::Kind as ConnectionTerminationKind;
 use gs_schemas::schemas::network_capnp::authenticated_server_connection::{
-    BootstrapGameDataParams, BootstrapGameDataResults, SendChatMessageParams, SendChatMessageResults,
+    ApplyInventoryTransactionParams, ApplyInventoryTransactionResults, BootstrapGameDataParams,
+    BootstrapGameDataResults, GetServerTickParams, GetServerTickResults, OpenInventoryParams, OpenInventoryResults,
+    SendChatMessageParams, SendChatMessageResults, SetBlockParams, SetBlockResults, SetRenderDistanceParams,
+    SetRenderDistanceResults,
 };
+use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::network_capnp::PacketCompression;
 use gs_schemas::schemas::{network_capnp as rpc, NetworkStreamHeader, SchemaUuidExt};
+use gs_schemas::voxel::chunk_storage::ChunkStorage;
+use gs_schemas::voxel::voxeltypes::{BlockEntry, PLACEHOLDER_BLOCK_NAME};
+use gs_schemas::GameSide;
 use tokio::task::JoinHandle;
+use tokio_util::bytes::Bytes;
 use tracing::Instrument;
 use uuid::Uuid;
 
+use crate::config::{GameConfig, ServerConfig, ServerRuntimeSettings};
+use crate::inventory::{
+    apply_player_transaction, can_view_inventory, spawn_inventory_stream, starting_player_inventory,
+    InventoryTransaction, InventoryViewer,
+};
+use crate::items::ItemRegistryHolder;
+use crate::network::admin::admin_listener_task;
+use crate::network::capture::{record_traffic, TrafficRecorder};
+use crate::network::chunk_stream::spawn_chunk_stream;
+use crate::network::conditions::{simulate_network_conditions, NetworkConditions};
+use crate::network::custom_stream::reject_stream;
+use crate::network::datagram::{DatagramReceiver, DatagramSender};
+use crate::network::discovery::lan_announcer_task;
+use crate::network::entity_replication::spawn_entity_stream;
+use crate::network::player_replication::player_spawn_components;
+use crate::network::protocol::{
+    check_protocol_compatibility, write_game_version, PROTOCOL_VERSION, SUPPORTED_PROTOCOL_VERSIONS,
+};
 use crate::network::thread::NetworkThreadState;
 use crate::network::transport::{create_local_rpc_server, InProcessDuplex, InProcessStream};
 use crate::network::PeerAddress;
 use crate::prelude::*;
-use crate::{
-    GameServer, GAME_VERSION_BUILD, GAME_VERSION_MAJOR, GAME_VERSION_MINOR, GAME_VERSION_PATCH, GAME_VERSION_PRERELEASE,
-};
+use crate::voxel::plugin::{BlockRegistryHolder, ChunkLoader, VoxelUniverse, DEFAULT_PLAYER_RENDER_DISTANCE};
+use crate::{GameServer, ServerData};
+
+/// How long to wait for a client to acknowledge the reason of its disconnection before dropping the connection anyway.
+pub const CONNECTION_TERMINATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The maximum distance from a player to the center of a block it can edit, in blocks.
+pub const MAX_BLOCK_EDIT_DISTANCE: f32 = 16.0;
 
 /// The network thread game server state, accessible from network functions.
 pub struct NetworkThreadServerState {
@@ -32,6 +69,9 @@ pub struct NetworkThreadServerState {
     free_local_id: i32,
     connected_clients: HashMap<PeerAddress, ConnectedNetClient>,
     bootstrapped_clients: HashMap<PeerAddress, Rc<RefCell<AuthenticatedServer2ClientEndpoint>>>,
+    lan_announcer: Option<JoinHandle<()>>,
+    admin_listener: Option<JoinHandle<()>>,
+    config_watcher: Option<JoinHandle<()>>,
 }
 
 /// Network thread data for a live connected client.
@@ -39,9 +79,9 @@ pub struct ConnectedNetClient {
     rpc_task: JoinHandle<Result<()>>,
     stream_task: JoinHandle<Result<()>>,
     stream_sender: AsyncUnboundedSender<InProcessStream>,
-
-    /// The network stream for chunk data.
-    pub chunk_stream: Option<InProcessStream>,
+    datagram_sender: AsyncBoundedSender<Bytes>,
+    /// Taken by the player entity on authentication.
+    datagram_receiver: Option<AsyncBoundedReceiver<Bytes>>,
 }
 
 impl ConnectedNetClient {
@@ -81,8 +121,16 @@ impl Plugin for NetworkServerPlugin {
 }
 
 impl NetworkThreadState for NetworkThreadServerState {
-    async fn shutdown(_this: Rc<RefCell<Self>>) {
-        //
+    async fn shutdown(this: Rc<RefCell<Self>>) {
+        if let Some(announcer) = this.borrow_mut().lan_announcer.take() {
+            announcer.abort();
+        }
+        if let Some(listener) = this.borrow_mut().admin_listener.take() {
+            listener.abort();
+        }
+        if let Some(watcher) = this.borrow_mut().config_watcher.take() {
+            watcher.abort();
+        }
     }
 }
 
@@ -97,6 +145,9 @@ impl Default for NetworkThreadServerState {
             free_local_id: Default::default(),
             connected_clients: Default::default(),
             bootstrapped_clients: Default::default(),
+            lan_announcer: None,
+            admin_listener: None,
+            config_watcher: None,
         }
     }
 }
@@ -125,6 +176,44 @@ impl NetworkThreadServerState {
         self.bootstrapped_clients.get(&address)
     }
 
+    /// Finds a bootstrapped client by the nickname of its player.
+    pub fn find_bootstrapped_client_by_nickname(
+        &self,
+        nickname: &str,
+    ) -> Option<(PeerAddress, &Rc<RefCell<AuthenticatedServer2ClientEndpoint>>)> {
+        self.bootstrapped_clients
+            .iter()
+            .find(|(_, client)| client.borrow().username() == nickname)
+            .map(|(&address, client)| (address, client))
+    }
+
+    /// Iterates over all the bootstrapped clients.
+    pub fn bootstrapped_clients(
+        &self,
+    ) -> impl Iterator<Item = (PeerAddress, &Rc<RefCell<AuthenticatedServer2ClientEndpoint>>)> {
+        self.bootstrapped_clients
+            .iter()
+            .map(|(&address, client)| (address, client))
+    }
+
+    /// Sends the ID mappings of the given reloaded registries to all bootstrapped clients.
+    pub fn broadcast_reloaded_registries(&self, registries: &GameRegistries) {
+        for (address, client) in self.bootstrapped_clients() {
+            let mut request = client.borrow().rpc().reload_registries_request();
+            write_game_bootstrap_data(registries, request.get().init_data());
+            tokio::task::spawn_local(async move {
+                if let Err(e) = request.send().promise.await {
+                    log::warn!("Could not send the reloaded registries to {address:?}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Number of clients that authenticated as players.
+    pub fn player_count(&self) -> usize {
+        self.bootstrapped_clients.len()
+    }
+
     /// Unblocks stream processing, call after all the handlers are registered.
     pub async fn allow_streams(this: &Rc<RefCell<Self>>) {
         this.borrow_mut().ready_to_accept_streams.send_replace(true);
@@ -137,13 +226,87 @@ impl NetworkThreadServerState {
         let config = config_listener.borrow_and_update().server.clone();
 
         Self::update_listeners(this, &engine, &config.listen_addresses).await;
+        Self::update_lan_announcer(this, &engine, config.lan_discovery);
+
+        match (config.admin_listen_address, &config.admin_credential) {
+            (Some(address), Some(_)) => {
+                let listener = admin_listener_task(Rc::clone(this), Arc::clone(&engine), address);
+                let listener = async move {
+                    if let Err(e) = listener.await {
+                        error!("Remote administration listener stopped: {e:#}");
+                    }
+                }
+                .instrument(tracing::info_span!("server-admin-listener"));
+                this.borrow_mut().admin_listener = Some(tokio::task::spawn_local(listener));
+            }
+            (Some(_), None) => info!("Remote administration is disabled, no admin credential is configured"),
+            (None, _) => {}
+        }
+
+        let watcher = Self::config_watcher_task(Rc::clone(this), engine, config_listener, config)
+            .instrument(tracing::info_span!("server-config-watcher"));
+        this.borrow_mut().config_watcher = Some(tokio::task::spawn_local(watcher));
         Ok(())
     }
 
+    /// Applies the changes of the server configuration to the listeners and the LAN announcer, and saves the changed
+    /// runtime settings into the [`ServerConfig::runtime_settings_file`], until aborted.
+    async fn config_watcher_task(
+        this: Rc<RefCell<Self>>,
+        engine: Arc<GameServer>,
+        mut config_listener: AsyncWatchReceiver<GameConfig>,
+        mut previous: ServerConfig,
+    ) {
+        while config_listener.changed().await.is_ok() {
+            let config = config_listener.borrow_and_update().server.clone();
+            if config.listen_addresses != previous.listen_addresses {
+                Self::update_listeners(&this, &engine, &config.listen_addresses).await;
+            }
+            if config.lan_discovery != previous.lan_discovery {
+                Self::update_lan_announcer(&this, &engine, config.lan_discovery);
+            }
+            let settings = ServerRuntimeSettings::from_config(&config);
+            if let Some(path) = config.runtime_settings_file.clone() {
+                if settings != ServerRuntimeSettings::from_config(&previous) {
+                    match tokio::task::spawn_blocking(move || settings.save(&path)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => error!("Could not save the server settings: {e:#}"),
+                        Err(e) => error!("Could not save the server settings: {e}"),
+                    }
+                }
+            }
+            previous = config;
+        }
+    }
+
+    /// Starts or stops announcing the server on the local network.
+    fn update_lan_announcer(this: &Rc<RefCell<Self>>, engine: &Arc<GameServer>, enabled: bool) {
+        if !enabled {
+            if let Some(announcer) = this.borrow_mut().lan_announcer.take() {
+                info!("Stopped the LAN announcements");
+                announcer.abort();
+            }
+            return;
+        }
+        if this.borrow().lan_announcer.is_some() {
+            return;
+        }
+        let announcer = lan_announcer_task(Rc::clone(this), Arc::clone(engine));
+        let announcer = async move {
+            if let Err(e) = announcer.await {
+                warn!("LAN announcements stopped: {e:#}");
+            }
+        }
+        .instrument(tracing::info_span!("server-lan-announcer"));
+        this.borrow_mut().lan_announcer = Some(tokio::task::spawn_local(announcer));
+    }
+
     /// Creates a new local server->client connection and returns the client address and stream to pass into the client object.
+    /// Non-ideal `conditions` are simulated on the client end of the connection.
     pub async fn accept_local_connection(
         this_ptr: &Rc<RefCell<Self>>,
         engine: Arc<GameServer>,
+        conditions: NetworkConditions,
     ) -> Result<LocalConnectionPipe> {
         let mut this = this_ptr.borrow_mut();
         let id = this.free_local_id;
@@ -151,6 +314,28 @@ impl NetworkThreadServerState {
         let peer = PeerAddress::Local(id);
 
         let (spipe, cpipe) = InProcessDuplex::new_pair();
+        let capture_directory = engine.config().borrow().server.traffic_capture_directory.clone();
+        let spipe = match capture_directory {
+            Some(directory) => {
+                let tick_engine = Arc::clone(&engine);
+                match TrafficRecorder::create_in_directory(&directory, peer, GameSide::Server, move || {
+                    tick_engine.current_tick()
+                }) {
+                    Ok(recorder) => record_traffic(spipe, Rc::new(recorder)),
+                    Err(e) => {
+                        error!("Could not start recording the traffic of {peer:?}: {e:#}");
+                        spipe
+                    }
+                }
+            }
+            None => spipe,
+        };
+        let cpipe = if conditions.is_ideal() {
+            cpipe
+        } else {
+            info!("Simulating network conditions for {peer:?}: {conditions:?}");
+            simulate_network_conditions(cpipe, &conditions)
+        };
         let rpc_server = create_local_rpc_server(this_ptr.clone(), Arc::clone(&engine), spipe.rpc_pipe, peer);
         let rpc_listener = Self::local_listener_task(peer, Arc::clone(&engine), rpc_server)
             .instrument(tracing::info_span!("server-rpc", address = ?peer));
@@ -166,7 +351,8 @@ impl NetworkThreadServerState {
                 rpc_task,
                 stream_task,
                 stream_sender: spipe.outgoing_streams,
-                chunk_stream: None,
+                datagram_sender: spipe.outgoing_datagrams,
+                datagram_receiver: Some(spipe.incoming_datagrams),
             },
         );
 
@@ -185,53 +371,7 @@ impl NetworkThreadServerState {
                 .filter_map(PeerAddress::remote_addr),
         );
         for &shutdown_addr in old_set.difference(&new_set) {
-            let addr = PeerAddress::Remote(shutdown_addr);
-
-            // remove from the bevy world
-            engine
-                .schedule_bevy(move |world| {
-                    let mut table = world.query::<(Entity, &ConnectedPlayersTable)>();
-                    let table = table.get_single(world);
-                    match table {
-                        Ok((etable, table)) => {
-                            let (pent, nick) = {
-                                let pent = table.players_by_address.get(&addr);
-                                let Some(&pent) = pent else {
-                                    bail!("Mismatched player table and bevy state for {addr:?}");
-                                };
-                                let Some(player) = world.get::<ConnectedPlayer>(pent) else {
-                                    bail!(
-                                        "Mismatched player table and bevy state for {addr:?} with entity ID {pent:?}"
-                                    );
-                                };
-                                (pent, player.nickname.clone())
-                            };
-                            world.despawn(pent);
-                            // we have to re-borrow here
-                            let mut table = world
-                                .get_mut::<ConnectedPlayersTable>(etable)
-                                .context("Getting ConnectedPlayersTable")?;
-                            table.players_by_address.remove(&addr);
-                            table.players_by_nickname.remove(&nick);
-                        }
-                        Err(e) => {
-                            warn!("Could not remove player connection {addr:?}: {e}");
-                        }
-                    }
-                    Ok(())
-                })
-                .async_log_when_fails("removing disconnected players from the ConnectedPlayersTable");
-
-            let listener = this.borrow_mut().connected_clients.remove(&addr);
-            let Some(listener) = listener else { continue };
-            listener.rpc_task.abort();
-            listener.stream_task.abort();
-            if let Ok(Err(e)) = listener.rpc_task.await {
-                log::warn!("RPC listener for address {shutdown_addr} finished with an error {e}");
-            }
-            if let Ok(Err(e)) = listener.stream_task.await {
-                log::warn!("Stream listener for address {shutdown_addr} finished with an error {e}");
-            }
+            Self::disconnect_client(this, engine, PeerAddress::Remote(shutdown_addr)).await;
         }
         for &_setup_addr in new_set.difference(&old_set) {
             /*
@@ -244,6 +384,81 @@ impl NetworkThreadServerState {
         }
     }
 
+    /// Notifies a bootstrapped client why it's being disconnected, then drops its connection like [`Self::disconnect_client`].
+    pub async fn terminate_client(
+        this: &Rc<RefCell<Self>>,
+        engine: &Arc<GameServer>,
+        addr: PeerAddress,
+        kind: ConnectionTerminationKind,
+        message: &str,
+    ) {
+        let client_rpc = this
+            .borrow()
+            .find_bootstrapped_client(addr)
+            .map(|client| client.borrow().rpc().clone());
+        if let Some(client_rpc) = client_rpc {
+            let mut request = client_rpc.terminate_connection_request();
+            let mut reason = request.get().init_reason();
+            reason.set_kind(kind);
+            reason.set_message(message);
+            // Don't let an unresponsive client delay its own disconnection.
+            match tokio::time::timeout(CONNECTION_TERMINATION_TIMEOUT, request.send().promise).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log::debug!("Could not notify {addr:?} of the connection termination: {e}"),
+                Err(_) => log::debug!("Timed out notifying {addr:?} of the connection termination"),
+            }
+        }
+        Self::disconnect_client(this, engine, addr).await;
+    }
+
+    /// Drops the connection to the given client and removes its player from the game world.
+    pub async fn disconnect_client(this: &Rc<RefCell<Self>>, engine: &Arc<GameServer>, addr: PeerAddress) {
+        // remove from the bevy world
+        engine
+            .schedule_bevy(move |world| {
+                let mut table = world.query::<(Entity, &ConnectedPlayersTable)>();
+                let table = table.get_single(world);
+                match table {
+                    Ok((etable, table)) => {
+                        let (pent, nick) = {
+                            let pent = table.players_by_address.get(&addr);
+                            let Some(&pent) = pent else {
+                                bail!("Mismatched player table and bevy state for {addr:?}");
+                            };
+                            let Some(player) = world.get::<ConnectedPlayer>(pent) else {
+                                bail!("Mismatched player table and bevy state for {addr:?} with entity ID {pent:?}");
+                            };
+                            (pent, player.nickname.clone())
+                        };
+                        world.despawn(pent);
+                        // we have to re-borrow here
+                        let mut table = world
+                            .get_mut::<ConnectedPlayersTable>(etable)
+                            .context("Getting ConnectedPlayersTable")?;
+                        table.players_by_address.remove(&addr);
+                        table.players_by_nickname.remove(&nick);
+                    }
+                    Err(e) => {
+                        warn!("Could not remove player connection {addr:?}: {e}");
+                    }
+                }
+                Ok(())
+            })
+            .async_log_when_fails("removing disconnected players from the ConnectedPlayersTable");
+
+        this.borrow_mut().bootstrapped_clients.remove(&addr);
+        let listener = this.borrow_mut().connected_clients.remove(&addr);
+        let Some(listener) = listener else { return };
+        listener.rpc_task.abort();
+        listener.stream_task.abort();
+        if let Ok(Err(e)) = listener.rpc_task.await {
+            log::warn!("RPC listener for address {addr:?} finished with an error {e}");
+        }
+        if let Ok(Err(e)) = listener.stream_task.await {
+            log::warn!("Stream listener for address {addr:?} finished with an error {e}");
+        }
+    }
+
     async fn local_listener_task(
         addr: PeerAddress,
         _engine: Arc<GameServer>,
@@ -258,7 +473,7 @@ impl NetworkThreadServerState {
     async fn local_stream_task(
         this: Rc<RefCell<Self>>,
         engine: Arc<GameServer>,
-        _addr: PeerAddress,
+        addr: PeerAddress,
         mut incoming_streams: AsyncUnboundedReceiver<InProcessStream>,
     ) -> Result<()> {
         let mut ready_watcher = this.borrow().ready_to_accept_streams.subscribe();
@@ -266,17 +481,14 @@ impl NetworkThreadServerState {
             ready_watcher.changed().await?;
         }
         while let Some(stream) = incoming_streams.recv().await {
-            let handler = engine.network_thread.create_stream_handler(Rc::clone(&this), stream);
+            let handler = engine
+                .network_thread
+                .create_stream_handler(Rc::clone(&this), addr, stream);
             match handler {
                 Ok(handler) => {
                     tokio::task::spawn_local(handler);
                 }
-                Err(stream) => {
-                    error!(
-                        "No stream handler found for incoming client stream of type {:?}",
-                        stream.header
-                    );
-                }
+                Err(stream) => reject_stream(addr, stream),
             }
         }
         Ok(())
@@ -288,6 +500,8 @@ pub struct Server2ClientEndpoint {
     net_state: Rc<RefCell<NetworkThreadServerState>>,
     server: Arc<GameServer>,
     peer: PeerAddress,
+    protocol_negotiated: bool,
+    packet_compression: PacketCompression,
     auth_attempted: bool,
 }
 
@@ -298,6 +512,7 @@ pub struct AuthenticatedServer2ClientEndpoint {
     peer: PeerAddress,
     username: KString,
     connection: rpc::authenticated_client_connection::Client,
+    packet_compression: PacketCompression,
 }
 
 #[derive(Clone, Deref)]
@@ -311,6 +526,8 @@ impl Server2ClientEndpoint {
             net_state,
             server,
             peer,
+            protocol_negotiated: false,
+            packet_compression: PacketCompression::None,
             auth_attempted: false,
         }
     }
@@ -326,25 +543,26 @@ impl Server2ClientEndpoint {
     }
 }
 
+/// Fills in the publicly visible server metadata, shared by the RPC metadata query and LAN announcements.
+pub fn write_server_metadata(server: &GameServer, player_count: u32, mut meta: rpc::game_server::metadata::Builder) {
+    let config = server.config().borrow();
+    write_game_version(meta.reborrow().init_server_version());
+    meta.set_protocol_version(PROTOCOL_VERSION);
+
+    meta.set_title(&config.server.server_title);
+    meta.set_subtitle(&config.server.server_subtitle);
+    meta.set_player_count(player_count as i32);
+    meta.set_player_limit(config.server.max_players as i32);
+}
+
 impl rpc::game_server::Server for Server2ClientEndpoint {
     fn get_server_metadata(
         &mut self,
         _params: rpc::game_server::GetServerMetadataParams,
         mut results: rpc::game_server::GetServerMetadataResults,
     ) -> Promise<(), Error> {
-        let config = self.server.config().borrow();
-        let mut meta = results.get().init_metadata();
-        let mut ver = meta.reborrow().init_server_version();
-        ver.set_major(GAME_VERSION_MAJOR);
-        ver.set_minor(GAME_VERSION_MINOR);
-        ver.set_patch(GAME_VERSION_PATCH);
-        ver.set_build(GAME_VERSION_BUILD);
-        ver.set_prerelease(GAME_VERSION_PRERELEASE);
-
-        meta.set_title(&config.server.server_title);
-        meta.set_subtitle(&config.server.server_subtitle);
-        meta.set_player_count(0);
-        meta.set_player_limit(config.server.max_players as i32);
+        let player_count = self.net_state.borrow().player_count() as u32;
+        write_server_metadata(&self.server, player_count, results.get().init_metadata());
         Promise::ok(())
     }
 
@@ -358,6 +576,45 @@ impl rpc::game_server::Server for Server2ClientEndpoint {
         Promise::ok(())
     }
 
+    fn negotiate_protocol(
+        &mut self,
+        params: rpc::game_server::NegotiateProtocolParams,
+        mut results: rpc::game_server::NegotiateProtocolResults,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let client_protocol = params.get_protocol_version();
+        let compatibility = check_protocol_compatibility(client_protocol);
+
+        let mut result = results.get().init_result();
+        write_game_version(result.reborrow().init_server_version());
+        result.set_protocol_version(PROTOCOL_VERSION);
+        result.set_min_protocol_version(*SUPPORTED_PROTOCOL_VERSIONS.start());
+        result.set_max_protocol_version(*SUPPORTED_PROTOCOL_VERSIONS.end());
+        match compatibility {
+            Ok(()) => {
+                self.protocol_negotiated = true;
+                // In-process connections are not limited by any real network link, so compression would only waste CPU time.
+                let client_compression = pry!(params.get_supported_compression());
+                let deflate_supported = client_compression.iter().any(|c| c == Ok(PacketCompression::Deflate));
+                self.packet_compression = match self.peer {
+                    PeerAddress::Remote(_) if deflate_supported => PacketCompression::Deflate,
+                    _ => PacketCompression::None,
+                };
+                result.set_accepted(true);
+                result.set_packet_compression(self.packet_compression);
+            }
+            Err(e) => {
+                log::info!("Rejecting client {:?} with incompatible protocol: {e}", self.peer);
+                // A rejected renegotiation revokes the previously accepted protocol version.
+                self.protocol_negotiated = false;
+                self.packet_compression = PacketCompression::None;
+                result.set_accepted(false);
+                result.set_message(e.to_string());
+            }
+        }
+        Promise::ok(())
+    }
+
     fn authenticate(
         &mut self,
         params: rpc::game_server::AuthenticateParams,
@@ -366,20 +623,78 @@ impl rpc::game_server::Server for Server2ClientEndpoint {
         if self.auth_attempted {
             return Promise::err(Error::failed("Authentication was already attempted once".to_owned()));
         }
+
+        if !self.protocol_negotiated {
+            let mut err = results.get().init_conn().init_err();
+            err.set_kind(rpc::authentication_error::Kind::IncompatibleProtocol);
+            err.set_message("A compatible protocol version must be negotiated before authentication");
+            return Promise::ok(());
+        }
         self.auth_attempted = true;
 
         let params = pry!(params.get());
         let username = KString::from_ref(pry!(pry!(params.get_username()).to_str()));
         let connection = pry!(params.get_connection());
 
+        if self
+            .server
+            .config()
+            .borrow()
+            .server
+            .banned_players
+            .contains(username.as_str())
+        {
+            log::info!("Rejecting banned player {username} connecting from {:?}", self.peer);
+            let mut err = results.get().init_conn().init_err();
+            err.set_kind(rpc::authentication_error::Kind::Banned);
+            err.set_message("You are banned from this server");
+            return Promise::ok(());
+        }
+
         // TODO: validate username
 
+        let (chunk_stream, entity_stream, inventory_stream, datagram_sender, datagram_receiver) = {
+            let mut state = self.net_state.borrow_mut();
+            let Some(net_client) = state.find_connected_client_mut(self.peer) else {
+                return Promise::err(Error::failed("Authenticating client is not connected".to_owned()));
+            };
+            let Some(datagram_receiver) = net_client.datagram_receiver.take() else {
+                return Promise::err(Error::failed("Client datagrams are already in use".to_owned()));
+            };
+            let chunk_stream = pry!(net_client
+                .open_stream(NetworkStreamHeader::Standard(StandardTypes::ChunkData))
+                .map_err(|e| Error::failed(format!("Could not open the chunk stream: {e}"))));
+            let entity_stream = pry!(net_client
+                .open_stream(NetworkStreamHeader::Standard(StandardTypes::EntityReplication))
+                .map_err(|e| Error::failed(format!("Could not open the entity replication stream: {e}"))));
+            let inventory_stream = pry!(net_client
+                .open_stream(NetworkStreamHeader::Standard(StandardTypes::Inventory))
+                .map_err(|e| Error::failed(format!("Could not open the inventory stream: {e}"))));
+            (
+                chunk_stream,
+                entity_stream,
+                inventory_stream,
+                DatagramSender::new(net_client.datagram_sender.clone()),
+                DatagramReceiver::new(datagram_receiver),
+            )
+        };
+        let chunk_sender = spawn_chunk_stream(
+            Rc::clone(&self.net_state),
+            Arc::clone(&self.server),
+            self.peer,
+            chunk_stream,
+            self.packet_compression,
+        );
+        let entity_sender = spawn_entity_stream(self.peer, entity_stream, self.packet_compression);
+        let inventory_sender = spawn_inventory_stream(self.peer, inventory_stream, self.packet_compression);
+
         let client = Rc::new(RefCell::new(AuthenticatedServer2ClientEndpoint {
             _net_state: self.net_state.clone(),
             server: self.server.clone(),
             peer: self.peer,
             username: username.clone(),
             connection,
+            packet_compression: self.packet_compression,
         }));
 
         let mut result = results.get().init_conn();
@@ -395,13 +710,26 @@ impl rpc::game_server::Server for Server2ClientEndpoint {
         // add to the bevy world
         let nickname = username.clone();
         let address = self.peer;
+        let render_distance =
+            DEFAULT_PLAYER_RENDER_DISTANCE.min(self.server.config().borrow().server.max_render_distance);
         self.server
             .schedule_bevy(move |world| {
+                let inventory = starting_player_inventory(&world.resource::<ItemRegistryHolder>().0);
                 let player = world
-                    .spawn(ConnectedPlayer {
-                        nickname: nickname.clone(),
-                        address,
-                    })
+                    .spawn((
+                        ConnectedPlayer {
+                            nickname: nickname.clone(),
+                            address,
+                        },
+                        chunk_sender,
+                        entity_sender,
+                        inventory_sender,
+                        datagram_sender,
+                        datagram_receiver,
+                        player_spawn_components(render_distance),
+                        inventory,
+                        InventoryViewer::default(),
+                    ))
                     .id();
                 let mut table = world.query::<&mut ConnectedPlayersTable>();
                 let Ok(mut table) = table.get_single_mut(world) else {
@@ -422,6 +750,30 @@ impl AuthenticatedServer2ClientEndpoint {
     pub fn rpc(&self) -> &rpc::authenticated_client_connection::Client {
         &self.connection
     }
+
+    /// The nickname the client authenticated with.
+    pub fn username(&self) -> &KString {
+        &self.username
+    }
+
+    /// The peer address the client is connected from.
+    pub fn peer(&self) -> PeerAddress {
+        self.peer
+    }
+
+    /// The compression negotiated for stream packets sent to the client.
+    pub fn packet_compression(&self) -> PacketCompression {
+        self.packet_compression
+    }
+}
+
+/// Writes the data clients set up their game state from, both on bootstrap and after the registries are reloaded.
+fn write_game_bootstrap_data(registries: &GameRegistries, mut data: game_bootstrap_data::Builder) {
+    // TODO: use saved world data here
+    Uuid::parse_str("05aaf964-aefa-49d0-9b6a-0aa376016ac2")
+        .unwrap()
+        .write_to_message(&mut data.reborrow().init_universe_id());
+    registries.serialize_ids(&mut data);
 }
 
 impl rpc::authenticated_server_connection::Server for RcAuthenticatedServer2ClientEndpoint {
@@ -430,18 +782,8 @@ impl rpc::authenticated_server_connection::Server for RcAuthenticatedServer2Clie
         _: BootstrapGameDataParams,
         mut results: BootstrapGameDataResults,
     ) -> Promise<(), Error> {
-        let builder = results.get();
-        let mut data = builder.init_data();
-        // TODO: use saved world data here
-        Uuid::parse_str("05aaf964-aefa-49d0-9b6a-0aa376016ac2")
-            .unwrap()
-            .write_to_message(&mut data.reborrow().init_universe_id());
-        self.0
-            .borrow()
-            .server
-            .server_data
-            .shared_registries
-            .serialize_ids(&mut data);
+        let registries = self.0.borrow().server.server_data.shared_registries.load_full();
+        write_game_bootstrap_data(&registries, results.get().init_data());
         Promise::ok(())
     }
 
@@ -456,4 +798,192 @@ impl rpc::authenticated_server_connection::Server for RcAuthenticatedServer2Clie
         );
         Promise::ok(())
     }
+
+    fn get_server_tick(&mut self, _: GetServerTickParams, mut results: GetServerTickResults) -> Promise<(), Error> {
+        let this = self.0.borrow();
+        let mut results = results.get();
+        results.set_tick(this.server.current_tick());
+        results.set_paused(this.server.is_paused());
+        Promise::ok(())
+    }
+
+    fn set_render_distance(
+        &mut self,
+        params: SetRenderDistanceParams,
+        mut results: SetRenderDistanceResults,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let this = self.0.borrow();
+        let max_radius = this.server.config().borrow().server.max_render_distance;
+        let radius = params.get_radius().clamp(0, max_radius.max(0));
+        results.get().set_radius(radius);
+
+        let address = this.peer;
+        this.server
+            .schedule_bevy(move |world| {
+                let mut table = world.query::<&ConnectedPlayersTable>();
+                let table = table.get_single(world)?;
+                let Some(&player) = table.players_by_address.get(&address) else {
+                    bail!("No player entity for {address:?}");
+                };
+                let Some(mut loader) = world.get_mut::<ChunkLoader>(player) else {
+                    bail!("Player {address:?} with entity ID {player:?} has no chunk loader");
+                };
+                loader.radius = radius;
+                Ok(())
+            })
+            .async_log_when_fails("Updating player render distance");
+        Promise::ok(())
+    }
+
+    fn set_block(&mut self, params: SetBlockParams, mut results: SetBlockResults) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let position = pry!(params.get_position());
+        let position = AbsBlockPos::new(position.get_x(), position.get_y(), position.get_z());
+        let Some(block) = BlockEntry::from_packed(params.get_block()) else {
+            results.get().set_accepted(false);
+            return Promise::ok(());
+        };
+        let this = self.0.borrow();
+        let address = this.peer;
+        let edit = this
+            .server
+            .schedule_bevy(move |world| place_player_block(world, address, position, block));
+        Promise::from_future(async move {
+            let accepted = match edit.async_wait().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Could not place a block for {address:?}: {e:#}");
+                    false
+                }
+            };
+            results.get().set_accepted(accepted);
+            Ok(())
+        })
+    }
+
+    fn apply_inventory_transaction(
+        &mut self,
+        params: ApplyInventoryTransactionParams,
+        mut results: ApplyInventoryTransactionResults,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let transaction = pry!(InventoryTransaction::read_from_message(pry!(params.get_transaction()))
+            .map_err(|e| Error::failed(format!("Invalid inventory transaction: {e}"))));
+        let this = self.0.borrow();
+        let address = this.peer;
+        let edit = this
+            .server
+            .schedule_bevy(move |world| apply_player_inventory_transaction(world, address, transaction));
+        Promise::from_future(async move {
+            let accepted = match edit.async_wait().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Could not apply an inventory transaction for {address:?}: {e:#}");
+                    false
+                }
+            };
+            results.get().set_accepted(accepted);
+            Ok(())
+        })
+    }
+
+    fn open_inventory(&mut self, params: OpenInventoryParams, mut results: OpenInventoryResults) -> Promise<(), Error> {
+        let entity_id = pry!(params.get()).get_entity_id();
+        let this = self.0.borrow();
+        let address = this.peer;
+        let open = this
+            .server
+            .schedule_bevy(move |world| open_player_inventory_view(world, address, entity_id));
+        Promise::from_future(async move {
+            let accepted = match open.async_wait().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Could not open an inventory for {address:?}: {e:#}");
+                    false
+                }
+            };
+            results.get().set_accepted(accepted);
+            Ok(())
+        })
+    }
+}
+
+/// The entity of the player connected from `address`.
+fn find_player_entity(world: &mut World, address: PeerAddress) -> Result<Entity> {
+    let mut table = world.query::<&ConnectedPlayersTable>();
+    let Some(&player) = table.get_single(world)?.players_by_address.get(&address) else {
+        bail!("No player entity for {address:?}");
+    };
+    Ok(player)
+}
+
+/// Applies an inventory transaction on behalf of the player connected from `address`, returns false for invalid ones.
+fn apply_player_inventory_transaction(
+    world: &mut World,
+    address: PeerAddress,
+    transaction: InventoryTransaction,
+) -> Result<bool> {
+    let player = find_player_entity(world, address)?;
+    match apply_player_transaction(world, player, &transaction)? {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            debug!("Rejected inventory transaction {transaction:?} of {address:?}: {e}");
+            Ok(false)
+        }
+    }
+}
+
+/// Switches the inventory viewed by the player connected from `address`, returns false if it cannot be viewed.
+fn open_player_inventory_view(world: &mut World, address: PeerAddress, entity_id: u64) -> Result<bool> {
+    let player = find_player_entity(world, address)?;
+    let target = match entity_id {
+        0 => None,
+        id => match Entity::try_from_bits(id) {
+            Ok(entity) if can_view_inventory(world, player, entity) => Some(entity),
+            _ => return Ok(false),
+        },
+    };
+    let Some(mut viewer) = world.get_mut::<InventoryViewer>(player) else {
+        bail!("Player {address:?} with entity ID {player:?} has no inventory viewer");
+    };
+    viewer.viewing = target;
+    Ok(true)
+}
+
+/// Places a block on behalf of the player connected from `address`, returns false for invalid edits.
+fn place_player_block(
+    world: &mut World,
+    address: PeerAddress,
+    position: AbsBlockPos,
+    block: BlockEntry,
+) -> Result<bool> {
+    let mut table = world.query::<&ConnectedPlayersTable>();
+    let Some(&player) = table.get_single(world)?.players_by_address.get(&address) else {
+        bail!("No player entity for {address:?}");
+    };
+    let Some(transform) = world.get::<Transform>(player) else {
+        bail!("Player {address:?} with entity ID {player:?} has no transform");
+    };
+    let block_center = position.into_ivec3().as_vec3() + Vec3::splat(0.5);
+    if block_center.distance_squared(transform.translation) > MAX_BLOCK_EDIT_DISTANCE * MAX_BLOCK_EDIT_DISTANCE {
+        return Ok(false);
+    }
+    let Some(definition) = world.resource::<BlockRegistryHolder>().lookup_id_to_object(block.id) else {
+        return Ok(false);
+    };
+    // Placeholder blocks are only placed along with their entity, see `attach_block_entity`.
+    if definition.name == PLACEHOLDER_BLOCK_NAME {
+        return Ok(false);
+    }
+
+    let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+    let mut voxels = voxels.get_single_mut(world)?;
+    let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+    let Some(chunk) = voxels.loaded_chunks_mut().chunks.get_mut(&chunk_pos) else {
+        return Ok(false);
+    };
+    // Bumps the chunk revision, so it gets sent again to the players that have it loaded.
+    chunk.mutate_stored().blocks.put(in_chunk_pos, block);
+    Ok(true)
 }
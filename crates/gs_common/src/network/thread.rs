@@ -12,6 +12,7 @@ use thiserror::Error;
 use tokio::task::LocalSet;
 
 use super::transport::InProcessStream;
+use super::PeerAddress;
 use crate::prelude::*;
 
 /// A wrapper for a tokio runtime, allowing for easy scheduling of tasks to run within the context of the network thread.
@@ -34,9 +35,9 @@ pub type NetworkThreadAsyncFuture<'state, Output = ()> = Pin<Box<dyn Future<Outp
 /// A future factory function used for network thread tasks.
 pub type NetworkThreadAsyncFunction<State> =
     dyn for<'state> FnOnce(&'state Rc<RefCell<State>>) -> NetworkThreadAsyncFuture<'state> + Send + 'static;
-/// Handler for newly opened async streams.
+/// Handler for newly opened async streams, receiving the address of the peer that opened the stream.
 pub type NetworkThreadStreamHandler<State> =
-    dyn FnMut(Rc<RefCell<State>>, InProcessStream) -> NetworkThreadAsyncFuture<'static> + Send + 'static;
+    dyn FnMut(Rc<RefCell<State>>, PeerAddress, InProcessStream) -> NetworkThreadAsyncFuture<'static> + Send + 'static;
 
 enum NetworkThreadCommand<State> {
     Shutdown(AsyncOneshotSender<()>),
@@ -133,12 +134,13 @@ impl<State: NetworkThreadState> NetworkThread<State> {
     pub fn create_stream_handler(
         &self,
         state: Rc<RefCell<State>>,
+        peer: PeerAddress,
         stream: InProcessStream,
     ) -> Result<NetworkThreadAsyncFuture<'static>, InProcessStream> {
         let mut factory = self.new_stream_handler.lock().unwrap();
         let factory = factory.get_mut(&stream.header);
         match factory {
-            Some(factory) => Ok(factory(state, stream)),
+            Some(factory) => Ok(factory(state, peer, stream)),
             None => Err(stream),
         }
     }
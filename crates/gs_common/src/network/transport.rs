@@ -19,23 +19,27 @@ pub static RPC_LOCAL_READER_OPTIONS: ReaderOptions = ReaderOptions {
 };
 
 /// Size in bytes of the in-process client-server "socket" buffer.
-const INPROCESS_SOCKET_BUFFER_SIZE: usize = 1024 * 1024;
+pub const INPROCESS_SOCKET_BUFFER_SIZE: usize = 1024 * 1024;
+/// Maximum number of packets buffered in each direction of an in-process stream, modelling QUIC stream flow control.
+pub const INPROCESS_STREAM_QUEUE_LENGTH: usize = 16;
+/// Maximum number of datagrams buffered in each direction of an in-process connection, further datagrams are dropped.
+pub const INPROCESS_DATAGRAM_QUEUE_LENGTH: usize = 64;
 
 /// An in-process stream, modelling QUIC streams when using in-process communication.
 pub struct InProcessStream {
     /// The stream header, determining its type.
     pub header: NetworkStreamHeader,
     /// The sender "socket" for this stream side.
-    pub tx: AsyncUnboundedSender<Bytes>,
+    pub tx: AsyncBoundedSender<Bytes>,
     /// The receiver "socket" for this stream side.
-    pub rx: AsyncUnboundedReceiver<Bytes>,
+    pub rx: AsyncBoundedReceiver<Bytes>,
 }
 
 impl InProcessStream {
     /// Constructs a new, pre-connected bidirectional stream for in-process communication.
     pub fn new_pair(header: NetworkStreamHeader) -> (Self, Self) {
-        let (tx12, rx12) = async_unbounded_channel();
-        let (tx21, rx21) = async_unbounded_channel();
+        let (tx12, rx12) = async_bounded_channel(INPROCESS_STREAM_QUEUE_LENGTH);
+        let (tx21, rx21) = async_bounded_channel(INPROCESS_STREAM_QUEUE_LENGTH);
         let header2 = header.clone();
         (
             Self {
@@ -60,6 +64,10 @@ pub struct InProcessDuplex {
     pub incoming_streams: AsyncUnboundedReceiver<InProcessStream>,
     /// Stream for sending new in-process streams to the other side.
     pub outgoing_streams: AsyncUnboundedSender<InProcessStream>,
+    /// Unreliable datagrams received from the other side.
+    pub incoming_datagrams: AsyncBoundedReceiver<Bytes>,
+    /// Sender for unreliable datagrams to the other side, modelling QUIC datagrams.
+    pub outgoing_datagrams: AsyncBoundedSender<Bytes>,
 }
 
 impl InProcessDuplex {
@@ -68,16 +76,22 @@ impl InProcessDuplex {
         let (duplex1, duplex2) = tokio::io::duplex(INPROCESS_SOCKET_BUFFER_SIZE);
         let (streams12_tx, streams12_rx) = async_unbounded_channel();
         let (streams21_tx, streams21_rx) = async_unbounded_channel();
+        let (datagrams12_tx, datagrams12_rx) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
+        let (datagrams21_tx, datagrams21_rx) = async_bounded_channel(INPROCESS_DATAGRAM_QUEUE_LENGTH);
         (
             Self {
                 rpc_pipe: duplex1,
                 incoming_streams: streams21_rx,
                 outgoing_streams: streams12_tx,
+                incoming_datagrams: datagrams21_rx,
+                outgoing_datagrams: datagrams12_tx,
             },
             Self {
                 rpc_pipe: duplex2,
                 incoming_streams: streams12_rx,
                 outgoing_streams: streams21_tx,
+                incoming_datagrams: datagrams12_rx,
+                outgoing_datagrams: datagrams21_tx,
             },
         )
     }
@@ -103,6 +117,7 @@ pub mod test {
 
     use capnp_rpc::twoparty::VatId;
 
+    use crate::network::protocol::{negotiate_protocol, PROTOCOL_VERSION};
     use crate::network::transport::*;
     use crate::GameServerControlCommand;
 
@@ -179,6 +194,63 @@ pub mod test {
                             "Metadata: {:?}",
                             metadata.get_metadata().expect("metadata nested get failed")
                         );
+                        assert_eq!(
+                            PROTOCOL_VERSION,
+                            metadata.get_metadata().unwrap().get_protocol_version()
+                        );
+
+                        let mut early_auth = c_server.server_rpc.authenticate_request();
+                        early_auth.get().set_username("Early");
+                        let early_auth = early_auth.send().promise.await.expect("authentication failed");
+                        let early_auth = early_auth.get().unwrap().get_conn().unwrap().which().unwrap();
+                        let gs_schemas::schemas::game_types_capnp::result::Which::Err(early_auth) = early_auth else {
+                            panic!("Authentication succeeded before the protocol negotiation");
+                        };
+                        assert_eq!(
+                            rpc::authentication_error::Kind::IncompatibleProtocol,
+                            early_auth.unwrap().get_kind().unwrap()
+                        );
+
+                        let mut bad_negotiation = c_server.server_rpc.negotiate_protocol_request();
+                        bad_negotiation.get().set_protocol_version(PROTOCOL_VERSION + 1);
+                        let bad_negotiation = bad_negotiation.send().promise.await.expect("negotiation failed");
+                        let bad_negotiation = bad_negotiation.get().unwrap().get_result().unwrap();
+                        assert!(!bad_negotiation.get_accepted());
+                        assert_eq!(PROTOCOL_VERSION, bad_negotiation.get_max_protocol_version());
+
+                        negotiate_protocol(&c_server.server_rpc)
+                            .await
+                            .expect("protocol negotiation failed");
+
+                        // A rejected renegotiation revokes the accepted protocol version.
+                        let mut renegotiation = c_server.server_rpc.negotiate_protocol_request();
+                        renegotiation.get().set_protocol_version(PROTOCOL_VERSION + 1);
+                        renegotiation.send().promise.await.expect("renegotiation failed");
+                        let mut late_auth = c_server.server_rpc.authenticate_request();
+                        late_auth.get().set_username("Late");
+                        let late_auth = late_auth.send().promise.await.expect("authentication failed");
+                        let late_auth = late_auth.get().unwrap().get_conn().unwrap().which().unwrap();
+                        let gs_schemas::schemas::game_types_capnp::result::Which::Err(late_auth) = late_auth else {
+                            panic!("Authentication succeeded after a rejected protocol negotiation");
+                        };
+                        assert_eq!(
+                            rpc::authentication_error::Kind::IncompatibleProtocol,
+                            late_auth.unwrap().get_kind().unwrap()
+                        );
+                        negotiate_protocol(&c_server.server_rpc)
+                            .await
+                            .expect("protocol negotiation failed");
+
+                        // The rejected attempts do not count, this one fails on the missing connection instead.
+                        let mut auth = c_server.server_rpc.authenticate_request();
+                        auth.get().set_username("Early");
+                        let auth_error = auth
+                            .send()
+                            .promise
+                            .await
+                            .err()
+                            .expect("authentication without a connection");
+                        assert!(!auth_error.to_string().contains("already attempted"), "{auth_error}");
 
                         // Disconnect the RPC endpoint, then await graceful shutdown.
                         let _ = s_disconnector.await;
@@ -0,0 +1,117 @@
+//! The authoritative server game tick counter, and the client-side estimation of it.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use crate::prelude::*;
+use crate::TICKS_PER_SECOND_F64;
+
+/// How often clients re-synchronize their [`ServerClockEstimator`] with the server.
+pub const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The authoritative game tick counter, advanced on the server at the start of every unpaused fixed update.
+#[derive(Resource, Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Deref)]
+pub struct ServerTick(pub u64);
+
+/// A single tick measurement received from the server.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct ClockSample {
+    tick: u64,
+    paused: bool,
+    received_at: Instant,
+}
+
+/// Estimates the current server tick on the client, based on periodic samples and the measured round-trip time.
+#[derive(Clone, Debug, Default)]
+pub struct ServerClockEstimator {
+    last_sample: Option<ClockSample>,
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+}
+
+/// A [`ServerClockEstimator`] shared between the network thread and the game systems.
+pub type SharedServerClock = Arc<Mutex<ServerClockEstimator>>;
+
+impl ServerClockEstimator {
+    /// Records a server tick sample, requested at `sent_at` and received at `received_at`.
+    pub fn record_sample(&mut self, tick: u64, paused: bool, sent_at: Instant, received_at: Instant) {
+        let rtt = received_at.saturating_duration_since(sent_at);
+        // Smoothing as done for TCP's retransmission timer (RFC 6298).
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
+            Some(srtt) => {
+                let deviation = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rtt_variance = (self.rtt_variance * 3 + deviation) / 4;
+                self.smoothed_rtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.last_sample = Some(ClockSample {
+            tick,
+            paused,
+            received_at,
+        });
+    }
+
+    /// Checks if at least one sample was received.
+    pub fn is_synchronized(&self) -> bool {
+        self.last_sample.is_some()
+    }
+
+    /// The smoothed round-trip time to the server, if measured.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    /// The mean deviation of the round-trip time.
+    pub fn rtt_variance(&self) -> Duration {
+        self.rtt_variance
+    }
+
+    /// Whether the server reported being paused in the latest sample.
+    pub fn is_server_paused(&self) -> bool {
+        self.last_sample.is_some_and(|s| s.paused)
+    }
+
+    /// Estimates the server tick currently being simulated, with a fractional part for interpolation.
+    pub fn estimated_tick_f64(&self, now: Instant) -> Option<f64> {
+        let sample = self.last_sample?;
+        if sample.paused {
+            return Some(sample.tick as f64);
+        }
+        let one_way = self.smoothed_rtt.unwrap_or_default() / 2;
+        let elapsed = now.saturating_duration_since(sample.received_at) + one_way;
+        Some(sample.tick as f64 + elapsed.as_secs_f64() * TICKS_PER_SECOND_F64)
+    }
+
+    /// Estimates the server tick currently being simulated.
+    pub fn estimated_tick(&self, now: Instant) -> Option<u64> {
+        self.estimated_tick_f64(now).map(|t| t as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TICK;
+
+    #[test]
+    fn clock_estimation() {
+        let mut clock = ServerClockEstimator::default();
+        let start = Instant::now();
+        assert_eq!(clock.estimated_tick(start), None);
+
+        clock.record_sample(100, false, start, start + TICK * 2);
+        assert_eq!(clock.rtt(), Some(TICK * 2));
+        // One way latency of one tick.
+        assert_eq!(clock.estimated_tick(start + TICK * 2), Some(101));
+        assert_eq!(clock.estimated_tick(start + TICK * 12), Some(111));
+
+        clock.record_sample(200, true, start + TICK * 20, start + TICK * 22);
+        assert!(clock.is_server_paused());
+        assert_eq!(clock.estimated_tick(start + TICK * 100), Some(200));
+    }
+}
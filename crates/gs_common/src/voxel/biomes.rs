@@ -1,22 +1,11 @@
-//! The builtin biome types.
-//! Most of this will be moved to a "base" mod at some point in the future.
+//! The builtin biome types, defined in the base content pack.
 
-use gs_schemas::voxel::generation::fbm_noise::Fbm;
-use gs_schemas::{
-    dependencies::rgb::RGBA8,
-    range::range,
-    registry::RegistryName,
-    voxel::{
-        biome::{BiomeDefinition, BiomeRegistry, VOID_BIOME_NAME},
-        generation::{Context, NoiseNDTo2D},
-        voxeltypes::{BlockEntry, BlockRegistry},
-    },
-};
-use noise::OpenSimplex;
-
-use super::blocks::{
-    DIRT_BLOCK_NAME, GRASS_BLOCK_NAME, SAND_BLOCK_NAME, SNOWY_GRASS_BLOCK_NAME, STONE_BLOCK_NAME, WATER_BLOCK_NAME,
-};
+use gs_schemas::content::{ContentError, ContentPack};
+use gs_schemas::registry::RegistryName;
+use gs_schemas::registry_aliases::load_alias_definitions;
+use gs_schemas::registry_tags::load_tag_definitions;
+use gs_schemas::voxel::biome::{load_biome_definitions, BiomeRegistry, BIOMES_DIRECTORY, VOID_BIOME};
+use gs_schemas::voxel::voxeltypes::BlockRegistry;
 
 /// Registry name for plains.
 pub const PLAINS_BIOME_NAME: RegistryName = RegistryName::gs_const("plains");
@@ -31,243 +20,99 @@ pub const BEACH_BIOME_NAME: RegistryName = RegistryName::gs_const("beach");
 /// Registry name for river.
 pub const RIVER_BIOME_NAME: RegistryName = RegistryName::gs_const("river");
 
-/// Installs the base set of biomes into the given block registry.
-pub fn setup_basic_biomes(biome_registry: &mut BiomeRegistry) {
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: VOID_BIOME_NAME,
-            representative_color: RGBA8::new(0, 0, 0, 0),
-            elevation: range(-1.0..-1.0),
-            temperature: range(-1.0..-1.0),
-            moisture: range(-1.0..-1.0),
-            rule_source: |_pos: &bevy_math::IVec3, _ctx: &Context, _reg: &BlockRegistry| None,
-            surface_noise: |_point, _noise| 0.0,
-            blend_influence: 0.0,
-            block_influence: 0.0,
-            can_generate: false,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: PLAINS_BIOME_NAME,
-            representative_color: RGBA8::new(20, 180, 10, 255),
-            elevation: range(0.5..1.5),
-            temperature: range(..),
-            moisture: range(..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_grass, _) = block_registry.lookup_name_to_object(GRASS_BLOCK_NAME.as_ref()).unwrap();
-                let (i_dirt, _) = block_registry.lookup_name_to_object(DIRT_BLOCK_NAME.as_ref()).unwrap();
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_snow_grass, _) = block_registry
-                    .lookup_name_to_object(SNOWY_GRASS_BLOCK_NAME.as_ref())
-                    .unwrap();
-
-                if context.ground_y == pos.y {
-                    if pos.y >= 80 {
-                        return Some(BlockEntry::new(i_snow_grass, 0));
-                    } else {
-                        return Some(BlockEntry::new(i_grass, 0));
-                    }
-                } else if pos.y <= context.ground_y && pos.y > context.ground_y - 5 {
-                    return Some(BlockEntry::new(i_dirt, 0));
-                } else if context.ground_y > pos.y {
-                    return Some(BlockEntry::new(i_stone, 0));
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                let new_point = point * 1.5;
-
-                let mut value = <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, new_point.to_array()) * 0.75;
-                value += <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, (new_point * 2.0).to_array()) * 0.25;
-                value *= 5.0;
-                value += 10.0;
-                value
-            },
-            blend_influence: 0.5,
-            block_influence: 1.0,
-            can_generate: true,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: HILLS_BIOME_NAME,
-            representative_color: RGBA8::new(15, 110, 10, 255),
-            elevation: range(1.5..3.0),
-            temperature: range(..),
-            moisture: range(..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_grass, _) = block_registry.lookup_name_to_object(GRASS_BLOCK_NAME.as_ref()).unwrap();
-                let (i_dirt, _) = block_registry.lookup_name_to_object(DIRT_BLOCK_NAME.as_ref()).unwrap();
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_snow_grass, _) = block_registry
-                    .lookup_name_to_object(SNOWY_GRASS_BLOCK_NAME.as_ref())
-                    .unwrap();
-
-                if context.ground_y == pos.y {
-                    return if pos.y >= 80 {
-                        Some(BlockEntry::new(i_snow_grass, 0))
-                    } else {
-                        Some(BlockEntry::new(i_grass, 0))
-                    };
-                } else if pos.y <= context.ground_y && pos.y > context.ground_y - 5 {
-                    return Some(BlockEntry::new(i_dirt, 0));
-                } else if context.ground_y > pos.y {
-                    return Some(BlockEntry::new(i_stone, 0));
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                let new_point = point / 3.0;
-                let new_point_arr = new_point.to_array();
-
-                let mut value = <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, new_point_arr) * 0.6;
-                value += <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, (new_point * 1.5).to_array()) * 0.25;
-                value += <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, (new_point * 3.0).to_array()) * 0.15;
-                value *= 8.0;
-                value += 15.0;
-                value
-            },
-            blend_influence: 1.0,
-            block_influence: 1.0,
-            can_generate: true,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: MOUNTAINS_BIOME_NAME,
-            representative_color: RGBA8::new(220, 220, 220, 255),
-            elevation: range(3.0..),
-            temperature: range(..),
-            moisture: range(..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_grass, _) = block_registry.lookup_name_to_object(GRASS_BLOCK_NAME.as_ref()).unwrap();
-                let (i_dirt, _) = block_registry.lookup_name_to_object(DIRT_BLOCK_NAME.as_ref()).unwrap();
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_snow_grass, _) = block_registry
-                    .lookup_name_to_object(SNOWY_GRASS_BLOCK_NAME.as_ref())
-                    .unwrap();
-
-                if context.ground_y == pos.y {
-                    return if pos.y >= 80 {
-                        Some(BlockEntry::new(i_snow_grass, 0))
-                    } else {
-                        Some(BlockEntry::new(i_grass, 0))
-                    };
-                } else if context.ground_y >= pos.y && pos.y > context.ground_y - 5 {
-                    return Some(BlockEntry::new(i_dirt, 0));
-                } else if context.ground_y > pos.y {
-                    return Some(BlockEntry::new(i_stone, 0));
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                let new_point = point / 4.0;
-                let new_point_arr = new_point.to_array();
-                let h_n = |p| (<Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, p) + 1.0) / 2.0;
-                let h_rn = |p| (0.5 - (0.5 - h_n(p)).abs()) * 2.0;
-
-                let h0 = 0.50 * h_rn(new_point_arr);
-                let h01 = 0.25 * h_rn((new_point * 2.0).to_array()) + h0;
-
-                (h01 + (h01 / 0.75) * 0.15 * h_n((new_point * 5.0).to_array())
-                    + (h01 / 0.75) * 0.05 * h_rn((new_point * 9.0).to_array()))
-                .abs()
-                    * 100.0
-                    + 40.0
-            },
-            blend_influence: 1.0,
-            block_influence: 1.0,
-            can_generate: true,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: OCEAN_BIOME_NAME,
-            representative_color: RGBA8::new(10, 120, 180, 255),
-            elevation: range(..1.0),
-            temperature: range(..),
-            moisture: range(2.5..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_water, _) = block_registry.lookup_name_to_object(WATER_BLOCK_NAME.as_ref()).unwrap();
-
-                if context.sea_level > pos.y {
-                    return if context.ground_y > pos.y {
-                        Some(BlockEntry::new(i_stone, 0))
-                    } else {
-                        Some(BlockEntry::new(i_water, 0))
-                    };
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, (point / 25.0).to_array()) * -7.5 + 1.0
-            },
-            blend_influence: 1.0,
-            block_influence: 1.0,
-            can_generate: true,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: BEACH_BIOME_NAME,
-            representative_color: RGBA8::new(224, 200, 130, 255),
-            elevation: range(1.0..),
-            temperature: range(..),
-            moisture: range(2.5..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_sand, _) = block_registry.lookup_name_to_object(SAND_BLOCK_NAME.as_ref()).unwrap();
-
-                if context.ground_y > pos.y - 1 {
-                    return Some(BlockEntry::new(i_stone, 0));
-                } else if context.ground_y == pos.y {
-                    return Some(BlockEntry::new(i_sand, 0));
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, point.to_array()) * 1.0 + 1.0
-            },
-            blend_influence: 1.0,
-            block_influence: 1.0,
-            can_generate: false,
-        })
-        .unwrap();
-
-    biome_registry
-        .push_object(BiomeDefinition {
-            name: RIVER_BIOME_NAME,
-            representative_color: RGBA8::new(10, 100, 200, 255),
-            elevation: range(..),
-            temperature: range(..),
-            moisture: range(..),
-            rule_source: |pos: &bevy_math::IVec3, context: &Context, block_registry: &BlockRegistry| {
-                let (i_stone, _) = block_registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
-                let (i_sand, _) = block_registry.lookup_name_to_object(SAND_BLOCK_NAME.as_ref()).unwrap();
-                let (i_water, _) = block_registry.lookup_name_to_object(WATER_BLOCK_NAME.as_ref()).unwrap();
+/// Installs the void biome and the biomes, biome aliases and biome tags defined in the given content packs into the
+/// given biome registry.
+pub fn setup_content_biomes(
+    biome_registry: &mut BiomeRegistry,
+    packs: &[ContentPack],
+    block_registry: &BlockRegistry,
+) -> Result<(), ContentError> {
+    biome_registry.push_object(VOID_BIOME.clone())?;
+    for biome in load_biome_definitions(packs, block_registry)? {
+        biome_registry.push_object(biome)?;
+    }
+    biome_registry.add_aliases(load_alias_definitions(packs, BIOMES_DIRECTORY)?)?;
+    biome_registry.resolve_tags(&load_tag_definitions(packs, BIOMES_DIRECTORY)?)?;
+    Ok(())
+}
 
-                if context.ground_y == pos.y {
-                    return Some(BlockEntry::new(i_sand, 0));
-                } else if pos.y <= context.ground_y && context.ground_y > pos.y - 3 {
-                    return Some(BlockEntry::new(i_water, 0));
-                } else if context.ground_y > pos.y {
-                    return Some(BlockEntry::new(i_stone, 0));
-                }
-                None
-            },
-            surface_noise: |point, noise| {
-                <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, point.to_array()) * -1.5 + 1.0
-            },
-            blend_influence: 1.0,
-            block_influence: 1.0,
-            can_generate: false,
-        })
-        .unwrap();
+#[cfg(test)]
+mod test {
+    use gs_schemas::registry::RegistryId;
+    use gs_schemas::voxel::biome::VOID_BIOME_NAME;
+
+    use super::*;
+    use crate::content::base_content_pack;
+    use crate::voxel::blocks::setup_content_blocks;
+
+    #[test]
+    fn base_biomes_are_defined() {
+        let packs = [base_content_pack()];
+        let mut block_registry = BlockRegistry::default();
+        setup_content_blocks(&mut block_registry, &packs).unwrap();
+        let mut biome_registry = BiomeRegistry::default();
+        setup_content_biomes(&mut biome_registry, &packs, &block_registry).unwrap();
+        // IDs are allocated in definition order
+        let names: Vec<String> = (1..=7u32)
+            .map(|id| {
+                let id = RegistryId::try_from(id).unwrap();
+                biome_registry.lookup_id_to_object(id).unwrap().name.to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                VOID_BIOME_NAME,
+                PLAINS_BIOME_NAME,
+                HILLS_BIOME_NAME,
+                MOUNTAINS_BIOME_NAME,
+                OCEAN_BIOME_NAME,
+                BEACH_BIOME_NAME,
+                RIVER_BIOME_NAME
+            ]
+            .map(|name| name.to_string())
+        );
+        for (_, _, biome) in biome_registry.iter() {
+            biome.compile(&block_registry).unwrap();
+        }
+    }
+
+    #[test]
+    fn water_biome_surfaces() {
+        let packs = [base_content_pack()];
+        let mut block_registry = BlockRegistry::default();
+        setup_content_blocks(&mut block_registry, &packs).unwrap();
+        let mut biome_registry = BiomeRegistry::default();
+        setup_content_biomes(&mut biome_registry, &packs, &block_registry).unwrap();
+        let surface = |biome: RegistryName, depth: i32, altitude: i32| {
+            let (_, biome) = biome_registry.lookup_name_to_object(biome.as_ref()).unwrap();
+            let rules = biome.compile(&block_registry).unwrap().surface_rules;
+            rules.block_at_depth(depth, altitude).map(|block| {
+                block_registry
+                    .lookup_id_to_object(block.id)
+                    .unwrap()
+                    .name
+                    .key
+                    .to_string()
+            })
+        };
+        let block = |name: &str| Some(name.to_string());
+
+        // (depth below the ground, altitude above the sea level)
+        assert_eq!(surface(OCEAN_BIOME_NAME, -3, -2), block("water"));
+        assert_eq!(surface(OCEAN_BIOME_NAME, 0, -1), block("water"));
+        assert_eq!(surface(OCEAN_BIOME_NAME, 1, -2), block("stone"));
+        assert_eq!(surface(OCEAN_BIOME_NAME, 5, 0), None);
+        assert_eq!(surface(OCEAN_BIOME_NAME, -1, 3), None);
+
+        assert_eq!(surface(BEACH_BIOME_NAME, -1, 2), None);
+        assert_eq!(surface(BEACH_BIOME_NAME, 0, 2), block("stone"));
+        assert_eq!(surface(BEACH_BIOME_NAME, 4, -3), block("stone"));
+
+        assert_eq!(surface(RIVER_BIOME_NAME, -1, 0), None);
+        assert_eq!(surface(RIVER_BIOME_NAME, 0, 0), block("sand"));
+        assert_eq!(surface(RIVER_BIOME_NAME, 1, -1), block("water"));
+        assert_eq!(surface(RIVER_BIOME_NAME, 2, 5), block("water"));
+        assert_eq!(surface(RIVER_BIOME_NAME, 3, -3), block("stone"));
+    }
 }
@@ -0,0 +1,702 @@
+//! Entities anchored to placeholder blocks, managing the block space they reserve.
+//!
+//! The chunks store a record of each such entity, see [`gs_schemas::voxel::block_entity`]. The server keeps the records
+//! in sync with the live entities: attaching an entity turns its block into a placeholder, changes of its replicated
+//! components are written back into the record, and the entity is despawned when its block gets replaced. The records
+//! are saved and sent to clients with the chunks, both sides spawn the entities again from them after loading or
+//! receiving a chunk. Later component changes are sent to the clients holding the chunk as record updates, which
+//! they apply to the live entities in place.
+
+use bevy::ecs::component::{ComponentHooks, StorageType};
+use bevy::ecs::system::SystemChangeTick;
+use bevy::prelude::*;
+use gs_schemas::coordinates::{AbsBlockPos, AbsChunkPos, InChunkPos};
+use gs_schemas::dependencies::itertools::Itertools;
+use gs_schemas::registry::RegistryId;
+use gs_schemas::voxel::block_entity::{BlockEntityComponent, BlockEntityRecord};
+use gs_schemas::voxel::chunk::Chunk;
+use gs_schemas::voxel::chunk_storage::ChunkStorage;
+use gs_schemas::voxel::voxeltypes::{BlockEntry, BlockRegistry, EMPTY_BLOCK_NAME, PLACEHOLDER_BLOCK_NAME};
+use gs_schemas::GsExtraData;
+use tokio_util::bytes::Bytes;
+
+use crate::network::chunk_stream::ChunkStreamSender;
+use crate::network::entity_replication::ReplicationRegistry;
+use crate::prelude::*;
+use crate::tick::ServerTick;
+use crate::voxel::plugin::{serialize_block_entity_update_packet, BlockRegistryHolder, VoxelPosition, VoxelUniverse};
+use crate::ServerData;
+
+/// Marks the entities anchored to a placeholder block, their [`VoxelPosition`] is the position of that block.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BlockEntity;
+
+impl Component for BlockEntity {
+    const STORAGE_TYPE: StorageType = StorageType::Table;
+
+    fn register_component_hooks(hooks: &mut ComponentHooks) {
+        // Records where removed block entities were anchored, so that their records can be found without a scan.
+        hooks.on_remove(|mut world, entity, _| {
+            let Some(position) = world.get::<VoxelPosition>(entity).map(|position| position.0) else {
+                return;
+            };
+            if let Some(mut removed) = world.get_resource_mut::<RemovedBlockEntities>() {
+                removed.0.push((entity, position));
+            }
+        });
+    }
+}
+
+/// The entities that stopped being block entities since the last cleanup, with the blocks they were anchored to.
+/// Only tracked on the server.
+#[derive(Resource, Default)]
+pub(crate) struct RemovedBlockEntities(Vec<(Entity, AbsBlockPos)>);
+
+/// The IDs of the empty and placeholder blocks.
+fn special_block_ids(registry: &BlockRegistry) -> Option<(RegistryId, RegistryId)> {
+    let (empty, _) = registry.lookup_name_to_object(EMPTY_BLOCK_NAME.as_ref())?;
+    let (placeholder, _) = registry.lookup_name_to_object(PLACEHOLDER_BLOCK_NAME.as_ref())?;
+    Some((empty, placeholder))
+}
+
+/// The absolute position of a block within the given chunk.
+fn block_position(chunk_pos: AbsChunkPos, in_chunk_pos: InChunkPos) -> AbsBlockPos {
+    AbsBlockPos::from_ivec3(AbsBlockPos::from(chunk_pos).into_ivec3() + IVec3::from(in_chunk_pos))
+}
+
+/// The transform of an entity anchored to the given block, centered on the block.
+fn block_entity_transform(position: AbsBlockPos) -> Transform {
+    Transform::from_translation(position.as_vec3() + Vec3::splat(0.5))
+}
+
+fn encode_block_entity(registry: &ReplicationRegistry, entity: &EntityRef) -> Vec<BlockEntityComponent> {
+    registry
+        .encode_components(entity)
+        .into_iter()
+        .map(|(name, data)| BlockEntityComponent {
+            name: name.to_owned(),
+            data,
+        })
+        .collect()
+}
+
+/// Anchors `entity` to the block at `position`, turning the block into a placeholder and storing the replicated
+/// components of the entity in its chunk. The chunk must be loaded, and the block must be empty or a placeholder
+/// without an entity.
+pub fn attach_block_entity(world: &mut World, position: AbsBlockPos, entity: Entity) -> Result<()> {
+    let (empty, placeholder) = special_block_ids(world.resource::<BlockRegistryHolder>())
+        .context("The block registry has no empty or placeholder block")?;
+    let Some(entity_ref) = world.get_entity(entity) else {
+        bail!("Entity {entity:?} does not exist");
+    };
+    ensure!(
+        !entity_ref.contains::<BlockEntity>(),
+        "Entity {entity:?} is already anchored to a block"
+    );
+    let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+    {
+        let mut voxels = world.query::<&VoxelUniverse<ServerData>>();
+        let voxels = voxels.get_single(world)?;
+        let Some(chunk) = voxels.loaded_chunks().chunks.get(&chunk_pos) else {
+            bail!("The chunk of block {position} is not loaded");
+        };
+        let block = chunk.blocks.get(in_chunk_pos).id;
+        ensure!(
+            block == empty || (block == placeholder && chunk.block_entities.get(in_chunk_pos).is_none()),
+            "Block {position} is already occupied"
+        );
+    }
+
+    world.entity_mut(entity).insert((
+        BlockEntity,
+        VoxelPosition(position),
+        TransformBundle::from_transform(block_entity_transform(position)),
+    ));
+    let components = encode_block_entity(world.resource::<ReplicationRegistry>(), &world.entity(entity));
+
+    let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+    let mut voxels = voxels.get_single_mut(world)?;
+    let chunk = voxels.loaded_chunks_mut().chunks.get_mut(&chunk_pos).unwrap();
+    // Bumps the chunk revision, so it gets sent again to the players that have it loaded.
+    let chunk = chunk.mutate_stored();
+    chunk.blocks.put(in_chunk_pos, BlockEntry::new(placeholder, 0));
+    chunk.block_entities.put(
+        in_chunk_pos,
+        Some(BlockEntityRecord {
+            entity: Some(entity.to_bits()),
+            components,
+        }),
+    );
+    Ok(())
+}
+
+/// Removes the entity anchored to the block at `position` from it, turning the placeholder block back into an empty
+/// block. Returns the detached entity, which is kept alive without the [`BlockEntity`] marker.
+pub fn detach_block_entity(world: &mut World, position: AbsBlockPos) -> Result<Option<Entity>> {
+    let (empty, placeholder) = special_block_ids(world.resource::<BlockRegistryHolder>())
+        .context("The block registry has no empty or placeholder block")?;
+    let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+    let record = {
+        let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+        let mut voxels = voxels.get_single_mut(world)?;
+        let Some(chunk) = voxels.loaded_chunks_mut().chunks.get_mut(&chunk_pos) else {
+            bail!("The chunk of block {position} is not loaded");
+        };
+        if chunk.block_entities.get(in_chunk_pos).is_none() {
+            return Ok(None);
+        }
+        let chunk = chunk.mutate_stored();
+        if chunk.blocks.get(in_chunk_pos).id == placeholder {
+            chunk.blocks.put(in_chunk_pos, BlockEntry::new(empty, 0));
+        }
+        chunk.block_entities.remove(in_chunk_pos)
+    };
+
+    let entity = record
+        .and_then(|record| record.entity)
+        .and_then(|bits| Entity::try_from_bits(bits).ok())
+        .filter(|&entity| world.get_entity(entity).is_some());
+    if let Some(entity) = entity {
+        world.entity_mut(entity).remove::<(BlockEntity, VoxelPosition)>();
+    }
+    Ok(entity)
+}
+
+/// Spawns the entities of the block entity records of the chunk at `chunk_pos` that don't have a live entity yet.
+pub fn spawn_chunk_block_entities<ExtraData: GsExtraData>(world: &mut World, chunk_pos: AbsChunkPos) {
+    let mut voxels = world.query::<&VoxelUniverse<ExtraData>>();
+    let Ok(voxels) = voxels.get_single(world) else {
+        return;
+    };
+    let Some(chunk) = voxels.loaded_chunks().chunks.get(&chunk_pos) else {
+        return;
+    };
+    let to_spawn = chunk
+        .block_entities
+        .iter()
+        .filter_map(|(in_chunk_pos, record)| {
+            record
+                .as_ref()
+                .filter(|record| record.entity.is_none())
+                .map(|record| (in_chunk_pos, record.components.clone()))
+        })
+        .collect_vec();
+    if to_spawn.is_empty() {
+        return;
+    }
+
+    let spawned = world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        to_spawn
+            .into_iter()
+            .map(|(in_chunk_pos, components)| {
+                let position = block_position(chunk_pos, in_chunk_pos);
+                let mut entity = world.spawn((
+                    BlockEntity,
+                    VoxelPosition(position),
+                    TransformBundle::from_transform(block_entity_transform(position)),
+                ));
+                for component in components {
+                    if let Err(e) = registry.insert_component(&mut entity, &component.name, &component.data) {
+                        error!("Could not restore a component of the block entity at {position}: {e:#}");
+                    }
+                }
+                (in_chunk_pos, entity.id())
+            })
+            .collect_vec()
+    });
+
+    let mut voxels = world.query::<&mut VoxelUniverse<ExtraData>>();
+    let mut voxels = voxels.single_mut(world);
+    let chunk = voxels.loaded_chunks_mut().chunks.get_mut(&chunk_pos).unwrap();
+    // Live entity IDs are not a part of the serialized chunk, so there is no need for a new revision.
+    let chunk = chunk.mutate_without_revision();
+    for (in_chunk_pos, entity) in spawned {
+        let record = chunk
+            .block_entities
+            .remove(in_chunk_pos)
+            .map(|record| BlockEntityRecord {
+                entity: Some(entity.to_bits()),
+                ..record
+            });
+        chunk.block_entities.put(in_chunk_pos, record);
+    }
+}
+
+/// Updates the replicated components of a live block entity from the `old` components of its record to the `new` ones.
+fn update_block_entity_components(
+    world: &mut World,
+    entity: Entity,
+    old: &[BlockEntityComponent],
+    new: &[BlockEntityComponent],
+) {
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        let Some(mut entity) = world.get_entity_mut(entity) else {
+            return;
+        };
+        for component in old {
+            if new.iter().all(|new| new.name != component.name) {
+                if let Err(e) = registry.remove_component(&mut entity, &component.name) {
+                    error!(
+                        "Could not remove a component of the block entity {:?}: {e:#}",
+                        entity.id()
+                    );
+                }
+            }
+        }
+        for component in new {
+            if old.contains(component) {
+                continue;
+            }
+            if let Err(e) = registry.insert_component(&mut entity, &component.name, &component.data) {
+                error!(
+                    "Could not update a component of the block entity {:?}: {e:#}",
+                    entity.id()
+                );
+            }
+        }
+    });
+}
+
+/// Prepares the block entities of a chunk being replaced by a newer version of it: the live entities of the records
+/// present in both versions are carried over to `new` and updated to its components, the others get despawned.
+pub fn replace_chunk_block_entities<ExtraData: GsExtraData>(
+    commands: &mut Commands,
+    old: &Chunk<ExtraData>,
+    new: &mut Chunk<ExtraData>,
+) {
+    for (in_chunk_pos, old_record) in old.block_entities.iter() {
+        let Some(entity) = old_record
+            .as_ref()
+            .and_then(|record| record.entity)
+            .and_then(|bits| Entity::try_from_bits(bits).ok())
+        else {
+            continue;
+        };
+        let mut new_record = new.block_entities.remove(in_chunk_pos);
+        let kept = match (new_record.as_mut(), old_record) {
+            (Some(new_record), Some(old_record)) if new_record.entity.is_none() => {
+                new_record.entity = Some(entity.to_bits());
+                if new_record.components != old_record.components {
+                    let old = old_record.components.clone();
+                    let new = new_record.components.clone();
+                    commands.add(move |world: &mut World| update_block_entity_components(world, entity, &old, &new));
+                }
+                true
+            }
+            _ => false,
+        };
+        if new_record.is_some() {
+            new.block_entities.put(in_chunk_pos, new_record);
+        }
+        if !kept {
+            if let Some(entity) = commands.get_entity(entity) {
+                entity.despawn_recursive();
+            }
+        }
+    }
+}
+
+/// Applies the block entity record updates received for the chunk at `chunk_pos`: the records get the new components
+/// and their live entities are updated in place. Updates of blocks without a record are ignored, the chunk data sent
+/// later carries them.
+pub fn apply_block_entity_updates<ExtraData: GsExtraData>(
+    world: &mut World,
+    chunk_pos: AbsChunkPos,
+    updates: Vec<(InChunkPos, BlockEntityRecord)>,
+) {
+    let mut voxels = world.query::<&mut VoxelUniverse<ExtraData>>();
+    let Ok(mut voxels) = voxels.get_single_mut(world) else {
+        return;
+    };
+    let Some(chunk) = voxels.loaded_chunks_mut().chunks.get_mut(&chunk_pos) else {
+        return;
+    };
+    // The records are not a part of the block data, so the chunk doesn't need remeshing.
+    let chunk = chunk.mutate_without_revision();
+    let mut changed = Vec::new();
+    for (in_chunk_pos, update) in updates {
+        let Some(record) = chunk.block_entities.remove(in_chunk_pos) else {
+            continue;
+        };
+        if let Some(entity) = record.entity.and_then(|bits| Entity::try_from_bits(bits).ok()) {
+            changed.push((entity, record.components, update.components.clone()));
+        }
+        chunk.block_entities.put(
+            in_chunk_pos,
+            Some(BlockEntityRecord {
+                entity: record.entity,
+                components: update.components,
+            }),
+        );
+    }
+    for (entity, old, new) in changed {
+        update_block_entity_components(world, entity, &old, &new);
+    }
+}
+
+/// Despawns all the block entities, used when leaving a game.
+pub fn despawn_block_entities(world: &mut World) {
+    let entities = world
+        .query_filtered::<Entity, With<BlockEntity>>()
+        .iter(world)
+        .collect_vec();
+    for entity in entities {
+        world.entity_mut(entity).despawn_recursive();
+    }
+}
+
+/// Despawns the entities whose placeholder block got replaced, and clears the placeholder blocks and records of the
+/// entities that got despawned.
+pub(crate) fn server_system_cleanup_block_entities(
+    mut commands: Commands,
+    block_registry: Res<BlockRegistryHolder>,
+    mut voxel_q: Query<&mut VoxelUniverse<ServerData>>,
+    block_entities: Query<(Entity, &VoxelPosition), With<BlockEntity>>,
+    mut removed: ResMut<RemovedBlockEntities>,
+) {
+    let Ok(mut voxels) = voxel_q.get_single_mut() else {
+        return;
+    };
+    let Some((empty, placeholder)) = special_block_ids(&block_registry) else {
+        return;
+    };
+    let chunks = &mut voxels.loaded_chunks_mut().chunks;
+
+    for (entity, position) in block_entities.iter() {
+        let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+        let Some(chunk) = chunks.get_mut(&chunk_pos) else {
+            continue;
+        };
+        let anchored = chunk
+            .block_entities
+            .get(in_chunk_pos)
+            .as_ref()
+            .is_some_and(|record| record.entity == Some(entity.to_bits()));
+        if anchored && chunk.blocks.get(in_chunk_pos).id == placeholder {
+            continue;
+        }
+        trace!(
+            "Despawning block entity {entity:?} of the replaced block {}",
+            position.0
+        );
+        if anchored {
+            chunk.mutate_stored().block_entities.remove(in_chunk_pos);
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for (entity, position) in removed.0.drain(..) {
+        let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+        let Some(chunk) = chunks.get_mut(&chunk_pos) else {
+            continue;
+        };
+        let orphaned = chunk
+            .block_entities
+            .get(in_chunk_pos)
+            .as_ref()
+            .is_some_and(|record| record.entity == Some(entity.to_bits()));
+        if !orphaned {
+            continue;
+        }
+        let chunk = chunk.mutate_stored();
+        chunk.block_entities.remove(in_chunk_pos);
+        if chunk.blocks.get(in_chunk_pos).id == placeholder {
+            chunk.blocks.put(in_chunk_pos, BlockEntry::new(empty, 0));
+        }
+    }
+}
+
+/// Writes the changed replicated components of the block entities into their records, to be saved and sent to the
+/// players with the chunk. Players that already have the chunk only get the updated records.
+pub(crate) fn server_system_store_block_entities(
+    tick: Res<ServerTick>,
+    registry: Res<ReplicationRegistry>,
+    change_tick: SystemChangeTick,
+    senders: Query<&ChunkStreamSender>,
+    mut queries: ParamSet<(
+        Query<EntityRef, With<BlockEntity>>,
+        Query<&mut VoxelUniverse<ServerData>>,
+    )>,
+) {
+    let changed = queries
+        .p0()
+        .iter()
+        .filter(|entity| registry.any_changed(entity, &change_tick))
+        .filter_map(|entity| {
+            let position = entity.get::<VoxelPosition>()?.0;
+            Some((entity.id(), position, encode_block_entity(&registry, &entity)))
+        })
+        .collect_vec();
+    if changed.is_empty() {
+        return;
+    }
+
+    let mut voxel_q = queries.p1();
+    let Ok(mut voxels) = voxel_q.get_single_mut() else {
+        return;
+    };
+    let chunks = &mut voxels.loaded_chunks_mut().chunks;
+    let mut updated: HashMap<AbsChunkPos, Vec<InChunkPos>> = HashMap::new();
+    for (entity, position, components) in changed {
+        let (chunk_pos, in_chunk_pos) = position.split_chunk_component();
+        let Some(chunk) = chunks.get_mut(&chunk_pos) else {
+            continue;
+        };
+        let Some(record) = chunk.block_entities.get(in_chunk_pos) else {
+            continue;
+        };
+        if record.entity != Some(entity.to_bits()) || record.components == components {
+            continue;
+        }
+        // The blocks don't change, so the players holding the chunk get just the record instead of a new revision.
+        chunk.mutate_without_revision().block_entities.put(
+            in_chunk_pos,
+            Some(BlockEntityRecord {
+                entity: Some(entity.to_bits()),
+                components,
+            }),
+        );
+        updated.entry(chunk_pos).or_default().push(in_chunk_pos);
+    }
+
+    for (chunk_pos, positions) in updated {
+        let chunk = chunks.get_mut(&chunk_pos).unwrap();
+        let players = chunk.extra_data.players_holding(chunk.local_revision()).collect_vec();
+        let mut serialized: HashMap<u16, Bytes> = HashMap::new();
+        for player in players {
+            let Ok(sender) = senders.get(player) else {
+                continue;
+            };
+            let compression = sender.compression();
+            let packet = serialized
+                .entry(compression as u16)
+                .or_insert_with(|| {
+                    serialize_block_entity_update_packet(tick.0, chunk_pos, &*chunk, &positions, compression)
+                })
+                .clone();
+            if sender.try_send(packet).is_err() {
+                // Sends the whole chunk with the current records once the player's queue has space again.
+                chunk.mutate_without_revision().extra_data.forget_player(player);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use capnp::message::{TypedBuilder, TypedReader};
+    use gs_schemas::mutwatcher::MutWatcher;
+    use gs_schemas::schemas::game_types_capnp::full_chunk_data;
+    use gs_schemas::schemas::stream_packet::decode_stream_packet;
+
+    use super::*;
+    use crate::builtin_game_registries;
+    use crate::network::entity_replication::{ReplicationAppExt, SerdeEncoding};
+    use crate::network::transport::RPC_LOCAL_READER_OPTIONS;
+    use crate::voxel::plugin::ServerChunkMetadata;
+
+    #[derive(Component, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Label(String);
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.replicate_component::<Transform, SerdeEncoding>("gs:transform")
+            .replicate_component::<Label, SerdeEncoding>("test:label");
+        let registries = builtin_game_registries();
+        let (empty, _) = registries
+            .block_types
+            .lookup_name_to_object(EMPTY_BLOCK_NAME.as_ref())
+            .unwrap();
+        let mut voxels = VoxelUniverse::<ServerData>::new(());
+        voxels.loaded_chunks_mut().chunks.insert(
+            AbsChunkPos::ZERO,
+            MutWatcher::new(Chunk::new(BlockEntry::new(empty, 0), default())),
+        );
+        app.insert_resource(BlockRegistryHolder(registries.block_types))
+            .init_resource::<RemovedBlockEntities>()
+            .world_mut()
+            .spawn(voxels);
+        app
+    }
+
+    fn stored_chunk(world: &mut World) -> Chunk<ServerData> {
+        let mut voxels = world.query::<&VoxelUniverse<ServerData>>();
+        voxels.single(world).loaded_chunks().chunks[&AbsChunkPos::ZERO]
+            .read()
+            .clone()
+    }
+
+    fn block_name(world: &World, chunk: &Chunk<ServerData>, position: InChunkPos) -> String {
+        let registry = world.resource::<BlockRegistryHolder>();
+        let definition = registry.lookup_id_to_object(chunk.blocks.get(position).id).unwrap();
+        definition.name.to_string()
+    }
+
+    #[test]
+    fn attach_and_detach() {
+        let mut app = test_app();
+        let world = app.world_mut();
+        let position = AbsBlockPos::new(1, 2, 3);
+        let (_, in_chunk_pos) = position.split_chunk_component();
+        let entity = world.spawn(Label(String::from("chest"))).id();
+
+        attach_block_entity(world, position, entity).unwrap();
+        let chunk = stored_chunk(world);
+        assert_eq!(block_name(world, &chunk, in_chunk_pos), "gs:placeholder");
+        let record = chunk.block_entities.get(in_chunk_pos).as_ref().unwrap();
+        assert_eq!(record.entity, Some(entity.to_bits()));
+        assert!(record.components.iter().any(|c| c.name == "test:label"));
+        assert_eq!(world.get::<VoxelPosition>(entity), Some(&VoxelPosition(position)));
+
+        let other = world.spawn_empty().id();
+        assert!(attach_block_entity(world, position, other).is_err());
+        assert!(attach_block_entity(world, AbsBlockPos::new(100, 0, 0), other).is_err());
+
+        assert_eq!(detach_block_entity(world, position).unwrap(), Some(entity));
+        let chunk = stored_chunk(world);
+        assert_eq!(block_name(world, &chunk, in_chunk_pos), "gs:empty");
+        assert!(chunk.block_entities.get(in_chunk_pos).is_none());
+        assert!(!world.entity(entity).contains::<BlockEntity>());
+        assert_eq!(detach_block_entity(world, position).unwrap(), None);
+    }
+
+    #[test]
+    fn cleanup_replaced_blocks_and_despawned_entities() {
+        let mut app = test_app();
+        app.add_systems(Update, server_system_cleanup_block_entities);
+        let replaced_pos = AbsBlockPos::new(0, 0, 0);
+        let despawned_pos = AbsBlockPos::new(5, 0, 0);
+        let replaced = app.world_mut().spawn_empty().id();
+        let despawned = app.world_mut().spawn_empty().id();
+        attach_block_entity(app.world_mut(), replaced_pos, replaced).unwrap();
+        attach_block_entity(app.world_mut(), despawned_pos, despawned).unwrap();
+
+        let world = app.world_mut();
+        let (stone, _) = world
+            .resource::<BlockRegistryHolder>()
+            .lookup_name_to_object(crate::voxel::blocks::STONE_BLOCK_NAME.as_ref())
+            .unwrap();
+        let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+        let mut voxels = voxels.single_mut(world);
+        let chunk = voxels.loaded_chunks_mut().chunks.get_mut(&AbsChunkPos::ZERO).unwrap();
+        let (_, replaced_in_chunk) = replaced_pos.split_chunk_component();
+        chunk
+            .mutate_stored()
+            .blocks
+            .put(replaced_in_chunk, BlockEntry::new(stone, 0));
+        world.despawn(despawned);
+        app.update();
+
+        let world = app.world_mut();
+        assert!(world.get_entity(replaced).is_none());
+        let chunk = stored_chunk(world);
+        assert!(chunk.block_entities.is_empty());
+        assert_eq!(block_name(world, &chunk, replaced_in_chunk), "gs:stone");
+        let (_, despawned_in_chunk) = despawned_pos.split_chunk_component();
+        assert_eq!(block_name(world, &chunk, despawned_in_chunk), "gs:empty");
+    }
+
+    #[test]
+    fn component_changes_are_sent_as_record_updates() {
+        let mut app = test_app();
+        app.init_resource::<ServerTick>()
+            .add_systems(Update, server_system_store_block_entities);
+        let position = AbsBlockPos::new(1, 1, 1);
+        let (_, in_chunk_pos) = position.split_chunk_component();
+        let entity = app.world_mut().spawn(Label(String::from("closed"))).id();
+        attach_block_entity(app.world_mut(), position, entity).unwrap();
+        app.update();
+
+        let (sender, mut packets) = ChunkStreamSender::new_test(1);
+        let (full_sender, _full_packets) = ChunkStreamSender::new_test(1);
+        full_sender.try_send(Bytes::new()).unwrap();
+        let world = app.world_mut();
+        let player = world.spawn(sender).id();
+        let slow_player = world.spawn(full_sender).id();
+        let revision = {
+            let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+            let mut voxels = voxels.single_mut(world);
+            let chunk = voxels.loaded_chunks_mut().chunks.get_mut(&AbsChunkPos::ZERO).unwrap();
+            let revision = chunk.local_revision();
+            let metadata = &mut chunk.mutate_without_revision().extra_data;
+            metadata.mark_sent(player, revision);
+            metadata.mark_sent(slow_player, revision);
+            revision
+        };
+        world.get_mut::<Label>(entity).unwrap().0 = String::from("open");
+        app.update();
+
+        // The record is updated without a new chunk revision, and sent to the player holding the chunk.
+        let world = app.world_mut();
+        let mut voxels = world.query::<&VoxelUniverse<ServerData>>();
+        let chunk = &voxels.single(world).loaded_chunks().chunks[&AbsChunkPos::ZERO];
+        assert_eq!(chunk.local_revision(), revision);
+        assert_eq!(chunk.extra_data.players_holding(revision).collect_vec(), vec![player]);
+        let record = chunk.block_entities.get(in_chunk_pos).clone().unwrap();
+        assert_eq!(record.entity, Some(entity.to_bits()));
+
+        let packet = packets.try_recv().unwrap();
+        let message = decode_stream_packet(&packet, RPC_LOCAL_READER_OPTIONS).unwrap();
+        let message = TypedReader::<_, rpc::chunk_data_stream_packet::Owned>::new(message);
+        let root = message.get().unwrap();
+        assert!(!root.has_data());
+        let updates = root
+            .get_block_entity_updates()
+            .unwrap()
+            .iter()
+            .map(|update| BlockEntityRecord::read_from_message(update).unwrap())
+            .collect_vec();
+        assert_eq!(updates, vec![(in_chunk_pos, BlockEntityRecord::new(record.components))]);
+
+        // A client applies the update to its live entity, without respawning it.
+        let mut client = test_app();
+        let client_world = client.world_mut();
+        let client_entity = client_world.spawn(Label(String::from("closed"))).id();
+        attach_block_entity(client_world, position, client_entity).unwrap();
+        apply_block_entity_updates::<ServerData>(client_world, AbsChunkPos::ZERO, updates);
+        assert_eq!(
+            client_world.get::<Label>(client_entity),
+            Some(&Label(String::from("open")))
+        );
+        assert!(client_world.entity(client_entity).contains::<BlockEntity>());
+    }
+
+    #[test]
+    fn serialize_and_respawn() {
+        let mut app = test_app();
+        let world = app.world_mut();
+        let position = AbsBlockPos::new(4, 5, 6);
+        let entity = world.spawn(Label(String::from("furnace"))).id();
+        attach_block_entity(world, position, entity).unwrap();
+        let original = stored_chunk(world);
+
+        let mut builder = TypedBuilder::<full_chunk_data::Owned>::new_default();
+        original.write_full(&mut builder.init_root());
+        let loaded =
+            Chunk::<ServerData>::read_full(&builder.get_root_as_reader().unwrap(), ServerChunkMetadata::default())
+                .unwrap();
+        let (_, in_chunk_pos) = position.split_chunk_component();
+        let record = loaded.block_entities.get(in_chunk_pos).as_ref().unwrap();
+        assert_eq!(record.entity, None);
+        assert_eq!(
+            record.components,
+            original.block_entities.get(in_chunk_pos).as_ref().unwrap().components
+        );
+
+        // Replace the chunk as if it was loaded again, the entity gets spawned from the record.
+        world.despawn(entity);
+        let mut voxels = world.query::<&mut VoxelUniverse<ServerData>>();
+        voxels
+            .single_mut(world)
+            .loaded_chunks_mut()
+            .chunks
+            .insert(AbsChunkPos::ZERO, MutWatcher::new(loaded));
+        spawn_chunk_block_entities::<ServerData>(world, AbsChunkPos::ZERO);
+        let chunk = stored_chunk(world);
+        let bits = chunk.block_entities.get(in_chunk_pos).as_ref().unwrap().entity.unwrap();
+        let respawned = Entity::from_bits(bits);
+        assert_eq!(world.get::<Label>(respawned), Some(&Label(String::from("furnace"))));
+        assert_eq!(world.get::<VoxelPosition>(respawned), Some(&VoxelPosition(position)));
+        assert!(world.entity(respawned).contains::<BlockEntity>());
+    }
+}
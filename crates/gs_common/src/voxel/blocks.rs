@@ -1,10 +1,12 @@
-//! The builtin block types.
-//! Most of this will be moved to a "base" mod at some point in the future.
+//! The builtin block types, defined in the base content pack.
 
-use gs_schemas::dependencies::rgb::RGBA8;
+use gs_schemas::content::{ContentError, ContentPack};
 use gs_schemas::registry::RegistryName;
-use gs_schemas::voxel::voxeltypes::BlockShapeSet::StandardShapedMaterial;
-use gs_schemas::voxel::voxeltypes::{BlockDefinition, BlockRegistry, EMPTY_BLOCK};
+use gs_schemas::registry_aliases::load_alias_definitions;
+use gs_schemas::registry_tags::load_tag_definitions;
+use gs_schemas::voxel::voxeltypes::{
+    load_block_definitions, BlockRegistry, BLOCKS_DIRECTORY, EMPTY_BLOCK, PLACEHOLDER_BLOCK, UNKNOWN_BLOCK,
+};
 
 /// Registry name for stone.
 pub const STONE_BLOCK_NAME: RegistryName = RegistryName::gs_const("stone");
@@ -19,61 +21,85 @@ pub const WATER_BLOCK_NAME: RegistryName = RegistryName::gs_const("water");
 /// Registry name for sand.
 pub const SAND_BLOCK_NAME: RegistryName = RegistryName::gs_const("sand");
 
-/// Installs the base set of blocks into the given block registry.
-pub fn setup_basic_blocks(registry: &mut BlockRegistry) {
-    registry.push_object(EMPTY_BLOCK.clone()).unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: STONE_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(64, 64, 64, 255),
-            has_collision_box: true,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: DIRT_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(110, 81, 0, 255),
-            has_collision_box: true,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: GRASS_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(30, 230, 30, 255),
-            has_collision_box: true,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: SNOWY_GRASS_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(200, 200, 200, 255),
-            has_collision_box: true,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: WATER_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(0, 0, 200, 100),
-            has_collision_box: false,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
-    registry
-        .push_object(BlockDefinition {
-            name: SAND_BLOCK_NAME,
-            shape_set: StandardShapedMaterial,
-            representative_color: RGBA8::new(224, 200, 130, 255),
-            has_collision_box: true,
-            has_drawable_mesh: true,
-        })
-        .unwrap();
+/// Tag of the dirt-like blocks.
+pub const SOIL_BLOCK_TAG: RegistryName = RegistryName::gs_const("soil");
+/// Tag of the liquid blocks.
+pub const FLUIDS_BLOCK_TAG: RegistryName = RegistryName::gs_const("fluids");
+/// Tag of the solid blocks the world generator builds terrain from.
+pub const NATURAL_TERRAIN_BLOCK_TAG: RegistryName = RegistryName::gs_const("natural_terrain");
+
+/// Installs the empty, unknown and placeholder blocks and the blocks, block aliases and block tags defined in the given content packs
+/// into the given block registry.
+pub fn setup_content_blocks(registry: &mut BlockRegistry, packs: &[ContentPack]) -> Result<(), ContentError> {
+    registry.push_object(EMPTY_BLOCK.clone())?;
+    for block in load_block_definitions(packs)? {
+        registry.push_object(block)?;
+    }
+    // Registered after the content so that the content block IDs match savefiles from before these blocks existed,
+    // which are loaded without an ID mapping.
+    registry.push_object(UNKNOWN_BLOCK.clone())?;
+    registry.push_object(PLACEHOLDER_BLOCK.clone())?;
+    registry.add_aliases(load_alias_definitions(packs, BLOCKS_DIRECTORY)?)?;
+    registry.resolve_tags(&load_tag_definitions(packs, BLOCKS_DIRECTORY)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::content::base_content_pack;
+
+    #[test]
+    fn base_blocks_are_defined() {
+        let mut registry = BlockRegistry::default();
+        setup_content_blocks(&mut registry, &[base_content_pack()]).unwrap();
+        for name in [
+            STONE_BLOCK_NAME,
+            DIRT_BLOCK_NAME,
+            GRASS_BLOCK_NAME,
+            SNOWY_GRASS_BLOCK_NAME,
+            WATER_BLOCK_NAME,
+            SAND_BLOCK_NAME,
+        ] {
+            assert!(registry.lookup_name_to_object(name.as_ref()).is_some(), "{name}");
+        }
+        let (water_id, water) = registry.lookup_name_to_object(WATER_BLOCK_NAME.as_ref()).unwrap();
+        assert!(!water.has_collision_box);
+        assert!(registry.has_tag(water_id, FLUIDS_BLOCK_TAG.as_ref()));
+        assert!(!registry.has_tag(water_id, NATURAL_TERRAIN_BLOCK_TAG.as_ref()));
+        let (grass_id, _) = registry.lookup_name_to_object(GRASS_BLOCK_NAME.as_ref()).unwrap();
+        assert!(registry.has_tag(grass_id, SOIL_BLOCK_TAG.as_ref()));
+        // Through the nested soil tag
+        assert!(registry.has_tag(grass_id, NATURAL_TERRAIN_BLOCK_TAG.as_ref()));
+    }
+
+    #[test]
+    fn engine_blocks_follow_the_content() {
+        let mut registry = BlockRegistry::default();
+        setup_content_blocks(&mut registry, &[base_content_pack()]).unwrap();
+        let (empty_id, _) = registry.lookup_name_to_object(EMPTY_BLOCK.name.as_ref()).unwrap();
+        let (stone_id, _) = registry.lookup_name_to_object(STONE_BLOCK_NAME.as_ref()).unwrap();
+        let (unknown_id, _) = registry.lookup_name_to_object(UNKNOWN_BLOCK.name.as_ref()).unwrap();
+        let (placeholder_id, _) = registry.lookup_name_to_object(PLACEHOLDER_BLOCK.name.as_ref()).unwrap();
+        let content_count = load_block_definitions(&[base_content_pack()]).unwrap().len() as u32;
+        assert_eq!(empty_id.0.get(), 1);
+        assert!(stone_id.0.get() <= content_count + 1);
+        assert_eq!(unknown_id.0.get(), content_count + 2);
+        assert_eq!(placeholder_id.0.get(), content_count + 3);
+    }
+
+    #[test]
+    fn renamed_blocks_are_aliased() {
+        let mut registry = BlockRegistry::default();
+        let renames = ContentPack::new("mod").with_file(
+            "aliases/blocks/renames.ron",
+            r#"[(alias: "grass_block", target: "grass")]"#,
+        );
+        setup_content_blocks(&mut registry, &[base_content_pack(), renames]).unwrap();
+        let (grass_id, _) = registry.lookup_name_to_object(GRASS_BLOCK_NAME.as_ref()).unwrap();
+        let (alias_id, _) = registry
+            .lookup_name_to_object(RegistryName::gs("grass_block").as_ref())
+            .unwrap();
+        assert_eq!(alias_id, grass_id);
+    }
 }
@@ -1,9 +1,12 @@
 //! Standard world generator.
 
+use arc_swap::ArcSwap;
 use gs_schemas::coordinates::AbsChunkPos;
 use gs_schemas::voxel::chunk::Chunk;
 use gs_schemas::GsExtraData;
 
+use crate::prelude::*;
+
 pub mod flat;
 pub mod multi_noise;
 
@@ -12,3 +15,32 @@ pub trait VoxelGenerator<ExtraData: GsExtraData>: Send + Sync {
     /// Generates a single chunk at the given coordinates, with the given pre-filled extra data.
     fn generate_chunk(&self, position: AbsChunkPos, extra_data: ExtraData::ChunkData) -> Chunk<ExtraData>;
 }
+
+/// A generator that can be replaced while chunks are being generated, e.g. with one rebuilt for reloaded registries.
+/// Chunks already being generated finish with the generator they started with.
+pub struct SwappableGenerator<Generator>(ArcSwap<Generator>);
+
+impl<Generator> SwappableGenerator<Generator> {
+    /// Wraps the initial generator.
+    pub fn new(generator: Generator) -> Self {
+        Self(ArcSwap::from_pointee(generator))
+    }
+
+    /// The generator currently used for new chunks.
+    pub fn current(&self) -> Arc<Generator> {
+        self.0.load_full()
+    }
+
+    /// Replaces the generator used for new chunks.
+    pub fn replace(&self, generator: Generator) {
+        self.0.store(Arc::new(generator));
+    }
+}
+
+impl<ExtraData: GsExtraData, Generator: VoxelGenerator<ExtraData>> VoxelGenerator<ExtraData>
+    for SwappableGenerator<Generator>
+{
+    fn generate_chunk(&self, position: AbsChunkPos, extra_data: ExtraData::ChunkData) -> Chunk<ExtraData> {
+        self.0.load().generate_chunk(position, extra_data)
+    }
+}
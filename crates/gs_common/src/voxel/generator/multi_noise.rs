@@ -15,12 +15,12 @@ use gs_schemas::{
     voxel::{
         biome::{
             biome_map::{EXPECTED_BIOME_COUNT, GLOBAL_BIOME_SCALE, GLOBAL_SCALE_MOD},
-            BiomeDefinition, BiomeEntry, BiomeRegistry, Noises, VOID_BIOME_NAME,
+            BiomeDefinition, BiomeEntry, CompiledBiome, FrozenBiomeRegistry, Noises, VOID_BIOME_NAME,
         },
         chunk::Chunk,
         chunk_storage::ChunkStorage,
         generation::{fbm_noise::Fbm, positional_random::PositionalRandomFactory, Context, NoiseNDTo2D},
-        voxeltypes::{BlockEntry, BlockRegistry, EMPTY_BLOCK_NAME},
+        voxeltypes::{BlockEntry, FrozenBlockRegistry, EMPTY_BLOCK_NAME},
     },
     GsExtraData,
 };
@@ -45,8 +45,8 @@ const BIOME_BLEND_RADIUS: f64 = 32.0;
 
 /// Standard world generator implementation
 pub struct MultiNoiseGenerator {
-    biome_registry: Arc<BiomeRegistry>,
-    block_registry: Arc<BlockRegistry>,
+    biome_registry: Arc<FrozenBiomeRegistry>,
+    block_registry: Arc<FrozenBlockRegistry>,
 
     seed: u64,
 
@@ -54,6 +54,7 @@ pub struct MultiNoiseGenerator {
     point_offset_noise: OpenSimplex,
 
     generatable_biomes: Vec<(RegistryId, BiomeDefinition)>,
+    compiled_biomes: HashMap<RegistryId, CompiledBiome>,
 }
 
 impl<ED: GsExtraData> VoxelGenerator<ED> for MultiNoiseGenerator {
@@ -144,7 +145,7 @@ impl<ED: GsExtraData> VoxelGenerator<ED> for MultiNoiseGenerator {
                 let p = Self::elevation_noise(
                     IVec2::new(ix, iz),
                     IVec2::new(position.x, position.z),
-                    &self.biome_registry,
+                    &self.compiled_biomes,
                     &blended,
                     &self.noises,
                 )
@@ -169,22 +170,16 @@ impl<ED: GsExtraData> VoxelGenerator<ED> for MultiNoiseGenerator {
             let g_pos = <IVec3>::from(b_pos) + (<IVec3>::from(position) * CHUNK_DIM);
             let height = vparams[(pos_x + pos_z * CHUNK_DIM) as usize];
 
-            let mut biomes: SmallVec<[(&BiomeDefinition, f64); 3]> = SmallVec::new();
+            let mut biomes: SmallVec<[(RegistryId, &CompiledBiome, f64); 3]> = SmallVec::new();
             for b in blended[(pos_x + pos_z * CHUNK_DIM) as usize].iter() {
-                let e = b.lookup(&self.biome_registry).unwrap();
+                let e = &self.compiled_biomes[&b.id];
                 let w = b.weight * e.block_influence;
-                biomes.push((e, w));
+                biomes.push((b.id, e, w));
             }
             // sort by block influence, then registry id if influence is same
-            biomes.sort_by(|a, b| {
-                a.1.partial_cmp(&b.1).unwrap_or_else(|| {
-                    self.biome_registry
-                        .search_object_to_id(a.0)
-                        .cmp(&self.biome_registry.search_object_to_id(b.0))
-                })
-            });
+            biomes.sort_by(|a, b| a.2.total_cmp(&b.2).then_with(|| a.0.cmp(&b.0)));
 
-            for (biome, _) in biomes.iter() {
+            for (_, biome, _) in biomes.iter() {
                 let ctx = Context {
                     seed: self.seed,
                     chunk: &chunk.blocks,
@@ -192,7 +187,7 @@ impl<ED: GsExtraData> VoxelGenerator<ED> for MultiNoiseGenerator {
                     ground_y: height,
                     sea_level: 0, /* hardcoded for now... */
                 };
-                let result = (biome.rule_source)(&g_pos, &ctx, &self.block_registry);
+                let result = biome.surface_rules.block_at(g_pos, &ctx);
                 if let Some(result) = result {
                     chunk.blocks.put(b_pos, result);
                 }
@@ -204,7 +199,7 @@ impl<ED: GsExtraData> VoxelGenerator<ED> for MultiNoiseGenerator {
 
 impl MultiNoiseGenerator {
     /// create a new StdGenerator.
-    pub fn new(seed: u64, biome_registry: Arc<BiomeRegistry>, block_registry: Arc<BlockRegistry>) -> Self {
+    pub fn new(seed: u64, biome_registry: Arc<FrozenBiomeRegistry>, block_registry: Arc<FrozenBlockRegistry>) -> Self {
         let seed_int = seed as u32;
 
         Self {
@@ -217,6 +212,15 @@ impl MultiNoiseGenerator {
                 }
                 biomes
             },
+            compiled_biomes: biome_registry
+                .iter()
+                .map(|(id, name, def)| {
+                    let compiled = def
+                        .compile(&block_registry)
+                        .unwrap_or_else(|block| panic!("Biome {name} places the unregistered block {block}"));
+                    (id, compiled)
+                })
+                .collect(),
 
             biome_registry,
             block_registry,
@@ -236,14 +240,23 @@ impl MultiNoiseGenerator {
         }
     }
 
+    /// Creates a generator with the same seed using the given registries, e.g. after they were reloaded.
+    pub fn with_registries(
+        &self,
+        biome_registry: Arc<FrozenBiomeRegistry>,
+        block_registry: Arc<FrozenBlockRegistry>,
+    ) -> Self {
+        Self::new(self.seed, biome_registry, block_registry)
+    }
+
     fn elevation_noise(
         in_chunk_pos: IVec2,
         chunk_pos: IVec2,
-        biome_registry: &BiomeRegistry,
+        compiled_biomes: &HashMap<RegistryId, CompiledBiome>,
         blended: &[SmallVec<[BiomeEntry; EXPECTED_BIOME_COUNT]>],
         noises: &Noises,
     ) -> f64 {
-        let nf = |p: DVec2, b: &BiomeDefinition| ((b.surface_noise)(p, &noises.base_terrain_noise) + 1.0) / 2.0;
+        let nf = |p: DVec2, b: &CompiledBiome| (b.surface_noise.evaluate(p, &noises.base_terrain_noise) + 1.0) / 2.0;
         let scale_factor = GLOBAL_BIOME_SCALE * GLOBAL_SCALE_MOD;
         let blend = &blended[(in_chunk_pos.x + in_chunk_pos.y * CHUNK_DIM) as usize];
         let global_pos = DVec2::new(
@@ -254,7 +267,7 @@ impl MultiNoiseGenerator {
         let mut heights = 0.0;
         let mut weights = 0.0;
         for entry in blend {
-            let biome = entry.lookup(biome_registry).unwrap();
+            let biome = &compiled_biomes[&entry.id];
             let noise = nf(global_pos / scale_factor, biome);
             let strength = entry.weight * biome.blend_influence;
             heights += noise * strength;
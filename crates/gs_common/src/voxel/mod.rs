@@ -1,6 +1,7 @@
 //! Voxel world management
 
 pub mod biomes;
+pub mod block_entities;
 pub mod blocks;
 pub mod generator;
 pub mod persistence;
@@ -55,6 +55,7 @@ impl<ExtraData: GsExtraData> ChunkPersistenceLayer<ExtraData> for EmptyPersisten
             loads_queued: 0,
             saves_queued: 0,
             responses_queued: self.queue.len(),
+            chunks_generated: 0,
         }
     }
 }
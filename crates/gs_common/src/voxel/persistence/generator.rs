@@ -19,6 +19,8 @@ pub struct GeneratorPersistenceLayer<ExtraData: GsExtraData> {
     live_tasks: HashMap<AbsChunkPos, Task<ChunkProviderResult<ExtraData>>>,
     // counts unfinished tasks
     wip_task_counter: Arc<AtomicI64>,
+    // counts finished generation tasks
+    generated_counter: Arc<AtomicU64>,
 }
 
 struct CounterDecrOnDrop(Arc<AtomicI64>);
@@ -36,6 +38,7 @@ impl<ExtraData: GsExtraData> GeneratorPersistenceLayer<ExtraData> {
             extra_data,
             live_tasks: HashMap::with_capacity(256),
             wip_task_counter: Arc::new(AtomicI64::new(0)),
+            generated_counter: Arc::new(AtomicU64::new(0)),
         }
     }
 }
@@ -46,11 +49,13 @@ impl<ExtraData: GsExtraData> ChunkPersistenceLayer<ExtraData> for GeneratorPersi
             self.wip_task_counter.fetch_add(1, AtomicOrdering::Relaxed);
             let counter = Arc::clone(&self.wip_task_counter);
             let counter = CounterDecrOnDrop(counter);
+            let generated_counter = Arc::clone(&self.generated_counter);
             let gen = Arc::clone(&self.generator);
             let extra_data = self.extra_data.clone();
             let task = AsyncComputeTaskPool::get().spawn(async move {
                 let _counter = counter; // decrement on drop()
                 let chunk = gen.generate_chunk(pos, extra_data);
+                generated_counter.fetch_add(1, AtomicOrdering::Relaxed);
                 (pos, Ok(MutWatcher::new(chunk)))
             });
             let _ = self.live_tasks.try_insert(pos, task);
@@ -92,6 +97,7 @@ impl<ExtraData: GsExtraData> ChunkPersistenceLayer<ExtraData> for GeneratorPersi
             loads_queued: wip_cnt,
             saves_queued: 0,
             responses_queued: total_cnt - wip_cnt,
+            chunks_generated: self.generated_counter.load(AtomicOrdering::Relaxed),
         }
     }
 }
@@ -7,7 +7,9 @@ use gs_schemas::GsExtraData;
 use gs_schemas::{coordinates::AbsChunkPos, mutwatcher::MutWatcher};
 use hashbrown::HashMap;
 
-use crate::voxel::persistence::{ChunkPersistenceLayer, ChunkPersistenceLayerStats, ChunkProviderResult};
+use crate::voxel::persistence::{
+    ChunkPersistenceLayer, ChunkPersistenceLayerStats, ChunkProviderResult, PersistedChunkLoader,
+};
 
 /// Synchronous, in-memory persistence layer.
 /// Missing chunks are generated from an underlying provider, they are only cached in memory on explicit save requests.
@@ -16,6 +18,7 @@ pub struct MemoryPersistenceLayer<ExtraData: GsExtraData> {
     underlying_provider: Box<dyn ChunkPersistenceLayer<ExtraData>>,
     queue: VecDeque<ChunkProviderResult<ExtraData>>,
     storage: HashMap<AbsChunkPos, MutWatcher<Chunk<ExtraData>>>,
+    chunk_loaders: Option<Vec<PersistedChunkLoader>>,
 }
 
 impl<ExtraData: GsExtraData> MemoryPersistenceLayer<ExtraData> {
@@ -25,6 +28,7 @@ impl<ExtraData: GsExtraData> MemoryPersistenceLayer<ExtraData> {
             underlying_provider,
             queue: VecDeque::with_capacity(32),
             storage: HashMap::new(),
+            chunk_loaders: None,
         }
     }
 }
@@ -73,6 +77,15 @@ impl<ExtraData: GsExtraData> ChunkPersistenceLayer<ExtraData> for MemoryPersiste
             loads_queued: underlying.loads_queued,
             saves_queued: underlying.saves_queued,
             responses_queued: self.queue.len() + underlying.responses_queued,
+            chunks_generated: underlying.chunks_generated,
         }
     }
+
+    fn load_chunk_loaders(&mut self) -> Option<Vec<PersistedChunkLoader>> {
+        self.chunk_loaders.clone()
+    }
+
+    fn request_save_chunk_loaders(&mut self, loaders: &[PersistedChunkLoader]) {
+        self.chunk_loaders = Some(loaders.to_vec());
+    }
 }
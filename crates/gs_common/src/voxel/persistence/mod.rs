@@ -3,7 +3,7 @@
 use std::ops::Deref;
 
 use anyhow::Result;
-use gs_schemas::coordinates::AbsChunkPos;
+use gs_schemas::coordinates::{AbsBlockPos, AbsChunkPos};
 use gs_schemas::mutwatcher::MutWatcher;
 use gs_schemas::voxel::chunk::Chunk;
 use gs_schemas::voxel::chunk_group::ChunkGroup;
@@ -13,6 +13,7 @@ use hashbrown::HashSet;
 pub mod empty;
 pub mod generator;
 pub mod memory;
+pub mod savefile;
 
 /// A single response to a chunk loading request, generated some time after calling [`ChunkPersistenceLayer::request_load`].
 pub type ChunkProviderResult<ExtraData> = (AbsChunkPos, Result<MutWatcher<Chunk<ExtraData>>>);
@@ -26,6 +27,17 @@ pub struct ChunkPersistenceLayerStats {
     pub saves_queued: usize,
     /// Number of chunk load responses waiting in the queue.
     pub responses_queued: usize,
+    /// Total number of chunks generated since the layer was created.
+    pub chunks_generated: u64,
+}
+
+/// A chunk loader permanently placed in the world (e.g. keeping the spawn area loaded), stored along with the chunks.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PersistedChunkLoader {
+    /// The center of the loaded area.
+    pub position: AbsBlockPos,
+    /// The radius of the loaded area, in chunk units.
+    pub radius: i32,
 }
 
 /// A provider for chunk data for chunks not present in memory that need to be created/loaded, and a sink for the same data when the chunks are unloaded.
@@ -45,6 +57,15 @@ pub trait ChunkPersistenceLayer<ExtraData: GsExtraData>: Send + Sync + 'static {
     fn try_dequeue_responses(&mut self, max_count: usize) -> Vec<ChunkProviderResult<ExtraData>>;
     /// Get current diagnostic statistics.
     fn stats(&self) -> ChunkPersistenceLayerStats;
+    /// Loads the chunk loaders placed in the world, or None if they were never saved (e.g. in a freshly created world).
+    /// Layers without persistent storage can rely on the default implementation that never has any saved loaders.
+    fn load_chunk_loaders(&mut self) -> Option<Vec<PersistedChunkLoader>> {
+        None
+    }
+    /// Reliably requests the saving of the chunk loaders placed in the world, replacing any previously saved ones.
+    fn request_save_chunk_loaders(&mut self, _loaders: &[PersistedChunkLoader]) {
+        // no-op
+    }
 }
 
 /// An object responsible for managing the presence of voxel chunks in memory via a persistent storage system (disk or network).
@@ -0,0 +1,241 @@
+//! Savefile headers recording the registry IDs the saved data was written with, so that worlds can still be loaded
+//! after content packs are added, removed or reordered.
+
+use std::io::{BufRead, Write};
+
+use bevy::log::warn;
+use capnp::message::ReaderOptions;
+use gs_schemas::coordinates::AbsBlockPos;
+use gs_schemas::registries::GameRegistries;
+use gs_schemas::registry_remap::{MissingEntryPolicy, RegistryIdRemap};
+use gs_schemas::schemas::game_types_capnp::save_file_header;
+use gs_schemas::voxel::biome::VOID_BIOME_NAME;
+use gs_schemas::voxel::voxeltypes::UNKNOWN_BLOCK_NAME;
+
+use crate::content::warn_resolved_aliases;
+use crate::prelude::*;
+use crate::voxel::persistence::PersistedChunkLoader;
+
+/// Limits for reading savefile messages, a header holds the full registry mappings and can get large.
+pub static SAVEFILE_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(1024 * 1024 * 1024),
+    nesting_limit: 64,
+};
+
+/// Identifies savefile header messages.
+pub const SAVEFILE_MAGIC: u64 = u64::from_le_bytes(*b"GSWORLD\0");
+/// The savefile format version written by this build.
+pub const SAVEFILE_VERSION: u32 = 1;
+
+/// How saved objects missing from the current registries are handled, per registry.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SaveRemapPolicy {
+    /// Handling of missing blocks.
+    pub blocks: MissingEntryPolicy,
+    /// Handling of missing biomes.
+    pub biomes: MissingEntryPolicy,
+}
+
+impl Default for SaveRemapPolicy {
+    /// Replaces missing blocks with the unknown block and missing biomes with the void biome.
+    fn default() -> Self {
+        Self {
+            blocks: MissingEntryPolicy::Replace(UNKNOWN_BLOCK_NAME),
+            biomes: MissingEntryPolicy::Replace(VOID_BIOME_NAME),
+        }
+    }
+}
+
+/// The translations from the registry IDs of a savefile to the current registry IDs.
+#[derive(Clone, Debug, Default)]
+pub struct SaveFileRemaps {
+    /// Block ID translations, to apply to every loaded chunk.
+    pub blocks: RegistryIdRemap,
+    /// Biome ID translations.
+    pub biomes: RegistryIdRemap,
+}
+
+/// The contents of a savefile header.
+#[derive(Clone, Debug, Default)]
+pub struct SaveFileHeader {
+    /// The translations from the saved registry IDs to the current ones.
+    pub remaps: SaveFileRemaps,
+    /// The chunk loaders placed in the saved world.
+    pub chunk_loaders: Vec<PersistedChunkLoader>,
+}
+
+/// Writes a savefile header message recording the current registry IDs and the given world chunk loaders.
+pub fn write_save_header(
+    registries: &GameRegistries,
+    chunk_loaders: &[PersistedChunkLoader],
+    output: &mut impl Write,
+) -> Result<()> {
+    let mut message = capnp::message::TypedBuilder::<save_file_header::Owned>::new_default();
+    let mut header = message.init_root();
+    header.set_magic(SAVEFILE_MAGIC);
+    header.set_version(SAVEFILE_VERSION);
+    registries
+        .block_types
+        .serialize_ids(&mut header.reborrow().init_block_registry());
+    registries
+        .biome_types
+        .serialize_ids(&mut header.reborrow().init_biome_registry());
+    let mut saved_loaders = header.init_chunk_loaders(chunk_loaders.len() as u32);
+    for (i, loader) in chunk_loaders.iter().enumerate() {
+        let mut saved = saved_loaders.reborrow().get(i as u32);
+        let mut position = saved.reborrow().init_position();
+        position.set_x(loader.position.x);
+        position.set_y(loader.position.y);
+        position.set_z(loader.position.z);
+        saved.set_radius(loader.radius);
+    }
+    capnp::serialize::write_message(output, message.borrow_inner())?;
+    Ok(())
+}
+
+/// Reads a savefile header message and builds the ID translations from the saved registries to the given current
+/// ones. Saved objects replaced according to the policy or found through aliases are logged.
+pub fn read_save_header(
+    input: &mut impl BufRead,
+    registries: &GameRegistries,
+    policy: &SaveRemapPolicy,
+) -> Result<SaveFileHeader> {
+    let message =
+        capnp::serialize::read_message(input, SAVEFILE_READER_OPTIONS).context("Reading the savefile header")?;
+    let header = message.get_root::<save_file_header::Reader>()?;
+    ensure!(
+        header.get_magic() == SAVEFILE_MAGIC,
+        "The savefile does not start with a header"
+    );
+    ensure!(
+        header.get_version() <= SAVEFILE_VERSION,
+        "The savefile format version {} is newer than the supported version {SAVEFILE_VERSION}",
+        header.get_version()
+    );
+    let remaps = SaveFileRemaps {
+        blocks: RegistryIdRemap::new(&header.get_block_registry()?, &**registries.block_types, &policy.blocks)
+            .context("Mapping the saved block IDs")?,
+        biomes: RegistryIdRemap::new(&header.get_biome_registry()?, &**registries.biome_types, &policy.biomes)
+            .context("Mapping the saved biome IDs")?,
+    };
+    for (kind, remap) in [("blocks", &remaps.blocks), ("biomes", &remaps.biomes)] {
+        warn_resolved_aliases(&format!("The savefile's {kind} registry"), remap.resolved_aliases());
+        if !remap.replaced_names().is_empty() {
+            warn!(
+                "The savefile has {kind} missing from the loaded content, they got replaced: {:?}",
+                remap.replaced_names()
+            );
+        }
+    }
+    let chunk_loaders = header
+        .get_chunk_loaders()?
+        .iter()
+        .map(|saved| {
+            let position = saved.get_position()?;
+            Ok(PersistedChunkLoader {
+                position: AbsBlockPos::new(position.get_x(), position.get_y(), position.get_z()),
+                radius: saved.get_radius(),
+            })
+        })
+        .collect::<Result<_>>()
+        .context("Reading the saved chunk loaders")?;
+    Ok(SaveFileHeader { remaps, chunk_loaders })
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::coordinates::InChunkPos;
+    use gs_schemas::registry::RegistryName;
+    use gs_schemas::voxel::chunk::Chunk;
+    use gs_schemas::voxel::chunk_storage::ChunkStorage;
+    use gs_schemas::voxel::voxeltypes::{BlockDefinition, BlockEntry, BlockRegistry, EMPTY_BLOCK, UNKNOWN_BLOCK};
+
+    use super::*;
+    use crate::builtin_game_registries;
+    use crate::voxel::plugin::ServerChunkMetadata;
+    use crate::ServerData;
+
+    fn block_registry(names: &[&str]) -> BlockRegistry {
+        let mut registry = BlockRegistry::default();
+        registry.push_object(EMPTY_BLOCK.clone()).unwrap();
+        registry.push_object(UNKNOWN_BLOCK.clone()).unwrap();
+        for &name in names {
+            registry
+                .push_object(BlockDefinition {
+                    name: RegistryName::gs(name),
+                    ..EMPTY_BLOCK.clone()
+                })
+                .unwrap();
+        }
+        registry
+    }
+
+    fn registries(blocks: BlockRegistry) -> GameRegistries {
+        GameRegistries {
+            block_types: Arc::new(blocks.freeze()),
+            ..builtin_game_registries()
+        }
+    }
+
+    fn block(registries: &GameRegistries, name: &str) -> BlockEntry {
+        let (id, _) = registries
+            .block_types
+            .lookup_name_to_object(RegistryName::gs(name).as_ref())
+            .unwrap();
+        BlockEntry::new(id, 0)
+    }
+
+    #[test]
+    fn load_with_changed_blocks() {
+        let saved = registries(block_registry(&["stone", "ore", "dirt"]));
+        let mut chunk = Chunk::<ServerData>::new(block(&saved, "stone"), ServerChunkMetadata::default());
+        let ore_pos = InChunkPos::try_new(1, 2, 3).unwrap();
+        let dirt_pos = InChunkPos::try_new(4, 5, 6).unwrap();
+        chunk.blocks.put(ore_pos, BlockEntry::new(block(&saved, "ore").id, 7));
+        chunk.blocks.put(dirt_pos, BlockEntry::new(block(&saved, "dirt").id, 5));
+
+        let mut file = Vec::new();
+        write_save_header(&saved, &[], &mut file).unwrap();
+
+        let current = registries(block_registry(&["dirt", "stone"]));
+        let strict = SaveRemapPolicy {
+            blocks: MissingEntryPolicy::Error,
+            ..SaveRemapPolicy::default()
+        };
+        assert!(read_save_header(&mut &file[..], &current, &strict).is_err());
+
+        let remaps = read_save_header(&mut &file[..], &current, &SaveRemapPolicy::default())
+            .unwrap()
+            .remaps;
+        assert!(remaps.biomes.is_identity());
+        chunk.remap_block_ids(&remaps.blocks).unwrap();
+        assert_eq!(chunk.blocks.get_copy(InChunkPos::ZERO), block(&current, "stone"));
+        assert_eq!(chunk.blocks.get_copy(ore_pos), block(&current, "unknown"));
+        assert_eq!(
+            chunk.blocks.get_copy(dirt_pos),
+            BlockEntry::new(block(&current, "dirt").id, 5)
+        );
+    }
+
+    #[test]
+    fn unchanged_registries_are_identity() {
+        let registries = builtin_game_registries();
+        let mut file = Vec::new();
+        write_save_header(&registries, &[], &mut file).unwrap();
+        let header = read_save_header(&mut &file[..], &registries, &SaveRemapPolicy::default()).unwrap();
+        assert!(header.remaps.blocks.is_identity() && header.remaps.biomes.is_identity());
+        assert!(header.chunk_loaders.is_empty());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let registries = builtin_game_registries();
+        let mut message = capnp::message::TypedBuilder::<save_file_header::Owned>::new_default();
+        let mut header = message.init_root();
+        header.set_magic(SAVEFILE_MAGIC);
+        header.set_version(SAVEFILE_VERSION + 1);
+        let mut file = Vec::new();
+        capnp::serialize::write_message(&mut file, message.borrow_inner()).unwrap();
+        assert!(read_save_header(&mut &file[..], &registries, &SaveRemapPolicy::default()).is_err());
+    }
+}
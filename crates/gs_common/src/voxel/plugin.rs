@@ -1,34 +1,51 @@
 //! The Bevy plugin for voxel universe handling.
 
+use std::collections::btree_map::Entry;
 use std::collections::BTreeSet;
 use std::marker::PhantomData;
+use std::path::Path;
 use std::sync::Arc;
 
 use bevy::prelude::*;
 use capnp::message::TypedBuilder;
-use gs_schemas::coordinates::{AbsBlockPos, AbsChunkPos, AbsChunkRange, RelChunkPos};
+use gs_schemas::coordinates::{AbsBlockPos, AbsChunkPos, AbsChunkRange, InChunkPos, RelChunkPos};
 use gs_schemas::dependencies::itertools::Itertools;
 use gs_schemas::mutwatcher::{MutWatcher, RevisionNumber};
+use gs_schemas::registries::GameRegistries;
 use gs_schemas::schemas::network_capnp::stream_header::StandardTypes;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::encode_stream_packet;
 use gs_schemas::schemas::NetworkStreamHeader;
-use gs_schemas::voxel::biome::BiomeRegistry;
+use gs_schemas::voxel::biome::FrozenBiomeRegistry;
 use gs_schemas::voxel::chunk::Chunk;
 use gs_schemas::voxel::chunk_group::ChunkGroup;
-use gs_schemas::voxel::voxeltypes::BlockRegistry;
+use gs_schemas::voxel::chunk_storage::ChunkStorage;
+use gs_schemas::voxel::voxeltypes::FrozenBlockRegistry;
 use gs_schemas::{GameSide, GsExtraData};
-use smallvec::SmallVec;
 use tokio_util::bytes::Bytes;
 
+use crate::network::chunk_stream::{ChunkSendError, ChunkStreamSender};
 use crate::network::server::{ConnectedPlayer, ConnectedPlayersTable};
 use crate::network::thread::{NetworkThread, NetworkThreadState};
 use crate::network::transport::InProcessStream;
-use crate::network::PeerAddress;
-use crate::voxel::persistence::ChunkPersistenceLayer;
-use crate::{prelude::*, GameServer, GameServerResource};
+use crate::prelude::*;
+use crate::tick::ServerTick;
+use crate::voxel::block_entities::{
+    server_system_cleanup_block_entities, server_system_store_block_entities, spawn_chunk_block_entities,
+    RemovedBlockEntities,
+};
+use crate::voxel::persistence::savefile::{
+    read_save_header, write_save_header, SaveFileRemaps, SaveRemapPolicy, SAVEFILE_READER_OPTIONS,
+};
+use crate::voxel::persistence::{ChunkPersistenceLayer, ChunkPersistenceLayerStats, PersistedChunkLoader};
 use crate::{InGameSystemSet, ServerData};
 
 /// The maximum number of stored chunk packets before applying stream backpressure.
 pub const CHUNK_PACKET_QUEUE_LENGTH: usize = 64;
+/// The chunk loading radius of players that have not requested a render distance yet.
+pub const DEFAULT_PLAYER_RENDER_DISTANCE: i32 = 4;
+/// The radius of the spawn chunk loader placed in newly created worlds.
+pub const DEFAULT_SPAWN_LOADER_RADIUS: i32 = 4;
 
 /// Initializes the settings related to the voxel universe.
 #[derive(Default)]
@@ -39,14 +56,24 @@ pub struct VoxelUniversePlugin<ExtraData: GsExtraData> {
 impl<ExtraData: GsExtraData> Plugin for VoxelUniversePlugin<ExtraData> {
     fn build(&self, app: &mut App) {
         if ExtraData::SIDE == GameSide::Server {
-            app.add_systems(
-                FixedPreUpdate,
-                (server_system_process_chunk_loading).in_set(InGameSystemSet),
-            )
-            .add_systems(
-                FixedPostUpdate,
-                (server_system_process_chunk_sending).in_set(InGameSystemSet),
-            );
+            app.init_resource::<RemovedBlockEntities>()
+                .add_systems(
+                    FixedPreUpdate,
+                    (server_system_process_chunk_loading).in_set(InGameSystemSet),
+                )
+                .add_systems(
+                    FixedPostUpdate,
+                    (
+                        (
+                            server_system_cleanup_block_entities,
+                            server_system_store_block_entities,
+                            server_system_process_chunk_sending,
+                        )
+                            .chain(),
+                        server_system_save_spawn_chunk_loaders,
+                    )
+                        .in_set(InGameSystemSet),
+                );
         }
     }
 
@@ -75,6 +102,26 @@ pub struct ServerChunkMetadata {
     player_held_revisions: HashMap<Entity, RevisionNumber>,
 }
 
+impl ServerChunkMetadata {
+    /// The players that were sent the given revision of the chunk.
+    pub(crate) fn players_holding(&self, revision: RevisionNumber) -> impl Iterator<Item = Entity> + '_ {
+        self.player_held_revisions
+            .iter()
+            .filter(move |(_, &held)| held == revision)
+            .map(|(&player, _)| player)
+    }
+
+    /// Records that the given revision of the chunk was sent to the player.
+    pub(crate) fn mark_sent(&mut self, player: Entity, revision: RevisionNumber) {
+        self.player_held_revisions.insert(player, revision);
+    }
+
+    /// Forgets the revision sent to the given player, so that the whole chunk gets sent to it again.
+    pub(crate) fn forget_player(&mut self, player: Entity) {
+        self.player_held_revisions.remove(&player);
+    }
+}
+
 /// A tag component marking voxel universes regardless of the generic type.
 #[derive(Clone, Copy, Component)]
 pub struct VoxelUniverseTag;
@@ -103,11 +150,11 @@ pub struct NetworkVoxelClient<ExtraData: GsExtraData> {
 
 /// The bevy [`Resource`] for shared voxel registry access from systems.
 #[derive(Resource, Clone, Deref)]
-pub struct BlockRegistryHolder(pub Arc<BlockRegistry>);
+pub struct BlockRegistryHolder(pub Arc<FrozenBlockRegistry>);
 
 /// The bevy [`Resource`] for shared biome registry access from systems.
 #[derive(Resource, Clone, Deref)]
-pub struct BiomeRegistryHolder(pub Arc<BiomeRegistry>);
+pub struct BiomeRegistryHolder(pub Arc<FrozenBiomeRegistry>);
 
 /// Component for entities anchored in the voxel grid.
 #[derive(Component, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Deref, DerefMut)]
@@ -128,10 +175,31 @@ pub struct ChunkLoader {
     pub radius: i32,
 }
 
+impl ChunkLoader {
+    /// Checks if the given chunk is in the loading area of a loader at `center`.
+    pub fn covers(&self, center: AbsChunkPos, chunk: AbsChunkPos) -> bool {
+        let offset = (chunk.into_ivec3() - center.into_ivec3()).abs();
+        self.radius > 0 && offset.max_element() <= self.radius
+    }
+}
+
+/// Marks chunk loaders placed in the world independently of any player, such as the spawn area loader.
+/// These loaders are saved along with the world by the persistence layer.
+#[derive(Component, Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SpawnChunkLoader;
+
+/// Places a persistent chunk loader keeping the area around `position` loaded, returns its entity.
+/// Despawning the entity removes the loader from the world.
+pub fn place_spawn_chunk_loader(world: &mut World, position: AbsBlockPos, radius: i32) -> Entity {
+    world
+        .spawn((SpawnChunkLoader, VoxelPosition(position), ChunkLoader { radius }))
+        .id()
+}
+
 /// Builder for voxel universe initialization
 pub struct VoxelUniverseBuilder<'world, ExtraData: GsExtraData> {
-    _block_registry: Arc<BlockRegistry>,
-    _biome_registry: Arc<BiomeRegistry>,
+    _block_registry: Arc<FrozenBlockRegistry>,
+    _biome_registry: Arc<FrozenBiomeRegistry>,
     /// The bundle being spawned
     pub bundle: EntityWorldMut<'world>,
     _extra_data: PhantomData<ExtraData>,
@@ -141,8 +209,8 @@ impl<'world, ED: GsExtraData> VoxelUniverseBuilder<'world, ED> {
     /// Starts initializing a new voxel universe in a bevy World. Cannot be used on a World without cleaning up the previous universe first.
     pub fn new(
         world: &'world mut World,
-        block_registry: Arc<BlockRegistry>,
-        biome_registry: Arc<BiomeRegistry>,
+        block_registry: Arc<FrozenBlockRegistry>,
+        biome_registry: Arc<FrozenBiomeRegistry>,
     ) -> Result<Self> {
         let mut old_worlds = world.query::<&VoxelUniverseTag>();
         if old_worlds.iter(world).next().is_some() {
@@ -162,14 +230,24 @@ impl<'world, ED: GsExtraData> VoxelUniverseBuilder<'world, ED> {
     }
 
     /// Adds persistent storage support to the universe.
-    pub fn with_persistent_storage(mut self, persistence_layer: Box<dyn ChunkPersistenceLayer<ED>>) -> Result<Self> {
+    pub fn with_persistent_storage(
+        mut self,
+        mut persistence_layer: Box<dyn ChunkPersistenceLayer<ED>>,
+    ) -> Result<Self> {
         if self.bundle.contains::<NetworkVoxelClient<ED>>() {
             bail!("Universe already has a network client, cannot add persistent storage");
         }
 
-        // TODO: make the player load the chunks
+        let loaders = persistence_layer.load_chunk_loaders().unwrap_or_else(|| {
+            vec![PersistedChunkLoader {
+                position: AbsBlockPos::ZERO,
+                radius: DEFAULT_SPAWN_LOADER_RADIUS,
+            }]
+        });
         self.bundle.world_scope(|w| {
-            w.spawn((VoxelPosition(AbsBlockPos::ZERO), ChunkLoader { radius: 4 }));
+            for loader in loaders {
+                place_spawn_chunk_loader(w, loader.position, loader.radius);
+            }
         });
 
         self.bundle.insert(PersistentVoxelStorage::<ED> {
@@ -191,7 +269,7 @@ impl<'world, ED: GsExtraData> VoxelUniverseBuilder<'world, ED> {
         });
         net_thread.insert_stream_handler(
             NetworkStreamHeader::Standard(StandardTypes::ChunkData),
-            Box::new(move |_state, stream| {
+            Box::new(move |_state, _peer, stream| {
                 Box::pin(NetworkVoxelClient::<ED>::chunk_stream_handler(stream, tx.clone()))
             }),
         );
@@ -226,6 +304,13 @@ impl<ExtraData: GsExtraData> VoxelUniverse<ExtraData> {
     }
 }
 
+impl<ExtraData: GsExtraData> PersistentVoxelStorage<ExtraData> {
+    /// Gets the [`ChunkPersistenceLayerStats`] statistics from the persistence layer.
+    pub fn persistence_stats(&self) -> ChunkPersistenceLayerStats {
+        self.persistence_layer.stats()
+    }
+}
+
 impl<ED: GsExtraData> NetworkVoxelClient<ED> {
     async fn chunk_stream_handler(stream: InProcessStream, packet_queue: AsyncBoundedSender<Bytes>) {
         let InProcessStream { mut rx, .. } = stream;
@@ -245,6 +330,7 @@ fn server_system_process_chunk_loading(
         &VoxelUniverseTag,
     )>,
     chunk_loaders: Query<(&ChunkLoader, &VoxelPosition)>,
+    mut commands: Commands,
 ) {
     let Ok((mut voxels, mut persistence, _)) = voxel_q.get_single_mut() else {
         return;
@@ -270,7 +356,12 @@ fn server_system_process_chunk_loading(
                 }
             };
             // Do not overwrite if the chunk was already loaded earlier.
-            chunk_map.entry(loaded_pos).or_insert(loaded_chunk);
+            if let Entry::Vacant(entry) = chunk_map.entry(loaded_pos) {
+                if !loaded_chunk.block_entities.is_empty() {
+                    commands.add(move |world: &mut World| spawn_chunk_block_entities::<ServerData>(world, loaded_pos));
+                }
+                entry.insert(loaded_chunk);
+            }
         }
     }
 
@@ -306,13 +397,54 @@ fn server_system_process_chunk_loading(
     }
 }
 
+fn server_system_save_spawn_chunk_loaders(
+    mut voxel_q: Query<&mut PersistentVoxelStorage<ServerData>>,
+    changed_loaders: Query<
+        (),
+        (
+            With<SpawnChunkLoader>,
+            Or<(Changed<ChunkLoader>, Changed<VoxelPosition>)>,
+        ),
+    >,
+    mut removed_loaders: RemovedComponents<SpawnChunkLoader>,
+    loaders: Query<(&ChunkLoader, &VoxelPosition), With<SpawnChunkLoader>>,
+) {
+    let removed_any = removed_loaders.read().count() > 0;
+    if changed_loaders.is_empty() && !removed_any {
+        return;
+    }
+    let Ok(mut persistence) = voxel_q.get_single_mut() else {
+        return;
+    };
+    let loaders = loaders
+        .iter()
+        .map(|(loader, position)| PersistedChunkLoader {
+            position: position.0,
+            radius: loader.radius,
+        })
+        .collect_vec();
+    persistence.persistence_layer.request_save_chunk_loaders(&loaders);
+}
+
+/// Sort key for queued chunk packets, lower values are sent first.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+struct ChunkSendPriority {
+    /// Chunks the player has not received yet go after updates (deltas) of chunks they already have.
+    is_new: bool,
+    /// Squared distance in chunks from the player.
+    distance_sq: i32,
+}
+
 fn server_system_process_chunk_sending(
-    engine: Res<GameServerResource>,
+    tick: Res<ServerTick>,
     mut voxel_q: Query<&mut VoxelUniverse<ServerData>>,
     connected_players_table_q: Query<&ConnectedPlayersTable>,
-    connected_players_q: Query<&ConnectedPlayer>,
+    connected_players_q: Query<
+        (Option<&ChunkStreamSender>, Option<&VoxelPosition>, Option<&ChunkLoader>),
+        With<ConnectedPlayer>,
+    >,
 ) {
-    // TODO: send only nearby chunks, not everything. Also don't iterate every chunk every tick.
+    // TODO: don't iterate every chunk every tick.
     let Ok(player_list) = connected_players_table_q.get_single() else {
         return;
     };
@@ -324,45 +456,249 @@ fn server_system_process_chunk_sending(
     let Ok(mut voxels) = voxel_q.get_single_mut() else {
         return;
     };
-    let voxels = &mut *voxels;
-
-    let engine = &engine.0 as &GameServer;
-
-    let mut send_list: SmallVec<[PeerAddress; 8]> = SmallVec::new();
-
-    for (&position, loaded_chunk) in voxels.loaded_chunks_mut().chunks.iter_mut() {
-        send_list.clear();
-        let chunk_rev = loaded_chunk.local_revision();
-        let chunk_player_list = &mut loaded_chunk.mutate_without_revision().extra_data.player_held_revisions;
-        // remove disconnected players
-        chunk_player_list.retain(|&player, _rev| connected_players_q.contains(player));
-        // find players with outdated revisions
-        for (&peer, &player) in player_list.iter() {
-            let entry = chunk_player_list.entry(player).or_insert_with(|| {
-                send_list.push(peer);
-                chunk_rev
-            });
-            if *entry < chunk_rev {
-                send_list.push(peer);
-                *entry = chunk_rev;
+    let chunks = &mut voxels.loaded_chunks_mut().chunks;
+
+    // remove disconnected players
+    for chunk in chunks.values_mut() {
+        chunk
+            .mutate_without_revision()
+            .extra_data
+            .player_held_revisions
+            .retain(|&player, _rev| connected_players_q.contains(player));
+    }
+
+    // serialize each chunk at most once per tick, and share the buffer between all players
+    let mut serialized: HashMap<(AbsChunkPos, u16), Bytes> = HashMap::new();
+    let mut candidates: Vec<(ChunkSendPriority, AbsChunkPos)> = Vec::new();
+
+    for (&peer, &player) in player_list.iter() {
+        let Ok((Some(sender), position, loader)) = connected_players_q.get(player) else {
+            continue;
+        };
+        // Applies backpressure: a client that does not keep up gets no new packets until its queue drains.
+        let budget = sender.free_capacity();
+        if budget == 0 {
+            if sender.is_closed() {
+                trace!("Skipping chunk sending to {peer:?} with a closed stream");
             }
+            continue;
         }
-        if send_list.is_empty() {
+        let center = position.map(|p| p.chunk_pos()).unwrap_or(AbsChunkPos::ZERO);
+
+        candidates.clear();
+        for (&pos, chunk) in chunks.iter() {
+            // Only stream the chunks within the player's render distance.
+            if loader.is_some_and(|loader| !loader.covers(center, pos)) {
+                continue;
+            }
+            let held = chunk.extra_data.player_held_revisions.get(&player);
+            if held.is_some_and(|&held| held >= chunk.local_revision()) {
+                continue;
+            }
+            let priority = ChunkSendPriority {
+                is_new: held.is_none(),
+                distance_sq: (pos.into_ivec3() - center.into_ivec3()).length_squared(),
+            };
+            candidates.push((priority, pos));
+        }
+        if candidates.is_empty() {
             continue;
         }
-        // serialize chunk once and send to all players
-        // TODO: tick counter system
-        send_chunk_to_players(0, engine, position, loaded_chunk, &send_list);
+        if candidates.len() > budget {
+            candidates.select_nth_unstable(budget - 1);
+            candidates.truncate(budget);
+        }
+        candidates.sort_unstable();
+
+        for &(_, pos) in candidates.iter() {
+            let chunk = chunks.get_mut(&pos).unwrap();
+            let compression = sender.compression();
+            let packet = serialized
+                .entry((pos, compression as u16))
+                .or_insert_with(|| serialize_chunk_packet(tick.0, pos, chunk, compression))
+                .clone();
+            match sender.try_send(packet) {
+                Ok(()) => {
+                    let revision = chunk.local_revision();
+                    chunk.mutate_without_revision().extra_data.mark_sent(player, revision);
+                }
+                Err(ChunkSendError::QueueFull) | Err(ChunkSendError::StreamClosed) => break,
+            }
+        }
+    }
+}
+
+/// Queues copies of all the loaded chunks of the server universe for saving in its persistent storage.
+/// Returns the number of queued chunks.
+pub fn save_loaded_chunks(world: &mut World) -> Result<usize> {
+    let mut voxel_q = world.query::<(&VoxelUniverse<ServerData>, &mut PersistentVoxelStorage<ServerData>)>();
+    let (voxels, mut persistence) = voxel_q
+        .get_single_mut(world)
+        .context("No voxel universe with persistent storage")?;
+    let chunks: Box<[_]> = voxels
+        .loaded_chunks
+        .chunks
+        .iter()
+        .map(|(&pos, chunk)| (pos, chunk.clone()))
+        .collect();
+    let count = chunks.len();
+    persistence.persistence_layer.request_save(chunks);
+    Ok(count)
+}
+
+/// The world data read back from a savefile.
+#[derive(Default)]
+pub struct SavedWorld {
+    /// The saved chunks, with block IDs translated to the current registries.
+    pub chunks: Vec<(AbsChunkPos, Chunk<ServerData>)>,
+    /// The saved spawn chunk loaders.
+    pub chunk_loaders: Vec<PersistedChunkLoader>,
+}
+
+impl SavedWorld {
+    /// Reads a world savefile written by [`WorldSnapshot::write`] from disk, see [`read_saved_chunks`].
+    pub fn load_file(path: &Path, registries: &GameRegistries, policy: &SaveRemapPolicy) -> Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("Opening the world savefile {path:?}"))?;
+        let world = read_saved_chunks(&mut std::io::BufReader::new(file), registries, policy)
+            .with_context(|| format!("Reading the world savefile {path:?}"))?;
+        info!("Loaded {} chunks from the world savefile {path:?}", world.chunks.len());
+        Ok(world)
+    }
+
+    /// Stores the saved world into a persistence layer, so that it is used instead of generating new chunks.
+    pub fn store_into(self, persistence_layer: &mut dyn ChunkPersistenceLayer<ServerData>) {
+        let chunks = self
+            .chunks
+            .into_iter()
+            .map(|(pos, chunk)| (pos, MutWatcher::new(chunk)))
+            .collect();
+        persistence_layer.request_save(chunks);
+        persistence_layer.request_save_chunk_loaders(&self.chunk_loaders);
     }
 }
 
-fn send_chunk_to_players(
+/// A copy of the loaded chunks and the spawn chunk loaders of the server universe, taken on the game thread by
+/// [`snapshot_loaded_world`] so that it can be written out on another thread.
+pub struct WorldSnapshot {
+    tick: u64,
+    chunks: Vec<(AbsChunkPos, MutWatcher<Chunk<ServerData>>)>,
+    chunk_loaders: Vec<PersistedChunkLoader>,
+}
+
+/// Copies all the loaded chunks and the spawn chunk loaders of the server universe, used for world backups.
+pub fn snapshot_loaded_world(world: &mut World) -> Result<WorldSnapshot> {
+    let tick = world.get_resource::<ServerTick>().map_or(0, |tick| tick.0);
+    let mut loaders_q = world.query_filtered::<(&ChunkLoader, &VoxelPosition), With<SpawnChunkLoader>>();
+    let chunk_loaders = loaders_q
+        .iter(world)
+        .map(|(loader, position)| PersistedChunkLoader {
+            position: position.0,
+            radius: loader.radius,
+        })
+        .collect_vec();
+    let mut voxel_q = world.query::<&VoxelUniverse<ServerData>>();
+    let voxels = voxel_q.get_single(world).context("No server voxel universe")?;
+    let chunks = voxels
+        .loaded_chunks
+        .chunks
+        .iter()
+        .map(|(&pos, chunk)| (pos, chunk.clone()))
+        .collect_vec();
+    Ok(WorldSnapshot {
+        tick,
+        chunks,
+        chunk_loaders,
+    })
+}
+
+impl WorldSnapshot {
+    /// Writes the snapshot into `output` as a savefile header with the IDs of the given registries and the spawn chunk
+    /// loaders, followed by a sequence of unpacked [`rpc::chunk_data_stream_packet`] messages.
+    /// Returns the number of written chunks.
+    pub fn write(&self, registries: &GameRegistries, output: &mut impl std::io::Write) -> Result<usize> {
+        write_save_header(registries, &self.chunk_loaders, &mut *output)?;
+        for (pos, chunk) in self.chunks.iter() {
+            let builder = chunk_packet_message(self.tick, *pos, chunk);
+            capnp::serialize::write_message(&mut *output, builder.borrow_inner())?;
+        }
+        Ok(self.chunks.len())
+    }
+}
+
+/// Reads the world written by [`WorldSnapshot::write`], translating its block IDs to the given current registries.
+pub fn read_saved_chunks(
+    input: &mut impl std::io::BufRead,
+    registries: &GameRegistries,
+    policy: &SaveRemapPolicy,
+) -> Result<SavedWorld> {
+    let header = read_save_header(&mut *input, registries, policy)?;
+    let mut world = SavedWorld {
+        chunk_loaders: header.chunk_loaders,
+        ..default()
+    };
+    while let Some(message) = capnp::serialize::try_read_message(&mut *input, SAVEFILE_READER_OPTIONS)? {
+        world.chunks.push(read_saved_chunk(&message, &header.remaps)?);
+    }
+    Ok(world)
+}
+
+/// Rewrites the world savefile read from `input` into `output` with dense registry IDs: the saved IDs are translated
+/// to the given registries, which are then compacted (see [`Registry::compact`](gs_schemas::registry::Registry::compact))
+/// for the written header and chunks. Returns the number of rewritten chunks.
+pub fn compact_saved_world(
+    input: &mut impl std::io::BufRead,
+    output: &mut impl std::io::Write,
+    registries: &GameRegistries,
+    policy: &SaveRemapPolicy,
+) -> Result<usize> {
+    let saved = read_saved_chunks(input, registries, policy)?;
+    let (block_types, block_remap) = registries.block_types.compact();
+    let (biome_types, _) = registries.biome_types.compact();
+    // Only the ID mappings of the compacted registries are written, the definitions referring to other objects by ID
+    // are not used.
+    let compacted = GameRegistries {
+        block_types: Arc::new(block_types.freeze()),
+        biome_types: Arc::new(biome_types.freeze()),
+        ..registries.clone()
+    };
+    let chunks = saved
+        .chunks
+        .into_iter()
+        .map(|(pos, mut chunk)| {
+            chunk
+                .remap_block_ids(&block_remap)
+                .with_context(|| format!("Compacting the block IDs of the saved chunk at {pos}"))?;
+            Ok((pos, MutWatcher::new(chunk)))
+        })
+        .collect::<Result<_>>()?;
+    let snapshot = WorldSnapshot {
+        tick: 0,
+        chunks,
+        chunk_loaders: saved.chunk_loaders,
+    };
+    snapshot.write(&compacted, output)
+}
+
+fn read_saved_chunk(
+    message: &capnp::message::Reader<capnp::serialize::OwnedSegments>,
+    remaps: &SaveFileRemaps,
+) -> Result<(AbsChunkPos, Chunk<ServerData>)> {
+    let packet = message.get_root::<rpc::chunk_data_stream_packet::Reader>()?;
+    let position = packet.get_position()?;
+    let pos = AbsChunkPos::new(position.get_x(), position.get_y(), position.get_z());
+    let mut chunk = Chunk::read_full(&packet.get_data()?, ServerChunkMetadata::default())
+        .with_context(|| format!("Reading the saved chunk at {pos}"))?;
+    chunk
+        .remap_block_ids(&remaps.blocks)
+        .with_context(|| format!("Mapping the block IDs of the saved chunk at {pos}"))?;
+    Ok((pos, chunk))
+}
+
+fn chunk_packet_message(
     tick: u64,
-    engine: &GameServer,
     pos: AbsChunkPos,
     chunk: &MutWatcher<Chunk<ServerData>>,
-    peers: &[PeerAddress],
-) {
+) -> TypedBuilder<rpc::chunk_data_stream_packet::Owned> {
     let mut builder = TypedBuilder::<rpc::chunk_data_stream_packet::Owned>::new_default();
     let mut root = builder.init_root();
     root.set_tick(tick);
@@ -372,30 +708,200 @@ fn send_chunk_to_players(
     position.set_y(pos.y);
     position.set_z(pos.z);
     chunk.write_full(&mut root.reborrow().init_data());
-    let mut buffer = Vec::new();
-    capnp::serialize::write_message(&mut buffer, builder.borrow_inner()).unwrap();
-    let buffer = Bytes::from(buffer);
-
-    // TODO: error handling, throttling
-    let peers: SmallVec<[_; 8]> = peers.into();
-    let _ = engine.network_thread.schedule_task(move |rstate| {
-        Box::pin(async move {
-            let mut state = rstate.borrow_mut();
-            for addr in peers {
-                let my_buffer = buffer.clone();
-                let Some(peer) = state.find_connected_client_mut(addr) else {
-                    bail!("Cannot find connected client {addr:?} anymore");
-                };
-                if peer.chunk_stream.is_none() {
-                    peer.chunk_stream = Some(
-                        peer.open_stream(NetworkStreamHeader::Standard(StandardTypes::ChunkData))
-                            .unwrap(),
-                    );
-                }
-                let chunk_stream = peer.chunk_stream.as_mut().unwrap();
-                chunk_stream.tx.send(my_buffer)?;
-            }
-            Ok(())
-        })
-    });
+    builder
+}
+
+/// Serializes a chunk packet without the chunk data, carrying the records of the block entities at `positions`
+/// to the clients that already have the current revision of the chunk.
+pub(crate) fn serialize_block_entity_update_packet(
+    tick: u64,
+    pos: AbsChunkPos,
+    chunk: &MutWatcher<Chunk<ServerData>>,
+    positions: &[InChunkPos],
+    compression: PacketCompression,
+) -> Bytes {
+    let mut builder = TypedBuilder::<rpc::chunk_data_stream_packet::Owned>::new_default();
+    let mut root = builder.init_root();
+    root.set_tick(tick);
+    root.set_revision(chunk.local_revision().into());
+    let mut position = root.reborrow().init_position();
+    position.set_x(pos.x);
+    position.set_y(pos.y);
+    position.set_z(pos.z);
+    let records = positions
+        .iter()
+        .filter_map(|&in_chunk_pos| Some((in_chunk_pos, chunk.block_entities.get(in_chunk_pos).as_ref()?)))
+        .collect_vec();
+    let mut updates = root.init_block_entity_updates(records.len() as u32);
+    for (i, (in_chunk_pos, record)) in records.into_iter().enumerate() {
+        record.write_to_message(in_chunk_pos, updates.reborrow().get(i as u32));
+    }
+    Bytes::from(encode_stream_packet(builder.borrow_inner(), compression))
+}
+
+fn serialize_chunk_packet(
+    tick: u64,
+    pos: AbsChunkPos,
+    chunk: &MutWatcher<Chunk<ServerData>>,
+    compression: PacketCompression,
+) -> Bytes {
+    let builder = chunk_packet_message(tick, pos, chunk);
+    Bytes::from(encode_stream_packet(builder.borrow_inner(), compression))
+}
+
+#[cfg(test)]
+mod test {
+    use gs_schemas::registry::{RegistryId, RegistryName, RegistryNameRef};
+    use gs_schemas::voxel::voxeltypes::{BlockDefinition, BlockEntry, EMPTY_BLOCK};
+
+    use super::*;
+    use crate::builtin_game_registries;
+    use crate::voxel::blocks::STONE_BLOCK_NAME;
+
+    #[test]
+    fn loader_coverage() {
+        let loader = ChunkLoader { radius: 2 };
+        let center = AbsChunkPos::new(10, -3, 0);
+        assert!(loader.covers(center, center));
+        assert!(loader.covers(center, AbsChunkPos::new(12, -5, 2)));
+        assert!(loader.covers(center, AbsChunkPos::new(8, -1, -2)));
+        assert!(!loader.covers(center, AbsChunkPos::new(13, -3, 0)));
+        assert!(!loader.covers(center, AbsChunkPos::new(10, -3, -3)));
+        assert!(!ChunkLoader { radius: 0 }.covers(center, center));
+        assert!(!ChunkLoader { radius: -1 }.covers(center, center));
+    }
+
+    #[test]
+    fn saved_world_round_trip() {
+        let registries = builtin_game_registries();
+        let (stone, _) = registries
+            .block_types
+            .lookup_name_to_object(STONE_BLOCK_NAME.as_ref())
+            .unwrap();
+        let chunk_pos = AbsChunkPos::new(1, -2, 3);
+        let mut voxels = VoxelUniverse::<ServerData>::new(());
+        voxels.loaded_chunks_mut().chunks.insert(
+            chunk_pos,
+            MutWatcher::new(Chunk::new(BlockEntry::new(stone, 0), default())),
+        );
+        let mut world = World::new();
+        world.spawn(voxels);
+        place_spawn_chunk_loader(&mut world, AbsBlockPos::new(5, 6, 7), 3);
+        place_spawn_chunk_loader(&mut world, AbsBlockPos::new(-100, 0, 40), 1);
+        // Player loaders are not part of the saved world.
+        world.spawn((VoxelPosition(AbsBlockPos::ZERO), ChunkLoader { radius: 8 }));
+
+        let mut file = Vec::new();
+        let snapshot = snapshot_loaded_world(&mut world).unwrap();
+        assert_eq!(snapshot.write(&registries, &mut file).unwrap(), 1);
+        let saved = read_saved_chunks(&mut &file[..], &registries, &SaveRemapPolicy::default()).unwrap();
+
+        assert_eq!(saved.chunks.len(), 1);
+        let (pos, chunk) = &saved.chunks[0];
+        assert_eq!(*pos, chunk_pos);
+        assert_eq!(chunk.blocks.get_copy(InChunkPos::ZERO), BlockEntry::new(stone, 0));
+        let mut loaders = saved.chunk_loaders;
+        loaders.sort_by_key(|loader| loader.radius);
+        assert_eq!(
+            loaders,
+            [
+                PersistedChunkLoader {
+                    position: AbsBlockPos::new(-100, 0, 40),
+                    radius: 1,
+                },
+                PersistedChunkLoader {
+                    position: AbsBlockPos::new(5, 6, 7),
+                    radius: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn savefile_without_header_is_rejected() {
+        let registries = builtin_game_registries();
+        let (stone, _) = registries
+            .block_types
+            .lookup_name_to_object(STONE_BLOCK_NAME.as_ref())
+            .unwrap();
+        let chunk = MutWatcher::new(Chunk::<ServerData>::new(BlockEntry::new(stone, 0), default()));
+        let mut file = Vec::new();
+        for pos in [AbsChunkPos::new(0, 0, 0), AbsChunkPos::new(0, 1, 0)] {
+            let builder = chunk_packet_message(1234, pos, &chunk);
+            capnp::serialize::write_message(&mut file, builder.borrow_inner()).unwrap();
+        }
+
+        assert!(read_saved_chunks(&mut &file[..], &registries, &SaveRemapPolicy::default()).is_err());
+        assert!(read_saved_chunks(&mut &[][..], &registries, &SaveRemapPolicy::default()).is_err());
+    }
+
+    #[test]
+    fn compacted_world_ids() {
+        let builtin = builtin_game_registries();
+        // A gap in the block IDs, like the ones left by a server following the IDs of another registry.
+        let mut block_types = (**builtin.block_types).clone();
+        let marble_id = RegistryId::try_from(200).unwrap();
+        let marble = BlockDefinition {
+            name: RegistryName::gs("marble"),
+            ..EMPTY_BLOCK.clone()
+        };
+        block_types.insert_object_with_id(marble_id, marble).unwrap();
+        let registries = GameRegistries {
+            block_types: Arc::new(block_types.freeze()),
+            ..builtin.clone()
+        };
+        let (stone, _) = registries
+            .block_types
+            .lookup_name_to_object(STONE_BLOCK_NAME.as_ref())
+            .unwrap();
+        let marble_pos = InChunkPos::try_new(1, 2, 3).unwrap();
+        let mut chunk = Chunk::<ServerData>::new(BlockEntry::new(stone, 0), default());
+        chunk.blocks.put(marble_pos, BlockEntry::new(marble_id, 4));
+        let snapshot = WorldSnapshot {
+            tick: 10,
+            chunks: vec![(AbsChunkPos::new(0, 1, 0), MutWatcher::new(chunk))],
+            chunk_loaders: vec![PersistedChunkLoader {
+                position: AbsBlockPos::new(5, 6, 7),
+                radius: 3,
+            }],
+        };
+        let mut file = Vec::new();
+        snapshot.write(&registries, &mut file).unwrap();
+
+        let policy = SaveRemapPolicy::default();
+        let mut compacted_file = Vec::new();
+        assert_eq!(
+            compact_saved_world(&mut &file[..], &mut compacted_file, &registries, &policy).unwrap(),
+            1
+        );
+        let (compacted_blocks, _) = registries.block_types.compact();
+        let compacted = GameRegistries {
+            block_types: Arc::new(compacted_blocks.freeze()),
+            ..builtin.clone()
+        };
+        let compacted_marble = RegistryId::try_from(builtin.block_types.len() as u32 + 1).unwrap();
+        assert_eq!(
+            compacted
+                .block_types
+                .lookup_name_to_object(RegistryNameRef::gs("marble"))
+                .unwrap()
+                .0,
+            compacted_marble
+        );
+        // The compacted savefile uses the dense IDs in both its header and its chunks.
+        let saved = read_saved_chunks(&mut &compacted_file[..], &compacted, &policy).unwrap();
+        assert_eq!(
+            saved.chunks[0].1.blocks.get_copy(marble_pos),
+            BlockEntry::new(compacted_marble, 4)
+        );
+        assert_eq!(saved.chunk_loaders, snapshot.chunk_loaders);
+        let header = read_save_header(&mut &compacted_file[..], &compacted, &policy).unwrap();
+        assert!(header.remaps.blocks.is_identity());
+        // Loading it with the original registries translates the IDs back.
+        let saved = read_saved_chunks(&mut &compacted_file[..], &registries, &policy).unwrap();
+        assert_eq!(
+            saved.chunks[0].1.blocks.get_copy(marble_pos),
+            BlockEntry::new(marble_id, 4)
+        );
+    }
 }
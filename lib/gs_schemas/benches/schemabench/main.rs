@@ -1,6 +1,11 @@
 use criterion::criterion_main;
 
 pub mod chunkbench;
+pub mod packetbench;
 pub mod zpackbench;
 
-criterion_main!(chunkbench::chunk_benches, zpackbench::zpack_benches);
+criterion_main!(
+    chunkbench::chunk_benches,
+    packetbench::packet_benches,
+    zpackbench::zpack_benches
+);
@@ -0,0 +1,119 @@
+use std::num::NonZeroU32;
+
+use capnp::message::{Builder, HeapAllocator, ReaderOptions};
+use criterion::{black_box, criterion_group, BenchmarkId, Criterion};
+use gs_schemas::coordinates::{InChunkPos, CHUNK_DIM};
+use gs_schemas::registry::RegistryId;
+use gs_schemas::schemas::game_types_capnp::full_chunk_data;
+use gs_schemas::schemas::network_capnp::PacketCompression;
+use gs_schemas::schemas::stream_packet::{decode_stream_packet, encode_stream_packet};
+use gs_schemas::voxel::chunk::Chunk;
+use gs_schemas::voxel::chunk_storage::ChunkStorage;
+use gs_schemas::voxel::voxeltypes::BlockEntry;
+use gs_schemas::{GameSide, GsExtraData};
+use noise::{NoiseFn, Perlin};
+
+const TERRAIN_SEED: u32 = 0x5eed;
+
+struct BenchData;
+
+impl GsExtraData for BenchData {
+    type ChunkData = ();
+    type GroupData = ();
+
+    const SIDE: GameSide = GameSide::Server;
+}
+
+fn block(id: u32) -> BlockEntry {
+    BlockEntry::new(RegistryId(NonZeroU32::new(id).unwrap()), 0)
+}
+
+/// Generates a chunk of simple noise terrain, with the surface at `surface_offset` blocks above the chunk bottom on average.
+fn terrain_chunk(surface_offset: f64) -> Chunk<BenchData> {
+    let (air, stone, dirt, grass, ore) = (block(1), block(2), block(3), block(4), block(5));
+    let height_noise = Perlin::new(TERRAIN_SEED);
+    let ore_noise = Perlin::new(TERRAIN_SEED + 1);
+    let mut chunk = Chunk::new(air, ());
+    for x in 0..CHUNK_DIM {
+        for z in 0..CHUNK_DIM {
+            let height = surface_offset + 12.0 * height_noise.get([x as f64 / 24.0, z as f64 / 24.0]);
+            let height = height as i32;
+            for y in 0..CHUNK_DIM.min(height + 1) {
+                let depth = height - y;
+                let entry = match depth {
+                    0 => grass,
+                    1..=3 => dirt,
+                    _ if ore_noise.get([x as f64 / 4.0, y as f64 / 4.0, z as f64 / 4.0]) > 0.6 => ore,
+                    _ => stone,
+                };
+                chunk.blocks.put(InChunkPos::try_new(x, y, z).unwrap(), entry);
+            }
+        }
+    }
+    chunk
+}
+
+fn chunk_message(chunk: &Chunk<BenchData>) -> Builder<HeapAllocator> {
+    let mut message = Builder::new_default();
+    chunk.write_full(&mut message.init_root::<full_chunk_data::Builder>());
+    message
+}
+
+fn chunk_cases() -> [(&'static str, Chunk<BenchData>); 3] {
+    [
+        ("surface", terrain_chunk(16.0)),
+        ("underground", terrain_chunk(1000.0)),
+        ("sky", terrain_chunk(-1000.0)),
+    ]
+}
+
+fn bench_chunk_packet_encode(c: &mut Criterion) {
+    for (name, chunk) in chunk_cases() {
+        let message = chunk_message(&chunk);
+        let unpacked = capnp::serialize::write_message_to_words(&message).len();
+        let packed = encode_stream_packet(&message, PacketCompression::None).len();
+        let deflated = encode_stream_packet(&message, PacketCompression::Deflate).len();
+        println!("Chunk packet size ({name}): unpacked {unpacked} B, packed {packed} B, packed+deflate {deflated} B");
+
+        c.bench_with_input(
+            BenchmarkId::new("Chunk packet encode - unpacked", name),
+            &message,
+            |b, m| b.iter(|| capnp::serialize::write_message_to_words(black_box(m))),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("Chunk packet encode - packed", name),
+            &message,
+            |b, m| b.iter(|| encode_stream_packet(black_box(m), PacketCompression::None)),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("Chunk packet encode - packed+deflate", name),
+            &message,
+            |b, m| b.iter(|| encode_stream_packet(black_box(m), PacketCompression::Deflate)),
+        );
+    }
+}
+
+fn bench_chunk_packet_decode(c: &mut Criterion) {
+    for (name, chunk) in chunk_cases() {
+        let message = chunk_message(&chunk);
+        for (kind, compression) in [
+            ("packed", PacketCompression::None),
+            ("packed+deflate", PacketCompression::Deflate),
+        ] {
+            let packet = encode_stream_packet(&message, compression);
+            c.bench_with_input(
+                BenchmarkId::new(format!("Chunk packet decode - {kind}"), name),
+                &packet,
+                |b, p| {
+                    b.iter(|| {
+                        let reader = decode_stream_packet(black_box(p), ReaderOptions::new()).unwrap();
+                        let root = reader.get_root::<full_chunk_data::Reader>().unwrap();
+                        Chunk::<BenchData>::read_full(&root, ()).unwrap()
+                    })
+                },
+            );
+        }
+    }
+}
+
+criterion_group!(packet_benches, bench_chunk_packet_encode, bench_chunk_packet_decode);
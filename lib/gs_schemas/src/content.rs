@@ -0,0 +1,370 @@
+//! Declarative game content (blocks, biomes, ...) defined in [RON](https://github.com/ron-rs/ron) data files.
+//!
+//! Content is grouped into [`ContentPack`]s, each a set of files with `/`-separated relative paths where the top
+//! directory selects the kind of content, e.g. `blocks/terrain.ron`. Packs are merged in the order they are given:
+//! within a pack files are processed in path order and defining a name twice is an error, while a later pack replaces
+//! the definition of an earlier pack in place, so the resulting order (and thus registry ID allocation) only depends
+//! on the pack order and contents.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use hashbrown::HashMap;
+use rgb::RGBA8;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::registry::{RegistryError, RegistryName, RegistryObject};
+use crate::registry_tags::RegistryTagError;
+
+/// The file extension of content definition files.
+pub const CONTENT_FILE_EXTENSION: &str = "ron";
+
+/// The location of a content file, for error messages.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ContentFileRef {
+    /// The name of the pack containing the file.
+    pub pack: String,
+    /// The `/`-separated path of the file relative to the pack root.
+    pub path: String,
+}
+
+impl Display for ContentFileRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.pack, self.path)
+    }
+}
+
+/// Errors that can happen while loading content packs.
+#[derive(Debug, Error)]
+pub enum ContentError {
+    /// A content file or directory could not be read.
+    #[error("{path}: could not be read: {error}")]
+    Io {
+        /// The path that failed to be read.
+        path: String,
+        /// The underlying error.
+        error: std::io::Error,
+    },
+    /// A content file is not valid RON or does not match the expected structure.
+    #[error("{file}:{line}:{column}: {message}")]
+    Syntax {
+        /// The file with the error.
+        file: ContentFileRef,
+        /// The 1-based line of the error.
+        line: usize,
+        /// The 1-based column of the error.
+        column: usize,
+        /// The error description.
+        message: String,
+    },
+    /// A definition has an invalid value.
+    #[error("{file}: {name}: {message}")]
+    Invalid {
+        /// The file with the definition.
+        file: ContentFileRef,
+        /// The name of the definition, as written in the file.
+        name: String,
+        /// The error description.
+        message: String,
+    },
+    /// A pack defines the same name twice.
+    #[error("{file}: {name} is already defined in {first}")]
+    Duplicate {
+        /// The file with the second definition.
+        file: ContentFileRef,
+        /// The duplicated name.
+        name: RegistryName,
+        /// The file with the first definition, boxed to keep the error small.
+        first: Box<ContentFileRef>,
+    },
+    /// The merged definitions could not be registered.
+    #[error("{0}")]
+    Registry(#[from] RegistryError),
+    /// The merged tag definitions could not be resolved.
+    #[error("{0}")]
+    Tag(#[from] RegistryTagError),
+}
+
+impl ContentError {
+    /// Constructs an [`ContentError::Invalid`] error.
+    pub fn invalid(file: &ContentFileRef, name: impl Display, message: impl Into<String>) -> Self {
+        Self::Invalid {
+            file: file.clone(),
+            name: name.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// A named set of content definition files.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContentPack {
+    name: String,
+    files: BTreeMap<String, String>,
+}
+
+impl ContentPack {
+    /// Constructs an empty pack.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a file to the pack, replacing any previous file at the same path.
+    pub fn with_file(mut self, path: impl Into<String>, contents: impl Into<String>) -> Self {
+        self.add_file(path, contents);
+        self
+    }
+
+    /// Adds a file to the pack, replacing any previous file at the same path.
+    pub fn add_file(&mut self, path: impl Into<String>, contents: impl Into<String>) {
+        self.files.insert(path.into(), contents.into());
+    }
+
+    /// Reads all the content files under the given directory into a pack named after the directory.
+    pub fn load_directory(root: &Path) -> Result<Self, ContentError> {
+        let name = root.file_name().map_or_else(
+            || root.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let mut pack = Self::new(name);
+        let mut pending = vec![(root.to_path_buf(), String::new())];
+        while let Some((directory, prefix)) = pending.pop() {
+            let io_error = |error| ContentError::Io {
+                path: directory.display().to_string(),
+                error,
+            };
+            for entry in std::fs::read_dir(&directory).map_err(io_error)? {
+                let entry = entry.map_err(io_error)?;
+                let path = entry.path();
+                let relative = format!("{prefix}{}", entry.file_name().to_string_lossy());
+                if entry.file_type().map_err(io_error)?.is_dir() {
+                    pending.push((path, format!("{relative}/")));
+                } else if path.extension().is_some_and(|ext| ext == CONTENT_FILE_EXTENSION) {
+                    let contents = std::fs::read_to_string(&path).map_err(|error| ContentError::Io {
+                        path: path.display().to_string(),
+                        error,
+                    })?;
+                    pack.add_file(relative, contents);
+                }
+            }
+        }
+        Ok(pack)
+    }
+
+    /// The pack name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Iterates over the content files in the given top directory of the pack, in path order.
+    pub fn files_in<'p>(&'p self, directory: &'p str) -> impl Iterator<Item = (ContentFileRef, &'p str)> + 'p {
+        self.files
+            .iter()
+            .filter(move |(path, _)| {
+                path.strip_prefix(directory)
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .is_some_and(|rest| rest.ends_with(&format!(".{CONTENT_FILE_EXTENSION}")))
+            })
+            .map(|(path, contents)| {
+                let file = ContentFileRef {
+                    pack: self.name.clone(),
+                    path: path.clone(),
+                };
+                (file, contents.as_str())
+            })
+    }
+}
+
+/// Parses the contents of a content file.
+pub fn parse_content_file<T: DeserializeOwned>(file: &ContentFileRef, contents: &str) -> Result<T, ContentError> {
+    ron::from_str(contents).map_err(|e| ContentError::Syntax {
+        file: file.clone(),
+        line: e.position.line,
+        column: e.position.col,
+        message: e.code.to_string(),
+    })
+}
+
+/// Parses a registry name written in a content file.
+pub fn parse_content_name(file: &ContentFileRef, name: &str) -> Result<RegistryName, ContentError> {
+    name.parse().map_err(|_| {
+        ContentError::invalid(
+            file,
+            name,
+            "names must be `namespace:key` (or just `key` in the gs namespace) made of `[a-z0-9_]+`",
+        )
+    })
+}
+
+/// Parses a `#rrggbb` or `#rrggbbaa` hexadecimal colour.
+pub fn parse_hex_color(color: &str) -> Result<RGBA8, String> {
+    let invalid = || format!("invalid colour {color:?}, expected #rrggbb or #rrggbbaa");
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    let alpha = if hex.len() == 8 { channel(3) } else { 255 };
+    Ok(RGBA8::new(channel(0), channel(1), channel(2), alpha))
+}
+
+/// Parses and merges the definitions from the given top directory of all the packs, see the module documentation for
+/// the merge rules. `parse` converts the contents of a single file into definitions.
+pub fn merge_content<D: RegistryObject>(
+    packs: &[ContentPack],
+    directory: &str,
+    parse: impl FnMut(&ContentFileRef, &str) -> Result<Vec<D>, ContentError>,
+) -> Result<Vec<D>, ContentError> {
+    merge_content_by(
+        packs,
+        directory,
+        |definition| definition.registry_name().to_owned(),
+        parse,
+    )
+}
+
+/// Like [`merge_content`], for definitions that are not registry objects themselves, merged by the name `name_of`
+/// returns for them.
+pub fn merge_content_by<D>(
+    packs: &[ContentPack],
+    directory: &str,
+    name_of: impl Fn(&D) -> RegistryName,
+    mut parse: impl FnMut(&ContentFileRef, &str) -> Result<Vec<D>, ContentError>,
+) -> Result<Vec<D>, ContentError> {
+    let mut merged: Vec<D> = Vec::new();
+    // name -> (index in merged, pack index, defining file)
+    let mut defined: HashMap<RegistryName, (usize, usize, ContentFileRef)> = HashMap::new();
+    for (pack_index, pack) in packs.iter().enumerate() {
+        for (file, contents) in pack.files_in(directory) {
+            for definition in parse(&file, contents)? {
+                let name = name_of(&definition);
+                match defined.get_mut(&name) {
+                    Some((_, previous_pack, first)) if *previous_pack == pack_index => {
+                        return Err(ContentError::Duplicate {
+                            file,
+                            name,
+                            first: Box::new(first.clone()),
+                        });
+                    }
+                    Some((index, previous_pack, previous_file)) => {
+                        merged[*index] = definition;
+                        *previous_pack = pack_index;
+                        *previous_file = file.clone();
+                    }
+                    None => {
+                        defined.insert(name, (merged.len(), pack_index, file.clone()));
+                        merged.push(definition);
+                    }
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::registry::RegistryNameRef;
+
+    #[derive(Clone, Debug, PartialEq, Hash)]
+    struct TestDefinition {
+        name: RegistryName,
+        value: u32,
+    }
+
+    impl RegistryObject for TestDefinition {
+        fn registry_name(&self) -> RegistryNameRef {
+            self.name.as_ref()
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TestDefinitionFile {
+        name: String,
+        value: u32,
+    }
+
+    fn parse_test_file(file: &ContentFileRef, contents: &str) -> Result<Vec<TestDefinition>, ContentError> {
+        let definitions: Vec<TestDefinitionFile> = parse_content_file(file, contents)?;
+        definitions
+            .into_iter()
+            .map(|definition| {
+                Ok(TestDefinition {
+                    name: parse_content_name(file, &definition.name)?,
+                    value: definition.value,
+                })
+            })
+            .collect()
+    }
+
+    fn merged(packs: &[ContentPack]) -> Result<Vec<(String, u32)>, ContentError> {
+        Ok(merge_content(packs, "tests", parse_test_file)?
+            .into_iter()
+            .map(|definition| (definition.name.to_string(), definition.value))
+            .collect())
+    }
+
+    #[test]
+    fn later_packs_override_in_place() {
+        let base = ContentPack::new("base")
+            .with_file("tests/b.ron", r#"[(name: "gs:c", value: 3)]"#)
+            .with_file("tests/a.ron", r#"[(name: "a", value: 1), (name: "gs:b", value: 2)]"#)
+            .with_file("other/ignored.ron", r#"[(name: "gs:ignored", value: 0)]"#);
+        let addon = ContentPack::new("addon").with_file(
+            "tests/extra.ron",
+            r#"[(name: "gs:b", value: 20), (name: "addon:d", value: 4)]"#,
+        );
+        assert_eq!(
+            merged(&[base.clone(), addon]).unwrap(),
+            vec![
+                ("gs:a".to_owned(), 1),
+                ("gs:b".to_owned(), 20),
+                ("gs:c".to_owned(), 3),
+                ("addon:d".to_owned(), 4)
+            ]
+        );
+        assert_eq!(merged(&[base]).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn duplicates_within_a_pack_are_rejected() {
+        let pack = ContentPack::new("base")
+            .with_file("tests/a.ron", r#"[(name: "gs:a", value: 1)]"#)
+            .with_file("tests/b.ron", r#"[(name: "gs:a", value: 2)]"#);
+        let error = merged(&[pack]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "base/tests/b.ron: gs:a is already defined in base/tests/a.ron"
+        );
+    }
+
+    #[test]
+    fn errors_are_located() {
+        let pack = ContentPack::new("base").with_file("tests/a.ron", "[\n  (name: \"gs:a\", valeu: 1),\n]");
+        let error = merged(&[pack]).unwrap_err();
+        assert!(matches!(&error, ContentError::Syntax { line: 2, .. }), "{error}");
+        assert!(error.to_string().starts_with("base/tests/a.ron:2:"), "{error}");
+
+        let pack = ContentPack::new("base").with_file("tests/a.ron", r#"[(name: "gs:Bad", value: 1)]"#);
+        let error = merged(&[pack]).unwrap_err();
+        assert!(error.to_string().starts_with("base/tests/a.ron: gs:Bad: "), "{error}");
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex_color("#102030"), Ok(RGBA8::new(16, 32, 48, 255)));
+        assert_eq!(parse_hex_color("#10203040"), Ok(RGBA8::new(16, 32, 48, 64)));
+        assert!(parse_hex_color("102030").is_err());
+        assert!(parse_hex_color("#1020").is_err());
+        assert!(parse_hex_color("#10203g").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Item types and stacks of items, as stored in inventories.
+//!
+//! Most items are "dumb", fully described by their registry ID and a count. Items needing more state carry a
+//! MessagePack-encoded byte buffer, compared byte-by-byte to decide if two stacks can be merged.
+
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::content::{
+    merge_content, parse_content_file, parse_content_name, ContentError, ContentFileRef, ContentPack,
+};
+use crate::registry::{
+    FrozenRegistry, Registry, RegistryDeserializationError, RegistryId, RegistryName, RegistryNameRef, RegistryObject,
+};
+use crate::schemas::game_types_capnp::item_stack;
+use crate::voxel::voxeltypes::BlockRegistry;
+
+/// The content pack directory containing item definition files.
+pub const ITEMS_DIRECTORY: &str = "items";
+
+/// The stack size of items that don't specify one.
+pub const DEFAULT_MAX_STACK_SIZE: u32 = 64;
+
+/// A definition of an item type.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemDefinition {
+    /// The unique registry name
+    pub name: RegistryName,
+    /// The maximum number of items in a single stack
+    pub max_stack_size: u32,
+    /// The block placed when using the item, if any
+    pub block: Option<RegistryName>,
+}
+
+/// A named registry of item definitions.
+pub type ItemRegistry = Registry<ItemDefinition>;
+/// A finished item registry, as shared between systems.
+pub type FrozenItemRegistry = FrozenRegistry<ItemDefinition>;
+
+impl RegistryObject for ItemDefinition {
+    fn registry_name(&self) -> RegistryNameRef {
+        self.name.as_ref()
+    }
+}
+
+impl PartialEq for ItemDefinition {
+    fn eq(&self, other: &Self) -> bool {
+        self.name.eq(&other.name)
+    }
+}
+
+impl Hash for ItemDefinition {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
+/// An item definition as written in a content file, see [`ItemDefinition`] for the meaning of the fields.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ItemDefinitionFile {
+    /// The registry name, `namespace:key` or just `key` for the gs namespace
+    pub name: String,
+    /// The maximum number of items in a single stack
+    #[serde(default = "default_max_stack_size")]
+    pub max_stack_size: u32,
+    /// The name of the block placed when using the item
+    #[serde(default)]
+    pub block: Option<String>,
+}
+
+fn default_max_stack_size() -> u32 {
+    DEFAULT_MAX_STACK_SIZE
+}
+
+impl ItemDefinitionFile {
+    /// Validates the definition read from the given file, checking that the block it places exists.
+    pub fn into_definition(
+        self,
+        file: &ContentFileRef,
+        block_registry: &BlockRegistry,
+    ) -> Result<ItemDefinition, ContentError> {
+        let name = parse_content_name(file, &self.name)?;
+        if self.max_stack_size == 0 {
+            return Err(ContentError::invalid(file, &name, "max_stack_size must be at least 1"));
+        }
+        let block = match self.block {
+            Some(block) => {
+                let block = parse_content_name(file, &block)?;
+                let Some((_, definition)) = block_registry.lookup_name_to_object(block.as_ref()) else {
+                    return Err(ContentError::invalid(file, &name, format!("unknown block {block}")));
+                };
+                // Store the current name in case the item refers to the block through an alias
+                Some(definition.name.clone())
+            }
+            None => None,
+        };
+        Ok(ItemDefinition {
+            name,
+            max_stack_size: self.max_stack_size,
+            block,
+        })
+    }
+}
+
+/// Loads and merges the item definitions of the given content packs.
+/// Each file in the items directory holds a list of [`ItemDefinitionFile`]s, placing blocks from the given registry.
+pub fn load_item_definitions(
+    packs: &[ContentPack],
+    block_registry: &BlockRegistry,
+) -> Result<Vec<ItemDefinition>, ContentError> {
+    merge_content(packs, ITEMS_DIRECTORY, |file, contents| {
+        let definitions: Vec<ItemDefinitionFile> = parse_content_file(file, contents)?;
+        definitions
+            .into_iter()
+            .map(|definition| definition.into_definition(file, block_registry))
+            .collect()
+    })
+}
+
+/// The two-way links between placeable items and the blocks they place, by registry ID.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockItemLinks {
+    block_to_item: HashMap<RegistryId, RegistryId>,
+    item_to_block: HashMap<RegistryId, RegistryId>,
+}
+
+impl BlockItemLinks {
+    /// Resolves the blocks of the given items, skipping blocks missing from the given block registry.
+    /// If multiple items place the same block, the one with the lowest ID is used as the block's item.
+    pub fn new(items: &ItemRegistry, blocks: &BlockRegistry) -> Self {
+        let mut links = Self::default();
+        for (item_id, _name, item) in items.iter() {
+            let Some((block_id, _)) = item
+                .block
+                .as_ref()
+                .and_then(|block| blocks.lookup_name_to_object(block.as_ref()))
+            else {
+                continue;
+            };
+            links.item_to_block.insert(item_id, block_id);
+            links.block_to_item.entry(block_id).or_insert(item_id);
+        }
+        links
+    }
+
+    /// The item used to pick up or place the given block.
+    pub fn item_for_block(&self, block: RegistryId) -> Option<RegistryId> {
+        self.block_to_item.get(&block).copied()
+    }
+
+    /// The block placed by the given item.
+    pub fn block_for_item(&self, item: RegistryId) -> Option<RegistryId> {
+        self.item_to_block.get(&item).copied()
+    }
+}
+
+/// Complex item data: a MessagePack-encoded buffer owned by the item implementation.
+/// The buffer is shared, so cloning a stack (e.g. when splitting it) does not copy the data.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct ItemData(Arc<[u8]>);
+
+impl ItemData {
+    /// Wraps an already MessagePack-encoded buffer.
+    pub fn from_bytes(bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self(bytes.into())
+    }
+
+    /// The encoded bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for ItemData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ItemData{{{} bytes}}", self.0.len())
+    }
+}
+
+/// A number of items of the same type and data.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ItemStack {
+    /// The item ID in the registry
+    pub item: RegistryId,
+    /// The number of items in the stack
+    pub count: u32,
+    /// Complex data shared by all the items in the stack
+    pub data: Option<ItemData>,
+}
+
+impl ItemStack {
+    /// Constructs a stack of dumb items.
+    pub fn new(item: RegistryId, count: u32) -> Self {
+        Self {
+            item,
+            count,
+            data: None,
+        }
+    }
+
+    /// Attaches complex data to the stack.
+    pub fn with_data(mut self, data: ItemData) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Checks if the stack holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Checks if the items of both stacks are identical, i.e. they could be merged into a single stack.
+    pub fn is_same_item(&self, other: &Self) -> bool {
+        self.item == other.item && self.data == other.data
+    }
+
+    /// Serializes the stack into a capnp message.
+    pub fn write_to_message(&self, builder: &mut item_stack::Builder) {
+        builder.set_item(self.item.0.get());
+        builder.set_count(self.count);
+        if let Some(data) = &self.data {
+            builder.set_data(data.as_bytes());
+        }
+    }
+
+    /// Deserializes a stack from a capnp message.
+    pub fn read_from_message(reader: &item_stack::Reader) -> Result<Self, RegistryDeserializationError> {
+        let item = RegistryId::try_from(reader.get_item()).or(Err(RegistryDeserializationError::IllegalID))?;
+        let data = if reader.has_data() {
+            Some(ItemData::from_bytes(reader.get_data()?))
+        } else {
+            None
+        };
+        Ok(Self {
+            item,
+            count: reader.get_count(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::voxeltypes::EMPTY_BLOCK;
+
+    fn blocks() -> BlockRegistry {
+        let mut registry = BlockRegistry::default();
+        for name in ["empty", "stone", "dirt"] {
+            registry
+                .push_object(crate::voxel::voxeltypes::BlockDefinition {
+                    name: RegistryName::gs(name),
+                    ..EMPTY_BLOCK.clone()
+                })
+                .unwrap();
+        }
+        registry
+    }
+
+    #[test]
+    fn load_items() {
+        let blocks = blocks();
+        let load = |contents| {
+            load_item_definitions(
+                &[ContentPack::new("base").with_file("items/test.ron", contents)],
+                &blocks,
+            )
+        };
+        let items = load(r#"[(name: "stone", block: Some("stone")), (name: "mod:gem", max_stack_size: 16)]"#).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].block, Some(RegistryName::gs("stone")));
+        assert_eq!(items[0].max_stack_size, DEFAULT_MAX_STACK_SIZE);
+        assert_eq!(items[1].name, RegistryName::new("mod", "gem"));
+        assert_eq!(items[1].block, None);
+
+        assert_eq!(
+            load(r#"[(name: "gravel", block: Some("gravel"))]"#)
+                .unwrap_err()
+                .to_string(),
+            "base/items/test.ron: gs:gravel: unknown block gs:gravel"
+        );
+        assert!(load(r#"[(name: "gem", max_stack_size: 0)]"#)
+            .unwrap_err()
+            .to_string()
+            .contains("at least 1"));
+
+        let mut registry = ItemRegistry::default();
+        let gem_id = registry.push_object(items[1].clone()).unwrap();
+        let stone_id = registry.push_object(items[0].clone()).unwrap();
+        let links = BlockItemLinks::new(&registry, &blocks);
+        let (stone_block, _) = blocks.lookup_name_to_object(RegistryNameRef::gs("stone")).unwrap();
+        assert_eq!(links.item_for_block(stone_block), Some(stone_id));
+        assert_eq!(links.block_for_item(stone_id), Some(stone_block));
+        assert_eq!(links.block_for_item(gem_id), None);
+    }
+
+    #[test]
+    fn serialize_stacks() {
+        let id = RegistryId::try_from(3).unwrap();
+        let dumb = ItemStack::new(id, 12);
+        let complex = ItemStack::new(id, 1).with_data(ItemData::from_bytes(vec![0x81, 0xa1, b'a', 0x01]));
+        assert!(!dumb.is_same_item(&complex));
+        assert!(complex.is_same_item(&ItemStack {
+            count: 5,
+            ..complex.clone()
+        }));
+
+        for stack in [dumb, complex] {
+            let mut message = capnp::message::Builder::new_default();
+            let mut builder = message.init_root::<item_stack::Builder>();
+            stack.write_to_message(&mut builder);
+            let read = ItemStack::read_from_message(&builder.into_reader()).unwrap();
+            assert_eq!(read, stack);
+        }
+    }
+}
@@ -11,14 +11,20 @@ extern crate core;
 
 use smallvec::{Array, SmallVec};
 
+pub mod content;
 pub mod coordinates;
 pub mod direction;
+pub mod items;
 pub mod mutwatcher;
 pub mod physics;
 pub mod range;
 pub mod registries;
 pub mod registry;
+pub mod registry_aliases;
+pub mod registry_remap;
+pub mod registry_tags;
 pub mod schemas;
+pub mod stream_types;
 pub mod voxel;
 
 /// A trait implemented by the game server and client, specifying the concrete types to attach as extra metadata for every chunk, chunk group, entity, etc.
@@ -59,6 +65,7 @@ pub mod dependencies {
     pub use rand;
     pub use rand_xoshiro;
     pub use rgb;
+    pub use ron;
     pub use serde;
     pub use smallvec;
     pub use thiserror;
@@ -1,8 +1,10 @@
 //! Range wrappers for mostly world generation, because the default std::ops::Range isn't an enum for some reason.
 
+use serde::{Deserialize, Serialize};
+
 // My own type of ranges, now that I cannot use the built-in type...
 /// Wrapper of Range that we can work with within Rust's type system
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Range<Idx> {
     /// start..end
     Closed(Idx, Idx),
@@ -33,6 +35,15 @@ where
             Range::ClosedInclusive(s, e) => *s <= x && *e >= x,
         }
     }
+
+    /// Are the bounds in order, so that the range can contain a value?
+    pub fn is_ordered(&self) -> bool {
+        match self {
+            Range::Closed(s, e) => s < e,
+            Range::ClosedInclusive(s, e) => s <= e,
+            _ => true,
+        }
+    }
 }
 
 impl<Idx> std::fmt::Display for Range<Idx>
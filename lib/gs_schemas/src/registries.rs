@@ -3,17 +3,24 @@
 
 use std::sync::Arc;
 
+use crate::items::FrozenItemRegistry;
 use crate::registry::RegistryDeserializationError;
-use crate::voxel::biome::BiomeRegistry;
-use crate::voxel::voxeltypes::BlockRegistry;
+use crate::registry_aliases::ResolvedAlias;
+use crate::stream_types::FrozenStreamTypeRegistry;
+use crate::voxel::biome::FrozenBiomeRegistry;
+use crate::voxel::voxeltypes::FrozenBlockRegistry;
 
 /// A struct holding all the relevant shared registries.
 #[derive(Clone)]
 pub struct GameRegistries {
     /// Block (voxel) type definitions.
-    pub block_types: Arc<BlockRegistry>,
+    pub block_types: Arc<FrozenBlockRegistry>,
     /// Biome type definitions.
-    pub biome_types: Arc<BiomeRegistry>,
+    pub biome_types: Arc<FrozenBiomeRegistry>,
+    /// Custom network stream type definitions.
+    pub stream_types: Arc<FrozenStreamTypeRegistry>,
+    /// Item type definitions.
+    pub item_types: Arc<FrozenItemRegistry>,
 }
 
 impl GameRegistries {
@@ -23,22 +30,41 @@ impl GameRegistries {
             .serialize_ids(&mut builder.reborrow().init_block_registry());
         self.biome_types
             .serialize_ids(&mut builder.reborrow().init_biome_registry());
+        self.stream_types
+            .serialize_ids(&mut builder.reborrow().init_stream_type_registry());
+        self.item_types
+            .serialize_ids(&mut builder.reborrow().init_item_registry());
     }
 
     /// Creates a derivative registry based on serialized bootstrap data.
+    /// Also returns the names in the bootstrap data that were resolved through aliases of any registry.
     pub fn clone_with_serialized_ids(
         &self,
         bundle: &crate::schemas::game_types_capnp::game_bootstrap_data::Reader,
-    ) -> Result<Self, RegistryDeserializationError> {
-        let block_types = self
+    ) -> Result<(Self, Vec<ResolvedAlias>), RegistryDeserializationError> {
+        let (block_types, mut resolved_aliases) = self
             .block_types
             .clone_with_serialized_ids(&bundle.get_block_registry()?)?;
-        let biome_types = self
+        let (biome_types, biome_aliases) = self
             .biome_types
             .clone_with_serialized_ids(&bundle.get_biome_registry()?)?;
-        Ok(Self {
-            block_types: Arc::new(block_types),
-            biome_types: Arc::new(biome_types),
-        })
+        let (stream_types, stream_type_aliases) = self
+            .stream_types
+            .clone_with_serialized_ids(&bundle.get_stream_type_registry()?)?;
+        let (item_types, item_aliases) = self
+            .item_types
+            .clone_with_serialized_ids(&bundle.get_item_registry()?)?;
+        resolved_aliases.extend(biome_aliases);
+        resolved_aliases.extend(stream_type_aliases);
+        resolved_aliases.extend(item_aliases);
+        Ok((
+            Self {
+                block_types: Arc::new(block_types.freeze()),
+                biome_types: Arc::new(biome_types.freeze()),
+                stream_types: Arc::new(stream_types.freeze()),
+                item_types: Arc::new(item_types.freeze()),
+            },
+            resolved_aliases,
+        ))
     }
 }
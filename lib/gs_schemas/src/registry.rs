@@ -2,16 +2,22 @@
 use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 use std::num::{NonZeroU32, TryFromIntError};
+use std::ops::Deref;
+use std::str::FromStr;
 use std::str::Utf8Error;
 use std::sync::Arc;
 
 use bytemuck::{PodInOption, TransparentWrapper, ZeroableInOption};
-use hashbrown::{Equivalent, HashMap};
+use hashbrown::{Equivalent, HashMap, HashSet};
 use itertools::Itertools;
 use kstring::{KString, KStringRef};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::registry_aliases::{AliasDefinition, ResolvedAlias};
+use crate::registry_remap::RegistryIdRemap;
+use crate::registry_tags::{resolve_tag_definitions, RegistryIdSet, RegistryTagError, TagDefinition};
+
 /// Default namespace for the game objects (as a `const` for compile-time functions)
 pub const GS_REGISTRY_DOMAIN_CONST: &str = "gs";
 /// Default namespace for the game objects
@@ -174,6 +180,22 @@ impl Display for RegistryName {
     }
 }
 
+impl FromStr for RegistryName {
+    type Err = RegistryError;
+
+    /// Parses a `namespace:key` name, names without a namespace are in the `gs` namespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = match s.split_once(':') {
+            Some((ns, key)) => Self::new(ns, key),
+            None => Self::gs(s),
+        };
+        if !is_valid_registry_name(&name.ns) || !is_valid_registry_name(&name.key) {
+            return Err(RegistryError::IllegalName { name });
+        }
+        Ok(name)
+    }
+}
+
 impl<'a> Display for RegistryNameRef<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.ns, self.key)
@@ -198,6 +220,11 @@ pub struct Registry<Object: RegistryObject> {
     next_free_id: NonZeroU32,
     id_to_obj: Vec<Option<Object>>,
     name_to_id: HashMap<RegistryName, RegistryId>,
+    #[serde(default)]
+    tags: HashMap<RegistryName, RegistryIdSet>,
+    /// Old name -> current name
+    #[serde(default)]
+    aliases: HashMap<RegistryName, RegistryName>,
 }
 
 impl<Object: RegistryObject> Default for Registry<Object> {
@@ -206,6 +233,8 @@ impl<Object: RegistryObject> Default for Registry<Object> {
             next_free_id: NonZeroU32::new(1).unwrap(),
             id_to_obj: vec![None],
             name_to_id: HashMap::with_capacity(64),
+            tags: HashMap::new(),
+            aliases: HashMap::new(),
         }
     }
 }
@@ -233,6 +262,20 @@ pub enum RegistryError {
         /// The conflicting name.
         name: RegistryName,
     },
+    /// An alias was added for a name that is not in the registry.
+    #[error("Alias {alias} redirects to {target}, which is not in the registry")]
+    UnknownAliasTarget {
+        /// The alias name.
+        alias: RegistryName,
+        /// The missing target name.
+        target: RegistryName,
+    },
+    /// An alias redirects back to itself, possibly through other aliases.
+    #[error("Alias {alias} redirects back to itself")]
+    AliasCycle {
+        /// One of the aliases in the cycle.
+        alias: RegistryName,
+    },
     /// No more unallocated space in the registry. The allocator is a simple bump allocator, so if objects were removed, it might be possible to optimize the registry down to have free space again.
     #[error("No free space in the registry")]
     NoFreeSpace,
@@ -277,7 +320,7 @@ impl<Object: RegistryObject> Registry<Object> {
         if !is_valid_registry_name(&name.ns) || !is_valid_registry_name(&name.key) {
             return Err(RegistryError::IllegalName { name });
         }
-        if self.name_to_id.contains_key(&name) {
+        if self.name_to_id.contains_key(&name) || self.aliases.contains_key(&name) {
             return Err(RegistryError::NameAlreadyExists { name });
         }
         let id = self.allocate_id()?;
@@ -314,7 +357,7 @@ impl<Object: RegistryObject> Registry<Object> {
         if !is_valid_registry_name(&name.ns) || !is_valid_registry_name(&name.key) {
             return Err(RegistryError::IllegalName { name });
         }
-        if self.name_to_id.contains_key(&name) {
+        if self.name_to_id.contains_key(&name) || self.aliases.contains_key(&name) {
             return Err(RegistryError::NameAlreadyExists { name });
         }
         if id.0 >= self.next_free_id {
@@ -325,9 +368,12 @@ impl<Object: RegistryObject> Registry<Object> {
         Ok(())
     }
 
-    /// Given a namespaced name, look up an object and its ID, or return `None` if it's not found.
+    /// Given a namespaced name or an alias, look up an object and its ID, or return `None` if it's not found.
     pub fn lookup_name_to_object(&self, name: RegistryNameRef) -> Option<(RegistryId, &Object)> {
-        let id = *self.name_to_id.get(&name)?;
+        let id = match self.name_to_id.get(&name) {
+            Some(&id) => id,
+            None => *self.name_to_id.get(self.aliases.get(&name)?)?,
+        };
         let obj = self.id_to_obj.get(id.0.get() as usize)?.as_ref()?;
         Some((id, obj))
     }
@@ -337,21 +383,18 @@ impl<Object: RegistryObject> Registry<Object> {
         self.id_to_obj.get(id.0.get() as usize)?.as_ref()
     }
 
-    /// Given a registry object, find look up its ID, or return `None` if it's not found.
-    pub fn search_object_to_id(&self, object: &Object) -> Option<RegistryId> {
-        self.id_to_obj
-            .iter()
-            .position(|r| r.as_ref().is_some_and(|o| o == object))
-            .map(|i| RegistryId(NonZeroU32::new(i as u32).unwrap()))
+    /// Given a registry object, look up its ID by name, or return `None` if it's not found.
+    pub fn lookup_object_to_id(&self, object: &Object) -> Option<RegistryId> {
+        let id = *self.name_to_id.get(&object.registry_name())?;
+        (self.lookup_id_to_object(id)? == object).then_some(id)
     }
 
-    /// Iterates over all the registry objects.
+    /// Iterates over all the registry objects, in increasing ID order.
     pub fn iter(&self) -> impl Iterator<Item = (RegistryId, RegistryNameRef, &Object)> {
-        self.name_to_id.iter().filter_map(|(name, &id)| {
-            self.id_to_obj
-                .get(id.0.get() as usize)
-                .and_then(Option::as_ref)
-                .map(|obj| (id, name.as_ref(), obj))
+        self.id_to_obj.iter().enumerate().filter_map(|(raw_id, obj)| {
+            let obj = obj.as_ref()?;
+            let id = RegistryId(NonZeroU32::new(raw_id as u32)?);
+            Some((id, obj.registry_name(), obj))
         })
     }
 
@@ -365,7 +408,99 @@ impl<Object: RegistryObject> Registry<Object> {
         self.name_to_id.is_empty()
     }
 
-    /// Serializes the ID-name mappings to a schema bundle message.
+    /// Adds an old name redirecting to an object already in the registry, or to the target of another alias.
+    pub fn add_alias(&mut self, alias: RegistryName, target: RegistryNameRef) -> Result<(), RegistryError> {
+        self.add_aliases([AliasDefinition {
+            alias,
+            target: target.to_owned(),
+        }])
+    }
+
+    /// Adds a set of aliases, each redirecting to an object already in the registry or to another alias, from the
+    /// registry or from the set in any order. Chains of aliases are resolved to point straight to the object.
+    /// Nothing is added if any of the aliases is invalid.
+    pub fn add_aliases(&mut self, aliases: impl IntoIterator<Item = AliasDefinition>) -> Result<(), RegistryError> {
+        // Resolved in the given order, so that errors don't depend on the hash map order.
+        let mut order = Vec::new();
+        let mut pending: HashMap<RegistryName, RegistryName> = HashMap::new();
+        for AliasDefinition { alias, target } in aliases {
+            if !is_valid_registry_name(&alias.ns) || !is_valid_registry_name(&alias.key) {
+                return Err(RegistryError::IllegalName { name: alias });
+            }
+            if self.name_to_id.contains_key(&alias) || self.aliases.contains_key(&alias) || pending.contains_key(&alias)
+            {
+                return Err(RegistryError::NameAlreadyExists { name: alias });
+            }
+            order.push(alias.clone());
+            pending.insert(alias, target);
+        }
+
+        let mut resolved = Vec::with_capacity(order.len());
+        for alias in &order {
+            let mut visited: HashSet<&RegistryName> = HashSet::new();
+            visited.insert(alias);
+            let mut target = &pending[alias];
+            let final_target = loop {
+                if self.name_to_id.contains_key(target) {
+                    break target;
+                } else if let Some(final_target) = self.aliases.get(target) {
+                    break final_target;
+                }
+                let Some(next) = pending.get(target) else {
+                    return Err(RegistryError::UnknownAliasTarget {
+                        alias: alias.clone(),
+                        target: target.clone(),
+                    });
+                };
+                if !visited.insert(target) {
+                    return Err(RegistryError::AliasCycle { alias: target.clone() });
+                }
+                target = next;
+            };
+            resolved.push((alias.clone(), final_target.clone()));
+        }
+        self.aliases.extend(resolved);
+        Ok(())
+    }
+
+    /// Returns the current name the given alias redirects to, or `None` if it's not an alias.
+    pub fn resolve_alias(&self, alias: RegistryNameRef) -> Option<RegistryNameRef> {
+        self.aliases.get(&alias).map(RegistryName::as_ref)
+    }
+
+    /// Iterates over all the aliases and the names they redirect to.
+    pub fn iter_aliases(&self) -> impl Iterator<Item = (RegistryNameRef, RegistryNameRef)> {
+        self.aliases
+            .iter()
+            .map(|(alias, target)| (alias.as_ref(), target.as_ref()))
+    }
+
+    /// Replaces the tags of the registry with the given definitions, resolving nested tags into ID sets.
+    /// Tag members are looked up only once (also through aliases), so this should be done after all the objects and
+    /// aliases are registered.
+    pub fn resolve_tags(&mut self, definitions: &[TagDefinition]) -> Result<(), RegistryTagError> {
+        self.tags = resolve_tag_definitions(definitions, |name| {
+            self.lookup_name_to_object(name.as_ref()).map(|(id, _)| id)
+        })?;
+        Ok(())
+    }
+
+    /// Looks up the IDs of all the objects in the given tag, including nested tags.
+    pub fn lookup_tag(&self, tag: RegistryNameRef) -> Option<&RegistryIdSet> {
+        self.tags.get(&tag)
+    }
+
+    /// Checks if the object with the given ID is in the given tag, `false` if the tag does not exist.
+    pub fn has_tag(&self, id: RegistryId, tag: RegistryNameRef) -> bool {
+        self.lookup_tag(tag).is_some_and(|set| set.contains(id))
+    }
+
+    /// Iterates over all the tags.
+    pub fn iter_tags(&self) -> impl Iterator<Item = (RegistryNameRef, &RegistryIdSet)> {
+        self.tags.iter().map(|(name, set)| (name.as_ref(), set))
+    }
+
+    /// Serializes the ID-name mappings and the resolved tags to a schema bundle message.
     pub fn serialize_ids(&self, builder: &mut crate::schemas::game_types_capnp::registry_id_mapping_bundle::Builder) {
         let mut mappings = self.iter().map(|(id, name, _obj)| (id, name)).collect_vec();
         mappings.sort_by_key(|(id, _name)| *id);
@@ -390,17 +525,35 @@ impl<Object: RegistryObject> Registry<Object> {
                 keys.set(idx as u32, name.key);
             }
         }
+        let mut tags = self.iter_tags().collect_vec();
+        tags.sort_by_key(|(name, _set)| *name);
+        let mut tags_builder = builder.reborrow().init_tags(tags.len().try_into().unwrap());
+        for (idx, (name, set)) in tags.into_iter().enumerate() {
+            let mut tag = tags_builder.reborrow().get(idx as u32);
+            tag.set_ns(name.ns.as_str());
+            tag.set_key(name.key.as_str());
+            let mut ids = tag.init_ids(set.len().try_into().unwrap());
+            for (id_idx, id) in set.iter().enumerate() {
+                ids.set(id_idx as u32, id.0.get());
+            }
+        }
     }
 
     /// Constructs a new registry by cloning the entries from this registry that have ID mappings available in the given mapping bundle.
+    /// The tags are taken from the bundle, and names in the bundle are also looked up through aliases.
+    /// Returns the new registry along with the bundle names that were resolved through aliases.
     pub fn clone_with_serialized_ids(
         &self,
         bundle: &crate::schemas::game_types_capnp::registry_id_mapping_bundle::Reader,
-    ) -> Result<Self, RegistryDeserializationError>
+    ) -> Result<(Self, Vec<ResolvedAlias>), RegistryDeserializationError>
     where
         Object: Clone,
     {
-        let mut out = Self::default();
+        let mut out = Self {
+            aliases: self.aliases.clone(),
+            ..Self::default()
+        };
+        let mut resolved_aliases = Vec::new();
 
         let ids = bundle.reborrow().get_ids()?;
         let nss = bundle.reborrow().get_nss()?;
@@ -422,6 +575,12 @@ impl<Object: RegistryObject> Registry<Object> {
 
             let old_obj = self.lookup_name_to_object(name.as_ref());
             if let Some((_old_id, old_obj)) = old_obj {
+                if old_obj.registry_name() != name.as_ref() {
+                    resolved_aliases.push(ResolvedAlias {
+                        target: old_obj.registry_name().to_owned(),
+                        alias: name,
+                    });
+                }
                 out.insert_object_with_id(new_id, old_obj.clone())?;
             } else {
                 missing_entries.push(name);
@@ -432,13 +591,147 @@ impl<Object: RegistryObject> Registry<Object> {
             return Err(RegistryDeserializationError::MissingEntries(missing_entries));
         }
 
+        for tag in bundle.reborrow().get_tags()? {
+            let name = RegistryName::new(tag.get_ns()?.to_str()?, tag.get_key()?.to_str()?);
+            let mut set = RegistryIdSet::new();
+            for id in tag.get_ids()?.iter() {
+                let id = RegistryId::try_from(id).or(Err(RegistryDeserializationError::IllegalID))?;
+                if out.lookup_id_to_object(id).is_none() {
+                    return Err(RegistryDeserializationError::IllegalID);
+                }
+                set.insert(id);
+            }
+            out.tags.insert(name, set);
+        }
+
+        Ok((out, resolved_aliases))
+    }
+
+    /// Finishes building the registry, making it immutable.
+    pub fn freeze(mut self) -> FrozenRegistry<Object> {
+        let used_len = self
+            .id_to_obj
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(1, |last| last + 1);
+        self.id_to_obj.truncate(used_len);
+        self.id_to_obj.shrink_to_fit();
+        FrozenRegistry { registry: self }
+    }
+
+    /// Builds a copy of the registry with the objects renumbered to consecutive IDs starting from 1, keeping their
+    /// order, along with the table translating the old IDs to the new ones (e.g. to rewrite saved chunks).
+    /// Tags and aliases are carried over.
+    pub fn compact(&self) -> (Self, RegistryIdRemap)
+    where
+        Object: Clone,
+    {
+        let mut compacted = Self {
+            aliases: self.aliases.clone(),
+            ..Self::default()
+        };
+        let mut remap = RegistryIdRemap::default();
+        for (old_id, _name, object) in self.iter() {
+            let new_id = compacted
+                .push_object(object.clone())
+                .expect("Objects of a valid registry should fit in a compacted registry");
+            remap.insert(old_id, new_id);
+        }
+        compacted.tags = self
+            .tags
+            .iter()
+            .map(|(name, set)| {
+                let set = set.iter().filter_map(|id| remap.get(id)).collect();
+                (name.clone(), set)
+            })
+            .collect();
+        (compacted, remap)
+    }
+
+    /// Builds a copy of the `rebuilt` registry that keeps the IDs already in use in this one, for reloading content in
+    /// a running game without invalidating the IDs stored in loaded chunks.
+    /// Objects still present in `rebuilt` (also through its aliases) keep their IDs with the new definitions, removed
+    /// objects stay registered with their old definitions, and new objects get IDs after all the existing ones.
+    /// Tags and aliases are taken from `rebuilt`, except for aliases shadowed by the removed objects' names.
+    pub fn rebuild_with_stable_ids(&self, rebuilt: &Self) -> Result<Self, RegistryError>
+    where
+        Object: Clone,
+    {
+        let mut out = Self::default();
+        // Rebuilt ID -> stable ID
+        let mut remap = RegistryIdRemap::default();
+        // Match the unchanged names first, so that an alias can't claim an object that still exists under its name.
+        for (old_id, name, _old_object) in self.iter() {
+            if let Some(&new_id) = rebuilt.name_to_id.get(&name) {
+                remap.insert(new_id, old_id);
+            }
+        }
+        for (old_id, name, old_object) in self.iter() {
+            let new_id = match rebuilt.name_to_id.get(&name) {
+                Some(&new_id) => Some(new_id),
+                None => rebuilt
+                    .lookup_name_to_object(name)
+                    .map(|(new_id, _)| new_id)
+                    .filter(|&new_id| remap.get(new_id).is_none()),
+            };
+            let object = match new_id {
+                Some(new_id) => {
+                    remap.insert(new_id, old_id);
+                    rebuilt.lookup_id_to_object(new_id).unwrap().clone()
+                }
+                None => old_object.clone(),
+            };
+            out.insert_object_with_id(old_id, object)?;
+        }
+        out.next_free_id = out.next_free_id.max(self.next_free_id);
+        for (new_id, _name, object) in rebuilt.iter() {
+            if remap.get(new_id).is_none() {
+                let stable_id = out.push_object(object.clone())?;
+                remap.insert(new_id, stable_id);
+            }
+        }
+        for (alias, target) in rebuilt.aliases.iter() {
+            if !out.name_to_id.contains_key(alias) {
+                out.aliases.insert(alias.clone(), target.clone());
+            }
+        }
+        out.tags = rebuilt
+            .tags
+            .iter()
+            .map(|(name, set)| {
+                let set = set.iter().filter_map(|id| remap.get(id)).collect();
+                (name.clone(), set)
+            })
+            .collect();
         Ok(out)
     }
 }
 
+/// A registry that can no longer be modified, created by [`Registry::freeze`] once all the objects, aliases and tags
+/// are registered. This is the form registries are shared in, all the lookups are available through [`Deref`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FrozenRegistry<Object: RegistryObject> {
+    registry: Registry<Object>,
+}
+
+impl<Object: RegistryObject> Deref for FrozenRegistry<Object> {
+    type Target = Registry<Object>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+impl<Object: RegistryObject> Default for FrozenRegistry<Object> {
+    fn default() -> Self {
+        Registry::default().freeze()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::registry_tags::TagValue;
 
     #[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
     struct DummyObject(RegistryName);
@@ -492,6 +785,16 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn parse_names() {
+        assert_eq!("gs:stone".parse(), Ok(RegistryName::gs("stone")));
+        assert_eq!("stone".parse(), Ok(RegistryName::gs("stone")));
+        assert_eq!("my_mod:ore_2".parse(), Ok(RegistryName::new("my_mod", "ore_2")));
+        assert!("Stone".parse::<RegistryName>().is_err());
+        assert!("gs:".parse::<RegistryName>().is_err());
+        assert!("a:b:c".parse::<RegistryName>().is_err());
+    }
+
     #[test]
     pub fn serialize_registry() {
         let mut original: Registry<DummyObject> = Registry::default();
@@ -499,6 +802,15 @@ mod test {
         let o_a = original.push_object(DummyObject(RegistryName::gs_const("a"))).unwrap();
         let o_b = original.push_object(DummyObject(RegistryName::gs_const("b"))).unwrap();
         let o_c = original.push_object(DummyObject(RegistryName::gs_const("c"))).unwrap();
+        original
+            .resolve_tags(&[TagDefinition {
+                name: RegistryName::gs("ac"),
+                values: vec![
+                    TagValue::Object(RegistryName::gs("a")),
+                    TagValue::Object(RegistryName::gs("c")),
+                ],
+            }])
+            .unwrap();
 
         let mut original_rev: Registry<DummyObject> = Registry::default();
 
@@ -544,7 +856,7 @@ mod test {
             }
         }
 
-        let original_result = original.clone_with_serialized_ids(&bundle_reader).unwrap();
+        let original_result = original.clone_with_serialized_ids(&bundle_reader).unwrap().0;
         assert_eq!(
             original_result
                 .lookup_name_to_object(RegistryNameRef::gs("a"))
@@ -567,7 +879,7 @@ mod test {
             o_c
         );
 
-        let original_rev_result = original_rev.clone_with_serialized_ids(&bundle_reader).unwrap();
+        let original_rev_result = original_rev.clone_with_serialized_ids(&bundle_reader).unwrap().0;
         assert_eq!(
             original_rev_result
                 .lookup_name_to_object(RegistryNameRef::gs("a"))
@@ -589,5 +901,202 @@ mod test {
                 .0,
             o_c
         );
+        let tag = original_rev_result.lookup_tag(RegistryNameRef::gs("ac")).unwrap();
+        assert_eq!(tag.iter().collect_vec(), vec![o_a, o_c]);
+        assert!(!original_rev_result.has_tag(o_b, RegistryNameRef::gs("ac")));
+    }
+
+    #[test]
+    pub fn aliases() {
+        let mut old: Registry<DummyObject> = Registry::default();
+        let old_a = old.push_object(DummyObject(RegistryName::gs_const("a"))).unwrap();
+        let old_b = old.push_object(DummyObject(RegistryName::gs_const("old_b"))).unwrap();
+
+        let mut renamed: Registry<DummyObject> = Registry::default();
+        let new_b = renamed.push_object(DummyObject(RegistryName::gs_const("b"))).unwrap();
+        renamed.push_object(DummyObject(RegistryName::gs_const("a"))).unwrap();
+        renamed
+            .add_alias(RegistryName::gs("old_b"), RegistryNameRef::gs("b"))
+            .unwrap();
+        // Chained aliases point straight to the object
+        renamed
+            .add_alias(RegistryName::gs("older_b"), RegistryNameRef::gs("old_b"))
+            .unwrap();
+        assert_eq!(
+            renamed.resolve_alias(RegistryNameRef::gs("older_b")),
+            Some(RegistryNameRef::gs("b"))
+        );
+        assert_eq!(
+            renamed.add_alias(RegistryName::gs("a"), RegistryNameRef::gs("b")),
+            Err(RegistryError::NameAlreadyExists {
+                name: RegistryName::gs("a")
+            })
+        );
+        assert_eq!(
+            renamed.add_alias(RegistryName::gs("c"), RegistryNameRef::gs("d")),
+            Err(RegistryError::UnknownAliasTarget {
+                alias: RegistryName::gs("c"),
+                target: RegistryName::gs("d")
+            })
+        );
+        // Aliases added together can redirect to each other in any order
+        let alias = |alias, target| AliasDefinition {
+            alias: RegistryName::gs(alias),
+            target: RegistryName::gs(target),
+        };
+        renamed
+            .add_aliases([
+                alias("oldest_a", "older_a"),
+                alias("older_a", "old_a"),
+                alias("old_a", "a"),
+            ])
+            .unwrap();
+        assert_eq!(
+            renamed.resolve_alias(RegistryNameRef::gs("oldest_a")),
+            Some(RegistryNameRef::gs("a"))
+        );
+        assert_eq!(
+            renamed.add_aliases([alias("x", "y"), alias("y", "z"), alias("z", "y")]),
+            Err(RegistryError::AliasCycle {
+                alias: RegistryName::gs("y")
+            })
+        );
+        assert_eq!(
+            renamed.add_aliases([alias("x", "y"), alias("y", "d")]),
+            Err(RegistryError::UnknownAliasTarget {
+                alias: RegistryName::gs("x"),
+                target: RegistryName::gs("d")
+            })
+        );
+        assert_eq!(renamed.resolve_alias(RegistryNameRef::gs("x")), None);
+        assert!(renamed
+            .push_object(DummyObject(RegistryName::gs_const("old_b")))
+            .is_err());
+        assert_eq!(
+            renamed.lookup_name_to_object(RegistryNameRef::gs("old_b")).unwrap().0,
+            new_b
+        );
+
+        let mut message = capnp::message::Builder::default();
+        let mut bundle = message.init_root::<crate::schemas::game_types_capnp::registry_id_mapping_bundle::Builder>();
+        old.serialize_ids(&mut bundle);
+        let (result, resolved) = renamed.clone_with_serialized_ids(&bundle.into_reader()).unwrap();
+        assert_eq!(result.lookup_name_to_object(RegistryNameRef::gs("a")).unwrap().0, old_a);
+        assert_eq!(result.lookup_name_to_object(RegistryNameRef::gs("b")).unwrap().0, old_b);
+        assert_eq!(
+            result.lookup_name_to_object(RegistryNameRef::gs("old_b")).unwrap().0,
+            old_b
+        );
+        assert_eq!(
+            resolved,
+            vec![ResolvedAlias {
+                alias: RegistryName::gs("old_b"),
+                target: RegistryName::gs("b"),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn freeze_and_compact() {
+        let mut reg: Registry<DummyObject> = Registry::default();
+        let id = |raw| RegistryId::try_from(raw).unwrap();
+        for (raw, key) in [(7, "g"), (2, "b"), (40, "z"), (5, "e")] {
+            reg.insert_object_with_id(id(raw), DummyObject(RegistryName::gs(key)))
+                .unwrap();
+        }
+        reg.resolve_tags(&[TagDefinition {
+            name: RegistryName::gs("ez"),
+            values: vec![
+                TagValue::Object(RegistryName::gs("e")),
+                TagValue::Object(RegistryName::gs("z")),
+            ],
+        }])
+        .unwrap();
+        reg.add_alias(RegistryName::gs("old_g"), RegistryNameRef::gs("g"))
+            .unwrap();
+
+        let frozen = reg.freeze();
+        let order: Vec<_> = frozen
+            .iter()
+            .map(|(id, name, _)| (id.0.get(), name.key.to_string()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![(2, "b".into()), (5, "e".into()), (7, "g".into()), (40, "z".into())]
+        );
+        assert_eq!(
+            frozen.lookup_object_to_id(&DummyObject(RegistryName::gs("z"))),
+            Some(id(40))
+        );
+        assert_eq!(frozen.lookup_object_to_id(&DummyObject(RegistryName::gs("y"))), None);
+
+        let (compacted, remap) = frozen.compact();
+        let order: Vec<_> = compacted
+            .iter()
+            .map(|(id, name, _)| (id.0.get(), name.key.to_string()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![(1, "b".into()), (2, "e".into()), (3, "g".into()), (4, "z".into())]
+        );
+        assert_eq!(remap.get(id(40)), Some(id(4)));
+        assert_eq!(remap.get(id(3)), None);
+        assert!(compacted.has_tag(id(2), RegistryNameRef::gs("ez")));
+        assert!(compacted.has_tag(id(4), RegistryNameRef::gs("ez")));
+        assert!(!compacted.has_tag(id(3), RegistryNameRef::gs("ez")));
+        assert_eq!(
+            compacted.lookup_name_to_object(RegistryNameRef::gs("old_g")).unwrap().0,
+            id(3)
+        );
+    }
+
+    #[test]
+    pub fn rebuild_with_stable_ids() {
+        let id = |raw| RegistryId::try_from(raw).unwrap();
+        let mut old: Registry<DummyObject> = Registry::default();
+        for key in ["a", "b", "c"] {
+            old.push_object(DummyObject(RegistryName::gs(key))).unwrap();
+        }
+
+        // "a" removed, "b" renamed to "bb", "d" added before "c".
+        let mut rebuilt: Registry<DummyObject> = Registry::default();
+        for key in ["bb", "d", "c"] {
+            rebuilt.push_object(DummyObject(RegistryName::gs(key))).unwrap();
+        }
+        rebuilt
+            .add_alias(RegistryName::gs("b"), RegistryNameRef::gs("bb"))
+            .unwrap();
+        rebuilt
+            .add_alias(RegistryName::gs("a"), RegistryNameRef::gs("c"))
+            .unwrap();
+        rebuilt
+            .resolve_tags(&[TagDefinition {
+                name: RegistryName::gs("new"),
+                values: vec![
+                    TagValue::Object(RegistryName::gs("bb")),
+                    TagValue::Object(RegistryName::gs("d")),
+                ],
+            }])
+            .unwrap();
+
+        let stable = old.rebuild_with_stable_ids(&rebuilt).unwrap();
+        let order: Vec<_> = stable
+            .iter()
+            .map(|(id, name, _)| (id.0.get(), name.key.to_string()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![(1, "a".into()), (2, "bb".into()), (3, "c".into()), (4, "d".into())]
+        );
+        assert_eq!(stable.lookup_name_to_object(RegistryNameRef::gs("b")).unwrap().0, id(2));
+        assert_eq!(stable.lookup_name_to_object(RegistryNameRef::gs("a")).unwrap().0, id(1));
+        assert!(stable.has_tag(id(2), RegistryNameRef::gs("new")));
+        assert!(stable.has_tag(id(4), RegistryNameRef::gs("new")));
+        assert!(!stable.has_tag(id(3), RegistryNameRef::gs("new")));
+
+        // Rebuilding again from the same content doesn't change anything.
+        let again = stable.rebuild_with_stable_ids(&rebuilt).unwrap();
+        assert_eq!(again.iter().count(), 4);
+        assert_eq!(again.lookup_name_to_object(RegistryNameRef::gs("d")).unwrap().0, id(4));
     }
 }
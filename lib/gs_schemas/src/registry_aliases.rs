@@ -0,0 +1,122 @@
+//! Aliases: old names of renamed registry objects, redirecting to the current names so that saves and servers using
+//! the old names keep working.
+
+use serde::Deserialize;
+
+use crate::content::{
+    merge_content_by, parse_content_file, parse_content_name, ContentError, ContentFileRef, ContentPack,
+};
+use crate::registry::RegistryName;
+
+/// The content pack directory containing the alias definition directories of each registry, e.g. `aliases/blocks`.
+pub const ALIASES_DIRECTORY: &str = "aliases";
+
+/// An old name redirecting to a current registry object.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct AliasDefinition {
+    /// The old name.
+    pub alias: RegistryName,
+    /// The name of the object the alias redirects to.
+    pub target: RegistryName,
+}
+
+/// A name that was not found in a registry and got resolved through an alias, reported so that content authors can
+/// track which old names are still in use.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ResolvedAlias {
+    /// The old name that was looked up.
+    pub alias: RegistryName,
+    /// The name of the object it resolved to.
+    pub target: RegistryName,
+}
+
+/// An alias definition as written in a content file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AliasDefinitionFile {
+    /// The old name, `namespace:key` or just `key` for the gs namespace
+    pub alias: String,
+    /// The current name of the object
+    pub target: String,
+}
+
+impl AliasDefinitionFile {
+    /// Validates the definition read from the given file.
+    pub fn into_definition(self, file: &ContentFileRef) -> Result<AliasDefinition, ContentError> {
+        let alias = parse_content_name(file, &self.alias)?;
+        let target = parse_content_name(file, &self.target)?;
+        if alias == target {
+            return Err(ContentError::invalid(
+                file,
+                &alias,
+                "an alias cannot redirect to itself",
+            ));
+        }
+        Ok(AliasDefinition { alias, target })
+    }
+}
+
+/// Loads and merges the alias definitions of a registry from the `aliases/<registry_directory>` directory of the given
+/// content packs. Each file holds a list of [`AliasDefinitionFile`]s, a later pack can redirect an alias elsewhere.
+/// Targets may be other aliases, they are resolved when the aliases are added with [`crate::registry::Registry::add_aliases`].
+pub fn load_alias_definitions(
+    packs: &[ContentPack],
+    registry_directory: &str,
+) -> Result<Vec<AliasDefinition>, ContentError> {
+    let directory = format!("{ALIASES_DIRECTORY}/{registry_directory}");
+    merge_content_by(
+        packs,
+        &directory,
+        |definition: &AliasDefinition| definition.alias.clone(),
+        |file, contents| {
+            let definitions: Vec<AliasDefinitionFile> = parse_content_file(file, contents)?;
+            definitions
+                .into_iter()
+                .map(|definition| definition.into_definition(file))
+                .collect()
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_aliases() {
+        let packs = [
+            ContentPack::new("base").with_file(
+                "aliases/blocks/renames.ron",
+                r#"[(alias: "cobble", target: "cobblestone"), (alias: "grass_block", target: "grass")]"#,
+            ),
+            ContentPack::new("mod").with_file(
+                "aliases/blocks/renames.ron",
+                r#"[(alias: "cobble", target: "mod:rubble")]"#,
+            ),
+        ];
+        let aliases = load_alias_definitions(&packs, "blocks").unwrap();
+        assert_eq!(
+            aliases,
+            vec![
+                AliasDefinition {
+                    alias: RegistryName::gs("cobble"),
+                    target: RegistryName::new("mod", "rubble"),
+                },
+                AliasDefinition {
+                    alias: RegistryName::gs("grass_block"),
+                    target: RegistryName::gs("grass"),
+                },
+            ]
+        );
+
+        let error = load_alias_definitions(
+            &[ContentPack::new("base").with_file("aliases/blocks/renames.ron", r#"[(alias: "a", target: "a")]"#)],
+            "blocks",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "base/aliases/blocks/renames.ron: gs:a: an alias cannot redirect to itself"
+        );
+    }
+}
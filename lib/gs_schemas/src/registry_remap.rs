@@ -0,0 +1,231 @@
+//! Translation of registry IDs stored in savefiles written with different registry contents (e.g. before a content
+//! pack was added or removed) into the IDs of the current registry.
+
+use thiserror::Error;
+
+use crate::registry::{Registry, RegistryDeserializationError, RegistryId, RegistryName, RegistryObject};
+use crate::registry_aliases::ResolvedAlias;
+use crate::registry_tags::RegistryIdSet;
+
+/// What to do with saved objects that no longer exist in the current registry.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum MissingEntryPolicy {
+    /// Refuse to load the saved data.
+    #[default]
+    Error,
+    /// Replace the missing objects with the given object of the current registry, e.g. a placeholder "unknown block".
+    Replace(RegistryName),
+}
+
+/// Error from building a [`RegistryIdRemap`].
+#[derive(Debug, Error)]
+pub enum RegistryRemapError {
+    /// The saved mapping bundle is malformed.
+    #[error("The saved registry IDs could not be read: {0}")]
+    Deserialization(#[from] RegistryDeserializationError),
+    /// Some saved objects are missing from the current registry and the policy is [`MissingEntryPolicy::Error`].
+    #[error("The current registry is missing the following saved names: {0:?}")]
+    MissingEntries(Vec<RegistryName>),
+    /// The replacement object of [`MissingEntryPolicy::Replace`] is not in the current registry.
+    #[error("The replacement {0} for missing registry entries is not in the current registry")]
+    UnknownReplacement(RegistryName),
+}
+
+/// A table from the registry IDs of a savefile to the IDs of the same objects in the current registry.
+/// Applied to paletted chunk data by translating only the palette, see [`crate::voxel::chunk::Chunk::remap_block_ids`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegistryIdRemap {
+    /// Indexed by the saved raw ID, `None` for IDs that were not in the saved registry.
+    table: Vec<Option<RegistryId>>,
+    /// Saved IDs of objects replaced according to [`MissingEntryPolicy::Replace`].
+    replaced: RegistryIdSet,
+    /// Names of the objects replaced according to [`MissingEntryPolicy::Replace`].
+    replaced_names: Vec<RegistryName>,
+    /// Saved names found through aliases of the current registry.
+    resolved_aliases: Vec<ResolvedAlias>,
+}
+
+impl RegistryIdRemap {
+    /// Builds the remap table from the mapping bundle stored in a savefile to the given current registry.
+    pub fn new<Object: RegistryObject>(
+        saved: &crate::schemas::game_types_capnp::registry_id_mapping_bundle::Reader,
+        current: &Registry<Object>,
+        policy: &MissingEntryPolicy,
+    ) -> Result<Self, RegistryRemapError> {
+        let ids = saved.get_ids().map_err(RegistryDeserializationError::from)?;
+        let nss = saved.get_nss().map_err(RegistryDeserializationError::from)?;
+        let keys = saved.get_keys().map_err(RegistryDeserializationError::from)?;
+        if ids.len() != nss.len() || keys.len() != nss.len() {
+            return Err(RegistryDeserializationError::MismatchedArrayLengths.into());
+        }
+
+        let mut remap = Self::default();
+        let mut missing_entries = Vec::new();
+        for idx in 0..ids.len() {
+            let saved_id = RegistryId::try_from(ids.get(idx)).or(Err(RegistryDeserializationError::IllegalID))?;
+            let ns = nss.get(idx).map_err(RegistryDeserializationError::from)?;
+            let key = keys.get(idx).map_err(RegistryDeserializationError::from)?;
+            let name = RegistryName::new(
+                ns.to_str().map_err(RegistryDeserializationError::from)?,
+                key.to_str().map_err(RegistryDeserializationError::from)?,
+            );
+
+            let current_id = match current.lookup_name_to_object(name.as_ref()) {
+                Some((id, object)) => {
+                    if object.registry_name() != name.as_ref() {
+                        remap.resolved_aliases.push(ResolvedAlias {
+                            target: object.registry_name().to_owned(),
+                            alias: name,
+                        });
+                    }
+                    id
+                }
+                None => match policy {
+                    MissingEntryPolicy::Error => {
+                        missing_entries.push(name);
+                        continue;
+                    }
+                    MissingEntryPolicy::Replace(replacement) => {
+                        let (id, _) = current
+                            .lookup_name_to_object(replacement.as_ref())
+                            .ok_or_else(|| RegistryRemapError::UnknownReplacement(replacement.clone()))?;
+                        remap.replaced.insert(saved_id);
+                        remap.replaced_names.push(name);
+                        id
+                    }
+                },
+            };
+            remap.insert(saved_id, current_id);
+        }
+
+        if !missing_entries.is_empty() {
+            return Err(RegistryRemapError::MissingEntries(missing_entries));
+        }
+        Ok(remap)
+    }
+
+    /// Maps the given saved ID to the given current ID.
+    pub(crate) fn insert(&mut self, saved: RegistryId, current: RegistryId) {
+        let raw_id = saved.0.get() as usize;
+        if self.table.len() <= raw_id {
+            self.table.resize(raw_id + 1, None);
+        }
+        self.table[raw_id] = Some(current);
+    }
+
+    /// The current ID of the object with the given saved ID, `None` if the ID was not in the saved registry.
+    pub fn get(&self, saved: RegistryId) -> Option<RegistryId> {
+        self.table.get(saved.0.get() as usize).copied().flatten()
+    }
+
+    /// Checks if the object with the given saved ID is missing from the current registry and got replaced.
+    pub fn is_replaced(&self, saved: RegistryId) -> bool {
+        self.replaced.contains(saved)
+    }
+
+    /// The names of the saved objects that are missing from the current registry and got replaced.
+    pub fn replaced_names(&self) -> &[RegistryName] {
+        &self.replaced_names
+    }
+
+    /// The saved names that were found through aliases of the current registry.
+    pub fn resolved_aliases(&self) -> &[ResolvedAlias] {
+        &self.resolved_aliases
+    }
+
+    /// Checks if every saved ID maps to itself, in which case the saved data can be used as-is.
+    pub fn is_identity(&self) -> bool {
+        self.table
+            .iter()
+            .enumerate()
+            .all(|(raw_id, id)| id.map_or(true, |id| id.0.get() as usize == raw_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, Eq, PartialEq, Debug, Hash)]
+    struct DummyObject(RegistryName);
+
+    impl RegistryObject for DummyObject {
+        fn registry_name(&self) -> crate::registry::RegistryNameRef {
+            self.0.as_ref()
+        }
+    }
+
+    fn registry(names: &[&str]) -> Registry<DummyObject> {
+        let mut registry = Registry::default();
+        for &name in names {
+            registry.push_object(DummyObject(RegistryName::gs(name))).unwrap();
+        }
+        registry
+    }
+
+    fn remap(
+        saved: &Registry<DummyObject>,
+        current: &Registry<DummyObject>,
+        policy: &MissingEntryPolicy,
+    ) -> Result<RegistryIdRemap, RegistryRemapError> {
+        let mut message = capnp::message::Builder::new_default();
+        let mut bundle = message.init_root::<crate::schemas::game_types_capnp::registry_id_mapping_bundle::Builder>();
+        saved.serialize_ids(&mut bundle);
+        let bundle = bundle.into_reader();
+        RegistryIdRemap::new(&bundle, current, policy)
+    }
+
+    fn id(raw: u32) -> RegistryId {
+        RegistryId::try_from(raw).unwrap()
+    }
+
+    #[test]
+    fn remap_ids() {
+        let saved = registry(&["stone", "dirt", "ore"]);
+
+        let same = remap(&saved, &saved, &MissingEntryPolicy::Error).unwrap();
+        assert!(same.is_identity());
+
+        let current = registry(&["unknown", "dirt", "stone"]);
+        assert!(matches!(
+            remap(&saved, &current, &MissingEntryPolicy::Error),
+            Err(RegistryRemapError::MissingEntries(missing)) if missing == vec![RegistryName::gs("ore")]
+        ));
+        assert!(matches!(
+            remap(&saved, &current, &MissingEntryPolicy::Replace(RegistryName::gs("air"))),
+            Err(RegistryRemapError::UnknownReplacement(_))
+        ));
+
+        let remapped = remap(
+            &saved,
+            &current,
+            &MissingEntryPolicy::Replace(RegistryName::gs("unknown")),
+        )
+        .unwrap();
+        assert!(!remapped.is_identity());
+        assert_eq!(remapped.get(id(1)), Some(id(3)));
+        assert_eq!(remapped.get(id(2)), Some(id(2)));
+        assert_eq!(remapped.get(id(3)), Some(id(1)));
+        assert_eq!(remapped.get(id(4)), None);
+        assert!(remapped.is_replaced(id(3)));
+        assert!(!remapped.is_replaced(id(1)));
+        assert_eq!(remapped.replaced_names(), &[RegistryName::gs("ore")]);
+
+        let mut renamed = registry(&["stone", "dirt", "iron_ore"]);
+        renamed
+            .add_alias(
+                RegistryName::gs("ore"),
+                crate::registry::RegistryNameRef::gs("iron_ore"),
+            )
+            .unwrap();
+        let aliased = remap(&saved, &renamed, &MissingEntryPolicy::Error).unwrap();
+        assert!(aliased.is_identity());
+        assert_eq!(
+            aliased.resolved_aliases(),
+            &[ResolvedAlias {
+                alias: RegistryName::gs("ore"),
+                target: RegistryName::gs("iron_ore"),
+            }]
+        );
+    }
+}
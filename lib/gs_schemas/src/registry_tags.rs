@@ -0,0 +1,389 @@
+//! Tags: named sets of registry objects (e.g. "all the soil blocks"), defined in content packs and resolved into
+//! compact ID bitsets.
+
+use std::num::NonZeroU32;
+
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::content::{parse_content_file, parse_content_name, ContentError, ContentFileRef, ContentPack};
+use crate::registry::{RegistryId, RegistryName};
+
+/// The content pack directory containing the tag definition directories of each registry, e.g. `tags/blocks`.
+pub const TAGS_DIRECTORY: &str = "tags";
+
+/// The prefix marking tag names in the list of tag values.
+pub const NESTED_TAG_PREFIX: char = '#';
+
+/// A set of registry IDs stored as a bitset, with O(1) membership tests.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct RegistryIdSet {
+    words: Vec<u64>,
+}
+
+impl RegistryIdSet {
+    /// Constructs an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn position(id: RegistryId) -> (usize, u64) {
+        let raw = id.0.get() as usize;
+        (raw / 64, 1 << (raw % 64))
+    }
+
+    /// Checks if the ID is in the set.
+    pub fn contains(&self, id: RegistryId) -> bool {
+        let (word, bit) = Self::position(id);
+        self.words.get(word).is_some_and(|w| w & bit != 0)
+    }
+
+    /// Adds the ID to the set, returns `true` if it was not present before.
+    pub fn insert(&mut self, id: RegistryId) -> bool {
+        let (word, bit) = Self::position(id);
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
+        }
+        let absent = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        absent
+    }
+
+    /// Adds all the IDs of the other set to this set.
+    pub fn union_with(&mut self, other: &Self) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// The number of IDs in the set.
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Checks if the set has no IDs.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// Iterates over the IDs in the set in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = RegistryId> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1u64 << bit) != 0)
+                .map(move |bit| RegistryId(NonZeroU32::new((index * 64 + bit) as u32).unwrap()))
+        })
+    }
+}
+
+impl FromIterator<RegistryId> for RegistryIdSet {
+    fn from_iter<T: IntoIterator<Item = RegistryId>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for id in iter {
+            set.insert(id);
+        }
+        set
+    }
+}
+
+/// A value of a tag definition.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TagValue {
+    /// A registry object.
+    Object(RegistryName),
+    /// All the objects of another tag of the same registry.
+    Tag(RegistryName),
+}
+
+/// An unresolved tag, as defined in content packs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagDefinition {
+    /// The tag name.
+    pub name: RegistryName,
+    /// The objects and nested tags in the tag.
+    pub values: Vec<TagValue>,
+}
+
+/// Errors from resolving tag definitions.
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum RegistryTagError {
+    /// A tag lists an object that is not in the registry.
+    #[error("Tag {tag} contains {object}, which is not registered")]
+    UnknownObject {
+        /// The tag name.
+        tag: RegistryName,
+        /// The missing object name.
+        object: RegistryName,
+    },
+    /// A tag includes a tag that is not defined.
+    #[error("Tag {tag} includes the undefined tag #{nested}")]
+    UnknownTag {
+        /// The tag name.
+        tag: RegistryName,
+        /// The missing tag name.
+        nested: RegistryName,
+    },
+    /// A tag includes itself through nested tags.
+    #[error("Tag {tag} includes itself through {}", .path.iter().map(|t| format!("#{t}")).collect::<Vec<_>>().join(" -> "))]
+    Cycle {
+        /// The tag name.
+        tag: RegistryName,
+        /// The chain of nested tags leading back to the tag.
+        path: Vec<RegistryName>,
+    },
+    /// A tag is defined twice.
+    #[error("Tag {tag} is defined twice")]
+    Duplicate {
+        /// The tag name.
+        tag: RegistryName,
+    },
+}
+
+/// Resolves the nested tags of the given definitions, `lookup` finds the ID of an object name in the registry.
+pub(crate) fn resolve_tag_definitions(
+    definitions: &[TagDefinition],
+    lookup: impl Fn(&RegistryName) -> Option<RegistryId>,
+) -> Result<HashMap<RegistryName, RegistryIdSet>, RegistryTagError> {
+    let mut indices: HashMap<&RegistryName, usize> = HashMap::with_capacity(definitions.len());
+    for (index, definition) in definitions.iter().enumerate() {
+        if indices.insert(&definition.name, index).is_some() {
+            return Err(RegistryTagError::Duplicate {
+                tag: definition.name.clone(),
+            });
+        }
+    }
+
+    enum State {
+        Unvisited,
+        Visiting,
+        Resolved(RegistryIdSet),
+    }
+    struct Resolver<'d, L> {
+        definitions: &'d [TagDefinition],
+        indices: HashMap<&'d RegistryName, usize>,
+        states: Vec<State>,
+        stack: Vec<usize>,
+        lookup: L,
+    }
+    impl<'d, L: Fn(&RegistryName) -> Option<RegistryId>> Resolver<'d, L> {
+        fn resolve(&mut self, index: usize) -> Result<RegistryIdSet, RegistryTagError> {
+            match &self.states[index] {
+                State::Resolved(set) => return Ok(set.clone()),
+                State::Visiting => {
+                    let start = self.stack.iter().position(|&i| i == index).unwrap();
+                    let mut path: Vec<RegistryName> = self.stack[start + 1..]
+                        .iter()
+                        .map(|&i| self.definitions[i].name.clone())
+                        .collect();
+                    path.push(self.definitions[index].name.clone());
+                    return Err(RegistryTagError::Cycle {
+                        tag: self.definitions[index].name.clone(),
+                        path,
+                    });
+                }
+                State::Unvisited => {}
+            }
+            self.states[index] = State::Visiting;
+            self.stack.push(index);
+            let definitions = self.definitions;
+            let definition = &definitions[index];
+            let mut set = RegistryIdSet::new();
+            for value in &definition.values {
+                match value {
+                    TagValue::Object(object) => {
+                        let id = (self.lookup)(object).ok_or_else(|| RegistryTagError::UnknownObject {
+                            tag: definition.name.clone(),
+                            object: object.clone(),
+                        })?;
+                        set.insert(id);
+                    }
+                    TagValue::Tag(nested) => {
+                        let &nested_index = self.indices.get(nested).ok_or_else(|| RegistryTagError::UnknownTag {
+                            tag: definition.name.clone(),
+                            nested: nested.clone(),
+                        })?;
+                        set.union_with(&self.resolve(nested_index)?);
+                    }
+                }
+            }
+            self.stack.pop();
+            self.states[index] = State::Resolved(set.clone());
+            Ok(set)
+        }
+    }
+
+    let mut resolver = Resolver {
+        definitions,
+        indices,
+        states: definitions.iter().map(|_| State::Unvisited).collect(),
+        stack: Vec::new(),
+        lookup,
+    };
+    let mut tags = HashMap::with_capacity(definitions.len());
+    for (index, definition) in definitions.iter().enumerate() {
+        tags.insert(definition.name.clone(), resolver.resolve(index)?);
+    }
+    Ok(tags)
+}
+
+/// A tag definition as written in a content file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagDefinitionFile {
+    /// The tag name, `namespace:key` or just `key` for the gs namespace
+    pub name: String,
+    /// Object names, and tag names prefixed with `#`
+    pub values: Vec<String>,
+    /// If set, replaces the values from the previous packs instead of adding to them
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl TagDefinitionFile {
+    /// Validates the definition read from the given file.
+    pub fn into_definition(self, file: &ContentFileRef) -> Result<TagDefinition, ContentError> {
+        let name = parse_content_name(file, &self.name)?;
+        let values = self
+            .values
+            .iter()
+            .map(|value| match value.strip_prefix(NESTED_TAG_PREFIX) {
+                Some(tag) => parse_content_name(file, tag).map(TagValue::Tag),
+                None => parse_content_name(file, value).map(TagValue::Object),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TagDefinition { name, values })
+    }
+}
+
+/// Loads and merges the tag definitions of a registry from the `tags/<registry_directory>` directory of the given
+/// content packs. Each file holds a list of [`TagDefinitionFile`]s. Unlike other content, the values of a tag defined
+/// by multiple packs are combined, unless a later pack sets `replace`.
+pub fn load_tag_definitions(
+    packs: &[ContentPack],
+    registry_directory: &str,
+) -> Result<Vec<TagDefinition>, ContentError> {
+    let directory = format!("{TAGS_DIRECTORY}/{registry_directory}");
+    let mut merged: Vec<TagDefinition> = Vec::new();
+    // name -> (index in merged, pack index, defining file)
+    let mut defined: HashMap<RegistryName, (usize, usize, ContentFileRef)> = HashMap::new();
+    for (pack_index, pack) in packs.iter().enumerate() {
+        for (file, contents) in pack.files_in(&directory) {
+            let definitions: Vec<TagDefinitionFile> = parse_content_file(&file, contents)?;
+            for definition in definitions {
+                let replace = definition.replace;
+                let definition = definition.into_definition(&file)?;
+                match defined.get_mut(&definition.name) {
+                    Some((_, previous_pack, first)) if *previous_pack == pack_index => {
+                        return Err(ContentError::Duplicate {
+                            file,
+                            name: definition.name,
+                            first: Box::new(first.clone()),
+                        });
+                    }
+                    Some((index, previous_pack, previous_file)) => {
+                        let existing = &mut merged[*index];
+                        if replace {
+                            existing.values = definition.values;
+                        } else {
+                            existing.values.extend(definition.values);
+                        }
+                        *previous_pack = pack_index;
+                        *previous_file = file.clone();
+                    }
+                    None => {
+                        defined.insert(definition.name.clone(), (merged.len(), pack_index, file.clone()));
+                        merged.push(definition);
+                    }
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn id(raw: u32) -> RegistryId {
+        RegistryId::try_from(raw).unwrap()
+    }
+
+    #[test]
+    fn id_sets() {
+        let mut set = RegistryIdSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(id(3)));
+        assert!(!set.insert(id(3)));
+        assert!(set.insert(id(130)));
+        assert!(set.contains(id(3)) && set.contains(id(130)));
+        assert!(!set.contains(id(4)) && !set.contains(id(1000)));
+        let other: RegistryIdSet = [id(1), id(64)].into_iter().collect();
+        set.union_with(&other);
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![id(1), id(3), id(64), id(130)]);
+    }
+
+    fn load(packs: &[ContentPack]) -> Result<HashMap<RegistryName, Vec<u32>>, String> {
+        let definitions = load_tag_definitions(packs, "things").map_err(|e| e.to_string())?;
+        let lookup = |name: &RegistryName| match name.key.as_str() {
+            "a" => Some(id(1)),
+            "b" => Some(id(2)),
+            "c" => Some(id(3)),
+            _ => None,
+        };
+        let tags = resolve_tag_definitions(&definitions, lookup).map_err(|e| e.to_string())?;
+        Ok(tags
+            .into_iter()
+            .map(|(name, set)| (name, set.iter().map(|id| id.0.get()).collect()))
+            .collect())
+    }
+
+    #[test]
+    fn nested_and_merged_tags() {
+        let base = ContentPack::new("base").with_file(
+            "tags/things/test.ron",
+            r##"[
+                (name: "ab", values: ["a", "gs:b"]),
+                (name: "all", values: ["#ab", "#gs:just_c"]),
+                (name: "just_c", values: ["c"]),
+            ]"##,
+        );
+        let tags = load(&[base.clone()]).unwrap();
+        assert_eq!(tags[&RegistryName::gs("all")], vec![1, 2, 3]);
+        assert_eq!(tags[&RegistryName::gs("ab")], vec![1, 2]);
+
+        let addon = ContentPack::new("addon").with_file(
+            "tags/things/test.ron",
+            r##"[(name: "just_c", values: ["a"]), (name: "ab", values: ["c"], replace: true)]"##,
+        );
+        let tags = load(&[base, addon]).unwrap();
+        assert_eq!(tags[&RegistryName::gs("just_c")], vec![1, 3]);
+        assert_eq!(tags[&RegistryName::gs("ab")], vec![3]);
+        assert_eq!(tags[&RegistryName::gs("all")], vec![1, 3]);
+    }
+
+    #[test]
+    fn invalid_tags() {
+        let pack = |contents: &str| [ContentPack::new("base").with_file("tags/things/test.ron", contents)];
+        assert_eq!(
+            load(&pack(r##"[(name: "x", values: ["a", "d"])]"##)).unwrap_err(),
+            "Tag gs:x contains gs:d, which is not registered"
+        );
+        assert_eq!(
+            load(&pack(r##"[(name: "x", values: ["#y"])]"##)).unwrap_err(),
+            "Tag gs:x includes the undefined tag #gs:y"
+        );
+        assert_eq!(
+            load(&pack(
+                r##"[(name: "x", values: ["#y"]), (name: "y", values: ["#z"]), (name: "z", values: ["#x"])]"##
+            ))
+            .unwrap_err(),
+            "Tag gs:x includes itself through #gs:y -> #gs:z -> #gs:x"
+        );
+    }
+}
@@ -33,6 +33,8 @@ pub mod network_capnp {
     include!(concat!(env!("CARGO_MANIFEST_DIR"), "/capnp-generated/network_capnp.rs"));
 }
 
+pub mod stream_packet;
+
 /// Helpers for (de)serializing UUIDs.
 pub trait SchemaUuidExt {
     /// Serializes a UUID into a capnp message.
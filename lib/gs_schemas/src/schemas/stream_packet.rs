@@ -0,0 +1,129 @@
+//! Encoding of asynchronous stream packets: capnp packing with optional compression on top.
+//!
+//! Every packet starts with a single [`PacketCompression`] byte describing how the rest of it is encoded,
+//! so the receiver can decode packets regardless of the compression negotiated for the connection.
+
+use std::io::{Read, Write};
+
+use capnp::message::{Allocator, Builder, Reader, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use thiserror::Error;
+
+use crate::schemas::network_capnp::PacketCompression;
+
+/// Packed messages smaller than this many bytes are never compressed, the overhead would outweigh the savings.
+pub const MIN_COMPRESSED_PACKET_SIZE: usize = 256;
+/// Maximum size of a decompressed packet, to guard against decompression bombs.
+pub const MAX_DECOMPRESSED_PACKET_SIZE: usize = 64 * 1024 * 1024;
+
+/// Error during stream packet decoding.
+#[derive(Debug, Error)]
+pub enum StreamPacketError {
+    /// The packet has no header byte.
+    #[error("Empty stream packet")]
+    Empty,
+    /// Unknown compression kind in the header.
+    #[error("Unknown stream packet compression {0}")]
+    UnknownCompression(u8),
+    /// The decompressed packet is larger than [`MAX_DECOMPRESSED_PACKET_SIZE`].
+    #[error("Stream packet too large after decompression")]
+    TooLarge,
+    /// Decompression failure.
+    #[error("Stream packet decompression error {0}")]
+    Io(#[from] std::io::Error),
+    /// Low level data encoding error.
+    #[error("Low level data encoding error {0}")]
+    SchemaError(#[from] capnp::Error),
+}
+
+/// Serializes the message into a stream packet, using the given compression if worthwhile.
+pub fn encode_stream_packet<A: Allocator>(message: &Builder<A>, compression: PacketCompression) -> Vec<u8> {
+    let mut packed = vec![PacketCompression::None as u8];
+    capnp::serialize_packed::write_message(&mut packed, message).expect("Writing to a Vec cannot fail");
+    match compression {
+        PacketCompression::None => packed,
+        _ if packed.len() < MIN_COMPRESSED_PACKET_SIZE => packed,
+        PacketCompression::Deflate => {
+            let mut compressed = Vec::with_capacity(packed.len() / 2);
+            compressed.push(PacketCompression::Deflate as u8);
+            let mut encoder = DeflateEncoder::new(compressed, flate2::Compression::fast());
+            encoder.write_all(&packed[1..]).expect("Writing to a Vec cannot fail");
+            let compressed = encoder.finish().expect("Writing to a Vec cannot fail");
+            // Incompressible data is sent as-is.
+            if compressed.len() < packed.len() {
+                compressed
+            } else {
+                packed
+            }
+        }
+    }
+}
+
+/// Deserializes a stream packet encoded with [`encode_stream_packet`].
+pub fn decode_stream_packet(packet: &[u8], options: ReaderOptions) -> Result<Reader<OwnedSegments>, StreamPacketError> {
+    let (&header, payload) = packet.split_first().ok_or(StreamPacketError::Empty)?;
+    let compression =
+        PacketCompression::try_from(header as u16).map_err(|_| StreamPacketError::UnknownCompression(header))?;
+    match compression {
+        PacketCompression::None => {
+            let mut payload = payload;
+            Ok(capnp::serialize_packed::read_message(&mut payload, options)?)
+        }
+        PacketCompression::Deflate => {
+            let mut packed = Vec::new();
+            DeflateDecoder::new(payload)
+                .take(MAX_DECOMPRESSED_PACKET_SIZE as u64 + 1)
+                .read_to_end(&mut packed)?;
+            if packed.len() > MAX_DECOMPRESSED_PACKET_SIZE {
+                return Err(StreamPacketError::TooLarge);
+            }
+            let mut packed = packed.as_slice();
+            Ok(capnp::serialize_packed::read_message(&mut packed, options)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schemas::game_types_capnp::{i_vec3, registry_name};
+
+    #[test]
+    fn stream_packet_roundtrip() {
+        let mut message = Builder::new_default();
+        let mut root = message.init_root::<i_vec3::Builder>();
+        root.set_x(1);
+        root.set_y(-2);
+        root.set_z(3);
+        for compression in [PacketCompression::None, PacketCompression::Deflate] {
+            let packet = encode_stream_packet(&message, compression);
+            let reader = decode_stream_packet(&packet, ReaderOptions::new()).unwrap();
+            let root = reader.get_root::<i_vec3::Reader>().unwrap();
+            assert_eq!((root.get_x(), root.get_y(), root.get_z()), (1, -2, 3));
+        }
+
+        // A payload over the compression threshold is actually deflated.
+        let key = "stone".repeat(MIN_COMPRESSED_PACKET_SIZE);
+        let mut message = Builder::new_default();
+        let mut root = message.init_root::<registry_name::Builder>();
+        root.set_ns("gs");
+        root.set_key(key.as_str());
+        let packet = encode_stream_packet(&message, PacketCompression::Deflate);
+        assert_eq!(packet[0], PacketCompression::Deflate as u8);
+        assert!(packet.len() < key.len());
+        let reader = decode_stream_packet(&packet, ReaderOptions::new()).unwrap();
+        let root = reader.get_root::<registry_name::Reader>().unwrap();
+        assert_eq!(root.get_ns().unwrap().to_str().unwrap(), "gs");
+        assert_eq!(root.get_key().unwrap().to_str().unwrap(), key);
+        assert!(matches!(
+            decode_stream_packet(&[], ReaderOptions::new()),
+            Err(StreamPacketError::Empty)
+        ));
+        assert!(matches!(
+            decode_stream_packet(&[200, 0, 0], ReaderOptions::new()),
+            Err(StreamPacketError::UnknownCompression(200))
+        ));
+    }
+}
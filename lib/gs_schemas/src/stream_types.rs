@@ -0,0 +1,83 @@
+//! Custom network stream types, registered by mods and agreed on by the server and client during the bootstrap.
+//! They are defined in the `streams` directory of content packs, so the server and client get them from the same packs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::content::{merge_content, parse_content_file, parse_content_name, ContentError, ContentPack};
+use crate::registry::{FrozenRegistry, Registry, RegistryName, RegistryNameRef, RegistryObject};
+use crate::schemas::NetworkStreamHeader;
+
+/// The content pack directory containing custom stream type definition files.
+pub const STREAMS_DIRECTORY: &str = "streams";
+
+/// A custom network stream type, opened with a [`NetworkStreamHeader::Custom`] header of the same name.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct StreamTypeDefinition {
+    /// The unique registry name, also used as the stream header.
+    pub name: RegistryName,
+}
+
+/// A registry of the custom network stream types.
+pub type StreamTypeRegistry = Registry<StreamTypeDefinition>;
+/// A finished registry of the custom network stream types.
+pub type FrozenStreamTypeRegistry = FrozenRegistry<StreamTypeDefinition>;
+
+impl RegistryObject for StreamTypeDefinition {
+    fn registry_name(&self) -> RegistryNameRef {
+        self.name.as_ref()
+    }
+}
+
+impl StreamTypeDefinition {
+    /// Constructs a definition of the stream type with the given name.
+    pub fn new(name: RegistryName) -> Self {
+        Self { name }
+    }
+
+    /// The header of streams of this type.
+    pub fn header(&self) -> NetworkStreamHeader {
+        NetworkStreamHeader::Custom(self.name.clone())
+    }
+}
+
+/// A custom stream type definition as written in content files.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamTypeDefinitionFile {
+    /// The registry name, `namespace:key` or just `key` for the gs namespace
+    pub name: String,
+}
+
+/// Loads the custom stream type definitions from all the `streams/*.ron` files of the given content packs, in load
+/// order.
+pub fn load_stream_type_definitions(packs: &[ContentPack]) -> Result<Vec<StreamTypeDefinition>, ContentError> {
+    merge_content(packs, STREAMS_DIRECTORY, |file, contents| {
+        let definitions: Vec<StreamTypeDefinitionFile> = parse_content_file(file, contents)?;
+        definitions
+            .into_iter()
+            .map(|definition| Ok(StreamTypeDefinition::new(parse_content_name(file, &definition.name)?)))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_stream_types() {
+        let packs = [
+            ContentPack::new("base").with_file("streams/base.ron", r#"[(name: "positions")]"#),
+            ContentPack::new("mod").with_file("streams/mod.ron", r#"[(name: "mod:chat"), (name: "positions")]"#),
+        ];
+        let names: Vec<_> = load_stream_type_definitions(&packs)
+            .unwrap()
+            .into_iter()
+            .map(|definition| definition.name)
+            .collect();
+        assert_eq!(names, [RegistryName::gs("positions"), RegistryName::new("mod", "chat")]);
+
+        let invalid = [ContentPack::new("base").with_file("streams/base.ron", r#"[(name: "a", compression: 1)]"#)];
+        assert!(load_stream_type_definitions(&invalid).is_err());
+    }
+}
@@ -3,22 +3,24 @@
 use std::fmt::{Debug, Display};
 use std::hash::{Hash, Hasher};
 
-use bevy_math::DVec2;
 use noise::OpenSimplex;
 use rgb::RGBA8;
 use serde::{Deserialize, Serialize};
 
-use super::{
-    generation::Context,
-    voxeltypes::{BlockEntry, BlockRegistry},
+use self::surface::{CompiledSurfaceRules, SurfaceRules, SurfaceRulesFile};
+use super::voxeltypes::BlockRegistry;
+use crate::content::{
+    merge_content, parse_content_file, parse_content_name, parse_hex_color, ContentError, ContentFileRef, ContentPack,
 };
 use crate::voxel::generation::fbm_noise::Fbm;
+use crate::voxel::generation::noise_expression::{CompiledNoise, NoiseExpression};
 use crate::{
     range::Range,
-    registry::{Registry, RegistryId, RegistryName, RegistryObject},
+    registry::{FrozenRegistry, Registry, RegistryId, RegistryName, RegistryObject},
 };
 
 pub mod biome_map;
+pub mod surface;
 
 /// A biome entry stored in the per-planet biome map.
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
@@ -44,10 +46,11 @@ impl BiomeEntry {
 
 /// A named registry of block definitions.
 pub type BiomeRegistry = Registry<BiomeDefinition>;
+/// A finished biome registry, as shared between systems.
+pub type FrozenBiomeRegistry = FrozenRegistry<BiomeDefinition>;
 
-/// A definition of a biome type, specifying properties such as registry name, shape, textures.
-// TODO fix serialization of `BiomeDefinition`
-#[derive(Clone)]
+/// A definition of a biome type, specifying properties such as registry name, climate, terrain shape and blocks.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BiomeDefinition {
     /// The unique registry name
     pub name: RegistryName,
@@ -61,16 +64,42 @@ pub struct BiomeDefinition {
     pub temperature: Range<f64>,
     /// Moisture of this biome.
     pub moisture: Range<f64>,
-    /// The block placement rule source for this biome.
-    pub rule_source: fn(pos: &bevy_math::IVec3, ctx: &Context, registry: &BlockRegistry) -> Option<BlockEntry>,
-    /// The noise function for this biome.
-    pub surface_noise: fn(pos: DVec2, noise: &Fbm<OpenSimplex>) -> f64,
+    /// The block placement rules for this biome.
+    pub surface_rules: SurfaceRules,
+    /// The terrain height noise for this biome.
+    pub surface_noise: NoiseExpression,
     /// The strength of this biome in the blending step.
     pub blend_influence: f64,
     /// The strength of this biome in the block placement step.
     pub block_influence: f64,
 }
 
+/// The parts of a [`BiomeDefinition`] evaluated during world generation, prepared for fast evaluation.
+#[derive(Clone, Debug)]
+pub struct CompiledBiome {
+    /// The compiled [`BiomeDefinition::surface_rules`].
+    pub surface_rules: CompiledSurfaceRules,
+    /// The compiled [`BiomeDefinition::surface_noise`].
+    pub surface_noise: CompiledNoise,
+    /// The strength of this biome in the blending step.
+    pub blend_influence: f64,
+    /// The strength of this biome in the block placement step.
+    pub block_influence: f64,
+}
+
+impl BiomeDefinition {
+    /// Compiles the biome for world generation, returning the name of the first block missing from the registry on
+    /// failure.
+    pub fn compile(&self, block_registry: &BlockRegistry) -> Result<CompiledBiome, RegistryName> {
+        Ok(CompiledBiome {
+            surface_rules: self.surface_rules.compile(block_registry)?,
+            surface_noise: self.surface_noise.compile(),
+            blend_influence: self.blend_influence,
+            block_influence: self.block_influence,
+        })
+    }
+}
+
 impl Debug for BiomeDefinition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BiomeDefinition").field("id", &self.name).finish()
@@ -116,3 +145,139 @@ pub struct Noises {
 
 /// Name of the default void biome.
 pub const VOID_BIOME_NAME: RegistryName = RegistryName::gs_const("void");
+
+/// The default void biome, placing no blocks.
+pub static VOID_BIOME: BiomeDefinition = BiomeDefinition {
+    name: VOID_BIOME_NAME,
+    representative_color: RGBA8::new(0, 0, 0, 0),
+    can_generate: false,
+    elevation: Range::Closed(-1.0, -1.0),
+    temperature: Range::Closed(-1.0, -1.0),
+    moisture: Range::Closed(-1.0, -1.0),
+    surface_rules: SurfaceRules::EMPTY,
+    surface_noise: NoiseExpression::Constant(0.0),
+    blend_influence: 0.0,
+    block_influence: 0.0,
+};
+
+/// The content pack directory containing biome definition files.
+pub const BIOMES_DIRECTORY: &str = "biomes";
+
+/// A biome definition as written in a content file, see [`BiomeDefinition`] for the meaning of the fields.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BiomeDefinitionFile {
+    /// The registry name, `namespace:key` or just `key` for the gs namespace
+    pub name: String,
+    /// The representative color as `#rrggbb` or `#rrggbbaa`
+    pub color: String,
+    /// Can this biome generate in the world?
+    #[serde(default = "default_true")]
+    pub can_generate: bool,
+    /// Elevation of this biome, any if not given
+    #[serde(default = "full_range")]
+    pub elevation: Range<f64>,
+    /// Temperature of this biome, any if not given
+    #[serde(default = "full_range")]
+    pub temperature: Range<f64>,
+    /// Moisture of this biome, any if not given
+    #[serde(default = "full_range")]
+    pub moisture: Range<f64>,
+    /// The block placement rules for this biome
+    pub surface: SurfaceRulesFile,
+    /// The terrain height noise for this biome
+    pub surface_noise: NoiseExpression,
+    /// The strength of this biome in the blending step
+    #[serde(default = "default_influence")]
+    pub blend_influence: f64,
+    /// The strength of this biome in the block placement step
+    #[serde(default = "default_influence")]
+    pub block_influence: f64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn full_range() -> Range<f64> {
+    Range::Full
+}
+
+fn default_influence() -> f64 {
+    1.0
+}
+
+impl BiomeDefinitionFile {
+    /// Validates the definition read from the given file, checking that the blocks it places exist.
+    pub fn into_definition(
+        self,
+        file: &ContentFileRef,
+        block_registry: &BlockRegistry,
+    ) -> Result<BiomeDefinition, ContentError> {
+        let name = parse_content_name(file, &self.name)?;
+        if name == VOID_BIOME_NAME {
+            return Err(ContentError::invalid(
+                file,
+                &name,
+                "the void biome is built into the engine and cannot be redefined",
+            ));
+        }
+        let representative_color = parse_hex_color(&self.color).map_err(|e| ContentError::invalid(file, &name, e))?;
+        for (climate, range) in [
+            ("elevation", self.elevation),
+            ("temperature", self.temperature),
+            ("moisture", self.moisture),
+        ] {
+            if !range.is_ordered() {
+                return Err(ContentError::invalid(
+                    file,
+                    &name,
+                    format!("the {climate} range {range} has its start after its end"),
+                ));
+            }
+        }
+        for (influence, value) in [
+            ("blend_influence", self.blend_influence),
+            ("block_influence", self.block_influence),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(ContentError::invalid(
+                    file,
+                    &name,
+                    format!("{influence} has to be a non-negative number, got {value}"),
+                ));
+            }
+        }
+        self.surface_noise
+            .validate()
+            .map_err(|e| ContentError::invalid(file, &name, format!("invalid surface_noise: {e}")))?;
+        let surface_rules = self.surface.into_rules(file, name.as_ref(), block_registry)?;
+        Ok(BiomeDefinition {
+            name,
+            representative_color,
+            can_generate: self.can_generate,
+            elevation: self.elevation,
+            temperature: self.temperature,
+            moisture: self.moisture,
+            surface_rules,
+            surface_noise: self.surface_noise,
+            blend_influence: self.blend_influence,
+            block_influence: self.block_influence,
+        })
+    }
+}
+
+/// Loads and merges the biome definitions of the given content packs, not including [`VOID_BIOME`].
+/// Each file in the biomes directory holds a list of [`BiomeDefinitionFile`]s, placing blocks from the given registry.
+pub fn load_biome_definitions(
+    packs: &[ContentPack],
+    block_registry: &BlockRegistry,
+) -> Result<Vec<BiomeDefinition>, ContentError> {
+    merge_content(packs, BIOMES_DIRECTORY, |file, contents| {
+        let definitions: Vec<BiomeDefinitionFile> = parse_content_file(file, contents)?;
+        definitions
+            .into_iter()
+            .map(|definition| definition.into_definition(file, block_registry))
+            .collect()
+    })
+}
@@ -0,0 +1,272 @@
+//! Layered block placement rules of biomes.
+
+use bevy_math::IVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::content::{parse_content_name, ContentError, ContentFileRef};
+use crate::range::Range;
+use crate::registry::{RegistryName, RegistryNameRef};
+use crate::voxel::generation::Context;
+use crate::voxel::voxeltypes::{BlockEntry, BlockRegistry};
+
+/// A layer of blocks placed relative to the terrain surface.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SurfaceLayer {
+    /// The depth below the ground the layer covers: 0 is the topmost ground block and negative depths are above it.
+    pub depth: Range<i32>,
+    /// The height above the sea level the layer covers.
+    pub altitude: Range<i32>,
+    /// The block placed in the layer.
+    pub block: RegistryName,
+}
+
+/// The block placement rules of a biome, the first layer containing a position decides the block placed there.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SurfaceRules {
+    /// The layers, in decreasing order of priority.
+    pub layers: Vec<SurfaceLayer>,
+}
+
+impl SurfaceRules {
+    /// No layers, nothing is placed.
+    pub const EMPTY: Self = Self { layers: Vec::new() };
+
+    /// Resolves the block names of the layers, returning the name of the first block missing from the registry on
+    /// failure.
+    pub fn compile(&self, block_registry: &BlockRegistry) -> Result<CompiledSurfaceRules, RegistryName> {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| -> Result<CompiledSurfaceLayer, RegistryName> {
+                let (id, _) = block_registry
+                    .lookup_name_to_object(layer.block.as_ref())
+                    .ok_or_else(|| layer.block.clone())?;
+                Ok(CompiledSurfaceLayer {
+                    depth: layer.depth,
+                    altitude: layer.altitude,
+                    block: BlockEntry::new(id, 0),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(CompiledSurfaceRules { layers })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CompiledSurfaceLayer {
+    depth: Range<i32>,
+    altitude: Range<i32>,
+    block: BlockEntry,
+}
+
+/// [`SurfaceRules`] with the blocks resolved to registry IDs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompiledSurfaceRules {
+    layers: Vec<CompiledSurfaceLayer>,
+}
+
+impl CompiledSurfaceRules {
+    /// The block to place at the given position, if any.
+    pub fn block_at(&self, pos: IVec3, context: &Context) -> Option<BlockEntry> {
+        self.block_at_depth(context.ground_y - pos.y, pos.y - context.sea_level)
+    }
+
+    /// The block to place at the given depth below the ground and altitude above the sea level, if any.
+    pub fn block_at_depth(&self, depth: i32, altitude: i32) -> Option<BlockEntry> {
+        self.layers
+            .iter()
+            .find(|layer| layer.depth.contains(depth) && layer.altitude.contains(altitude))
+            .map(|layer| layer.block)
+    }
+}
+
+/// Surface rules as written in a content file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SurfaceRulesFile {
+    /// The usual terrain layering: a top block (replaced by `snow` from `snow_line` altitude up), `filler_depth`
+    /// filler blocks below it and stone underneath.
+    Layered {
+        /// The topmost ground block
+        top: String,
+        /// The block below the top block
+        filler: String,
+        /// The thickness of the filler layer
+        filler_depth: i32,
+        /// The block below the filler layer
+        stone: String,
+        /// The altitude from which `snow` replaces the top block
+        #[serde(default)]
+        snow_line: Option<i32>,
+        /// The top block above the snow line
+        #[serde(default)]
+        snow: Option<String>,
+    },
+    /// Explicit layers, in decreasing order of priority.
+    Layers(Vec<SurfaceLayerFile>),
+}
+
+/// A surface layer as written in a content file, see [`SurfaceLayer`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SurfaceLayerFile {
+    /// The depth below the ground the layer covers, everything if not given
+    #[serde(default = "full_range")]
+    pub depth: Range<i32>,
+    /// The height above the sea level the layer covers, everything if not given
+    #[serde(default = "full_range")]
+    pub altitude: Range<i32>,
+    /// The block placed in the layer
+    pub block: String,
+}
+
+fn full_range() -> Range<i32> {
+    Range::Full
+}
+
+impl SurfaceRulesFile {
+    /// Validates the rules of the named biome read from the given file, checking that all the blocks exist.
+    pub fn into_rules(
+        self,
+        file: &ContentFileRef,
+        biome: RegistryNameRef,
+        block_registry: &BlockRegistry,
+    ) -> Result<SurfaceRules, ContentError> {
+        let layers = match self {
+            Self::Layered {
+                top,
+                filler,
+                filler_depth,
+                stone,
+                snow_line,
+                snow,
+            } => {
+                if filler_depth < 0 {
+                    return Err(ContentError::invalid(file, biome, "filler_depth cannot be negative"));
+                }
+                let surface = Range::ClosedInclusive(0, 0);
+                let mut layers = Vec::with_capacity(4);
+                match (snow_line, snow) {
+                    (Some(snow_line), Some(snow)) => layers.push(SurfaceLayerFile {
+                        depth: surface,
+                        altitude: Range::Left(snow_line),
+                        block: snow,
+                    }),
+                    (None, None) => {}
+                    _ => {
+                        return Err(ContentError::invalid(
+                            file,
+                            biome,
+                            "snow_line and snow have to be given together",
+                        ))
+                    }
+                }
+                layers.push(SurfaceLayerFile {
+                    depth: surface,
+                    altitude: Range::Full,
+                    block: top,
+                });
+                if filler_depth > 0 {
+                    layers.push(SurfaceLayerFile {
+                        depth: Range::Closed(1, filler_depth + 1),
+                        altitude: Range::Full,
+                        block: filler,
+                    });
+                }
+                layers.push(SurfaceLayerFile {
+                    depth: Range::Left(filler_depth + 1),
+                    altitude: Range::Full,
+                    block: stone,
+                });
+                layers
+            }
+            Self::Layers(layers) => layers,
+        };
+        let layers = layers
+            .into_iter()
+            .map(|layer| {
+                if !layer.depth.is_ordered() || !layer.altitude.is_ordered() {
+                    return Err(ContentError::invalid(
+                        file,
+                        biome,
+                        format!(
+                            "the layer of {} has a range with its start after its end (depth {}, altitude {})",
+                            layer.block, layer.depth, layer.altitude
+                        ),
+                    ));
+                }
+                let block = parse_content_name(file, &layer.block)?;
+                if block_registry.lookup_name_to_object(block.as_ref()).is_none() {
+                    return Err(ContentError::invalid(file, biome, format!("unknown block {block}")));
+                }
+                Ok(SurfaceLayer {
+                    depth: layer.depth,
+                    altitude: layer.altitude,
+                    block,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SurfaceRules { layers })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::content::parse_content_file;
+    use crate::voxel::voxeltypes::{BlockDefinition, EMPTY_BLOCK};
+
+    fn rules(contents: &str) -> Result<SurfaceRules, ContentError> {
+        let mut block_registry = BlockRegistry::default();
+        for name in ["grass", "dirt", "stone", "snow"] {
+            block_registry
+                .push_object(BlockDefinition {
+                    name: RegistryName::gs(name),
+                    ..EMPTY_BLOCK.clone()
+                })
+                .unwrap();
+        }
+        let file = ContentFileRef {
+            pack: String::from("base"),
+            path: String::from("biomes/test.ron"),
+        };
+        let rules: SurfaceRulesFile = parse_content_file(&file, contents)?;
+        rules.into_rules(&file, RegistryName::gs("test").as_ref(), &block_registry)
+    }
+
+    #[test]
+    fn layered_rules() {
+        let layers = rules(
+            r#"Layered(top: "grass", filler: "dirt", filler_depth: 3, stone: "stone", snow_line: Some(80), snow: Some("snow"))"#,
+        )
+        .unwrap()
+        .layers;
+        let summary: Vec<(Range<i32>, Range<i32>, String)> = layers
+            .into_iter()
+            .map(|layer| (layer.depth, layer.altitude, layer.block.key.to_string()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Range::ClosedInclusive(0, 0), Range::Left(80), String::from("snow")),
+                (Range::ClosedInclusive(0, 0), Range::Full, String::from("grass")),
+                (Range::Closed(1, 4), Range::Full, String::from("dirt")),
+                (Range::Left(4), Range::Full, String::from("stone")),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let error = |contents| rules(contents).unwrap_err().to_string();
+        assert_eq!(
+            error(r#"Layers([(depth: Left(1), block: "gravel")])"#),
+            "base/biomes/test.ron: gs:test: unknown block gs:gravel"
+        );
+        assert!(error(
+            r#"Layered(top: "grass", filler: "dirt", filler_depth: 3, stone: "stone", snow_line: Some(80))"#
+        )
+        .contains("snow_line and snow"));
+        assert!(error(r#"Layers([(depth: Closed(3, 1), block: "dirt")])"#).contains("start after its end"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Per-chunk records of the entities managing placeholder blocks.
+//!
+//! A placeholder block reserves its block space for an ECS entity implementing it, for example a container.
+//! The chunk stores a record for each such block, with the encoded components of the entity, so that the entities
+//! get saved and sent to clients along with the chunk, and spawned again when the chunk is loaded.
+
+use crate::coordinates::InChunkPos;
+use crate::schemas::game_types_capnp::block_entity_data;
+use crate::voxel::chunk::ChunkDeserializationError;
+use crate::voxel::chunk_storage::SparseStorage;
+
+/// The maximum number of components stored for a single block entity.
+pub const MAX_BLOCK_ENTITY_COMPONENTS: u32 = 64;
+
+/// Per-block storage of the block entity records of a chunk, only the placeholder blocks have a record.
+pub type BlockEntityStorage = SparseStorage<Option<BlockEntityRecord>>;
+
+/// A single encoded component of a block entity.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct BlockEntityComponent {
+    /// The name the component type is registered with for replication.
+    pub name: String,
+    /// The encoded value.
+    pub data: Vec<u8>,
+}
+
+/// The state of the entity managing a placeholder block.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct BlockEntityRecord {
+    /// The bits of the live entity on this side of the game, if it's spawned.
+    /// Not serialized, entity IDs are only valid within a single running game.
+    pub entity: Option<u64>,
+    /// The encoded components of the entity, the entity is spawned again from these after loading the chunk.
+    pub components: Vec<BlockEntityComponent>,
+}
+
+impl BlockEntityRecord {
+    /// Creates a record for a not yet spawned entity with the given components.
+    pub fn new(components: Vec<BlockEntityComponent>) -> Self {
+        Self {
+            entity: None,
+            components,
+        }
+    }
+
+    /// Writes the record of the block at `position` to the given builder.
+    pub fn write_to_message(&self, position: InChunkPos, mut builder: block_entity_data::Builder) {
+        builder.set_position(position.as_index() as u16);
+        let mut components = builder.init_components(self.components.len() as u32);
+        for (i, component) in self.components.iter().enumerate() {
+            let mut component_builder = components.reborrow().get(i as u32);
+            component_builder.set_name(component.name.as_str());
+            component_builder.set_data(&component.data[..]);
+        }
+    }
+
+    /// Reads a record written by [`Self::write_to_message`], returning the position of its block.
+    pub fn read_from_message(
+        reader: block_entity_data::Reader,
+    ) -> Result<(InChunkPos, Self), ChunkDeserializationError> {
+        let position = reader.get_position();
+        let position = InChunkPos::try_from_index(position as usize)
+            .map_err(|_| ChunkDeserializationError::IllegalBlockEntityPosition(position))?;
+        let components_reader = reader.get_components()?;
+        if components_reader.len() > MAX_BLOCK_ENTITY_COMPONENTS {
+            return Err(ChunkDeserializationError::TooManyBlockEntityComponents(
+                components_reader.len(),
+            ));
+        }
+        let components = components_reader
+            .iter()
+            .map(|component| {
+                Ok(BlockEntityComponent {
+                    name: component.get_name()?.to_str().map_err(capnp::Error::from)?.to_owned(),
+                    data: component.get_data()?.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, capnp::Error>>()?;
+        Ok((position, Self::new(components)))
+    }
+}
@@ -1,11 +1,14 @@
 //! Representation of chunks of voxel data in the game.
 use bytemuck::{Pod, Zeroable};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use thiserror::Error;
 
+use crate::registry_remap::RegistryIdRemap;
+use crate::voxel::block_entity::{BlockEntityRecord, BlockEntityStorage};
 use crate::voxel::chunk_storage::palette::PaletteDeserializationError;
-use crate::voxel::chunk_storage::{ArrayStorage, PaletteStorage};
+use crate::voxel::chunk_storage::{ArrayStorage, ChunkStorage, PaletteStorage};
 use crate::voxel::voxeltypes::BlockEntry;
 use crate::{GsExtraData, SmallCowVec};
 
@@ -21,6 +24,8 @@ pub struct Chunk<ExtraData: GsExtraData> {
     pub blocks: PaletteStorage<BlockEntry>,
     /// Light data
     pub light_level: ArrayStorage<BlockLight>,
+    /// Records of the entities managing the placeholder blocks
+    pub block_entities: BlockEntityStorage,
     /// Any extra per-chunk data needed by the API user
     pub extra_data: ExtraData::ChunkData,
 }
@@ -37,6 +42,12 @@ pub enum ChunkDeserializationError {
     /// Illegal block ID in palette data.
     #[error("Illegal block ID in palette data")]
     IllegalBlockID,
+    /// Block entity position outside of the chunk.
+    #[error("Block entity position {0} outside of the chunk")]
+    IllegalBlockEntityPosition(u16),
+    /// Block entity with more components than allowed.
+    #[error("Block entity with {0} components, more than allowed")]
+    TooManyBlockEntityComponents(u32),
 }
 
 /// Manual clone implementation, because the auto-derived one puts an unnecessary bound on ExtraData.
@@ -45,6 +56,7 @@ impl<ExtraData: GsExtraData> Clone for Chunk<ExtraData> {
         Self {
             blocks: self.blocks.clone(),
             light_level: self.light_level.clone(),
+            block_entities: self.block_entities.clone(),
             extra_data: self.extra_data.clone(),
         }
     }
@@ -56,6 +68,7 @@ impl<ExtraData: GsExtraData> Chunk<ExtraData> {
         Self {
             blocks: PaletteStorage::new(fill_block),
             light_level: ArrayStorage::default(),
+            block_entities: BlockEntityStorage::default(),
             extra_data,
         }
     }
@@ -71,6 +84,19 @@ impl<ExtraData: GsExtraData> Chunk<ExtraData> {
             palette_builder.set(i as u32, entry.as_packed());
         }
         output.set_block_data(block_data).unwrap();
+        // Sorted by position to keep the serialized form of equal chunks identical.
+        let block_entities: SmallVec<[_; 8]> = self
+            .block_entities
+            .iter()
+            .filter_map(|(position, record)| Some((position, record.as_ref()?)))
+            .sorted_unstable_by_key(|(position, _)| position.as_index())
+            .collect();
+        let mut block_entities_builder = output
+            .reborrow()
+            .init_block_entities(block_entities.len().try_into().unwrap());
+        for (i, (position, record)) in block_entities.into_iter().enumerate() {
+            record.write_to_message(position, block_entities_builder.reborrow().get(i as u32));
+        }
     }
 
     /// Reads a fully serialized chunk from the given schema reader.
@@ -96,12 +122,41 @@ impl<ExtraData: GsExtraData> Chunk<ExtraData> {
             SmallCowVec::Owned(SmallVec::from_iter(data_reader.iter()))
         };
 
+        let mut block_entities = BlockEntityStorage::default();
+        for record in reader.get_block_entities()? {
+            let (position, record) = BlockEntityRecord::read_from_message(record)?;
+            block_entities.put(position, Some(record));
+        }
+
         let chunk = Self {
             blocks: PaletteStorage::from_serialized(palette.into(), data)?,
             light_level: ArrayStorage::default(),
+            block_entities,
             extra_data,
         };
 
         Ok(chunk)
     }
+
+    /// Translates the block IDs of a chunk read from a savefile into the IDs of the current block registry.
+    /// Only the block palette is rewritten, so the cost doesn't depend on the chunk contents.
+    /// Blocks replaced because they no longer exist lose their metadata.
+    pub fn remap_block_ids(&mut self, remap: &RegistryIdRemap) -> Result<(), ChunkDeserializationError> {
+        if remap.is_identity() {
+            return Ok(());
+        }
+        if self
+            .blocks
+            .palette_entries()
+            .iter()
+            .any(|entry| remap.get(entry.id).is_none())
+        {
+            return Err(ChunkDeserializationError::IllegalBlockID);
+        }
+        self.blocks.map_palette(|entry| {
+            let metadata = if remap.is_replaced(entry.id) { 0 } else { entry.metadata };
+            BlockEntry::new(remap.get(entry.id).unwrap(), metadata)
+        });
+        Ok(())
+    }
 }
@@ -174,6 +174,15 @@ impl<DataType: ChunkDataType + Copy> PaletteStorage<DataType> {
         self.iter().enumerate_xzy()
     }
 
+    /// Replaces every palette entry with the result of the given function, changing all the elements of the chunk
+    /// equal to that entry at once without touching the per-element data.
+    /// Different entries may map to the same value, the palette then keeps the duplicates.
+    pub fn map_palette(&mut self, mut f: impl FnMut(DataType) -> DataType) {
+        for entry in self.palette.iter_mut() {
+            *entry = f(*entry);
+        }
+    }
+
     /// Optimizes the internal storage by removing redundant data and shrinking the data array if possible.
     pub fn optimize(&mut self) {
         self.palette_gc(None);
@@ -464,5 +473,11 @@ mod test {
                 assert_eq!(*val, 1_000_000);
             }
         }
+
+        chunk.map_palette(|val| if val == 2_000_000 { 1_000_000 } else { val + 1 });
+        assert!(chunk.iter().all(|&val| val == 1_000_000 || val == 1_000_001));
+        assert_eq!(*chunk.get(InChunkPos::ZERO), 1_000_000);
+        chunk.put(InChunkPos::ZERO, 3);
+        assert_eq!(*chunk.get(InChunkPos::ZERO), 3);
     }
 }
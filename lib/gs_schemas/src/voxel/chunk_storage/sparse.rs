@@ -47,3 +47,29 @@ impl<DataType: ChunkDataType + Default> ChunkStorage<DataType> for SparseStorage
         }
     }
 }
+
+impl<DataType: ChunkDataType + Default> SparseStorage<DataType> {
+    /// Number of positions with an allocated value, including values explicitly set to the default.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Checks if no values are allocated.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterates over the positions with an allocated value, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (InChunkPos, &DataType)> {
+        self.data
+            .iter()
+            .map(|(&idx, value)| (InChunkPos::try_from_index(idx as usize).unwrap(), value))
+    }
+
+    /// Frees the value at the given coordinates, resetting it to the default.
+    ///
+    /// Returns the old value.
+    pub fn remove(&mut self, position: InChunkPos) -> DataType {
+        self.data.remove(&(position.as_index() as u16)).unwrap_or_default()
+    }
+}
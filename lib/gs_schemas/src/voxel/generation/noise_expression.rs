@@ -0,0 +1,262 @@
+//! Declarative 2D terrain noise expressions, compiled into a flat list of operations for fast evaluation.
+
+use bevy_math::DVec2;
+use hashbrown::HashMap;
+use noise::OpenSimplex;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use super::fbm_noise::Fbm;
+use super::NoiseNDTo2D;
+
+/// A tree of operations over the terrain [`Fbm`] noise, evaluated at a 2D world position.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NoiseExpression {
+    /// A constant value.
+    Constant(f64),
+    /// The terrain noise sampled at the position multiplied by the given frequency.
+    Fbm(f64),
+    /// The sum of all the expressions.
+    Add(Vec<NoiseExpression>),
+    /// The product of all the expressions.
+    Mul(Vec<NoiseExpression>),
+    /// The first expression minus the second one.
+    Sub(Box<NoiseExpression>, Box<NoiseExpression>),
+    /// The first expression divided by the second one.
+    Div(Box<NoiseExpression>, Box<NoiseExpression>),
+    /// The smallest value of all the expressions.
+    Min(Vec<NoiseExpression>),
+    /// The largest value of all the expressions.
+    Max(Vec<NoiseExpression>),
+    /// The absolute value of the expression.
+    Abs(Box<NoiseExpression>),
+}
+
+impl NoiseExpression {
+    /// Checks that all the numbers are finite and all the operations have operands.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Constant(value) | Self::Fbm(value) => {
+                if value.is_finite() {
+                    Ok(())
+                } else {
+                    Err(format!("{value} is not a finite number"))
+                }
+            }
+            Self::Add(operands) | Self::Mul(operands) | Self::Min(operands) | Self::Max(operands) => {
+                if operands.is_empty() {
+                    return Err(String::from("operations need at least one operand"));
+                }
+                operands.iter().try_for_each(Self::validate)
+            }
+            Self::Sub(a, b) | Self::Div(a, b) => a.validate().and_then(|_| b.validate()),
+            Self::Abs(a) => a.validate(),
+        }
+    }
+
+    /// Compiles the expression, folding constants and evaluating identical sub-expressions only once.
+    /// The expression should be [validated](Self::validate) first.
+    pub fn compile(&self) -> CompiledNoise {
+        let mut compiler = NoiseCompiler::default();
+        let result = compiler.compile(self);
+        CompiledNoise {
+            ops: compiler.ops,
+            result,
+        }
+    }
+}
+
+/// A single compiled operation, operands are indices of earlier operations.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum NoiseOp {
+    Constant(f64),
+    Fbm(f64),
+    Binary(BinaryOp, u32, u32),
+    Abs(u32),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum BinaryOp {
+    Add,
+    Mul,
+    Sub,
+    Div,
+    Min,
+    Max,
+}
+
+impl BinaryOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Add => a + b,
+            Self::Mul => a * b,
+            Self::Sub => a - b,
+            Self::Div => a / b,
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+
+    fn is_commutative(self) -> bool {
+        !matches!(self, Self::Sub | Self::Div)
+    }
+}
+
+/// [`NoiseOp`] with bit-exact floats and ordered commutative operands, to find duplicate operations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+enum NoiseOpKey {
+    Constant(u64),
+    Fbm(u64),
+    Binary(BinaryOp, u32, u32),
+    Abs(u32),
+}
+
+impl NoiseOp {
+    fn key(self) -> NoiseOpKey {
+        match self {
+            Self::Constant(value) => NoiseOpKey::Constant(value.to_bits()),
+            Self::Fbm(frequency) => NoiseOpKey::Fbm(frequency.to_bits()),
+            Self::Binary(op, a, b) if op.is_commutative() => NoiseOpKey::Binary(op, a.min(b), a.max(b)),
+            Self::Binary(op, a, b) => NoiseOpKey::Binary(op, a, b),
+            Self::Abs(a) => NoiseOpKey::Abs(a),
+        }
+    }
+}
+
+#[derive(Default)]
+struct NoiseCompiler {
+    ops: Vec<NoiseOp>,
+    existing: HashMap<NoiseOpKey, u32>,
+}
+
+impl NoiseCompiler {
+    fn constant(&self, index: u32) -> Option<f64> {
+        match self.ops[index as usize] {
+            NoiseOp::Constant(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, op: NoiseOp) -> u32 {
+        let folded = match op {
+            NoiseOp::Binary(binary, a, b) => self.constant(a).zip(self.constant(b)).map(|(a, b)| binary.apply(a, b)),
+            NoiseOp::Abs(a) => self.constant(a).map(f64::abs),
+            NoiseOp::Constant(_) | NoiseOp::Fbm(_) => None,
+        };
+        let op = folded.map_or(op, NoiseOp::Constant);
+        *self.existing.entry(op.key()).or_insert_with(|| {
+            self.ops.push(op);
+            (self.ops.len() - 1) as u32
+        })
+    }
+
+    fn compile_fold(&mut self, operands: &[NoiseExpression], op: BinaryOp) -> u32 {
+        let mut result = self.compile(&operands[0]);
+        for operand in &operands[1..] {
+            let operand = self.compile(operand);
+            result = self.push(NoiseOp::Binary(op, result, operand));
+        }
+        result
+    }
+
+    fn compile_binary(&mut self, a: &NoiseExpression, b: &NoiseExpression, op: BinaryOp) -> u32 {
+        let a = self.compile(a);
+        let b = self.compile(b);
+        self.push(NoiseOp::Binary(op, a, b))
+    }
+
+    fn compile(&mut self, expression: &NoiseExpression) -> u32 {
+        match expression {
+            NoiseExpression::Constant(value) => self.push(NoiseOp::Constant(*value)),
+            NoiseExpression::Fbm(frequency) => self.push(NoiseOp::Fbm(*frequency)),
+            NoiseExpression::Add(operands) => self.compile_fold(operands, BinaryOp::Add),
+            NoiseExpression::Mul(operands) => self.compile_fold(operands, BinaryOp::Mul),
+            NoiseExpression::Min(operands) => self.compile_fold(operands, BinaryOp::Min),
+            NoiseExpression::Max(operands) => self.compile_fold(operands, BinaryOp::Max),
+            NoiseExpression::Sub(a, b) => self.compile_binary(a, b, BinaryOp::Sub),
+            NoiseExpression::Div(a, b) => self.compile_binary(a, b, BinaryOp::Div),
+            NoiseExpression::Abs(a) => {
+                let a = self.compile(a);
+                self.push(NoiseOp::Abs(a))
+            }
+        }
+    }
+}
+
+/// A [`NoiseExpression`] compiled for evaluation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledNoise {
+    ops: Vec<NoiseOp>,
+    result: u32,
+}
+
+impl CompiledNoise {
+    /// Evaluates the expression at the given point.
+    pub fn evaluate(&self, point: DVec2, noise: &Fbm<OpenSimplex>) -> f64 {
+        let mut values: SmallVec<[f64; 32]> = SmallVec::with_capacity(self.ops.len());
+        for op in &self.ops {
+            let value = match *op {
+                NoiseOp::Constant(value) => value,
+                NoiseOp::Fbm(frequency) => {
+                    <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(noise, (point * frequency).to_array())
+                }
+                NoiseOp::Binary(binary, a, b) => binary.apply(values[a as usize], values[b as usize]),
+                NoiseOp::Abs(a) => values[a as usize].abs(),
+            };
+            values.push(value);
+        }
+        values[self.result as usize]
+    }
+
+    /// The number of operations evaluated per point.
+    pub fn op_count(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fbm(frequency: f64) -> NoiseExpression {
+        NoiseExpression::Fbm(frequency)
+    }
+
+    fn constant(value: f64) -> NoiseExpression {
+        NoiseExpression::Constant(value)
+    }
+
+    #[test]
+    fn compile_and_evaluate() {
+        let noise = Fbm::<OpenSimplex>::new(1234).set_octaves(vec![1.0, 1.0]);
+        let ridge = |frequency| {
+            NoiseExpression::Sub(
+                Box::new(constant(1.0)),
+                Box::new(NoiseExpression::Abs(Box::new(fbm(frequency)))),
+            )
+        };
+        let expression = NoiseExpression::Add(vec![
+            NoiseExpression::Mul(vec![ridge(0.5), constant(2.0), constant(3.0)]),
+            NoiseExpression::Max(vec![ridge(0.5), fbm(2.0)]),
+            NoiseExpression::Div(Box::new(constant(1.0)), Box::new(constant(4.0))),
+        ]);
+        expression.validate().unwrap();
+        let compiled = expression.compile();
+        // 1 - |fbm(0.5)| is computed once and 1 / 4 is folded into a constant
+        assert_eq!(compiled.op_count(), 14);
+        for point in [DVec2::new(0.0, 0.0), DVec2::new(0.3, -1.7), DVec2::new(12.5, 4.25)] {
+            let get =
+                |frequency: f64| <Fbm<OpenSimplex> as NoiseNDTo2D<4>>::get_2d(&noise, (point * frequency).to_array());
+            let r = 1.0 - get(0.5).abs();
+            let expected = r * 2.0 * 3.0 + r.max(get(2.0)) + 0.25;
+            assert!((compiled.evaluate(point, &noise) - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn validation() {
+        assert!(NoiseExpression::Add(vec![]).validate().is_err());
+        assert!(NoiseExpression::Abs(Box::new(fbm(f64::NAN))).validate().is_err());
+        assert!(NoiseExpression::Min(vec![constant(1.0), fbm(2.0)]).validate().is_ok());
+    }
+}
@@ -1,6 +1,7 @@
 //! All voxel storage related types
 
 pub mod biome;
+pub mod block_entity;
 pub mod chunk;
 pub mod chunk_group;
 pub mod chunk_storage;
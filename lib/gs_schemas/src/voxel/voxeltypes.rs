@@ -5,7 +5,10 @@ use std::hash::{Hash, Hasher};
 use rgb::RGBA8;
 use serde::{Deserialize, Serialize};
 
-use crate::registry::{Registry, RegistryId, RegistryName, RegistryNameRef, RegistryObject};
+use crate::content::{
+    merge_content, parse_content_file, parse_content_name, parse_hex_color, ContentError, ContentFileRef, ContentPack,
+};
+use crate::registry::{FrozenRegistry, Registry, RegistryId, RegistryName, RegistryNameRef, RegistryObject};
 
 /// The type for metadata attached to a block entry, used for determining the shape to render and/or collide with.
 pub type BlockMetadata = u32;
@@ -22,6 +25,8 @@ pub struct BlockEntry {
 
 /// A named registry of block definitions.
 pub type BlockRegistry = Registry<BlockDefinition>;
+/// A finished block registry, as shared between systems.
+pub type FrozenBlockRegistry = FrozenRegistry<BlockDefinition>;
 
 impl BlockEntry {
     /// Helper to construct a new block ID
@@ -80,6 +85,39 @@ pub struct BlockDefinition {
     pub has_collision_box: bool,
     /// If the block has a mesh that can be rendered
     pub has_drawable_mesh: bool,
+    /// The textures to draw the block's faces with
+    pub textures: BlockTextures,
+}
+
+/// Texture asset paths of the faces of a block, the more specific faces take precedence over `all`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockTextures {
+    /// The texture of all the faces without a more specific texture
+    pub all: Option<String>,
+    /// The texture of the upwards facing face
+    pub top: Option<String>,
+    /// The texture of the downwards facing face
+    pub bottom: Option<String>,
+    /// The texture of the horizontally facing faces
+    pub side: Option<String>,
+}
+
+impl BlockTextures {
+    /// No textures, the block is drawn with its representative color.
+    pub const NONE: Self = Self {
+        all: None,
+        top: None,
+        bottom: None,
+        side: None,
+    };
+
+    /// Iterates over the texture paths that are set.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        [&self.all, &self.top, &self.bottom, &self.side]
+            .into_iter()
+            .filter_map(|texture| texture.as_deref())
+    }
 }
 
 /// The registry name of [`EMPTY_BLOCK`]
@@ -92,8 +130,113 @@ pub static EMPTY_BLOCK: BlockDefinition = BlockDefinition {
     representative_color: RGBA8::new(0, 0, 0, 0),
     has_collision_box: false,
     has_drawable_mesh: false,
+    textures: BlockTextures::NONE,
+};
+
+/// The registry name of [`UNKNOWN_BLOCK`]
+pub const UNKNOWN_BLOCK_NAME: RegistryName = RegistryName::gs_const("unknown");
+
+/// The placeholder block definition, replacing saved blocks that no longer exist in the current content packs
+pub static UNKNOWN_BLOCK: BlockDefinition = BlockDefinition {
+    name: UNKNOWN_BLOCK_NAME,
+    shape_set: BlockShapeSet::FullCubeOnly,
+    representative_color: RGBA8::new(255, 0, 255, 255),
+    has_collision_box: true,
+    has_drawable_mesh: true,
+    textures: BlockTextures::NONE,
+};
+
+/// The registry name of [`PLACEHOLDER_BLOCK`]
+pub const PLACEHOLDER_BLOCK_NAME: RegistryName = RegistryName::gs_const("placeholder");
+
+/// The block reserving its block space for an entity managing it, see [`crate::voxel::block_entity`]
+pub static PLACEHOLDER_BLOCK: BlockDefinition = BlockDefinition {
+    name: PLACEHOLDER_BLOCK_NAME,
+    shape_set: BlockShapeSet::FullCubeOnly,
+    representative_color: RGBA8::new(0, 0, 0, 0),
+    has_collision_box: true,
+    has_drawable_mesh: false,
+    textures: BlockTextures::NONE,
 };
 
+/// The content pack directory containing block definition files.
+pub const BLOCKS_DIRECTORY: &str = "blocks";
+
+/// A block definition as written in a content file, see [`BlockDefinition`] for the meaning of the fields.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDefinitionFile {
+    /// The registry name, `namespace:key` or just `key` for the gs namespace
+    pub name: String,
+    /// The set of shapes available
+    #[serde(default)]
+    pub shape_set: BlockShapeSet,
+    /// The representative color as `#rrggbb` or `#rrggbbaa`
+    pub color: String,
+    /// If the block can be collided with
+    #[serde(default = "default_true")]
+    pub collision: bool,
+    /// If the block has a mesh that can be rendered
+    #[serde(default = "default_true")]
+    pub drawable: bool,
+    /// The textures to draw the block's faces with
+    #[serde(default)]
+    pub textures: BlockTextures,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl BlockDefinitionFile {
+    /// Validates the definition read from the given file.
+    pub fn into_definition(self, file: &ContentFileRef) -> Result<BlockDefinition, ContentError> {
+        let name = parse_content_name(file, &self.name)?;
+        if name == EMPTY_BLOCK_NAME || name == UNKNOWN_BLOCK_NAME || name == PLACEHOLDER_BLOCK_NAME {
+            return Err(ContentError::invalid(
+                file,
+                &name,
+                format!(
+                    "the {} block is built into the engine and cannot be redefined",
+                    name.key
+                ),
+            ));
+        }
+        let representative_color = parse_hex_color(&self.color).map_err(|e| ContentError::invalid(file, &name, e))?;
+        if self.textures.iter().any(|texture| texture.trim().is_empty()) {
+            return Err(ContentError::invalid(file, &name, "texture paths cannot be empty"));
+        }
+        if !self.drawable && self.textures.iter().next().is_some() {
+            return Err(ContentError::invalid(
+                file,
+                &name,
+                "textures are set, but the block is not drawable",
+            ));
+        }
+        Ok(BlockDefinition {
+            name,
+            shape_set: self.shape_set,
+            representative_color,
+            has_collision_box: self.collision,
+            has_drawable_mesh: self.drawable,
+            textures: self.textures,
+        })
+    }
+}
+
+/// Loads and merges the block definitions of the given content packs, not including [`EMPTY_BLOCK`],
+/// [`UNKNOWN_BLOCK`] and [`PLACEHOLDER_BLOCK`].
+/// Each file in the blocks directory holds a list of [`BlockDefinitionFile`]s.
+pub fn load_block_definitions(packs: &[ContentPack]) -> Result<Vec<BlockDefinition>, ContentError> {
+    merge_content(packs, BLOCKS_DIRECTORY, |file, contents| {
+        let definitions: Vec<BlockDefinitionFile> = parse_content_file(file, contents)?;
+        definitions
+            .into_iter()
+            .map(|definition| definition.into_definition(file))
+            .collect()
+    })
+}
+
 impl RegistryObject for BlockDefinition {
     fn registry_name(&self) -> RegistryNameRef {
         self.name.as_ref()
@@ -113,3 +256,47 @@ impl Hash for BlockDefinition {
 }
 
 impl BlockDefinition {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load(contents: &str) -> Result<Vec<BlockDefinition>, ContentError> {
+        load_block_definitions(&[ContentPack::new("base").with_file("blocks/test.ron", contents)])
+    }
+
+    #[test]
+    fn load_blocks() {
+        let blocks = load(
+            r##"[
+                (name: "stone", color: "#404040", textures: (all: Some("blocks/stone.png"))),
+                (name: "mod:glass", shape_set: FullCubeOnly, color: "#ffffff40", collision: false),
+            ]"##,
+        )
+        .unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].name, RegistryName::gs("stone"));
+        assert_eq!(blocks[0].representative_color, RGBA8::new(64, 64, 64, 255));
+        assert_eq!(blocks[0].textures.all.as_deref(), Some("blocks/stone.png"));
+        assert!(blocks[0].has_collision_box && blocks[0].has_drawable_mesh);
+        assert_eq!(blocks[1].name, RegistryName::new("mod", "glass"));
+        assert_eq!(blocks[1].shape_set, BlockShapeSet::FullCubeOnly);
+        assert!(!blocks[1].has_collision_box);
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        let error = |contents| load(contents).unwrap_err().to_string();
+        assert_eq!(
+            error(r##"[(name: "empty", color: "#000000")]"##),
+            "base/blocks/test.ron: gs:empty: the empty block is built into the engine and cannot be redefined"
+        );
+        assert!(error(r##"[(name: "stone", color: "grey")]"##).contains("invalid colour"));
+        assert!(
+            error(r##"[(name: "air", color: "#000000", drawable: false, textures: (top: Some("a.png")))]"##)
+                .contains("not drawable")
+        );
+        assert!(error(r##"[(name: "stone", color: "#000000", textures: (all: Some(" ")))]"##).contains("empty"));
+        assert!(error(r##"[(name: "stone", colour: "#000000")]"##).starts_with("base/blocks/test.ron:1:"));
+    }
+}